@@ -0,0 +1,125 @@
+use crate::append_vec::append_vec_iter;
+use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use solana_snapshot_etl::Result;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Newest versions of selected accounts of a snapshot, for seeding a `solana-program-test`
+/// `ProgramTest` (through `add_account`) or a test bank with mainnet state.
+///
+/// `ProgramTest` takes its accounts before the test starts and has no hook for loading one
+/// on first access, and an archive can only be read front to back, so the accounts are
+/// collected in one scan up front. Accounts whose newest version holds no lamports are
+/// closed and left out.
+pub struct SnapshotAccounts {
+    accounts: HashMap<Pubkey, Account>,
+}
+
+impl SnapshotAccounts {
+    pub fn load<E: SnapshotExtractor>(
+        extractor: &mut E,
+        pubkeys: &HashSet<Pubkey>,
+    ) -> Result<Self> {
+        let mut newest: HashMap<Pubkey, ((u64, u64), Account)> = HashMap::new();
+        for append_vec in extractor.iter() {
+            let (slot, _id, append_vec) = append_vec?;
+            for account in append_vec_iter(Rc::new(append_vec)) {
+                let account = account.access().unwrap();
+                if !pubkeys.contains(&account.meta.pubkey) {
+                    continue;
+                }
+                let version = (slot, account.meta.write_version);
+                if newest
+                    .get(&account.meta.pubkey)
+                    .is_some_and(|(newest, _)| *newest >= version)
+                {
+                    continue;
+                }
+                let stored = Account {
+                    lamports: account.account_meta.lamports,
+                    data: account.data.to_vec(),
+                    owner: account.account_meta.owner,
+                    executable: account.account_meta.executable,
+                    rent_epoch: account.account_meta.rent_epoch,
+                };
+                newest.insert(account.meta.pubkey, (version, stored));
+            }
+        }
+        Ok(Self {
+            accounts: newest
+                .into_iter()
+                .filter(|(_, (_, account))| account.lamports > 0)
+                .map(|(pubkey, (_, account))| (pubkey, account))
+                .collect(),
+        })
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&Account> {
+        self.accounts.get(pubkey)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pubkey, &Account)> {
+        self.accounts.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestAccount, TestSnapshot};
+
+    fn version(pubkey: Pubkey, write_version: u64, lamports: u64, data: &[u8]) -> TestAccount {
+        let mut account = TestAccount::new(Pubkey::new_unique(), data.to_vec());
+        account.meta.pubkey = pubkey;
+        account.meta.write_version = write_version;
+        account.account_meta.lamports = lamports;
+        account
+    }
+
+    #[test]
+    fn keeps_the_newest_version_and_drops_closed_accounts() {
+        let (updated, closed, unrequested) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        // slot 12 is read before the older slot 10, and the tombstone of `closed` comes last
+        let mut snapshot = TestSnapshot(vec![
+            (12, vec![version(updated, 5, 2, &[2; 4])]),
+            (
+                10,
+                vec![
+                    version(updated, 9, 1, &[1; 4]),
+                    version(closed, 1, 7, &[7]),
+                    version(unrequested, 1, 3, &[3]),
+                ],
+            ),
+            (
+                11,
+                vec![version(updated, 3, 4, &[4; 4]), version(closed, 2, 0, &[])],
+            ),
+        ]);
+
+        let accounts =
+            SnapshotAccounts::load(&mut snapshot, &HashSet::from([updated, closed])).unwrap();
+        assert_eq!(accounts.len(), 1);
+        let account = accounts.get(&updated).unwrap();
+        assert_eq!(
+            (account.lamports, account.data.as_slice()),
+            (2, &[2; 4][..])
+        );
+        assert!(accounts.get(&closed).is_none());
+        assert!(accounts.get(&unrequested).is_none());
+    }
+}
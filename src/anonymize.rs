@@ -0,0 +1,156 @@
+use solana_program::hash::hashv;
+use solana_program::pubkey::Pubkey;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+const HMAC_BLOCK_SIZE: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum AnonymizeParseError {
+    #[error("Invalid anonymization spec (expected hmac:KEY)")]
+    InvalidAnonymizeSpec,
+}
+
+/// Replaces pubkeys with keyed HMAC-SHA256 digests, rendered as base58 so joins on the
+/// tokenized values keep working across datasets built with the same key.
+#[derive(Clone)]
+pub struct Anonymizer {
+    key_block: [u8; HMAC_BLOCK_SIZE],
+}
+
+// keep the key out of debug output
+impl fmt::Debug for Anonymizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Anonymizer(hmac)")
+    }
+}
+
+impl FromStr for Anonymizer {
+    type Err = AnonymizeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("hmac", key)) if !key.is_empty() => Ok(Anonymizer::new(key.as_bytes())),
+            _ => Err(AnonymizeParseError::InvalidAnonymizeSpec),
+        }
+    }
+}
+
+impl Anonymizer {
+    pub fn new(key: &[u8]) -> Self {
+        let mut key_block = [0u8; HMAC_BLOCK_SIZE];
+        if key.len() > HMAC_BLOCK_SIZE {
+            key_block[..32].copy_from_slice(&hashv(&[key]).to_bytes());
+        } else {
+            key_block[..key.len()].copy_from_slice(key);
+        }
+        Anonymizer { key_block }
+    }
+
+    fn hmac(&self, message: &[u8]) -> [u8; 32] {
+        let mut ipad = [0x36u8; HMAC_BLOCK_SIZE];
+        let mut opad = [0x5cu8; HMAC_BLOCK_SIZE];
+        for ((i, o), k) in ipad
+            .iter_mut()
+            .zip(opad.iter_mut())
+            .zip(self.key_block.iter())
+        {
+            *i ^= k;
+            *o ^= k;
+        }
+        let inner = hashv(&[&ipad[..], message]);
+        hashv(&[&opad[..], inner.as_ref()]).to_bytes()
+    }
+
+    /// Raw token for a 32 byte key, for callers encoding into their own buffers.
+    pub fn digest(&self, bytes: &[u8; 32]) -> [u8; 32] {
+        self.hmac(bytes)
+    }
+
+    pub fn bytes32(&self, bytes: &[u8; 32]) -> String {
+        bs58::encode(self.digest(bytes)).into_string()
+    }
+
+    pub fn pubkey(&self, pubkey: &Pubkey) -> String {
+        self.bytes32(&pubkey.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4231 HMAC-SHA-256 test cases 1, 2, 3, 4, 6 and 7 (case 5 truncates the output)
+    #[test]
+    fn matches_rfc_4231_vectors() {
+        let long_key = [0xaa; 131];
+        let cases: [(&[u8], &[u8], &str); 6] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &[
+                    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
+                    23, 24, 25,
+                ],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // keys longer than the block size are hashed first
+            (
+                &long_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &long_key,
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (key, message, expected) in cases {
+            assert_eq!(hex::encode(Anonymizer::new(key).hmac(message)), expected);
+        }
+    }
+
+    #[test]
+    fn tokens_are_stable_for_a_key() {
+        let pubkey = Pubkey::default();
+        let anonymizer = Anonymizer::from_str("hmac:secret").unwrap();
+        assert_eq!(
+            anonymizer.pubkey(&pubkey),
+            "ArvUbkNjmLMmnxbjvJrJfubYkDNpyJJ6ak7vAdmStjP1"
+        );
+        assert_eq!(
+            anonymizer.bytes32(&pubkey.to_bytes()),
+            anonymizer.pubkey(&pubkey)
+        );
+        let other = Anonymizer::from_str("hmac:other").unwrap();
+        assert_eq!(
+            other.pubkey(&pubkey),
+            "5CFCTAWqq19krfwxrMzFrJTyFS3EDfkd8muLDDHjL8eF"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_specs() {
+        for spec in ["", "hmac", "hmac:", "sha256:key"] {
+            assert!(Anonymizer::from_str(spec).is_err(), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use memmap2::MmapMut;
+use solana_sdk::hash::Hash;
+use solana_snapshot_etl::append_vec::{AccountMeta, StoredAccountMeta, StoredMeta};
+use std::io::{self, Read};
+use std::mem::{offset_of, size_of};
+use std::rc::Rc;
+
+/// Records in an append vec start at multiples of 8 bytes.
+const RECORD_ALIGN: usize = 8;
+
+/// Account storage file of a snapshot, read into anonymous memory.
+///
+/// Replaces `solana_snapshot_etl::append_vec::AppendVec`, whose reader casts pointers at
+/// any offset without checking alignment or the `executable` flag. Accounts are read
+/// through [`AccountReader`], so a bad offset or a corrupt record yields `None`.
+pub struct AppendVec {
+    map: MmapMut,
+    len: usize,
+}
+
+impl AppendVec {
+    /// Reads the first `current_len` bytes of `reader`, the used part of the storage file.
+    pub fn new_from_reader<R: Read>(reader: &mut R, current_len: usize) -> io::Result<Self> {
+        // an empty mapping is invalid, so empty append vecs still map a byte
+        let mut map = MmapMut::map_anon(current_len.max(1))?;
+        reader.read_exact(&mut map[..current_len])?;
+        Ok(Self {
+            map,
+            len: current_len,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The account stored at `offset` and the offset of the next one.
+    pub fn get_account(&self, offset: usize) -> Option<(StoredAccountMeta<'_>, usize)> {
+        AccountReader::new(&self.map[..self.len]).get_account(offset)
+    }
+}
+
+/// An account of an append vec, read when accessed.
+pub struct StoredAccountHandle {
+    append_vec: Rc<AppendVec>,
+    offset: usize,
+}
+
+impl StoredAccountHandle {
+    pub fn access(&self) -> Option<StoredAccountMeta<'_>> {
+        Some(self.append_vec.get_account(self.offset)?.0)
+    }
+}
+
+/// Accounts of `append_vec` in storage order, up to the first record that can't be read.
+pub fn append_vec_iter(append_vec: Rc<AppendVec>) -> impl Iterator<Item = StoredAccountHandle> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let (_, next) = append_vec.get_account(offset)?;
+        let handle = StoredAccountHandle {
+            append_vec: append_vec.clone(),
+            offset,
+        };
+        offset = next;
+        Some(handle)
+    })
+}
+
+/// Header types of an account record, laid out in the buffer as `repr(C)` structs.
+///
+/// # Safety
+///
+/// Implementors must be valid for every byte pattern that `is_valid` accepts.
+#[allow(unsafe_code)]
+unsafe trait Header: Sized {
+    fn is_valid(_bytes: &[u8]) -> bool {
+        true
+    }
+}
+
+// SAFETY: integers and `Pubkey` bytes, valid for any bytes
+#[allow(unsafe_code)]
+unsafe impl Header for StoredMeta {}
+
+// SAFETY: 32 bytes, valid for any bytes
+#[allow(unsafe_code)]
+unsafe impl Header for Hash {}
+
+// SAFETY: integers and `Pubkey` bytes besides the `bool`, which `is_valid` checks
+#[allow(unsafe_code)]
+unsafe impl Header for AccountMeta {
+    fn is_valid(bytes: &[u8]) -> bool {
+        bytes[offset_of!(AccountMeta, executable)] <= 1
+    }
+}
+
+/// Bounds- and alignment-checked reads of account records out of append vec bytes.
+#[derive(Clone, Copy)]
+pub struct AccountReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> AccountReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// The bytes `offset..offset + len` and the record-aligned offset after them.
+    fn slice(&self, offset: usize, len: usize) -> Option<(&'a [u8], usize)> {
+        let end = offset.checked_add(len)?;
+        let slice = self.bytes.get(offset..end)?;
+        let next = end.checked_next_multiple_of(RECORD_ALIGN)?;
+        Some((slice, next))
+    }
+
+    fn header<T: Header>(&self, offset: usize) -> Option<(&'a T, usize)> {
+        let (bytes, next) = self.slice(offset, size_of::<T>())?;
+        let ptr = bytes.as_ptr().cast::<T>();
+        if !ptr.is_aligned() || !T::is_valid(bytes) {
+            return None;
+        }
+        // SAFETY: `bytes` is in bounds, aligned for `T` and holds a valid `T` as checked
+        // above, and stays borrowed for `'a`
+        #[allow(unsafe_code)]
+        let header = unsafe { &*ptr };
+        Some((header, next))
+    }
+
+    /// The account stored at `offset` and the offset of the next one, or `None` if no
+    /// whole record starts there.
+    pub fn get_account(&self, offset: usize) -> Option<(StoredAccountMeta<'a>, usize)> {
+        let (meta, next) = self.header::<StoredMeta>(offset)?;
+        let (account_meta, next) = self.header::<AccountMeta>(next)?;
+        let (hash, next) = self.header::<Hash>(next)?;
+        let data_len = usize::try_from(meta.data_len).ok()?;
+        let (data, next) = self.slice(next, data_len)?;
+        Some((
+            StoredAccountMeta {
+                meta,
+                account_meta,
+                data,
+                offset,
+                stored_size: next - offset,
+                hash,
+            },
+            next,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::pubkey::Pubkey;
+
+    /// Synthetic append vec memory, aligned like a mapped storage file.
+    #[repr(C, align(8))]
+    struct Aligned([u8; 1024]);
+
+    fn put(buf: &mut [u8], at: usize, bytes: &[u8]) {
+        buf[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Writes an account record at `offset`, returning the offset of the next record.
+    fn write_account(
+        buf: &mut [u8],
+        offset: usize,
+        pubkey: u8,
+        lamports: u64,
+        executable: u8,
+        data: &[u8],
+    ) -> usize {
+        put(
+            buf,
+            offset + offset_of!(StoredMeta, write_version),
+            &9u64.to_ne_bytes(),
+        );
+        let data_len = (data.len() as u64).to_ne_bytes();
+        put(buf, offset + offset_of!(StoredMeta, data_len), &data_len);
+        put(buf, offset + offset_of!(StoredMeta, pubkey), &[pubkey; 32]);
+        let account_meta = offset + size_of::<StoredMeta>();
+        let lamports = lamports.to_ne_bytes();
+        put(
+            buf,
+            account_meta + offset_of!(AccountMeta, lamports),
+            &lamports,
+        );
+        put(
+            buf,
+            account_meta + offset_of!(AccountMeta, rent_epoch),
+            &3u64.to_ne_bytes(),
+        );
+        put(
+            buf,
+            account_meta + offset_of!(AccountMeta, owner),
+            &[0xee; 32],
+        );
+        put(
+            buf,
+            account_meta + offset_of!(AccountMeta, executable),
+            &[executable],
+        );
+        let hash = account_meta + size_of::<AccountMeta>();
+        put(buf, hash, &[0xab; 32]);
+        let data_offset = hash + size_of::<Hash>();
+        put(buf, data_offset, data);
+        (data_offset + data.len()).next_multiple_of(RECORD_ALIGN)
+    }
+
+    /// Two accounts, the first with data that isn't a multiple of the record alignment.
+    fn two_accounts() -> (Box<Aligned>, usize, usize) {
+        let mut buf = Box::new(Aligned([0; 1024]));
+        let second = write_account(&mut buf.0, 0, 1, 500, 0, &[1, 2, 3, 4, 5]);
+        let end = write_account(&mut buf.0, second, 2, 700, 1, &[]);
+        (buf, second, end)
+    }
+
+    #[test]
+    fn reads_accounts_in_order() {
+        let (buf, second, end) = two_accounts();
+        let reader = AccountReader::new(&buf.0[..end]);
+
+        let (account, next) = reader.get_account(0).unwrap();
+        assert_eq!(next, second);
+        assert_eq!(account.meta.pubkey, Pubkey::new_from_array([1; 32]));
+        assert_eq!(account.meta.write_version, 9);
+        assert_eq!(
+            account.account_meta.owner,
+            Pubkey::new_from_array([0xee; 32])
+        );
+        assert_eq!(account.account_meta.lamports, 500);
+        assert_eq!(account.account_meta.rent_epoch, 3);
+        assert!(!account.account_meta.executable);
+        assert_eq!(account.hash.to_bytes(), [0xab; 32]);
+        assert_eq!(account.data, &[1, 2, 3, 4, 5]);
+        assert_eq!(account.offset, 0);
+        assert_eq!(account.stored_size, second);
+
+        let (account, next) = reader.get_account(second).unwrap();
+        assert_eq!(next, end);
+        assert_eq!(account.meta.pubkey, Pubkey::new_from_array([2; 32]));
+        assert!(account.account_meta.executable);
+        assert!(account.data.is_empty());
+
+        assert!(reader.get_account(end).is_none());
+    }
+
+    #[test]
+    fn rejects_misaligned_offsets() {
+        let (buf, second, end) = two_accounts();
+        let reader = AccountReader::new(&buf.0[..end]);
+        for offset in [1, 4, second - 1, second + 1] {
+            assert!(reader.get_account(offset).is_none(), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_offsets() {
+        let (buf, _, end) = two_accounts();
+        let reader = AccountReader::new(&buf.0[..end]);
+        for offset in [end + 8, usize::MAX - 7, usize::MAX] {
+            assert!(reader.get_account(offset).is_none(), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let (buf, second, end) = two_accounts();
+        // the header of the second account is cut short
+        let reader = AccountReader::new(&buf.0[..second + 20]);
+        assert!(reader.get_account(0).is_some());
+        assert!(reader.get_account(second).is_none());
+        // the data of the first account is cut short
+        let data_end = second - 3;
+        assert!(AccountReader::new(&buf.0[..data_end - 1])
+            .get_account(0)
+            .is_none());
+        assert!(AccountReader::new(&buf.0[..end])
+            .get_account(second)
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_data_len_past_the_buffer() {
+        let (mut buf, second, end) = two_accounts();
+        for data_len in [u64::MAX, u64::MAX - 100, end as u64] {
+            put(
+                &mut buf.0,
+                offset_of!(StoredMeta, data_len),
+                &data_len.to_ne_bytes(),
+            );
+            let reader = AccountReader::new(&buf.0[..end]);
+            assert!(reader.get_account(0).is_none(), "data_len {}", data_len);
+            assert!(reader.get_account(second).is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_executable_flags() {
+        let (mut buf, second, end) = two_accounts();
+        let executable = second + size_of::<StoredMeta>() + offset_of!(AccountMeta, executable);
+        for flag in [2, 0x80, 0xff] {
+            buf.0[executable] = flag;
+            let reader = AccountReader::new(&buf.0[..end]);
+            assert!(reader.get_account(second).is_none(), "flag {}", flag);
+            assert!(reader.get_account(0).is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_misaligned_buffers() {
+        let (buf, _, end) = two_accounts();
+        assert!(AccountReader::new(&buf.0[1..end]).get_account(0).is_none());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // anonymous mmap
+    fn iterates_an_append_vec_read_from_a_stream() {
+        let (buf, _, end) = two_accounts();
+        let append_vec = AppendVec::new_from_reader(&mut &buf.0[..], end).unwrap();
+        assert_eq!(append_vec.len(), end);
+        let pubkeys = append_vec_iter(Rc::new(append_vec))
+            .map(|account| account.access().unwrap().meta.pubkey)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pubkeys,
+            [
+                Pubkey::new_from_array([1; 32]),
+                Pubkey::new_from_array([2; 32])
+            ]
+        );
+    }
+}
@@ -0,0 +1,550 @@
+use crate::catalog::kept_within_budget;
+
+use log::{info, warn};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{
+    HeaderMap, HeaderName, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, RANGE,
+};
+use reqwest::StatusCode;
+use solana_program::hash::hashv;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Reconnect attempts per run when connecting fails or the connection drops mid-stream.
+const MAX_RETRIES: usize = 5;
+
+/// Snapshot archive found in the cache, or a download that fills it.
+pub(crate) enum CachedSource {
+    Complete(CachedArchive),
+    Download(Box<CachedDownload>),
+}
+
+/// Marks a download in progress with `<cache-dir>/<key>.lock`, so a second run on the
+/// same cache directory fails instead of appending to the same `.part` file.
+///
+/// The file holds the PID of the run that took it. It is removed on drop; a lock left by
+/// a run that was killed or exited without dropping it is broken once that PID is gone.
+struct DownloadLock {
+    path: PathBuf,
+}
+
+impl DownloadLock {
+    fn acquire(path: PathBuf) -> io::Result<Self> {
+        match Self::create(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match lock_holder(&path) {
+                Some(pid) if !is_running(pid) => {
+                    warn!(
+                        "Breaking stale lock {} of run {}, which is gone",
+                        path.display(),
+                        pid
+                    );
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                        _ => {}
+                    }
+                    Self::create(&path).map_err(|e| Self::held(e, &path))
+                }
+                _ => Err(Self::held(e, &path)),
+            },
+            result => result,
+        }
+    }
+
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let lock = Self {
+            path: path.to_path_buf(),
+        };
+        writeln!(file, "{}", std::process::id())?;
+        Ok(lock)
+    }
+
+    fn held(e: io::Error, path: &Path) -> io::Error {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return e;
+        }
+        let holder = match lock_holder(path) {
+            Some(pid) => format!("run {}", pid),
+            None => "another run".to_string(),
+        };
+        io::Error::new(
+            e.kind(),
+            format!(
+                "{} is downloading into the cache ({} exists; remove it if that run is gone)",
+                holder,
+                path.display()
+            ),
+        )
+    }
+}
+
+/// PID recorded in a lock file, or `None` while it is still being written or unreadable.
+fn lock_holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Whether process `pid` is still running, asking `kill -0` where there is no `/proc`.
+/// Assumes it is when that can't be told.
+fn is_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    let proc = Path::new("/proc");
+    if proc.join("self").exists() {
+        return proc.join(pid.to_string()).exists();
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+impl Drop for DownloadLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Marks a finished archive as being read with `<cache-dir>/<key>.reader-<pid>-<n>.lock`,
+/// so `gc` doesn't delete it under a running dump. Any number of runs read at once; like
+/// [`DownloadLock`], the file holds the reader's PID and is ignored once that PID is gone.
+struct ReaderLock {
+    path: PathBuf,
+}
+
+impl ReaderLock {
+    fn acquire(cache_dir: &Path, key: &str) -> io::Result<Self> {
+        static READERS: AtomicUsize = AtomicUsize::new(0);
+        let path = cache_dir.join(format!(
+            "{}.reader-{}-{}.lock",
+            key,
+            std::process::id(),
+            READERS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let lock = Self { path };
+        fs::write(&lock.path, format!("{}\n", std::process::id()))?;
+        Ok(lock)
+    }
+}
+
+impl Drop for ReaderLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Finished archive in the cache, holding its [`ReaderLock`] until it is dropped.
+pub(crate) struct CachedArchive {
+    file: File,
+    _lock: ReaderLock,
+}
+
+impl Read for CachedArchive {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+/// Opens the finished archive of `key`, or returns `None` when it isn't in the cache.
+fn open_complete(cache_dir: &Path, key: &str) -> io::Result<Option<CachedArchive>> {
+    // lock before opening, so a `gc` listing the cache from now on skips the archive
+    let lock = ReaderLock::acquire(cache_dir, key)?;
+    let path = cache_dir.join(format!("{}.tar.zst", key));
+    match File::open(&path) {
+        Ok(file) => {
+            info!("Reading cached snapshot {}", path.display());
+            Ok(Some(CachedArchive { file, _lock: lock }))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// HTTP stream that tees every fetched byte into `<cache-dir>/<key>.part`.
+///
+/// The key is the SHA-256 of the URL the request resolves to after redirects and of the
+/// `ETag` and `Content-Length` the server sends, so `.../snapshot.tar.zst` redirecting to
+/// a new slot or replaced in place never reuses stale bytes. Without either header only
+/// the URL tells archives apart. Bytes already in the cache are replayed first and the
+/// download resumes with a `Range` request from where the previous attempt stopped.
+pub(crate) struct CachedDownload {
+    client: Client,
+    url: String,
+    etag: Option<String>,
+    _lock: DownloadLock,
+    cached: Option<File>,
+    response: Option<Response>,
+    part: File,
+    part_path: PathBuf,
+    complete_path: PathBuf,
+    offset: u64,
+    retries: usize,
+    done: bool,
+}
+
+/// Files in `cache_dir` for `gc` to delete: finished downloads past `keep_last` and
+/// `max_disk`, newest (by modification time) first, `.part` files of downloads no run
+/// holds the lock of, and locks left by runs that are gone. Archives a running dump is
+/// reading count towards the budget but are never deleted.
+pub(crate) fn gc_candidates(
+    cache_dir: &Path,
+    keep_last: Option<usize>,
+    max_disk: Option<u64>,
+) -> io::Result<Vec<(PathBuf, u64)>> {
+    let mut complete = vec![];
+    let mut parts = vec![];
+    let mut evicted = vec![];
+    let mut in_use = HashSet::new();
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let metadata = fs::metadata(&path)?;
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let key = name.split('.').next().unwrap_or_default().to_string();
+        if name.ends_with(".tar.zst") {
+            complete.push((metadata.modified()?, key, path, metadata.len()));
+        } else if name.ends_with(".part") {
+            parts.push((key, path, metadata.len()));
+        } else if name.ends_with(".lock") {
+            if lock_holder(&path).is_none_or(is_running) {
+                in_use.insert(key);
+            } else {
+                evicted.push((path, metadata.len()));
+            }
+        }
+    }
+    evicted.extend(
+        parts
+            .into_iter()
+            .filter(|(key, _, _)| !in_use.contains(key))
+            .map(|(_, path, size)| (path, size)),
+    );
+    complete.sort_by_key(|(modified, _, _, _)| Reverse(*modified));
+    let kept = kept_within_budget(
+        complete.iter().map(|(_, _, _, size)| *size),
+        keep_last,
+        max_disk,
+    );
+    evicted.extend(
+        complete
+            .drain(kept..)
+            .filter(|(_, key, _, _)| !in_use.contains(key))
+            .map(|(_, _, path, size)| (path, size)),
+    );
+    Ok(evicted)
+}
+
+pub(crate) fn open(url: &str, cache_dir: &Path) -> io::Result<CachedSource> {
+    fs::create_dir_all(cache_dir)?;
+    let client = Client::new();
+    // only the headers name the cache entry; the body is requested once it's needed
+    let response = client
+        .head(url)
+        .send()
+        .and_then(Response::error_for_status)
+        .map_err(io::Error::other)?;
+    let url = response.url().to_string();
+    let etag = header(response.headers(), ETAG);
+    let length = header(response.headers(), CONTENT_LENGTH);
+    let key = hex::encode(
+        hashv(&[
+            url.as_bytes(),
+            etag.as_deref().unwrap_or_default().as_bytes(),
+            length.as_deref().unwrap_or_default().as_bytes(),
+        ])
+        .to_bytes(),
+    );
+    let complete_path = cache_dir.join(format!("{}.tar.zst", key));
+    let part_path = cache_dir.join(format!("{}.part", key));
+
+    if let Some(archive) = open_complete(cache_dir, &key)? {
+        return Ok(CachedSource::Complete(archive));
+    }
+    let lock = DownloadLock::acquire(cache_dir.join(format!("{}.lock", key)))?;
+    // another run may have finished the download before the lock was taken
+    if let Some(archive) = open_complete(cache_dir, &key)? {
+        return Ok(CachedSource::Complete(archive));
+    }
+
+    let cached_len = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+    // the download request is issued on the first read past the cached prefix
+    let cached = if cached_len > 0 {
+        info!(
+            "Resuming download of {} from {} cached bytes",
+            url, cached_len
+        );
+        Some(File::open(&part_path)?)
+    } else {
+        None
+    };
+    let part = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&part_path)?;
+
+    Ok(CachedSource::Download(Box::new(CachedDownload {
+        client,
+        url,
+        etag,
+        _lock: lock,
+        cached,
+        response: None,
+        part,
+        part_path,
+        complete_path,
+        offset: 0,
+        retries: 0,
+        done: false,
+    })))
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+impl CachedDownload {
+    /// Requests the bytes from `offset` on, or returns `None` when the server answers
+    /// `416 Range Not Satisfiable` because the cached bytes already are the whole archive.
+    fn connect(&self) -> io::Result<Option<Response>> {
+        let mut request = self.client.get(&self.url);
+        if self.offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", self.offset));
+            if let Some(etag) = &self.etag {
+                request = request.header(IF_RANGE, etag);
+            }
+        }
+        let response = request.send().map_err(io::Error::other)?;
+        if self.offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // `Content-Range: bytes */<total>` gives the full length
+            let total = header(response.headers(), CONTENT_RANGE)
+                .and_then(|range| range.strip_prefix("bytes */")?.parse::<u64>().ok());
+            if total == Some(self.offset) {
+                return Ok(None);
+            }
+        }
+        let mut response = response.error_for_status().map_err(io::Error::other)?;
+        if self.offset > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+            if self.etag.is_some() && header(response.headers(), ETAG) != self.etag {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} changed on the server during the download", self.url),
+                ));
+            }
+            warn!("Server ignored the range request, skipping cached bytes");
+            io::copy(&mut response.by_ref().take(self.offset), &mut io::sink())?;
+        }
+        Ok(Some(response))
+    }
+
+    /// Counts a retry and waits before it, or returns `err` once retries run out. A changed
+    /// archive is not retried.
+    fn retry(&mut self, err: io::Error, what: &str) -> io::Result<()> {
+        if self.retries >= MAX_RETRIES || err.kind() == io::ErrorKind::InvalidData {
+            return Err(err);
+        }
+        self.retries += 1;
+        warn!("{} at byte {} ({}), retrying", what, self.offset, err);
+        std::thread::sleep(Duration::from_secs(1 << self.retries));
+        Ok(())
+    }
+
+    fn complete(&mut self) -> io::Result<()> {
+        if !self.done {
+            self.part.flush()?;
+            fs::rename(&self.part_path, &self.complete_path)?;
+            info!("Cached snapshot at {}", self.complete_path.display());
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl Read for CachedDownload {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(cached) = self.cached.as_mut() {
+            let n = cached.read(buf)?;
+            if n > 0 {
+                self.offset += n as u64;
+                return Ok(n);
+            }
+            self.cached = None;
+        }
+
+        loop {
+            if self.response.is_none() {
+                match self.connect() {
+                    Ok(Some(response)) => self.response = Some(response),
+                    Ok(None) => {
+                        self.complete()?;
+                        return Ok(0);
+                    }
+                    Err(e) => {
+                        self.retry(e, "Connecting failed")?;
+                        continue;
+                    }
+                }
+            }
+            match self.response.as_mut().unwrap().read(buf) {
+                Ok(0) => {
+                    self.complete()?;
+                    return Ok(0);
+                }
+                Ok(n) => {
+                    self.part.write_all(&buf[..n])?;
+                    self.offset += n as u64;
+                    return Ok(n);
+                }
+                Err(e) => {
+                    self.response = None;
+                    self.retry(e, "Download interrupted")?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Serves `body` over HTTP on a local port, recording the method of every request.
+    fn serve(body: &'static [u8]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/snapshot.tar.zst", listener.local_addr().unwrap());
+        let methods = Arc::new(Mutex::new(vec![]));
+        let recorded = methods.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                let request = lines.next().unwrap().unwrap();
+                while !lines.next().unwrap().unwrap().is_empty() {}
+                let method = request.split(' ').next().unwrap().to_string();
+                recorded.lock().unwrap().push(method.clone());
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"1\"\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                if method == "GET" {
+                    stream.write_all(body).unwrap();
+                }
+            }
+        });
+        (url, methods)
+    }
+
+    /// PID of a process that has already exited.
+    fn exited_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn breaks_lock_of_exited_run() {
+        let dir = TempPath::dir("stale-lock");
+        let path = dir.join("key.lock");
+        fs::write(&path, format!("{}\n", exited_pid())).unwrap();
+
+        let lock = DownloadLock::acquire(path.clone()).unwrap();
+        assert_eq!(lock_holder(&path), Some(std::process::id()));
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keeps_lock_of_running_run() {
+        let dir = TempPath::dir("live-lock");
+        let path = dir.join("key.lock");
+        let lock = DownloadLock::acquire(path.clone()).unwrap();
+
+        let err = DownloadLock::acquire(path.clone()).map(|_| ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        drop(lock);
+    }
+
+    #[test]
+    fn gc_skips_archives_being_read() {
+        let dir = TempPath::dir("gc-in-use");
+        fs::write(dir.join("read.tar.zst"), [0u8; 16]).unwrap();
+        fs::write(dir.join("idle.tar.zst"), [0u8; 16]).unwrap();
+        fs::write(
+            dir.join("idle.reader-1-0.lock"),
+            format!("{}\n", exited_pid()),
+        )
+        .unwrap();
+        let reading = open_complete(&dir, "read").unwrap().unwrap();
+
+        let mut candidates = gc_candidates(&dir, Some(0), None)
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        candidates.sort();
+        assert_eq!(
+            candidates,
+            [dir.join("idle.reader-1-0.lock"), dir.join("idle.tar.zst")]
+        );
+
+        drop(reading);
+        let candidates = gc_candidates(&dir, Some(0), None).unwrap();
+        assert!(candidates
+            .iter()
+            .any(|(path, _)| *path == dir.join("read.tar.zst")));
+    }
+
+    #[test]
+    fn gc_keeps_part_of_running_download() {
+        let dir = TempPath::dir("gc-part");
+        fs::write(dir.join("live.part"), [0u8; 16]).unwrap();
+        fs::write(dir.join("stale.part"), [0u8; 16]).unwrap();
+        fs::write(dir.join("stale.lock"), format!("{}\n", exited_pid())).unwrap();
+        let lock = DownloadLock::acquire(dir.join("live.lock")).unwrap();
+
+        let mut candidates = gc_candidates(&dir, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        candidates.sort();
+        assert_eq!(candidates, [dir.join("stale.lock"), dir.join("stale.part")]);
+        drop(lock);
+    }
+
+    #[test]
+    fn downloads_once_and_reads_the_cache_after() {
+        let (url, methods) = serve(b"archive");
+        let cache_dir = TempPath::dir("cache-download");
+        let mut archive = vec![];
+        match open(&url, &cache_dir).unwrap() {
+            CachedSource::Download(mut download) => download.read_to_end(&mut archive).unwrap(),
+            CachedSource::Complete(_) => panic!("nothing cached yet"),
+        };
+        assert_eq!(archive, b"archive");
+        assert!(matches!(
+            open(&url, &cache_dir).unwrap(),
+            CachedSource::Complete(_)
+        ));
+        assert_eq!(*methods.lock().unwrap(), ["HEAD", "GET", "HEAD"]);
+    }
+}
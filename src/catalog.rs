@@ -0,0 +1,353 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+const CATALOG_FILE_NAME: &str = "catalog.json";
+const HOME_DIR_NAME: &str = ".solana-snapshot-gpa";
+
+#[derive(Error, Debug)]
+pub enum CatalogError {
+    #[error("Invalid snapshot file name (expected snapshot-<slot>-<hash>.tar.zst)")]
+    InvalidSnapshotName,
+    #[error("Snapshot for slot {0} is already in the catalog")]
+    DuplicateSlot(u64),
+    #[error("Snapshot for slot {0} is not in the catalog")]
+    UnknownSlot(u64),
+    #[error("Catalog I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Catalog format error: {0}")]
+    Format(#[from] serde_json::Error),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CatalogEntry {
+    pub path: String,
+    pub slot: u64,
+    pub hash: String,
+    pub size: u64,
+    pub added_at: u64,
+    /// Full snapshot slot of an incremental archive, `None` for a full one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_slot: Option<u64>,
+}
+
+/// Local metadata store of known snapshot archives, kept as a JSON file.
+pub struct Catalog {
+    path: PathBuf,
+    entries: Vec<CatalogEntry>,
+}
+
+/// Directory holding the catalog and other local state.
+///
+/// `$SOLANA_SNAPSHOT_GPA_HOME` takes precedence over `~/.solana-snapshot-gpa`.
+pub fn home_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("SOLANA_SNAPSHOT_GPA_HOME") {
+        return PathBuf::from(dir);
+    }
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    home.join(HOME_DIR_NAME)
+}
+
+/// Parses human readable sizes such as `500GB`, `2TB` or `1.5TiB` into bytes.
+pub fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_e| format!("Invalid size: {}", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1_000,
+        "M" | "MB" => 1_000_000,
+        "G" | "GB" => 1_000_000_000,
+        "T" | "TB" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        _ => return Err(format!("Invalid size unit: {}", unit)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Number of items to keep from `sizes`, listed newest first, so that at most `keep_last`
+/// remain and their total fits into `max_disk` bytes. The walk stops at the first item
+/// over the size budget, so no older, smaller item is kept in its place.
+pub fn kept_within_budget(
+    sizes: impl IntoIterator<Item = u64>,
+    keep_last: Option<usize>,
+    max_disk: Option<u64>,
+) -> usize {
+    let mut kept = 0usize;
+    let mut kept_size = 0u64;
+    for size in sizes {
+        let over_count = keep_last.is_some_and(|keep| kept >= keep);
+        let over_size = max_disk.is_some_and(|max| kept_size.saturating_add(size) > max);
+        if over_count || over_size {
+            break;
+        }
+        kept += 1;
+        kept_size += size;
+    }
+    kept
+}
+
+/// Parses `snapshot-<slot>-<hash>.tar.zst` and
+/// `incremental-snapshot-<base_slot>-<slot>-<hash>.tar.zst` file names.
+pub fn parse_snapshot_name(name: &str) -> Option<(u64, String)> {
+    let stem = name.strip_suffix(".tar.zst")?;
+    let parts: Vec<&str> = if let Some(rest) = stem.strip_prefix("incremental-snapshot-") {
+        let parts: Vec<&str> = rest.splitn(3, '-').collect();
+        if parts.len() != 3 || parts[0].parse::<u64>().is_err() {
+            return None;
+        }
+        parts[1..].to_vec()
+    } else {
+        stem.strip_prefix("snapshot-")?.splitn(2, '-').collect()
+    };
+    if parts.len() != 2 || parts[1].is_empty() {
+        return None;
+    }
+    let slot = parts[0].parse::<u64>().ok()?;
+    Some((slot, parts[1].to_string()))
+}
+
+/// Full snapshot slot an `incremental-snapshot-<base_slot>-<slot>-<hash>.tar.zst` builds on.
+pub fn parse_incremental_base_slot(name: &str) -> Option<u64> {
+    parse_snapshot_name(name)?;
+    let rest = name.strip_prefix("incremental-snapshot-")?;
+    rest.split('-').next()?.parse().ok()
+}
+
+impl Catalog {
+    pub fn default_path() -> PathBuf {
+        home_dir().join(CATALOG_FILE_NAME)
+    }
+
+    /// Opens the catalog at `path`; a missing file is an empty catalog.
+    pub fn open(path: &Path) -> Result<Self, CatalogError> {
+        let mut entries: Vec<CatalogEntry> = if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            serde_json::from_reader(reader)?
+        } else {
+            vec![]
+        };
+        // catalogs written before `base_slot` existed still name incremental archives
+        for entry in entries.iter_mut().filter(|entry| entry.base_slot.is_none()) {
+            let name = Path::new(&entry.path)
+                .file_name()
+                .and_then(|name| name.to_str());
+            entry.base_slot = name.and_then(parse_incremental_base_slot);
+        }
+        Ok(Catalog {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), CatalogError> {
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        // write to a temporary file first so an interrupted save never truncates the catalog
+        let tmp_path = self.path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, &self.entries)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    pub fn add(&mut self, path: &Path) -> Result<&CatalogEntry, CatalogError> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(CatalogError::InvalidSnapshotName)?;
+        let (slot, hash) = parse_snapshot_name(name).ok_or(CatalogError::InvalidSnapshotName)?;
+        let base_slot = parse_incremental_base_slot(name);
+        if self
+            .entries
+            .iter()
+            .any(|entry| entry.slot == slot && entry.base_slot == base_slot)
+        {
+            return Err(CatalogError::DuplicateSlot(slot));
+        }
+
+        let size = fs::metadata(path)?.len();
+        let path = fs::canonicalize(path)?;
+        let added_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        self.entries.push(CatalogEntry {
+            path: path.to_string_lossy().to_string(),
+            slot,
+            hash,
+            size,
+            added_at,
+            base_slot,
+        });
+        self.entries.sort_by_key(|entry| entry.slot);
+        Ok(self
+            .entries
+            .iter()
+            .find(|entry| entry.slot == slot && entry.base_slot == base_slot)
+            .unwrap())
+    }
+
+    /// Forgets the full snapshot recorded for `slot`.
+    pub fn remove(&mut self, slot: u64) -> Result<CatalogEntry, CatalogError> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.slot == slot && entry.base_slot.is_none())
+            .ok_or(CatalogError::UnknownSlot(slot))?;
+        Ok(self.entries.remove(index))
+    }
+
+    /// Forgets the archive at `path`, full or incremental.
+    pub fn remove_path(&mut self, path: &str) -> Option<CatalogEntry> {
+        let index = self.entries.iter().position(|entry| entry.path == path)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Archives to evict, oldest first, so that at most `keep_last` full snapshots remain
+    /// and their total size fits into `max_disk` bytes. Incremental archives go with the
+    /// full snapshot they build on and count towards its size; those whose full snapshot
+    /// is not in the catalog are evicted.
+    pub fn gc_candidates(
+        &self,
+        keep_last: Option<usize>,
+        max_disk: Option<u64>,
+    ) -> Vec<&CatalogEntry> {
+        // entries are sorted by slot, newest snapshots get the budget first
+        let full = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.base_slot.is_none())
+            .collect::<Vec<_>>();
+        let group_size = |full: &CatalogEntry| {
+            self.entries
+                .iter()
+                .filter(|entry| entry.base_slot == Some(full.slot))
+                .map(|entry| entry.size)
+                .fold(full.size, u64::saturating_add)
+        };
+        let kept = kept_within_budget(
+            full.iter().map(|entry| group_size(entry)),
+            keep_last,
+            max_disk,
+        );
+        let kept_slots = full[..kept]
+            .iter()
+            .map(|entry| entry.slot)
+            .collect::<Vec<_>>();
+        self.entries
+            .iter()
+            .filter(|entry| !kept_slots.contains(&entry.base_slot.unwrap_or(entry.slot)))
+            .collect()
+    }
+
+    /// The full snapshot recorded for `slot`; incremental archives are never selected.
+    pub fn find_slot(&self, slot: u64) -> Result<&CatalogEntry, CatalogError> {
+        self.entries
+            .iter()
+            .find(|entry| entry.slot == slot && entry.base_slot.is_none())
+            .ok_or(CatalogError::UnknownSlot(slot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_snapshot_names() {
+        assert_eq!(
+            parse_snapshot_name("snapshot-139240745-7Ai5kAj3mKwXd.tar.zst"),
+            Some((139240745, "7Ai5kAj3mKwXd".to_string()))
+        );
+        assert_eq!(
+            parse_snapshot_name("incremental-snapshot-139240745-139250000-Fk2w.tar.zst"),
+            Some((139250000, "Fk2w".to_string()))
+        );
+        assert_eq!(
+            parse_incremental_base_slot("incremental-snapshot-139240745-139250000-Fk2w.tar.zst"),
+            Some(139240745)
+        );
+        assert_eq!(
+            parse_incremental_base_slot("snapshot-139240745-7Ai5kAj3mKwXd.tar.zst"),
+            None
+        );
+        for name in [
+            "",
+            "snapshot-139240745-7Ai5.tar",
+            "snapshot-139240745-7Ai5.tar.bz2",
+            "snapshot-139240745.tar.zst",
+            "snapshot-139240745-.tar.zst",
+            "snapshot--7Ai5.tar.zst",
+            "snapshot-x-7Ai5.tar.zst",
+            "snapshot-18446744073709551616-7Ai5.tar.zst",
+            "Snapshot-139240745-7Ai5.tar.zst",
+            "incremental-snapshot-139240745-7Ai5.tar.zst",
+            "incremental-snapshot-x-139250000-Fk2w.tar.zst",
+            "incremental-snapshot-139240745-x-Fk2w.tar.zst",
+            "incremental-snapshot-139240745-139250000-.tar.zst",
+        ] {
+            assert_eq!(parse_snapshot_name(name), None, "{}", name);
+            assert_eq!(parse_incremental_base_slot(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parses_byte_sizes() {
+        for (s, bytes) in [
+            ("0", 0),
+            ("512", 512),
+            ("512B", 512),
+            ("10k", 10_000),
+            ("500GB", 500_000_000_000),
+            (" 2 TB ", 2_000_000_000_000),
+            ("1.5TiB", 3 << 39),
+            ("64MiB", 64 << 20),
+            ("1gib", 1 << 30),
+            ("0.5KB", 500),
+        ] {
+            assert_eq!(parse_byte_size(s), Ok(bytes), "{}", s);
+        }
+        for s in ["", "GB", "-1GB", "1.2.3GB", "10 PB", "10XB", "1e3", "ten"] {
+            assert!(parse_byte_size(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_items_within_budget() {
+        let sizes = [30, 20, 10, 5];
+        assert_eq!(kept_within_budget(sizes, None, None), 4);
+        assert_eq!(kept_within_budget(sizes, Some(2), None), 2);
+        assert_eq!(kept_within_budget(sizes, Some(0), None), 0);
+        assert_eq!(kept_within_budget(sizes, None, Some(50)), 2);
+        assert_eq!(kept_within_budget(sizes, None, Some(49)), 1);
+        // the walk stops at the first misfit instead of keeping older, smaller items
+        assert_eq!(kept_within_budget([30, 40, 1], None, Some(35)), 1);
+        assert_eq!(kept_within_budget(sizes, Some(3), Some(100)), 3);
+    }
+}
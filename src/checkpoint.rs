@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// `--checkpoint` file: a `source SOURCE filter FILTER_HASH options OPTIONS_HASH` line, then a
+/// `SLOT ID OUTPUT_LEN` line for every append vec whose matches are all in the output, which
+/// was `OUTPUT_LEN` bytes long by then.
+pub(crate) struct Checkpoint {
+    file: File,
+}
+
+/// What an earlier run of the same dump got through.
+#[derive(Default)]
+pub(crate) struct Resume {
+    pub(crate) done: HashSet<(u64, u64)>,
+    /// Output length after the last completed append vec; anything past it was written for
+    /// an append vec the run didn't finish.
+    pub(crate) output_len: u64,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint at `path` for a dump of `source` with the filters hashed to
+    /// `filter_hash` and the output options to `options_hash`, reading what an earlier run
+    /// recorded, or starts one.
+    pub(crate) fn open(
+        path: &Path,
+        source: &str,
+        filter_hash: &str,
+        options_hash: &str,
+    ) -> io::Result<(Self, Option<Resume>)> {
+        let header = format!(
+            "source {} filter {} options {}",
+            source, filter_hash, options_hash
+        );
+        let resume = match File::open(path) {
+            // a crash right after creating the file leaves it without a header
+            Ok(file) if file.metadata()?.len() == 0 => None,
+            Ok(file) => Some(read(file, &header)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if resume.is_none() {
+            writeln!(file, "{}", header)?;
+        }
+        Ok((Self { file }, resume))
+    }
+
+    /// Records that the matches of append vec `(slot, id)` are all in the output, now
+    /// `output_len` bytes long. Not synced: the lines survive a crash of the process, which
+    /// the output buffered by the OS would not outlive either.
+    pub(crate) fn record(&mut self, slot: u64, id: u64, output_len: u64) -> io::Result<()> {
+        writeln!(self.file, "{} {} {}", slot, id, output_len)
+    }
+}
+
+fn read(file: File, header: &str) -> io::Result<Resume> {
+    let mut lines = BufReader::new(file).lines();
+    match lines.next().transpose()? {
+        Some(line) if line == header => {}
+        Some(line) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("checkpoint is for a different dump ({})", line),
+            ))
+        }
+        None => return Ok(Resume::default()),
+    }
+    let mut resume = Resume::default();
+    for line in lines {
+        let line = line?;
+        let fields: Vec<u64> = line
+            .split(' ')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .unwrap_or_default();
+        // only the last line can be cut short by a crash
+        let [slot, id, output_len] = fields[..] else {
+            break;
+        };
+        resume.done.insert((slot, id));
+        resume.output_len = output_len;
+    }
+    Ok(resume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn resumes_from_recorded_append_vecs() {
+        let path = TempPath::new("resume");
+        let (mut checkpoint, resume) =
+            Checkpoint::open(&path, "snapshot.tar.zst", "ab12", "cd34").unwrap();
+        assert!(resume.is_none());
+        checkpoint.record(100, 1, 4096).unwrap();
+        checkpoint.record(100, 2, 8192).unwrap();
+        drop(checkpoint);
+
+        let (mut checkpoint, resume) =
+            Checkpoint::open(&path, "snapshot.tar.zst", "ab12", "cd34").unwrap();
+        let resume = resume.unwrap();
+        assert_eq!(resume.done, HashSet::from([(100, 1), (100, 2)]));
+        assert_eq!(resume.output_len, 8192);
+        checkpoint.record(101, 0, 9000).unwrap();
+        drop(checkpoint);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "source snapshot.tar.zst filter ab12 options cd34\n100 1 4096\n100 2 8192\n101 0 9000\n"
+        );
+    }
+
+    #[test]
+    fn stops_at_a_line_cut_short() {
+        let path = TempPath::new("cut-short");
+        let header = "source s filter f options o\n";
+        for (lines, done, output_len) in [
+            ("", 0, 0),
+            ("7 1 10\n7 2", 1, 10),
+            ("7 1 10\n7 2 20", 2, 20),
+            ("7 1 10\n7 2 2x\n", 1, 10),
+            ("7 1 10\n\n7 2 20\n", 1, 10),
+            ("7 1 10 99\n", 0, 0),
+            ("-7 1 10\n", 0, 0),
+        ] {
+            std::fs::write(&path, format!("{}{}", header, lines)).unwrap();
+            let (_, resume) = Checkpoint::open(&path, "s", "f", "o").unwrap();
+            let resume = resume.unwrap();
+            assert_eq!(resume.done.len(), done, "{:?}", lines);
+            assert_eq!(resume.output_len, output_len, "{:?}", lines);
+        }
+    }
+
+    #[test]
+    fn starts_over_after_a_crash_before_the_header() {
+        let path = TempPath::new("empty");
+        std::fs::write(&path, "").unwrap();
+        let (_, resume) = Checkpoint::open(&path, "s", "f", "o").unwrap();
+        assert!(resume.is_none());
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "source s filter f options o\n");
+    }
+
+    #[test]
+    fn rejects_a_checkpoint_of_another_dump() {
+        let path = TempPath::new("other");
+        std::fs::write(&path, "source s filter f options o\n7 1 10\n").unwrap();
+        for (source, filter_hash, options_hash) in
+            [("s", "g", "o"), ("t", "f", "o"), ("s", "f", "p")]
+        {
+            let error = Checkpoint::open(&path, source, filter_hash, options_hash)
+                .err()
+                .expect("a checkpoint of another dump");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        // the checkpoint is left as it was
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "source s filter f options o\n7 1 10\n");
+    }
+}
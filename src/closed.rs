@@ -0,0 +1,152 @@
+use crate::append_vec::append_vec_iter;
+use crate::filter::AccountFilter;
+use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+use crate::prepass::newest_accounts;
+
+use itertools::Itertools;
+use log::info;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Latest state of an account seen while scanning a snapshot.
+pub(crate) struct AccountVersion {
+    pub(crate) slot: u64,
+    pub(crate) write_version: u64,
+    pub(crate) lamports: u64,
+    pub(crate) owner: Pubkey,
+}
+
+/// An account alive in the earlier snapshot and gone (or drained) in the later one.
+pub(crate) struct ClosedAccount {
+    pub(crate) pubkey: Pubkey,
+    pub(crate) owner: Pubkey,
+    pub(crate) lamports: u64,
+    pub(crate) slot: u64,
+}
+
+/// Keeps only the newest version of each account accepted by `keep`.
+///
+/// Snapshots may hold several versions of one account in different append vecs; the
+/// highest slot wins, then the highest write version.
+pub(crate) fn latest_versions<L: SnapshotExtractor>(
+    loader: &mut L,
+    keep: impl Fn(&StoredAccountMeta) -> bool,
+) -> solana_snapshot_etl::Result<HashMap<Pubkey, AccountVersion>> {
+    let mut versions: HashMap<Pubkey, AccountVersion> = HashMap::new();
+    for append_vec in loader.iter() {
+        let (slot, _id, append_vec) = append_vec?;
+        for account in append_vec_iter(Rc::new(append_vec)) {
+            let account = account.access().unwrap();
+            if !keep(&account) {
+                continue;
+            }
+            let write_version = account.meta.write_version;
+            let newer = versions
+                .get(&account.meta.pubkey)
+                .is_none_or(|v| (slot, write_version) > (v.slot, v.write_version));
+            if newer {
+                versions.insert(
+                    account.meta.pubkey,
+                    AccountVersion {
+                        slot,
+                        write_version,
+                        lamports: account.account_meta.lamports,
+                        owner: account.account_meta.owner,
+                    },
+                );
+            }
+        }
+    }
+    Ok(versions)
+}
+
+/// Accounts with lamports in `before` that are missing or zero-lamport in `after`,
+/// sorted by owner then pubkey.
+///
+/// `before` and `before_again` read the same snapshot: an account already closed there has
+/// a newest version the filter no longer accepts, so finding it takes two scans.
+pub(crate) fn closed_accounts<A: SnapshotExtractor, B: SnapshotExtractor, C: SnapshotExtractor>(
+    before: &mut A,
+    before_again: &mut B,
+    after: &mut C,
+    filter: &AccountFilter,
+) -> solana_snapshot_etl::Result<Vec<ClosedAccount>> {
+    let alive = newest_accounts(before, before_again, |account| filter.is_match(account))?;
+    info!(
+        "Tracking {} accounts from the earlier snapshot",
+        alive.len()
+    );
+
+    // only accounts alive in the earlier snapshot matter, whatever their owner is now
+    let later = latest_versions(after, |account| alive.contains_key(&account.meta.pubkey))?;
+
+    let mut closed: Vec<ClosedAccount> = alive
+        .into_iter()
+        .filter(|(pubkey, _)| later.get(pubkey).is_none_or(|v| v.lamports == 0))
+        .map(|(pubkey, version)| ClosedAccount {
+            pubkey,
+            owner: version.owner,
+            lamports: version.lamports,
+            slot: version.slot,
+        })
+        .collect();
+    closed.sort_by_key(|account| (account.owner.to_bytes(), account.pubkey.to_bytes()));
+    Ok(closed)
+}
+
+/// Number of closed accounts and their former lamports per owner, in owner order.
+pub(crate) fn owner_totals(closed: &[ClosedAccount]) -> Vec<(Pubkey, u64, u64)> {
+    closed
+        .iter()
+        .group_by(|account| account.owner)
+        .into_iter()
+        .map(|(owner, accounts)| {
+            accounts.fold((owner, 0, 0), |(owner, count, lamports), account| {
+                (owner, count + 1, lamports + account.lamports)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MatchMode;
+    use crate::testing::{TestAccount, TestSnapshot};
+    use solana_program::system_program;
+
+    fn account(pubkey: Pubkey, owner: Pubkey, lamports: u64) -> TestAccount {
+        let mut account = TestAccount::new(owner, vec![0; 8]);
+        account.meta.pubkey = pubkey;
+        account.account_meta.lamports = lamports;
+        account
+    }
+
+    #[test]
+    fn skips_accounts_already_closed_in_the_earlier_snapshot() {
+        let owner = Pubkey::new_unique();
+        let (gone, closed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        // `gone` was closed in a later slot of the earlier snapshot, `closed` only after it
+        let before = || {
+            TestSnapshot(vec![
+                (
+                    10,
+                    vec![account(gone, owner, 500), account(closed, owner, 700)],
+                ),
+                (20, vec![account(gone, system_program::id(), 0)]),
+            ])
+        };
+        let mut after = TestSnapshot(vec![(30, vec![account(closed, system_program::id(), 0)])]);
+        let owners = [owner.to_string()];
+        let filter = AccountFilter::new(&[], None, &owners, &[], None, MatchMode::Any).unwrap();
+
+        let accounts = closed_accounts(&mut before(), &mut before(), &mut after, &filter).unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].pubkey, closed);
+        assert_eq!(accounts[0].owner, owner);
+        assert_eq!(accounts[0].lamports, 700);
+        assert_eq!(accounts[0].slot, 10);
+    }
+}
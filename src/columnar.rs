@@ -0,0 +1,144 @@
+use crate::dump::DumpOptions;
+
+use arrow_array::builder::{BinaryBuilder, StringBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+
+/// Records buffered per record batch; each batch becomes (part of) a row group.
+const ROWS_PER_BATCH: usize = 65_536;
+
+/// Data bytes buffered per record batch, so a run of huge accounts is written out early
+/// rather than held in memory or overflowing the builder's 32-bit offsets.
+const DATA_BYTES_PER_BATCH: usize = 256 << 20;
+
+/// Writes the default dump columns as a Snappy-compressed Parquet file, with `data` as raw
+/// bytes rather than base64.
+pub struct ParquetDumper {
+    /// `None` once the footer has been written.
+    writer: Option<ArrowWriter<BufWriter<File>>>,
+    schema: SchemaRef,
+    pubkey: StringBuilder,
+    owner: StringBuilder,
+    data_len: UInt64Builder,
+    lamports: UInt64Builder,
+    slot: UInt64Builder,
+    id: UInt64Builder,
+    offset: UInt64Builder,
+    write_version: UInt64Builder,
+    data: BinaryBuilder,
+    rows: usize,
+    data_bytes: usize,
+}
+
+impl ParquetDumper {
+    pub fn new(file: File) -> io::Result<Self> {
+        let u64_field = |name| Field::new(name, DataType::UInt64, false);
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("pubkey", DataType::Utf8, false),
+            Field::new("owner", DataType::Utf8, false),
+            u64_field("data_len"),
+            u64_field("lamports"),
+            u64_field("slot"),
+            u64_field("id"),
+            u64_field("offset"),
+            u64_field("write_version"),
+            Field::new("data", DataType::Binary, false),
+        ]));
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(BufWriter::new(file), schema.clone(), Some(properties))
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            writer: Some(writer),
+            schema,
+            pubkey: StringBuilder::new(),
+            owner: StringBuilder::new(),
+            data_len: UInt64Builder::new(),
+            lamports: UInt64Builder::new(),
+            slot: UInt64Builder::new(),
+            id: UInt64Builder::new(),
+            offset: UInt64Builder::new(),
+            write_version: UInt64Builder::new(),
+            data: BinaryBuilder::new(),
+            rows: 0,
+            data_bytes: 0,
+        })
+    }
+
+    pub fn dump_account(
+        &mut self,
+        options: &DumpOptions,
+        slot: u64,
+        id: u64,
+        account: &StoredAccountMeta,
+    ) -> io::Result<()> {
+        self.pubkey.append_value(options.format_pubkey(account));
+        self.owner
+            .append_value(account.account_meta.owner.to_string());
+        self.data_len.append_value(account.meta.data_len);
+        self.lamports.append_value(account.account_meta.lamports);
+        self.slot.append_value(slot);
+        self.id.append_value(id);
+        self.offset.append_value(account.offset as u64);
+        self.write_version.append_value(account.meta.write_version);
+        let data = options.data(account);
+        self.data.append_value(data);
+        self.rows += 1;
+        self.data_bytes += data.len();
+        if self.rows >= ROWS_PER_BATCH || self.data_bytes >= DATA_BYTES_PER_BATCH {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.pubkey.finish()),
+            Arc::new(self.owner.finish()),
+            Arc::new(self.data_len.finish()),
+            Arc::new(self.lamports.finish()),
+            Arc::new(self.slot.finish()),
+            Arc::new(self.id.finish()),
+            Arc::new(self.offset.finish()),
+            Arc::new(self.write_version.finish()),
+            Arc::new(self.data.finish()),
+        ];
+        let batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(io::Error::other)?;
+        writer.write(&batch).map_err(io::Error::other)?;
+        self.rows = 0;
+        self.data_bytes = 0;
+        Ok(())
+    }
+
+    /// Ends the current row group so everything so far is readable.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the remaining rows and the footer; a Parquet file without it is unreadable.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.take() {
+            writer.into_inner().map_err(io::Error::other)?.flush()?;
+        }
+        Ok(())
+    }
+}
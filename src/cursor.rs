@@ -0,0 +1,287 @@
+use crate::append_vec::{append_vec_iter, AppendVec};
+use crate::catalog::parse_snapshot_name;
+use crate::modified_solana_snapshot_etl::AppendVecIterator;
+
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CoordinateParseError {
+    #[error(
+        "Invalid account coordinate (expected SLOT:ID:OFFSET or snap://SNAPSHOT/SLOT.ID/OFFSET)"
+    )]
+    InvalidCoordinate,
+}
+
+pub const URI_SCHEME: &str = "snap://";
+
+/// `<slot>-<hash>` of a snapshot archive, taken from its file name (or URL).
+pub fn snapshot_id(source: &str) -> Option<String> {
+    let name = source.rsplit('/').next()?;
+    let (slot, hash) = parse_snapshot_name(name)?;
+    Some(format!("{}-{}", slot, hash))
+}
+
+/// Location of a stored account version: append vec `slot.id` and byte offset within it.
+///
+/// Written as `snap://<snapshot slot>-<hash>/<slot>.<id>/<offset>` where the snapshot is
+/// known, or as the bare `SLOT:ID:OFFSET` triple.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Coordinate {
+    pub snapshot: Option<String>,
+    pub slot: u64,
+    pub id: u64,
+    pub offset: usize,
+}
+
+impl Coordinate {
+    /// Whether the coordinate can be resolved in `snapshot` (a [`snapshot_id`]); bare
+    /// coordinates name no snapshot and fit any.
+    pub fn points_into(&self, snapshot: &str) -> bool {
+        self.snapshot.as_deref().is_none_or(|own| own == snapshot)
+    }
+}
+
+impl FromStr for Coordinate {
+    type Err = CoordinateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CoordinateParseError::InvalidCoordinate;
+        let (snapshot, slot, id, offset) = match s.strip_prefix(URI_SCHEME) {
+            Some(rest) => {
+                let mut parts = rest.splitn(3, '/');
+                let snapshot = parts.next().filter(|s| !s.is_empty()).ok_or_else(invalid)?;
+                let (slot, id) = parts
+                    .next()
+                    .and_then(|s| s.split_once('.'))
+                    .ok_or_else(invalid)?;
+                let offset = parts.next().ok_or_else(invalid)?;
+                (Some(snapshot.to_string()), slot, id, offset)
+            }
+            None => {
+                let mut parts = s.splitn(3, ':');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(slot), Some(id), Some(offset)) => (None, slot, id, offset),
+                    _ => return Err(invalid()),
+                }
+            }
+        };
+        Ok(Coordinate {
+            snapshot,
+            slot: slot.parse().map_err(|_e| invalid())?,
+            id: id.parse().map_err(|_e| invalid())?,
+            offset: offset.parse().map_err(|_e| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for Coordinate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.snapshot {
+            Some(snapshot) => write!(
+                f,
+                "{}{}/{}.{}/{}",
+                URI_SCHEME, snapshot, self.slot, self.id, self.offset
+            ),
+            None => write!(f, "{}:{}:{}", self.slot, self.id, self.offset),
+        }
+    }
+}
+
+/// Re-reads single accounts by the `(slot, id, offset)` coordinates found in dump output.
+///
+/// Archives are streamed, so seeking only moves forward: coordinates are cheapest to
+/// resolve in archive order, and seeking back to an append vec already passed fails.
+pub struct AppendVecCursor<'a> {
+    append_vecs: AppendVecIterator<'a>,
+    current: Option<(u64, u64, Rc<AppendVec>)>,
+}
+
+impl<'a> AppendVecCursor<'a> {
+    pub fn new(append_vecs: AppendVecIterator<'a>) -> Self {
+        Self {
+            append_vecs,
+            current: None,
+        }
+    }
+
+    /// Advances to the next append vec (or stays on the current one) for which `wanted`
+    /// returns true, and returns its `(slot, id)`. `None` once the archive is exhausted.
+    pub fn seek(
+        &mut self,
+        wanted: impl Fn(u64, u64) -> bool,
+    ) -> solana_snapshot_etl::Result<Option<(u64, u64)>> {
+        if let Some((slot, id, _)) = &self.current {
+            if wanted(*slot, *id) {
+                return Ok(Some((*slot, *id)));
+            }
+        }
+        for append_vec in self.append_vecs.by_ref() {
+            let (slot, id, append_vec) = append_vec?;
+            if wanted(slot, id) {
+                self.current = Some((slot, id, Rc::new(append_vec)));
+                return Ok(Some((slot, id)));
+            }
+        }
+        self.current = None;
+        Ok(None)
+    }
+
+    /// Calls `f` with the account stored at `offset` in the current append vec. `None`
+    /// if no account starts at that offset.
+    pub fn with_account<R>(
+        &self,
+        offset: usize,
+        f: impl FnOnce(u64, u64, &StoredAccountMeta) -> R,
+    ) -> Option<R> {
+        let (slot, id, append_vec) = self.current.as_ref()?;
+        // walk the account headers so a stale offset never reads a bogus header
+        for account in append_vec_iter(append_vec.clone()) {
+            let account = account.access()?;
+            if account.offset == offset {
+                return Some(f(*slot, *id, &account));
+            }
+            if account.offset > offset {
+                break;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+    use crate::testing::{append_vec_bytes, TestAccount, TestSnapshot};
+    use solana_program::pubkey::Pubkey;
+
+    #[test]
+    fn coordinates_round_trip_through_display() {
+        let bare: Coordinate = "139240745:4021:7752".parse().unwrap();
+        assert_eq!(
+            bare,
+            Coordinate {
+                snapshot: None,
+                slot: 139240745,
+                id: 4021,
+                offset: 7752,
+            }
+        );
+        assert_eq!(bare.to_string(), "139240745:4021:7752");
+
+        let uri = "snap://139240745-7Ai5kAj3mKwXd/139240700.4021/7752";
+        let coordinate: Coordinate = uri.parse().unwrap();
+        assert_eq!(
+            coordinate.snapshot.as_deref(),
+            Some("139240745-7Ai5kAj3mKwXd")
+        );
+        assert_eq!(
+            (coordinate.slot, coordinate.id, coordinate.offset),
+            (139240700, 4021, 7752)
+        );
+        assert_eq!(coordinate.to_string(), uri);
+    }
+
+    #[test]
+    fn rejects_malformed_coordinates() {
+        for invalid in [
+            "",
+            "1:2",
+            "1:2:x",
+            "1:2:3:4",
+            "-1:2:3",
+            "snap://",
+            "snap:///1.2/3",
+            "snap://1-abc",
+            "snap://1-abc/1.2",
+            "snap://1-abc/1:2/3",
+            "snap://1-abc/1.2/3/4",
+            "snap://1-abc/1.x/3",
+            "http://1-abc/1.2/3",
+        ] {
+            assert!(invalid.parse::<Coordinate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn coordinates_only_point_into_their_snapshot() {
+        let snapshot =
+            snapshot_id("https://example.com/snapshots/snapshot-139240745-7Ai5kAj3mKwXd.tar.zst")
+                .unwrap();
+        assert_eq!(snapshot, "139240745-7Ai5kAj3mKwXd");
+        assert_eq!(
+            snapshot_id("incremental-snapshot-139240745-139250000-Fk2w.tar.zst").as_deref(),
+            Some("139250000-Fk2w")
+        );
+        assert_eq!(snapshot_id("/data/accounts.tar.zst"), None);
+
+        let coordinate = |s: &str| s.parse::<Coordinate>().unwrap();
+        assert!(coordinate("snap://139240745-7Ai5kAj3mKwXd/1.2/3").points_into(&snapshot));
+        assert!(!coordinate("snap://139240745-Fk2w/1.2/3").points_into(&snapshot));
+        assert!(!coordinate("snap://139240746-7Ai5kAj3mKwXd/1.2/3").points_into(&snapshot));
+        assert!(coordinate("1:2:3").points_into(&snapshot));
+    }
+
+    /// Append vecs `10.0` with accounts of 10 and 0 data bytes, `11.1` and `12.2`.
+    fn snapshot() -> TestSnapshot {
+        let accounts = |lens: &[usize]| {
+            lens.iter()
+                .map(|len| TestAccount::new(Pubkey::new_unique(), vec![7; *len]))
+                .collect()
+        };
+        TestSnapshot(vec![
+            (10, accounts(&[10, 0])),
+            (11, accounts(&[3])),
+            (12, accounts(&[5])),
+        ])
+    }
+
+    fn read(cursor: &AppendVecCursor, offset: usize) -> Option<(u64, u64, Pubkey)> {
+        cursor.with_account(offset, |slot, id, account| (slot, id, account.meta.pubkey))
+    }
+
+    #[test]
+    fn seeks_to_recorded_offsets() {
+        let mut snapshot = snapshot();
+        let pubkey = |vec: usize, account: usize| snapshot.0[vec].1[account].meta.pubkey;
+        let (first, second, last) = (pubkey(0, 0), pubkey(0, 1), pubkey(2, 0));
+        let second_offset = append_vec_bytes(&snapshot.0[0].1[..1]).len();
+
+        let mut cursor = AppendVecCursor::new(snapshot.iter());
+        assert_eq!(read(&cursor, 0), None);
+        assert_eq!(cursor.seek(|slot, _| slot == 10).unwrap(), Some((10, 0)));
+        assert_eq!(read(&cursor, 0), Some((10, 0, first)));
+        assert_eq!(read(&cursor, second_offset), Some((10, 0, second)));
+        // seeking to the current append vec stays on it
+        assert_eq!(cursor.seek(|slot, _| slot == 10).unwrap(), Some((10, 0)));
+        assert_eq!(read(&cursor, 0), Some((10, 0, first)));
+        assert_eq!(cursor.seek(|_, id| id == 2).unwrap(), Some((12, 2)));
+        assert_eq!(read(&cursor, 0), Some((12, 2, last)));
+    }
+
+    #[test]
+    fn refuses_offsets_between_accounts() {
+        let mut snapshot = snapshot();
+        let second_offset = append_vec_bytes(&snapshot.0[0].1[..1]).len();
+        let mut cursor = AppendVecCursor::new(snapshot.iter());
+        cursor.seek(|slot, _| slot == 10).unwrap();
+        for offset in [1, 8, second_offset - 8, second_offset + 8, 1 << 20] {
+            assert_eq!(read(&cursor, offset), None, "{}", offset);
+        }
+    }
+
+    #[test]
+    fn only_seeks_forward() {
+        let mut snapshot = snapshot();
+        let mut cursor = AppendVecCursor::new(snapshot.iter());
+        assert_eq!(cursor.seek(|slot, _| slot == 11).unwrap(), Some((11, 1)));
+        // 10.0 was passed on the way to 11.1, and the search for it drains the archive
+        assert_eq!(cursor.seek(|slot, _| slot == 10).unwrap(), None);
+        assert_eq!(read(&cursor, 0), None);
+        assert_eq!(cursor.seek(|slot, _| slot == 12).unwrap(), None);
+    }
+}
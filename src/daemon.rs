@@ -0,0 +1,289 @@
+use crate::catalog::parse_snapshot_name;
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DaemonConfigError {
+    #[error("Failed to read daemon config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid daemon config: {0}")]
+    Format(#[from] json5::Error),
+    #[error("Invalid daemon job name {0:?} (letters, digits, '-' and '_' only)")]
+    InvalidJobName(String),
+}
+
+fn default_poll_secs() -> u64 {
+    60
+}
+
+fn default_extension() -> String {
+    "csv".to_string()
+}
+
+/// `daemon` configuration, read as JSON5 so jobs can carry comments.
+#[derive(Deserialize, Debug)]
+pub struct DaemonConfig {
+    /// Directory polled for `snapshot-<slot>-<hash>.tar.zst` archives.
+    pub watch_dir: PathBuf,
+    /// Job outputs go to `<output_dir>/<job>/<slot>-<hash>.<extension>`.
+    pub output_dir: PathBuf,
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+    /// Outputs kept per job, newest slots first; older ones are deleted.
+    pub keep_outputs: Option<usize>,
+    pub jobs: Vec<Job>,
+}
+
+/// One extraction run on every new snapshot: dump arguments without the source and `--output`.
+#[derive(Deserialize, Debug)]
+pub struct Job {
+    pub name: String,
+    pub args: Vec<String>,
+    #[serde(default = "default_extension")]
+    pub extension: String,
+}
+
+impl DaemonConfig {
+    pub fn open(path: &Path) -> Result<Self, DaemonConfigError> {
+        let config: DaemonConfig = json5::from_str(&fs::read_to_string(path)?)?;
+        for job in config.jobs.iter() {
+            let valid = !job.name.is_empty()
+                && job
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(DaemonConfigError::InvalidJobName(job.name.clone()));
+            }
+        }
+        Ok(config)
+    }
+
+    fn output_path(&self, job: &Job, slot: u64, hash: &str) -> PathBuf {
+        self.output_dir
+            .join(&job.name)
+            .join(format!("{}-{}.{}", slot, hash, job.extension))
+    }
+}
+
+/// Full snapshot archives in `dir` as `(slot, hash, path, size)`, oldest first.
+fn list_snapshots(dir: &Path) -> std::io::Result<Vec<(u64, String, PathBuf, u64)>> {
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("incremental-") {
+            continue;
+        }
+        if let Some((slot, hash)) = parse_snapshot_name(&name) {
+            snapshots.push((slot, hash, entry.path(), entry.metadata()?.len()));
+        }
+    }
+    snapshots.sort_by_key(|(slot, _, _, _)| *slot);
+    Ok(snapshots)
+}
+
+/// Watches `config.watch_dir` and calls `run_job(args, source, output)` for each job on every
+/// snapshot without an output yet, until `stop` returns true.
+///
+/// An archive is only picked up once its size is unchanged between two polls, so files still
+/// being downloaded are left alone. Outputs are written to a `.partial` file and renamed when
+/// the job succeeds, making a finished output the only state there is: a restarted daemon
+/// resumes where it stopped. A failed job is logged and not retried until restart, and with
+/// `keep_outputs` snapshots older than the kept outputs aren't run again.
+pub fn run<E: std::fmt::Display>(
+    config: &DaemonConfig,
+    mut run_job: impl FnMut(&[String], &Path, &Path) -> Result<(), E>,
+    stop: impl Fn() -> bool,
+) -> std::io::Result<()> {
+    for job in config.jobs.iter() {
+        fs::create_dir_all(config.output_dir.join(&job.name))?;
+    }
+    info!(
+        "Watching {} for snapshots, {} jobs",
+        config.watch_dir.display(),
+        config.jobs.len()
+    );
+
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    let mut failed: HashSet<(String, PathBuf)> = HashSet::new();
+    while !stop() {
+        let snapshots = list_snapshots(&config.watch_dir)?;
+        let mut next_sizes = HashMap::new();
+        for (slot, hash, path, size) in snapshots {
+            next_sizes.insert(path.clone(), size);
+            if sizes.get(&path) != Some(&size) {
+                continue; // new or still growing
+            }
+            for job in config.jobs.iter() {
+                let output = config.output_path(job, slot, &hash);
+                let key = (job.name.clone(), path.clone());
+                if output.exists() || failed.contains(&key) || retired(config, job, slot)? || stop()
+                {
+                    continue;
+                }
+                info!("Running job {} on snapshot {}", job.name, slot);
+                let partial = output.with_extension(format!("{}.partial", job.extension));
+                match run_job(&job.args, &path, &partial) {
+                    Ok(()) => {
+                        fs::rename(&partial, &output)?;
+                        info!("Job {} wrote {}", job.name, output.display());
+                        if let Some(keep) = config.keep_outputs {
+                            apply_retention(config, job, keep)?;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Job {} failed on snapshot {}: {}", job.name, slot, e);
+                        let _ = fs::remove_file(&partial);
+                        failed.insert(key);
+                    }
+                }
+            }
+        }
+        sizes = next_sizes;
+
+        // sleep in short steps so Ctrl-C stops the daemon promptly
+        for _ in 0..config.poll_secs {
+            if stop() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+    info!("Daemon stopped");
+    Ok(())
+}
+
+/// Whether `keep_outputs` newer outputs of `job` than `slot` exist, so an output for it
+/// would only be deleted again.
+fn retired(config: &DaemonConfig, job: &Job, slot: u64) -> std::io::Result<bool> {
+    Ok(match config.keep_outputs {
+        Some(keep) => {
+            let outputs = finished_outputs(config, job)?;
+            outputs.iter().filter(|(newer, _)| *newer > slot).count() >= keep
+        }
+        None => false,
+    })
+}
+
+/// Deletes all but the newest `keep` finished outputs of `job`.
+fn apply_retention(config: &DaemonConfig, job: &Job, keep: usize) -> std::io::Result<()> {
+    for (_, path) in finished_outputs(config, job)?.into_iter().skip(keep) {
+        match fs::remove_file(&path) {
+            Ok(()) => info!("Deleted old output {}", path.display()),
+            Err(e) => warn!("Failed to delete old output {}: {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Finished outputs of `job` as `(slot, path)`, newest first.
+fn finished_outputs(config: &DaemonConfig, job: &Job) -> std::io::Result<Vec<(u64, PathBuf)>> {
+    let suffix = format!(".{}", job.extension);
+    let mut outputs = vec![];
+    for entry in fs::read_dir(config.output_dir.join(&job.name))? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let slot = name
+            .strip_suffix(&suffix)
+            .and_then(|stem| stem.split_once('-'))
+            .and_then(|(slot, _)| slot.parse::<u64>().ok());
+        if let Some(slot) = slot {
+            outputs.push((slot, path));
+        }
+    }
+    outputs.sort_by_key(|(slot, _)| std::cmp::Reverse(*slot));
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::cell::{Cell, RefCell};
+
+    fn job(name: &str) -> Job {
+        Job {
+            name: name.to_string(),
+            args: vec![name.to_string()],
+            extension: default_extension(),
+        }
+    }
+
+    #[test]
+    fn runs_jobs_on_finished_snapshots() {
+        let watch_dir = TempPath::dir("daemon-watch");
+        let output_dir = TempPath::dir("daemon-output");
+        let snapshot = |slot: u64| watch_dir.join(format!("snapshot-{}-hash.tar.zst", slot));
+        for slot in [100, 200, 300] {
+            fs::write(snapshot(slot), "archive").unwrap();
+        }
+        let config = DaemonConfig {
+            watch_dir: watch_dir.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            poll_secs: 0,
+            keep_outputs: Some(2),
+            jobs: vec![job("ok"), job("fail")],
+        };
+        let output = |slot: u64| config.output_path(&config.jobs[0], slot, "hash");
+        fs::create_dir_all(output_dir.join("ok")).unwrap();
+        // an output older than the retention, and one already finished
+        fs::write(output(10), "old").unwrap();
+        fs::write(output(200), "done").unwrap();
+
+        let runs = RefCell::new(vec![]);
+        let run_job = |args: &[String], source: &Path, output: &Path| {
+            let name = |path: &Path| path.file_name().unwrap().to_string_lossy().to_string();
+            runs.borrow_mut()
+                .push((args[0].clone(), name(source), name(output)));
+            match args[0].as_str() {
+                "ok" => fs::write(output, "dump").map_err(|e| e.to_string()),
+                _ => Err("failed".to_string()),
+            }
+        };
+        // 300 is still growing at the second poll, so it's only picked up by the third; polls
+        // start at calls 1, 2, 6, 9 and 10, and every job run is preceded by one more call
+        let calls = Cell::new(0);
+        let stop = || {
+            calls.set(calls.get() + 1);
+            if calls.get() == 2 {
+                fs::write(snapshot(300), "growing archive").unwrap();
+            }
+            calls.get() > 10
+        };
+        run(&config, run_job, stop).unwrap();
+
+        let run = |job: &str, slot: u64| {
+            (
+                job.to_string(),
+                format!("snapshot-{}-hash.tar.zst", slot),
+                format!("{}-hash.csv.partial", slot),
+            )
+        };
+        assert_eq!(
+            runs.into_inner(),
+            [
+                run("ok", 100),
+                run("fail", 100),
+                run("fail", 200),
+                run("ok", 300),
+                run("fail", 300)
+            ]
+        );
+        // the newest two outputs are kept, and the deleted one of 100 isn't written again
+        assert_eq!(fs::read_to_string(output(200)).unwrap(), "done");
+        assert_eq!(fs::read_to_string(output(300)).unwrap(), "dump");
+        let mut written: Vec<_> = fs::read_dir(output_dir.join("ok"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        written.sort();
+        assert_eq!(written, ["200-hash.csv", "300-hash.csv"]);
+        assert_eq!(fs::read_dir(output_dir.join("fail")).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,348 @@
+use super::{expect_anchor_account, expect_len, expect_owner, DecodeError, Keys, Layout};
+
+use solana_program::pubkey;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+pub(super) const WHIRLPOOL_PROGRAM_ID: Pubkey =
+    pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+pub(super) const RAYDIUM_AMM_V4_PROGRAM_ID: Pubkey =
+    pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8");
+
+const WHIRLPOOL_LEN: usize = 653;
+
+// whirlpool `Whirlpool` offsets, after the 8 byte discriminator
+const WHIRLPOOL_CONFIG: usize = 8;
+const WHIRLPOOL_BUMP: usize = 40;
+const WHIRLPOOL_TICK_SPACING: usize = 41;
+const WHIRLPOOL_TICK_SPACING_SEED: usize = 43;
+const WHIRLPOOL_FEE_RATE: usize = 45;
+const WHIRLPOOL_LIQUIDITY: usize = 49;
+const WHIRLPOOL_SQRT_PRICE: usize = 65;
+const WHIRLPOOL_TICK_CURRENT_INDEX: usize = 81;
+const WHIRLPOOL_TOKEN_MINT_A: usize = 101;
+const WHIRLPOOL_TOKEN_VAULT_A: usize = 133;
+const WHIRLPOOL_TOKEN_MINT_B: usize = 181;
+const WHIRLPOOL_TOKEN_VAULT_B: usize = 213;
+
+/// raydium-amm `AmmInfo`.
+const RAYDIUM_AMM_INFO_LEN: usize = 752;
+
+// raydium-amm `AmmInfo` offsets
+const RAYDIUM_STATUS: usize = 0;
+const RAYDIUM_COIN_DECIMALS: usize = 32;
+const RAYDIUM_PC_DECIMALS: usize = 40;
+const RAYDIUM_SWAP_FEE_NUMERATOR: usize = 176;
+const RAYDIUM_SWAP_FEE_DENOMINATOR: usize = 184;
+const RAYDIUM_COIN_VAULT: usize = 336;
+const RAYDIUM_PC_VAULT: usize = 368;
+const RAYDIUM_COIN_MINT: usize = 400;
+const RAYDIUM_PC_MINT: usize = 432;
+const RAYDIUM_LP_MINT: usize = 464;
+const RAYDIUM_MARKET: usize = 528;
+const RAYDIUM_LP_AMOUNT: usize = 720;
+
+pub(super) const WHIRLPOOL_COLUMNS: &[&str] = &[
+    "token_mint_a",
+    "token_mint_b",
+    "token_vault_a",
+    "token_vault_b",
+    "fee_rate",
+    "tick_spacing",
+    "liquidity",
+    "sqrt_price",
+    "tick_current_index",
+];
+
+pub(super) const RAYDIUM_AMM_COLUMNS: &[&str] = &[
+    "base_mint",
+    "quote_mint",
+    "base_vault",
+    "quote_vault",
+    "lp_mint",
+    "base_decimals",
+    "quote_decimals",
+    "swap_fee_numerator",
+    "swap_fee_denominator",
+    "lp_amount",
+    "market",
+    "status",
+];
+
+pub(super) fn decode_whirlpool(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[WHIRLPOOL_PROGRAM_ID])?;
+    expect_len(account, WHIRLPOOL_LEN)?;
+    expect_anchor_account(account, "Whirlpool")?;
+    let data = Layout(account.data);
+    let key = |offset| data.bytes::<32>(offset).map(|key| keys.format(&key));
+    Ok(vec![
+        key(WHIRLPOOL_TOKEN_MINT_A)?,
+        key(WHIRLPOOL_TOKEN_MINT_B)?,
+        key(WHIRLPOOL_TOKEN_VAULT_A)?,
+        key(WHIRLPOOL_TOKEN_VAULT_B)?,
+        data.u16(WHIRLPOOL_FEE_RATE)?.to_string(),
+        data.u16(WHIRLPOOL_TICK_SPACING)?.to_string(),
+        data.u128(WHIRLPOOL_LIQUIDITY)?.to_string(),
+        data.u128(WHIRLPOOL_SQRT_PRICE)?.to_string(),
+        data.i32(WHIRLPOOL_TICK_CURRENT_INDEX)?.to_string(),
+    ])
+}
+
+pub(super) fn decode_raydium_amm(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[RAYDIUM_AMM_V4_PROGRAM_ID])?;
+    expect_len(account, RAYDIUM_AMM_INFO_LEN)?;
+    let data = Layout(account.data);
+    let key = |offset| data.bytes::<32>(offset).map(|key| keys.format(&key));
+    Ok(vec![
+        key(RAYDIUM_COIN_MINT)?,
+        key(RAYDIUM_PC_MINT)?,
+        key(RAYDIUM_COIN_VAULT)?,
+        key(RAYDIUM_PC_VAULT)?,
+        key(RAYDIUM_LP_MINT)?,
+        data.u64(RAYDIUM_COIN_DECIMALS)?.to_string(),
+        data.u64(RAYDIUM_PC_DECIMALS)?.to_string(),
+        data.u64(RAYDIUM_SWAP_FEE_NUMERATOR)?.to_string(),
+        data.u64(RAYDIUM_SWAP_FEE_DENOMINATOR)?.to_string(),
+        data.u64(RAYDIUM_LP_AMOUNT)?.to_string(),
+        key(RAYDIUM_MARKET)?,
+        data.u64(RAYDIUM_STATUS)?.to_string(),
+    ])
+}
+
+pub(super) fn validate_whirlpool(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let config = data.bytes::<32>(WHIRLPOOL_CONFIG)?;
+    let mint_a = data.bytes::<32>(WHIRLPOOL_TOKEN_MINT_A)?;
+    let mint_b = data.bytes::<32>(WHIRLPOOL_TOKEN_MINT_B)?;
+    let tick_spacing_seed = data.bytes::<2>(WHIRLPOOL_TICK_SPACING_SEED)?;
+    let bump = [data.u8(WHIRLPOOL_BUMP)?];
+    let mut anomalies = vec![];
+    // pools live at PDA("whirlpool", config, mint_a, mint_b, tick_spacing)
+    let seeds: [&[u8]; 6] = [
+        b"whirlpool",
+        &config,
+        &mint_a,
+        &mint_b,
+        &tick_spacing_seed,
+        &bump,
+    ];
+    let address = Pubkey::create_program_address(&seeds, &WHIRLPOOL_PROGRAM_ID);
+    if address.ok() != Some(account.meta.pubkey) {
+        anomalies.push("pda_mismatch");
+    }
+    if mint_a >= mint_b {
+        anomalies.push("mints_not_ordered");
+    }
+    if data.u128(WHIRLPOOL_SQRT_PRICE)? == 0 {
+        anomalies.push("zero_sqrt_price");
+    }
+    Ok(anomalies)
+}
+
+pub(super) fn validate_raydium_amm(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let mut anomalies = vec![];
+    if data.bytes::<32>(RAYDIUM_COIN_MINT)? == data.bytes::<32>(RAYDIUM_PC_MINT)? {
+        anomalies.push("same_base_and_quote_mint");
+    }
+    if data.u64(RAYDIUM_SWAP_FEE_DENOMINATOR)? == 0 {
+        anomalies.push("zero_fee_denominator");
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::TokenAmounts;
+    use crate::filter::anchor_account_discriminator;
+    use crate::testing::{put, TestAccount};
+
+    fn key(byte: u8) -> String {
+        Pubkey::new_from_array([byte; 32]).to_string()
+    }
+
+    fn keys() -> Keys<'static> {
+        Keys::new(None, TokenAmounts::U64)
+    }
+
+    /// A pool of mints 1 < 2 and vaults 3 and 4, stored at its PDA.
+    fn whirlpool() -> TestAccount {
+        let mut data = vec![0; WHIRLPOOL_LEN];
+        put(&mut data, 0, &anchor_account_discriminator("Whirlpool"));
+        put(&mut data, WHIRLPOOL_CONFIG, &[9; 32]);
+        put(&mut data, WHIRLPOOL_TICK_SPACING, &64u16.to_le_bytes());
+        put(&mut data, WHIRLPOOL_TICK_SPACING_SEED, &64u16.to_le_bytes());
+        put(&mut data, WHIRLPOOL_FEE_RATE, &3000u16.to_le_bytes());
+        put(
+            &mut data,
+            WHIRLPOOL_LIQUIDITY,
+            &(u64::MAX as u128 + 1).to_le_bytes(),
+        );
+        put(
+            &mut data,
+            WHIRLPOOL_SQRT_PRICE,
+            &(1u128 << 64).to_le_bytes(),
+        );
+        put(
+            &mut data,
+            WHIRLPOOL_TICK_CURRENT_INDEX,
+            &(-20i32).to_le_bytes(),
+        );
+        put(&mut data, WHIRLPOOL_TOKEN_MINT_A, &[1; 32]);
+        put(&mut data, WHIRLPOOL_TOKEN_VAULT_A, &[3; 32]);
+        put(&mut data, WHIRLPOOL_TOKEN_MINT_B, &[2; 32]);
+        put(&mut data, WHIRLPOOL_TOKEN_VAULT_B, &[4; 32]);
+        let tick_spacing = 64u16.to_le_bytes();
+        let seeds: [&[u8]; 5] = [b"whirlpool", &[9; 32], &[1; 32], &[2; 32], &tick_spacing];
+        let (address, bump) = Pubkey::find_program_address(&seeds, &WHIRLPOOL_PROGRAM_ID);
+        data[WHIRLPOOL_BUMP] = bump;
+        let mut account = TestAccount::new(WHIRLPOOL_PROGRAM_ID, data);
+        account.meta.pubkey = address;
+        account
+    }
+
+    fn raydium_amm() -> Vec<u8> {
+        let mut data = vec![0; RAYDIUM_AMM_INFO_LEN];
+        put(&mut data, RAYDIUM_STATUS, &6u64.to_le_bytes());
+        put(&mut data, RAYDIUM_COIN_DECIMALS, &9u64.to_le_bytes());
+        put(&mut data, RAYDIUM_PC_DECIMALS, &6u64.to_le_bytes());
+        put(&mut data, RAYDIUM_SWAP_FEE_NUMERATOR, &25u64.to_le_bytes());
+        put(
+            &mut data,
+            RAYDIUM_SWAP_FEE_DENOMINATOR,
+            &10_000u64.to_le_bytes(),
+        );
+        put(&mut data, RAYDIUM_COIN_VAULT, &[3; 32]);
+        put(&mut data, RAYDIUM_PC_VAULT, &[4; 32]);
+        put(&mut data, RAYDIUM_COIN_MINT, &[1; 32]);
+        put(&mut data, RAYDIUM_PC_MINT, &[2; 32]);
+        put(&mut data, RAYDIUM_LP_MINT, &[5; 32]);
+        put(&mut data, RAYDIUM_MARKET, &[6; 32]);
+        put(&mut data, RAYDIUM_LP_AMOUNT, &1_000_000u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn decodes_whirlpools() {
+        let mut expected = (1..=4).map(key).collect::<Vec<_>>();
+        expected.extend(
+            [
+                "3000",
+                "64",
+                "18446744073709551616",
+                "18446744073709551616",
+                "-20",
+            ]
+            .map(String::from),
+        );
+        assert_eq!(
+            decode_whirlpool(&whirlpool().stored(), &keys()),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_whirlpools() {
+        let mut account = whirlpool();
+        account.data[..8].copy_from_slice(&anchor_account_discriminator("Position"));
+        assert_eq!(
+            decode_whirlpool(&account.stored(), &keys()),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        account.data.push(0);
+        assert_eq!(
+            decode_whirlpool(&account.stored(), &keys()),
+            Err(DecodeError::WrongSize {
+                expected: WHIRLPOOL_LEN,
+                actual: WHIRLPOOL_LEN + 1
+            })
+        );
+        let account = TestAccount::new(RAYDIUM_AMM_V4_PROGRAM_ID, whirlpool().data);
+        assert_eq!(
+            decode_whirlpool(&account.stored(), &keys()),
+            Err(DecodeError::WrongOwner(RAYDIUM_AMM_V4_PROGRAM_ID))
+        );
+    }
+
+    #[test]
+    fn flags_whirlpool_anomalies() {
+        let mut account = whirlpool();
+        assert_eq!(validate_whirlpool(&account.stored()), Ok(vec![]));
+
+        // a copy of the pool anywhere but its PDA
+        account.meta.pubkey = Pubkey::new_unique();
+        assert_eq!(
+            validate_whirlpool(&account.stored()),
+            Ok(vec!["pda_mismatch"])
+        );
+        let mut account = whirlpool();
+        account.data[WHIRLPOOL_BUMP] = account.data[WHIRLPOOL_BUMP].wrapping_sub(1);
+        assert!(validate_whirlpool(&account.stored())
+            .unwrap()
+            .contains(&"pda_mismatch"));
+
+        let mut account = whirlpool();
+        put(&mut account.data, WHIRLPOOL_TOKEN_MINT_A, &[2; 32]);
+        put(
+            &mut account.data,
+            WHIRLPOOL_SQRT_PRICE,
+            &0u128.to_le_bytes(),
+        );
+        assert_eq!(
+            validate_whirlpool(&account.stored()),
+            Ok(vec!["pda_mismatch", "mints_not_ordered", "zero_sqrt_price"])
+        );
+    }
+
+    #[test]
+    fn decodes_raydium_amms() {
+        let account = TestAccount::new(RAYDIUM_AMM_V4_PROGRAM_ID, raydium_amm());
+        let mut expected = (1..=5).map(key).collect::<Vec<_>>();
+        expected.extend(["9", "6", "25", "10000", "1000000"].map(String::from));
+        expected.extend([key(6), "6".to_string()]);
+        assert_eq!(decode_raydium_amm(&account.stored(), &keys()), Ok(expected));
+    }
+
+    #[test]
+    fn rejects_malformed_raydium_amms() {
+        let account = TestAccount::new(RAYDIUM_AMM_V4_PROGRAM_ID, raydium_amm()[..700].to_vec());
+        assert_eq!(
+            decode_raydium_amm(&account.stored(), &keys()),
+            Err(DecodeError::WrongSize {
+                expected: RAYDIUM_AMM_INFO_LEN,
+                actual: 700
+            })
+        );
+        let account = TestAccount::new(WHIRLPOOL_PROGRAM_ID, raydium_amm());
+        assert_eq!(
+            decode_raydium_amm(&account.stored(), &keys()),
+            Err(DecodeError::WrongOwner(WHIRLPOOL_PROGRAM_ID))
+        );
+    }
+
+    #[test]
+    fn flags_raydium_amm_anomalies() {
+        let validate = |data| {
+            validate_raydium_amm(&TestAccount::new(RAYDIUM_AMM_V4_PROGRAM_ID, data).stored())
+        };
+        assert_eq!(validate(raydium_amm()), Ok(vec![]));
+        let mut data = raydium_amm();
+        put(&mut data, RAYDIUM_PC_MINT, &[1; 32]);
+        put(&mut data, RAYDIUM_SWAP_FEE_DENOMINATOR, &0u64.to_le_bytes());
+        assert_eq!(
+            validate(data),
+            Ok(vec!["same_base_and_quote_mint", "zero_fee_denominator"])
+        );
+    }
+}
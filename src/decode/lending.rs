@@ -0,0 +1,357 @@
+use super::{decimal, expect_len, expect_owner, DecodeError, Keys, Layout};
+
+use solana_program::pubkey;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+pub(super) const SOLEND_PROGRAM_ID: Pubkey = pubkey!("So1endDq2YkqhipRh3WViPa8hdiSpxWy6z3Z6tMCpAo");
+
+const SOLEND_VERSION: u8 = 1;
+const SOLEND_RESERVE_LEN: usize = 619;
+const SOLEND_OBLIGATION_LEN: usize = 1300;
+/// Amounts and values stored as `Decimal` are scaled by 10^18.
+const WAD_DECIMALS: u32 = 18;
+
+// shared by both account types: version, then `LastUpdate`, then the lending market
+const LAST_UPDATE_SLOT: usize = 1;
+const LENDING_MARKET: usize = 10;
+
+// solend `Reserve` offsets
+const RESERVE_LIQUIDITY_MINT: usize = 42;
+const RESERVE_LIQUIDITY_DECIMALS: usize = 74;
+const RESERVE_LIQUIDITY_SUPPLY: usize = 75;
+const RESERVE_AVAILABLE_AMOUNT: usize = 171;
+const RESERVE_BORROWED_AMOUNT_WADS: usize = 179;
+const RESERVE_MARKET_PRICE: usize = 211;
+const RESERVE_COLLATERAL_MINT: usize = 227;
+const RESERVE_COLLATERAL_MINT_SUPPLY: usize = 259;
+const RESERVE_OPTIMAL_UTILIZATION_RATE: usize = 299;
+const RESERVE_LOAN_TO_VALUE_RATIO: usize = 300;
+const RESERVE_LIQUIDATION_BONUS: usize = 301;
+const RESERVE_LIQUIDATION_THRESHOLD: usize = 302;
+
+// solend `Obligation` offsets
+const OBLIGATION_OWNER: usize = 42;
+const OBLIGATION_DEPOSITED_VALUE: usize = 74;
+const OBLIGATION_BORROWED_VALUE: usize = 90;
+const OBLIGATION_ALLOWED_BORROW_VALUE: usize = 106;
+const OBLIGATION_UNHEALTHY_BORROW_VALUE: usize = 122;
+
+pub(super) const SOLEND_RESERVE_COLUMNS: &[&str] = &[
+    "lending_market",
+    "liquidity_mint",
+    "liquidity_decimals",
+    "liquidity_supply",
+    "available_amount",
+    "borrowed_amount",
+    "market_price",
+    "collateral_mint",
+    "collateral_mint_supply",
+    "optimal_utilization_rate",
+    "loan_to_value_ratio",
+    "liquidation_bonus",
+    "liquidation_threshold",
+    "last_update_slot",
+];
+
+pub(super) const SOLEND_OBLIGATION_COLUMNS: &[&str] = &[
+    "lending_market",
+    "owner",
+    "deposited_value",
+    "borrowed_value",
+    "allowed_borrow_value",
+    "unhealthy_borrow_value",
+    "last_update_slot",
+];
+
+fn expect_solend_account(account: &StoredAccountMeta, len: usize) -> Result<(), DecodeError> {
+    expect_owner(account, &[SOLEND_PROGRAM_ID])?;
+    expect_len(account, len)?;
+    match account.data[0] {
+        SOLEND_VERSION => Ok(()),
+        0 => Err(DecodeError::Uninitialized),
+        _ => Err(DecodeError::InvalidField("version")),
+    }
+}
+
+fn wads(data: &Layout, offset: usize) -> Result<String, DecodeError> {
+    let wads = data
+        .u128(offset)?
+        .try_into()
+        .map_err(|_e| DecodeError::InvalidField("decimal"))?;
+    Ok(decimal(wads, WAD_DECIMALS))
+}
+
+pub(super) fn decode_solend_reserve(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_solend_account(account, SOLEND_RESERVE_LEN)?;
+    let data = Layout(account.data);
+    let key = |offset| data.bytes::<32>(offset).map(|key| keys.format(&key));
+    Ok(vec![
+        key(LENDING_MARKET)?,
+        key(RESERVE_LIQUIDITY_MINT)?,
+        data.u8(RESERVE_LIQUIDITY_DECIMALS)?.to_string(),
+        key(RESERVE_LIQUIDITY_SUPPLY)?,
+        keys.amount(
+            data.u64(RESERVE_AVAILABLE_AMOUNT)?,
+            data.u8(RESERVE_LIQUIDITY_DECIMALS)?,
+        ),
+        wads(&data, RESERVE_BORROWED_AMOUNT_WADS)?,
+        wads(&data, RESERVE_MARKET_PRICE)?,
+        key(RESERVE_COLLATERAL_MINT)?,
+        data.u64(RESERVE_COLLATERAL_MINT_SUPPLY)?.to_string(),
+        data.u8(RESERVE_OPTIMAL_UTILIZATION_RATE)?.to_string(),
+        data.u8(RESERVE_LOAN_TO_VALUE_RATIO)?.to_string(),
+        data.u8(RESERVE_LIQUIDATION_BONUS)?.to_string(),
+        data.u8(RESERVE_LIQUIDATION_THRESHOLD)?.to_string(),
+        data.u64(LAST_UPDATE_SLOT)?.to_string(),
+    ])
+}
+
+pub(super) fn decode_solend_obligation(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_solend_account(account, SOLEND_OBLIGATION_LEN)?;
+    let data = Layout(account.data);
+    let key = |offset| data.bytes::<32>(offset).map(|key| keys.format(&key));
+    Ok(vec![
+        key(LENDING_MARKET)?,
+        key(OBLIGATION_OWNER)?,
+        wads(&data, OBLIGATION_DEPOSITED_VALUE)?,
+        wads(&data, OBLIGATION_BORROWED_VALUE)?,
+        wads(&data, OBLIGATION_ALLOWED_BORROW_VALUE)?,
+        wads(&data, OBLIGATION_UNHEALTHY_BORROW_VALUE)?,
+        data.u64(LAST_UPDATE_SLOT)?.to_string(),
+    ])
+}
+
+pub(super) fn validate_solend_reserve(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let ltv = data.u8(RESERVE_LOAN_TO_VALUE_RATIO)?;
+    let liquidation_threshold = data.u8(RESERVE_LIQUIDATION_THRESHOLD)?;
+    let mut anomalies = vec![];
+    if ltv > liquidation_threshold {
+        anomalies.push("ltv_exceeds_liquidation_threshold");
+    }
+    if liquidation_threshold > 100 {
+        anomalies.push("liquidation_threshold_above_100");
+    }
+    if data.u128(RESERVE_MARKET_PRICE)? == 0 {
+        anomalies.push("zero_market_price");
+    }
+    Ok(anomalies)
+}
+
+pub(super) fn validate_solend_obligation(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let deposited = data.u128(OBLIGATION_DEPOSITED_VALUE)?;
+    let borrowed = data.u128(OBLIGATION_BORROWED_VALUE)?;
+    let unhealthy = data.u128(OBLIGATION_UNHEALTHY_BORROW_VALUE)?;
+    let mut anomalies = vec![];
+    if data.u128(OBLIGATION_ALLOWED_BORROW_VALUE)? > deposited {
+        anomalies.push("borrow_limit_exceeds_deposits");
+    }
+    if borrowed > 0 && borrowed >= unhealthy {
+        anomalies.push("liquidatable");
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::TokenAmounts;
+    use crate::testing::{put, TestAccount};
+
+    const WAD: u128 = 1_000_000_000_000_000_000;
+
+    fn key(byte: u8) -> String {
+        Pubkey::new_from_array([byte; 32]).to_string()
+    }
+
+    fn solend_account(len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        data[0] = SOLEND_VERSION;
+        put(&mut data, LAST_UPDATE_SLOT, &250_000_000u64.to_le_bytes());
+        put(&mut data, LENDING_MARKET, &[1; 32]);
+        data
+    }
+
+    fn reserve() -> Vec<u8> {
+        let mut data = solend_account(SOLEND_RESERVE_LEN);
+        put(&mut data, RESERVE_LIQUIDITY_MINT, &[2; 32]);
+        data[RESERVE_LIQUIDITY_DECIMALS] = 6;
+        put(&mut data, RESERVE_LIQUIDITY_SUPPLY, &[3; 32]);
+        put(
+            &mut data,
+            RESERVE_AVAILABLE_AMOUNT,
+            &1_500_000u64.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            RESERVE_BORROWED_AMOUNT_WADS,
+            &(WAD * 5 / 2).to_le_bytes(),
+        );
+        put(&mut data, RESERVE_MARKET_PRICE, &WAD.to_le_bytes());
+        put(&mut data, RESERVE_COLLATERAL_MINT, &[4; 32]);
+        put(
+            &mut data,
+            RESERVE_COLLATERAL_MINT_SUPPLY,
+            &7u64.to_le_bytes(),
+        );
+        data[RESERVE_OPTIMAL_UTILIZATION_RATE] = 80;
+        data[RESERVE_LOAN_TO_VALUE_RATIO] = 75;
+        data[RESERVE_LIQUIDATION_BONUS] = 5;
+        data[RESERVE_LIQUIDATION_THRESHOLD] = 80;
+        data
+    }
+
+    fn obligation(deposited: u128, borrowed: u128, allowed: u128, unhealthy: u128) -> Vec<u8> {
+        let mut data = solend_account(SOLEND_OBLIGATION_LEN);
+        put(&mut data, OBLIGATION_OWNER, &[5; 32]);
+        put(
+            &mut data,
+            OBLIGATION_DEPOSITED_VALUE,
+            &deposited.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            OBLIGATION_BORROWED_VALUE,
+            &borrowed.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            OBLIGATION_ALLOWED_BORROW_VALUE,
+            &allowed.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            OBLIGATION_UNHEALTHY_BORROW_VALUE,
+            &unhealthy.to_le_bytes(),
+        );
+        data
+    }
+
+    fn solend(data: Vec<u8>) -> TestAccount {
+        TestAccount::new(SOLEND_PROGRAM_ID, data)
+    }
+
+    #[test]
+    fn decodes_reserves() {
+        let account = solend(reserve());
+        let values = decode_solend_reserve(&account.stored(), &Keys::new(None, TokenAmounts::U64));
+        assert_eq!(
+            values,
+            Ok(vec![
+                key(1),
+                key(2),
+                "6".to_string(),
+                key(3),
+                "1500000".to_string(),
+                "2.500000000000000000".to_string(),
+                "1.000000000000000000".to_string(),
+                key(4),
+                "7".to_string(),
+                "80".to_string(),
+                "75".to_string(),
+                "5".to_string(),
+                "80".to_string(),
+                "250000000".to_string(),
+            ])
+        );
+        let keys = Keys::new(None, TokenAmounts::Decimal);
+        let values = decode_solend_reserve(&account.stored(), &keys).unwrap();
+        assert_eq!(values[4], "1.500000");
+    }
+
+    #[test]
+    fn decodes_obligations() {
+        let account = solend(obligation(10 * WAD, WAD / 4, 8 * WAD, 9 * WAD));
+        let values =
+            decode_solend_obligation(&account.stored(), &Keys::new(None, TokenAmounts::U64));
+        assert_eq!(
+            values,
+            Ok(vec![
+                key(1),
+                key(5),
+                "10.000000000000000000".to_string(),
+                "0.250000000000000000".to_string(),
+                "8.000000000000000000".to_string(),
+                "9.000000000000000000".to_string(),
+                "250000000".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_solend_accounts() {
+        let keys = Keys::new(None, TokenAmounts::U64);
+        let reserve_of = |data| decode_solend_reserve(&solend(data).stored(), &keys);
+
+        let mut data = reserve();
+        data[0] = 0;
+        assert_eq!(reserve_of(data), Err(DecodeError::Uninitialized));
+        let mut data = reserve();
+        data[0] = 2;
+        assert_eq!(reserve_of(data), Err(DecodeError::InvalidField("version")));
+        let mut data = reserve();
+        put(&mut data, RESERVE_MARKET_PRICE, &u128::MAX.to_le_bytes());
+        assert_eq!(reserve_of(data), Err(DecodeError::InvalidField("decimal")));
+        // the decoders tell reserves and obligations apart by size
+        assert_eq!(
+            reserve_of(obligation(0, 0, 0, 0)),
+            Err(DecodeError::WrongSize {
+                expected: SOLEND_RESERVE_LEN,
+                actual: SOLEND_OBLIGATION_LEN
+            })
+        );
+        assert_eq!(
+            decode_solend_obligation(&solend(reserve()).stored(), &keys),
+            Err(DecodeError::WrongSize {
+                expected: SOLEND_OBLIGATION_LEN,
+                actual: SOLEND_RESERVE_LEN
+            })
+        );
+        let account = TestAccount::new(Pubkey::default(), reserve());
+        assert_eq!(
+            decode_solend_reserve(&account.stored(), &keys),
+            Err(DecodeError::WrongOwner(Pubkey::default()))
+        );
+    }
+
+    #[test]
+    fn flags_reserve_anomalies() {
+        let validate = |data| validate_solend_reserve(&solend(data).stored());
+        assert_eq!(validate(reserve()), Ok(vec![]));
+        let mut data = reserve();
+        data[RESERVE_LOAN_TO_VALUE_RATIO] = 120;
+        data[RESERVE_LIQUIDATION_THRESHOLD] = 110;
+        put(&mut data, RESERVE_MARKET_PRICE, &0u128.to_le_bytes());
+        assert_eq!(
+            validate(data),
+            Ok(vec![
+                "ltv_exceeds_liquidation_threshold",
+                "liquidation_threshold_above_100",
+                "zero_market_price"
+            ])
+        );
+    }
+
+    #[test]
+    fn flags_obligation_anomalies() {
+        let validate = |data| validate_solend_obligation(&solend(data).stored());
+        assert_eq!(validate(obligation(10, 0, 8, 0)), Ok(vec![]));
+        assert_eq!(validate(obligation(10, 5, 8, 9)), Ok(vec![]));
+        assert_eq!(
+            validate(obligation(10, 9, 11, 9)),
+            Ok(vec!["borrow_limit_exceeds_deposits", "liquidatable"])
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use super::{expect_len, expect_owner, DecodeError, Keys, Layout};
+
+use solana_program::pubkey;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+pub(super) const SERUM_DEX_V3_PROGRAM_ID: Pubkey =
+    pubkey!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
+pub(super) const OPENBOOK_V1_PROGRAM_ID: Pubkey =
+    pubkey!("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX");
+pub(super) const PHOENIX_PROGRAM_ID: Pubkey =
+    pubkey!("PhoeNiXZ8ByJGLkxNfZRnkUfjvmuYqLR89jjFHGqdXY");
+
+/// serum-dex `MarketState` between the 5 byte "serum" head and 7 byte "padding" tail.
+const SERUM_MARKET_LEN: usize = 388;
+const SERUM_HEAD: &[u8] = b"serum";
+const SERUM_ACCOUNT_FLAG_MARKET: u64 = 1 << 1;
+
+// serum-dex `MarketState` offsets, including the head
+const SERUM_ACCOUNT_FLAGS: usize = 5;
+const SERUM_OWN_ADDRESS: usize = 13;
+const SERUM_COIN_MINT: usize = 53;
+const SERUM_PC_MINT: usize = 85;
+const SERUM_COIN_VAULT: usize = 117;
+const SERUM_PC_VAULT: usize = 165;
+const SERUM_EVENT_QUEUE: usize = 253;
+const SERUM_BIDS: usize = 285;
+const SERUM_ASKS: usize = 317;
+const SERUM_COIN_LOT_SIZE: usize = 349;
+const SERUM_PC_LOT_SIZE: usize = 357;
+const SERUM_FEE_RATE_BPS: usize = 365;
+
+/// phoenix `MarketHeader`; the order book follows it in the same account.
+const PHOENIX_HEADER_LEN: usize = 576;
+
+// phoenix `MarketHeader` and `FIFOMarket` offsets
+const PHOENIX_STATUS: usize = 8;
+const PHOENIX_BASE_DECIMALS: usize = 40;
+const PHOENIX_BASE_MINT: usize = 48;
+const PHOENIX_BASE_VAULT: usize = 80;
+const PHOENIX_BASE_LOT_SIZE: usize = 112;
+const PHOENIX_QUOTE_DECIMALS: usize = 120;
+const PHOENIX_QUOTE_MINT: usize = 128;
+const PHOENIX_QUOTE_VAULT: usize = 160;
+const PHOENIX_QUOTE_LOT_SIZE: usize = 192;
+const PHOENIX_TICK_SIZE: usize = 200;
+const PHOENIX_TAKER_FEE_BPS: usize = PHOENIX_HEADER_LEN + 256 + 24;
+
+pub(super) const OPENBOOK_MARKET_COLUMNS: &[&str] = &[
+    "base_mint",
+    "quote_mint",
+    "base_vault",
+    "quote_vault",
+    "bids",
+    "asks",
+    "event_queue",
+    "base_lot_size",
+    "quote_lot_size",
+    "fee_rate_bps",
+];
+
+pub(super) const PHOENIX_MARKET_COLUMNS: &[&str] = &[
+    "base_mint",
+    "quote_mint",
+    "base_vault",
+    "quote_vault",
+    "base_decimals",
+    "quote_decimals",
+    "base_lot_size",
+    "quote_lot_size",
+    "tick_size",
+    "taker_fee_bps",
+    "status",
+];
+
+fn phoenix_status(status: u64) -> &'static str {
+    match status {
+        0 => "uninitialized",
+        1 => "active",
+        2 => "post_only",
+        3 => "paused",
+        4 => "closed",
+        5 => "tombstoned",
+        _ => "unknown",
+    }
+}
+
+/// Serum DEX v3 and OpenBook v1 share the market layout.
+pub(super) fn decode_openbook_market(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[OPENBOOK_V1_PROGRAM_ID, SERUM_DEX_V3_PROGRAM_ID])?;
+    expect_len(account, SERUM_MARKET_LEN)?;
+    let data = Layout(account.data);
+    if !account.data.starts_with(SERUM_HEAD) {
+        return Err(DecodeError::WrongDiscriminator);
+    }
+    // open orders, queues and slabs share the program and the head
+    if data.u64(SERUM_ACCOUNT_FLAGS)? & SERUM_ACCOUNT_FLAG_MARKET == 0 {
+        return Err(DecodeError::InvalidField("account_flags"));
+    }
+    let key = |offset| data.bytes::<32>(offset).map(|key| keys.format(&key));
+    Ok(vec![
+        key(SERUM_COIN_MINT)?,
+        key(SERUM_PC_MINT)?,
+        key(SERUM_COIN_VAULT)?,
+        key(SERUM_PC_VAULT)?,
+        key(SERUM_BIDS)?,
+        key(SERUM_ASKS)?,
+        key(SERUM_EVENT_QUEUE)?,
+        data.u64(SERUM_COIN_LOT_SIZE)?.to_string(),
+        data.u64(SERUM_PC_LOT_SIZE)?.to_string(),
+        data.u64(SERUM_FEE_RATE_BPS)?.to_string(),
+    ])
+}
+
+pub(super) fn decode_phoenix_market(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[PHOENIX_PROGRAM_ID])?;
+    // seat accounts share the owner but are far smaller than a market
+    if account.data.len() <= PHOENIX_HEADER_LEN {
+        return Err(DecodeError::TooShort {
+            min: PHOENIX_HEADER_LEN + 1,
+            actual: account.data.len(),
+        });
+    }
+    let data = Layout(account.data);
+    let key = |offset| data.bytes::<32>(offset).map(|key| keys.format(&key));
+    Ok(vec![
+        key(PHOENIX_BASE_MINT)?,
+        key(PHOENIX_QUOTE_MINT)?,
+        key(PHOENIX_BASE_VAULT)?,
+        key(PHOENIX_QUOTE_VAULT)?,
+        data.u32(PHOENIX_BASE_DECIMALS)?.to_string(),
+        data.u32(PHOENIX_QUOTE_DECIMALS)?.to_string(),
+        data.u64(PHOENIX_BASE_LOT_SIZE)?.to_string(),
+        data.u64(PHOENIX_QUOTE_LOT_SIZE)?.to_string(),
+        data.u64(PHOENIX_TICK_SIZE)?.to_string(),
+        data.u64(PHOENIX_TAKER_FEE_BPS)?.to_string(),
+        phoenix_status(data.u64(PHOENIX_STATUS)?).to_string(),
+    ])
+}
+
+pub(super) fn validate_openbook_market(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let mut anomalies = vec![];
+    // markets record their own address; a mismatch means the account was copied or forged
+    if data.bytes::<32>(SERUM_OWN_ADDRESS)? != account.meta.pubkey.to_bytes() {
+        anomalies.push("own_address_mismatch");
+    }
+    if data.bytes::<32>(SERUM_COIN_MINT)? == data.bytes::<32>(SERUM_PC_MINT)? {
+        anomalies.push("same_base_and_quote_mint");
+    }
+    if data.u64(SERUM_COIN_LOT_SIZE)? == 0 || data.u64(SERUM_PC_LOT_SIZE)? == 0 {
+        anomalies.push("zero_lot_size");
+    }
+    Ok(anomalies)
+}
+
+pub(super) fn validate_phoenix_market(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let mut anomalies = vec![];
+    if data.bytes::<32>(PHOENIX_BASE_MINT)? == data.bytes::<32>(PHOENIX_QUOTE_MINT)? {
+        anomalies.push("same_base_and_quote_mint");
+    }
+    if data.u64(PHOENIX_BASE_LOT_SIZE)? == 0 || data.u64(PHOENIX_QUOTE_LOT_SIZE)? == 0 {
+        anomalies.push("zero_lot_size");
+    }
+    if phoenix_status(data.u64(PHOENIX_STATUS)?) == "unknown" {
+        anomalies.push("unknown_status");
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::TokenAmounts;
+    use crate::testing::{put, TestAccount};
+
+    /// Keys 1..=7 at the mint, vault, book and queue offsets.
+    fn serum_market() -> Vec<u8> {
+        let mut data = vec![0; SERUM_MARKET_LEN];
+        put(&mut data, 0, SERUM_HEAD);
+        put(&mut data, SERUM_ACCOUNT_FLAGS, &3u64.to_le_bytes());
+        for (i, offset) in [
+            SERUM_COIN_MINT,
+            SERUM_PC_MINT,
+            SERUM_COIN_VAULT,
+            SERUM_PC_VAULT,
+            SERUM_BIDS,
+            SERUM_ASKS,
+            SERUM_EVENT_QUEUE,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            put(&mut data, offset, &[i as u8 + 1; 32]);
+        }
+        put(&mut data, SERUM_COIN_LOT_SIZE, &100u64.to_le_bytes());
+        put(&mut data, SERUM_PC_LOT_SIZE, &10u64.to_le_bytes());
+        put(&mut data, SERUM_FEE_RATE_BPS, &22u64.to_le_bytes());
+        data
+    }
+
+    fn phoenix_market(status: u64) -> Vec<u8> {
+        let mut data = vec![0; PHOENIX_TAKER_FEE_BPS + 8];
+        put(&mut data, PHOENIX_STATUS, &status.to_le_bytes());
+        put(&mut data, PHOENIX_BASE_DECIMALS, &9u32.to_le_bytes());
+        put(&mut data, PHOENIX_BASE_MINT, &[1; 32]);
+        put(&mut data, PHOENIX_BASE_VAULT, &[3; 32]);
+        put(&mut data, PHOENIX_BASE_LOT_SIZE, &1000u64.to_le_bytes());
+        put(&mut data, PHOENIX_QUOTE_DECIMALS, &6u32.to_le_bytes());
+        put(&mut data, PHOENIX_QUOTE_MINT, &[2; 32]);
+        put(&mut data, PHOENIX_QUOTE_VAULT, &[4; 32]);
+        put(&mut data, PHOENIX_QUOTE_LOT_SIZE, &10u64.to_le_bytes());
+        put(&mut data, PHOENIX_TICK_SIZE, &5u64.to_le_bytes());
+        put(&mut data, PHOENIX_TAKER_FEE_BPS, &2u64.to_le_bytes());
+        data
+    }
+
+    fn key(byte: u8) -> String {
+        Pubkey::new_from_array([byte; 32]).to_string()
+    }
+
+    fn keys() -> Keys<'static> {
+        Keys::new(None, TokenAmounts::U64)
+    }
+
+    #[test]
+    fn decodes_openbook_markets() {
+        let mut expected = (1..=7).map(key).collect::<Vec<_>>();
+        expected.extend(["100", "10", "22"].map(String::from));
+        for program_id in [OPENBOOK_V1_PROGRAM_ID, SERUM_DEX_V3_PROGRAM_ID] {
+            let account = TestAccount::new(program_id, serum_market());
+            assert_eq!(
+                decode_openbook_market(&account.stored(), &keys()),
+                Ok(expected.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_openbook_markets() {
+        let decode = |data| {
+            let account = TestAccount::new(OPENBOOK_V1_PROGRAM_ID, data);
+            decode_openbook_market(&account.stored(), &keys())
+        };
+        let mut data = serum_market();
+        data[0] = b'S';
+        assert_eq!(decode(data), Err(DecodeError::WrongDiscriminator));
+        // an open orders account
+        let mut data = serum_market();
+        put(&mut data, SERUM_ACCOUNT_FLAGS, &5u64.to_le_bytes());
+        assert_eq!(
+            decode(data),
+            Err(DecodeError::InvalidField("account_flags"))
+        );
+        assert_eq!(
+            decode(serum_market()[..SERUM_MARKET_LEN - 1].to_vec()),
+            Err(DecodeError::WrongSize {
+                expected: SERUM_MARKET_LEN,
+                actual: SERUM_MARKET_LEN - 1
+            })
+        );
+        let account = TestAccount::new(PHOENIX_PROGRAM_ID, serum_market());
+        assert_eq!(
+            decode_openbook_market(&account.stored(), &keys()),
+            Err(DecodeError::WrongOwner(PHOENIX_PROGRAM_ID))
+        );
+    }
+
+    #[test]
+    fn flags_openbook_market_anomalies() {
+        let mut account = TestAccount::new(OPENBOOK_V1_PROGRAM_ID, serum_market());
+        assert_eq!(
+            validate_openbook_market(&account.stored()),
+            Ok(vec!["own_address_mismatch"])
+        );
+        let pubkey = account.meta.pubkey;
+        put(&mut account.data, SERUM_OWN_ADDRESS, &pubkey.to_bytes());
+        assert_eq!(validate_openbook_market(&account.stored()), Ok(vec![]));
+
+        put(&mut account.data, SERUM_PC_MINT, &[1; 32]);
+        put(&mut account.data, SERUM_PC_LOT_SIZE, &0u64.to_le_bytes());
+        assert_eq!(
+            validate_openbook_market(&account.stored()),
+            Ok(vec!["same_base_and_quote_mint", "zero_lot_size"])
+        );
+    }
+
+    #[test]
+    fn decodes_phoenix_markets() {
+        let account = TestAccount::new(PHOENIX_PROGRAM_ID, phoenix_market(2));
+        let mut expected = (1..=4).map(key).collect::<Vec<_>>();
+        expected.extend(["9", "6", "1000", "10", "5", "2", "post_only"].map(String::from));
+        assert_eq!(
+            decode_phoenix_market(&account.stored(), &keys()),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_phoenix_markets() {
+        let decode = |data| {
+            let account = TestAccount::new(PHOENIX_PROGRAM_ID, data);
+            decode_phoenix_market(&account.stored(), &keys())
+        };
+        // a seat account
+        assert_eq!(
+            decode(vec![0; 128]),
+            Err(DecodeError::TooShort {
+                min: PHOENIX_HEADER_LEN + 1,
+                actual: 128
+            })
+        );
+        assert_eq!(
+            decode(phoenix_market(1)[..PHOENIX_TAKER_FEE_BPS].to_vec()),
+            Err(DecodeError::Truncated(PHOENIX_TAKER_FEE_BPS))
+        );
+        let account = TestAccount::new(OPENBOOK_V1_PROGRAM_ID, phoenix_market(1));
+        assert_eq!(
+            decode_phoenix_market(&account.stored(), &keys()),
+            Err(DecodeError::WrongOwner(OPENBOOK_V1_PROGRAM_ID))
+        );
+    }
+
+    #[test]
+    fn flags_phoenix_market_anomalies() {
+        let validate =
+            |data| validate_phoenix_market(&TestAccount::new(PHOENIX_PROGRAM_ID, data).stored());
+        assert_eq!(validate(phoenix_market(1)), Ok(vec![]));
+        let mut data = phoenix_market(6);
+        put(&mut data, PHOENIX_QUOTE_MINT, &[1; 32]);
+        put(&mut data, PHOENIX_BASE_LOT_SIZE, &0u64.to_le_bytes());
+        assert_eq!(
+            validate(data),
+            Ok(vec![
+                "same_base_and_quote_mint",
+                "zero_lot_size",
+                "unknown_status"
+            ])
+        );
+    }
+}
@@ -0,0 +1,584 @@
+use crate::anonymize::Anonymizer;
+use crate::filter::anchor_account_discriminator;
+
+use clap::ValueEnum;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+mod amm;
+mod lending;
+mod market;
+mod oracle;
+mod schema;
+mod stake;
+mod token;
+mod token2022;
+
+pub use schema::{Schema, SchemaError};
+pub use stake::{delegation, Delegation};
+
+/// Account layouts that can be expanded into named CSV columns.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoder {
+    /// SPL Token / Token-2022 token accounts: mint, owner, amount, delegate and state
+    SplToken,
+    /// Token-2022 mints and token accounts: base fields and TLV extensions as JSON
+    #[clap(name = "token-2022")]
+    Token2022,
+    /// SPL Token / Token-2022 multisig accounts (355 bytes): m, n and signers
+    SplMultisig,
+    /// Pyth price accounts: aggregate price, confidence, status and publish slot
+    PythPrice,
+    /// Switchboard v2 aggregators: latest confirmed round result and deviation
+    SwitchboardAggregator,
+    /// Serum DEX v3 / OpenBook v1 markets: mints, vaults, book accounts and lot sizes
+    OpenbookMarket,
+    /// Phoenix markets: mints, vaults, lot and tick sizes, taker fee and status
+    PhoenixMarket,
+    /// Orca Whirlpool pools: mints, vaults, fee rate, liquidity and sqrt price
+    Whirlpool,
+    /// Raydium AMM v4 pools: mints, vaults, LP mint and supply, swap fee
+    RaydiumAmm,
+    /// Solend reserves: liquidity and collateral mints, supplied/borrowed amounts, LTV config
+    SolendReserve,
+    /// Solend obligations: deposited, borrowed and borrow-limit values (USD)
+    SolendObligation,
+    /// Stake accounts: staker and withdrawer, lockup, vote account and delegated stake
+    Stake,
+}
+
+/// Why an account could not be decoded; written to the `decode_error` column.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("owner {0} has no registered decoder")]
+    UnregisteredOwner(Pubkey),
+    #[error("owner {0} is not a program this decoder reads")]
+    WrongOwner(Pubkey),
+    #[error("data is {actual} bytes, expected {expected}")]
+    WrongSize { expected: usize, actual: usize },
+    #[error("data is {actual} bytes, expected at least {min}")]
+    TooShort { min: usize, actual: usize },
+    #[error("unexpected discriminator or header")]
+    WrongDiscriminator,
+    #[error("account is not initialized")]
+    Uninitialized,
+    #[error("invalid {0}")]
+    InvalidField(&'static str),
+    #[error("data ends before offset {0}")]
+    Truncated(usize),
+}
+
+/// Outcome of decoding one account under a `DecodeMode`: the built-in decoder that accepted
+/// it, if any, and its values.
+pub type Decoded = Result<(Option<Decoder>, Vec<String>), DecodeError>;
+
+/// A `--decode-errors=fail` abort.
+#[derive(Error, Debug)]
+#[error("Failed to decode {pubkey}: {error}")]
+pub struct DecodeFailure {
+    /// As written to the output, so tokenized when anonymizing.
+    pub pubkey: String,
+    pub error: DecodeError,
+}
+
+/// What to do with matched accounts the decoder rejects.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodeErrors {
+    /// Leave the account out of the output
+    Skip,
+    /// Write the account with empty decoded columns and the reason in decode_error
+    #[default]
+    EmitRaw,
+    /// Stop the scan
+    Fail,
+}
+
+/// How decoded token amounts are written when the decoder knows their mint's decimals.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenAmounts {
+    /// The raw integer amount
+    #[default]
+    U64,
+    /// Fixed-point with the mint's decimals, e.g. 1.500000 for 1500000 of a 6-decimal mint
+    Decimal,
+}
+
+/// Decoders tried by `--decode=auto` for accounts of each owner, in order.
+const DECODERS_BY_OWNER: &[(Pubkey, &[Decoder])] = &[
+    (
+        token::TOKEN_PROGRAM_ID,
+        &[Decoder::SplToken, Decoder::SplMultisig],
+    ),
+    (
+        token::TOKEN_2022_PROGRAM_ID,
+        &[Decoder::Token2022, Decoder::SplMultisig],
+    ),
+    (oracle::PYTH_ORACLE_PROGRAM_ID, &[Decoder::PythPrice]),
+    (
+        oracle::SWITCHBOARD_V2_PROGRAM_ID,
+        &[Decoder::SwitchboardAggregator],
+    ),
+    (market::SERUM_DEX_V3_PROGRAM_ID, &[Decoder::OpenbookMarket]),
+    (market::OPENBOOK_V1_PROGRAM_ID, &[Decoder::OpenbookMarket]),
+    (market::PHOENIX_PROGRAM_ID, &[Decoder::PhoenixMarket]),
+    (amm::WHIRLPOOL_PROGRAM_ID, &[Decoder::Whirlpool]),
+    (amm::RAYDIUM_AMM_V4_PROGRAM_ID, &[Decoder::RaydiumAmm]),
+    (
+        lending::SOLEND_PROGRAM_ID,
+        &[Decoder::SolendReserve, Decoder::SolendObligation],
+    ),
+    (stake::STAKE_PROGRAM_ID, &[Decoder::Stake]),
+];
+
+const AUTO_COLUMNS: &[&str] = &["decoder", "decoded"];
+
+/// `--decode` value: one decoder for every account, or `auto` to pick one by owner.
+#[derive(Clone, Debug)]
+pub enum DecodeMode {
+    One(Decoder),
+    /// Emits the decoder name and its columns as a JSON object, since they differ per row
+    Auto,
+    /// A user-supplied layout, from `--borsh-schema` or `--idl`
+    Schema(Arc<Schema>),
+}
+
+impl FromStr for DecodeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(DecodeMode::Auto),
+            _ => <Decoder as ValueEnum>::from_str(s, false).map(DecodeMode::One),
+        }
+    }
+}
+
+impl DecodeMode {
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            DecodeMode::One(decoder) => decoder.columns(),
+            DecodeMode::Auto => AUTO_COLUMNS,
+            DecodeMode::Schema(schema) => schema.columns(),
+        }
+    }
+
+    /// The decoder that accepted the account and one value per column of this mode. In
+    /// `auto` mode, when every candidate rejects the account the last error is returned.
+    pub fn decode(&self, account: &StoredAccountMeta, keys: &Keys) -> Decoded {
+        let decoders = match self {
+            DecodeMode::One(decoder) => {
+                return Ok((Some(*decoder), decoder.decode(account, keys)?));
+            }
+            DecodeMode::Schema(schema) => return Ok((None, schema.decode(account, keys)?)),
+            DecodeMode::Auto => registered_decoders(&account.account_meta.owner),
+        };
+        let mut error = DecodeError::UnregisteredOwner(account.account_meta.owner);
+        for decoder in decoders {
+            match decoder.decode(account, keys) {
+                Ok(values) => {
+                    let object = json_object(decoder.columns(), &values);
+                    return Ok((Some(*decoder), vec![decoder.name().to_string(), object]));
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// True in `auto` mode for accounts whose owner has no registered decoder.
+    pub fn is_unknown_owner(&self, owner: &Pubkey) -> bool {
+        matches!(self, DecodeMode::Auto) && registered_decoders(owner).is_empty()
+    }
+}
+
+fn registered_decoders(owner: &Pubkey) -> &'static [Decoder] {
+    DECODERS_BY_OWNER
+        .iter()
+        .find(|(program_id, _)| program_id == owner)
+        .map_or(&[], |(_, decoders)| decoders)
+}
+
+/// `{"column": "value", ...}` in column order.
+fn json_object(columns: &[&str], values: &[String]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .zip(values)
+        .map(|(column, value)| {
+            format!(
+                "{}:{}",
+                serde_json::Value::from(*column),
+                serde_json::Value::from(value.as_str())
+            )
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+impl Decoder {
+    /// Name as given to `--decode`.
+    pub fn name(&self) -> &'static str {
+        self.to_possible_value()
+            .expect("no decoder is skipped")
+            .get_name()
+    }
+
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Decoder::SplToken => token::ACCOUNT_COLUMNS,
+            Decoder::Token2022 => token2022::TOKEN_2022_COLUMNS,
+            Decoder::SplMultisig => token::MULTISIG_COLUMNS,
+            Decoder::PythPrice => oracle::PYTH_PRICE_COLUMNS,
+            Decoder::SwitchboardAggregator => oracle::SWITCHBOARD_AGGREGATOR_COLUMNS,
+            Decoder::OpenbookMarket => market::OPENBOOK_MARKET_COLUMNS,
+            Decoder::PhoenixMarket => market::PHOENIX_MARKET_COLUMNS,
+            Decoder::Whirlpool => amm::WHIRLPOOL_COLUMNS,
+            Decoder::RaydiumAmm => amm::RAYDIUM_AMM_COLUMNS,
+            Decoder::SolendReserve => lending::SOLEND_RESERVE_COLUMNS,
+            Decoder::SolendObligation => lending::SOLEND_OBLIGATION_COLUMNS,
+            Decoder::Stake => stake::STAKE_COLUMNS,
+        }
+    }
+
+    /// One value per column, or why the account doesn't have this layout.
+    pub fn decode(
+        &self,
+        account: &StoredAccountMeta,
+        keys: &Keys,
+    ) -> Result<Vec<String>, DecodeError> {
+        match self {
+            Decoder::SplToken => token::decode_account(account, keys),
+            Decoder::Token2022 => token2022::decode_token_2022(account, keys),
+            Decoder::SplMultisig => token::decode_multisig(account, keys),
+            Decoder::PythPrice => oracle::decode_pyth_price(account),
+            Decoder::SwitchboardAggregator => oracle::decode_switchboard_aggregator(account),
+            Decoder::OpenbookMarket => market::decode_openbook_market(account, keys),
+            Decoder::PhoenixMarket => market::decode_phoenix_market(account, keys),
+            Decoder::Whirlpool => amm::decode_whirlpool(account, keys),
+            Decoder::RaydiumAmm => amm::decode_raydium_amm(account, keys),
+            Decoder::SolendReserve => lending::decode_solend_reserve(account, keys),
+            Decoder::SolendObligation => lending::decode_solend_obligation(account, keys),
+            Decoder::Stake => stake::decode_stake(account, keys),
+        }
+    }
+
+    /// Invariant violations of an account `decode` accepted, e.g. a pool not at its PDA.
+    pub fn anomalies(&self, account: &StoredAccountMeta) -> Vec<&'static str> {
+        match self {
+            Decoder::SplToken => token::validate_account(account),
+            Decoder::Token2022 => token2022::validate_token_2022(account),
+            Decoder::SplMultisig => token::validate_multisig(account),
+            Decoder::PythPrice => oracle::validate_pyth_price(account),
+            Decoder::SwitchboardAggregator => oracle::validate_switchboard_aggregator(account),
+            Decoder::OpenbookMarket => market::validate_openbook_market(account),
+            Decoder::PhoenixMarket => market::validate_phoenix_market(account),
+            Decoder::Whirlpool => amm::validate_whirlpool(account),
+            Decoder::RaydiumAmm => amm::validate_raydium_amm(account),
+            Decoder::SolendReserve => lending::validate_solend_reserve(account),
+            Decoder::SolendObligation => lending::validate_solend_obligation(account),
+            Decoder::Stake => stake::validate_stake(account),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Formats pubkeys and token amounts found inside account data.
+#[derive(Clone, Copy)]
+pub struct Keys<'a> {
+    anonymizer: Option<&'a Anonymizer>,
+    token_amounts: TokenAmounts,
+}
+
+impl<'a> Keys<'a> {
+    /// Decoded pubkeys are tokenized when an anonymizer is given.
+    pub fn new(anonymizer: Option<&'a Anonymizer>, token_amounts: TokenAmounts) -> Self {
+        Self {
+            anonymizer,
+            token_amounts,
+        }
+    }
+
+    fn format(&self, key: &[u8; 32]) -> String {
+        match self.anonymizer {
+            Some(anonymizer) => anonymizer.bytes32(key),
+            None => bs58::encode(key).into_string(),
+        }
+    }
+
+    /// A raw amount of a mint with `decimals`.
+    fn amount(&self, amount: u64, decimals: u8) -> String {
+        match self.token_amounts {
+            TokenAmounts::U64 => amount.to_string(),
+            TokenAmounts::Decimal => decimal(amount as i128, decimals as u32),
+        }
+    }
+}
+
+fn expect_owner(account: &StoredAccountMeta, owners: &[Pubkey]) -> Result<(), DecodeError> {
+    let owner = account.account_meta.owner;
+    if owners.contains(&owner) {
+        Ok(())
+    } else {
+        Err(DecodeError::WrongOwner(owner))
+    }
+}
+
+fn expect_len(account: &StoredAccountMeta, expected: usize) -> Result<(), DecodeError> {
+    match account.data.len() {
+        actual if actual == expected => Ok(()),
+        actual => Err(DecodeError::WrongSize { expected, actual }),
+    }
+}
+
+fn expect_anchor_account(account: &StoredAccountMeta, name: &str) -> Result<(), DecodeError> {
+    match account.data.get(..8) {
+        Some(head) if head == anchor_account_discriminator(name) => Ok(()),
+        _ => Err(DecodeError::WrongDiscriminator),
+    }
+}
+
+/// Bounds-checked little-endian reads at fixed offsets.
+struct Layout<'a>(&'a [u8]);
+
+impl Layout<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], DecodeError> {
+        offset
+            .checked_add(N)
+            .and_then(|end| self.0.get(offset..end))
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(DecodeError::Truncated(offset))
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, DecodeError> {
+        self.0
+            .get(offset)
+            .copied()
+            .ok_or(DecodeError::Truncated(offset))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, DecodeError> {
+        self.bytes(offset).map(u16::from_le_bytes)
+    }
+
+    fn i16(&self, offset: usize) -> Result<i16, DecodeError> {
+        self.bytes(offset).map(i16::from_le_bytes)
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, DecodeError> {
+        self.bytes(offset).map(u32::from_le_bytes)
+    }
+
+    fn i32(&self, offset: usize) -> Result<i32, DecodeError> {
+        self.bytes(offset).map(i32::from_le_bytes)
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, DecodeError> {
+        self.bytes(offset).map(u64::from_le_bytes)
+    }
+
+    fn i64(&self, offset: usize) -> Result<i64, DecodeError> {
+        self.bytes(offset).map(i64::from_le_bytes)
+    }
+
+    fn u128(&self, offset: usize) -> Result<u128, DecodeError> {
+        self.bytes(offset).map(u128::from_le_bytes)
+    }
+
+    fn i128(&self, offset: usize) -> Result<i128, DecodeError> {
+        self.bytes(offset).map(i128::from_le_bytes)
+    }
+}
+
+/// Sequential reads of Borsh-encoded fields.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        Self { data, offset }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.offset..end))
+            .ok_or(DecodeError::Truncated(self.offset))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_e| DecodeError::InvalidField("string"))
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.offset)
+    }
+}
+
+/// `mantissa * 10^-scale` as a decimal string, without going through floats.
+pub fn decimal(mantissa: i128, scale: u32) -> String {
+    let sign = if mantissa < 0 { "-" } else { "" };
+    let digits = mantissa.unsigned_abs().to_string();
+    let scale = scale as usize;
+    if scale == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (int, frac) = digits.split_at(digits.len() - scale);
+    format!("{}{}.{}", sign, int, frac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{put, TestAccount};
+
+    fn keys() -> Keys<'static> {
+        Keys::new(None, TokenAmounts::U64)
+    }
+
+    fn decode_mode(s: &str) -> DecodeMode {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_decode_modes() {
+        assert!(matches!(decode_mode("auto"), DecodeMode::Auto));
+        assert!(matches!(
+            decode_mode("spl-token"),
+            DecodeMode::One(Decoder::SplToken)
+        ));
+        assert!(matches!(
+            decode_mode("token-2022"),
+            DecodeMode::One(Decoder::Token2022)
+        ));
+        assert!("Auto".parse::<DecodeMode>().is_err());
+        assert!("token2022".parse::<DecodeMode>().is_err());
+        assert!("".parse::<DecodeMode>().is_err());
+
+        for decoder in Decoder::value_variants() {
+            assert!(matches!(decode_mode(decoder.name()), DecodeMode::One(d) if d == *decoder));
+            assert_eq!(DecodeMode::One(*decoder).columns(), decoder.columns());
+        }
+        assert_eq!(DecodeMode::Auto.columns(), ["decoder", "decoded"]);
+    }
+
+    #[test]
+    fn auto_mode_picks_a_decoder_by_owner() {
+        let mut data = vec![0; token::ACCOUNT_LEN];
+        put(&mut data, 64, &5u64.to_le_bytes());
+        data[108] = 1;
+        let account = TestAccount::new(token::TOKEN_PROGRAM_ID, data);
+        let (decoder, values) = DecodeMode::Auto.decode(&account.stored(), &keys()).unwrap();
+        assert_eq!(decoder, Some(Decoder::SplToken));
+        assert_eq!(values[0], "spl-token");
+        let object: serde_json::Value = serde_json::from_str(&values[1]).unwrap();
+        assert_eq!(object["amount"], "5");
+        assert_eq!(object["state"], "initialized");
+        assert_eq!(
+            object.as_object().unwrap().len(),
+            token::ACCOUNT_COLUMNS.len()
+        );
+
+        // the token program's multisigs come after its token accounts
+        let mut data = vec![0; token::MULTISIG_LEN];
+        put(&mut data, 0, &[1, 1, 1]);
+        put(&mut data, 3, &[7; 32]);
+        let account = TestAccount::new(token::TOKEN_PROGRAM_ID, data);
+        let (decoder, values) = DecodeMode::Auto.decode(&account.stored(), &keys()).unwrap();
+        assert_eq!(decoder, Some(Decoder::SplMultisig));
+        assert_eq!(values[0], "spl-multisig");
+    }
+
+    #[test]
+    fn auto_mode_reports_the_last_rejection() {
+        let account = TestAccount::new(token::TOKEN_PROGRAM_ID, vec![0; 10]);
+        assert_eq!(
+            DecodeMode::Auto.decode(&account.stored(), &keys()),
+            Err(DecodeError::WrongSize {
+                expected: token::MULTISIG_LEN,
+                actual: 10
+            })
+        );
+
+        let owner = Pubkey::new_unique();
+        let account = TestAccount::new(owner, vec![0; 165]);
+        assert_eq!(
+            DecodeMode::Auto.decode(&account.stored(), &keys()),
+            Err(DecodeError::UnregisteredOwner(owner))
+        );
+        assert!(DecodeMode::Auto.is_unknown_owner(&owner));
+        assert!(!DecodeMode::Auto.is_unknown_owner(&stake::STAKE_PROGRAM_ID));
+        assert!(!DecodeMode::One(Decoder::Stake).is_unknown_owner(&owner));
+    }
+
+    #[test]
+    fn one_decoder_mode_reports_its_error() {
+        let account = TestAccount::new(Pubkey::default(), vec![]);
+        assert_eq!(
+            DecodeMode::One(Decoder::Stake).decode(&account.stored(), &keys()),
+            Err(DecodeError::WrongOwner(Pubkey::default()))
+        );
+    }
+
+    #[test]
+    fn anomalies_of_unreadable_data_are_empty() {
+        let account = TestAccount::new(stake::STAKE_PROGRAM_ID, vec![0; 3]);
+        assert!(Decoder::Stake.anomalies(&account.stored()).is_empty());
+    }
+
+    #[test]
+    fn formats_decimals() {
+        assert_eq!(decimal(0, 0), "0");
+        assert_eq!(decimal(-42, 0), "-42");
+        assert_eq!(decimal(1_500_000, 6), "1.500000");
+        assert_eq!(decimal(5, 3), "0.005");
+        assert_eq!(decimal(-5, 3), "-0.005");
+        assert_eq!(decimal(1000, 3), "1.000");
+        assert_eq!(
+            decimal(i128::MIN, 18),
+            "-170141183460469231731.687303715884105728"
+        );
+        assert_eq!(decimal(1, 40), format!("0.{}1", "0".repeat(39)));
+    }
+
+    #[test]
+    fn layout_reads_out_of_bounds_as_truncated() {
+        let data = Layout(&[1, 2, 3, 4]);
+        assert_eq!(data.u32(0), Ok(0x04030201));
+        assert_eq!(data.u16(2), Ok(0x0403));
+        assert_eq!(data.u32(1), Err(DecodeError::Truncated(1)));
+        assert_eq!(data.u8(4), Err(DecodeError::Truncated(4)));
+        for offset in [usize::MAX, usize::MAX - 1] {
+            assert_eq!(data.u64(offset), Err(DecodeError::Truncated(offset)));
+            assert_eq!(data.u8(offset), Err(DecodeError::Truncated(offset)));
+        }
+    }
+
+    #[test]
+    fn reader_reads_out_of_bounds_as_truncated() {
+        let mut reader = Reader::new(&[2, 0, 0, 0, b'h', b'i', 0xff], 0);
+        assert_eq!(reader.string(), Ok("hi".to_string()));
+        assert_eq!(reader.remaining(), 1);
+        assert_eq!(reader.u32(), Err(DecodeError::Truncated(6)));
+        assert_eq!(reader.take(usize::MAX), Err(DecodeError::Truncated(6)));
+        assert_eq!(reader.take(1), Ok(&[0xff][..]));
+        assert_eq!(Reader::new(&[1, 2], 5).remaining(), 0);
+    }
+}
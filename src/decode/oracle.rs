@@ -0,0 +1,311 @@
+use super::{decimal, expect_anchor_account, expect_owner, DecodeError, Layout};
+
+use solana_program::pubkey;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+/// Header shared by all Pyth v2 accounts: magic, version, account type.
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_VERSION: u32 = 2;
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+
+// pyth `PriceAccount` offsets
+const PYTH_EXPO: usize = 20;
+const PYTH_TIMESTAMP: usize = 96;
+const PYTH_AGG_PRICE: usize = 208;
+const PYTH_AGG_CONF: usize = 216;
+const PYTH_AGG_STATUS: usize = 224;
+const PYTH_AGG_PUB_SLOT: usize = 232;
+
+/// Pyth oracle program on mainnet-beta and Pythnet.
+pub(super) const PYTH_ORACLE_PROGRAM_ID: Pubkey =
+    pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+pub(super) const SWITCHBOARD_V2_PROGRAM_ID: Pubkey =
+    pubkey!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+
+// switchboard-v2 `AggregatorAccountData` offsets (packed, after the 8 byte discriminator)
+const SWITCHBOARD_ROUND_NUM_SUCCESS: usize = 341;
+const SWITCHBOARD_ROUND_OPEN_SLOT: usize = 350;
+const SWITCHBOARD_ROUND_OPEN_TIMESTAMP: usize = 358;
+const SWITCHBOARD_ROUND_RESULT: usize = 366;
+const SWITCHBOARD_ROUND_STD_DEVIATION: usize = 386;
+
+pub(super) const PYTH_PRICE_COLUMNS: &[&str] =
+    &["price", "confidence", "status", "publish_slot", "timestamp"];
+
+pub(super) const SWITCHBOARD_AGGREGATOR_COLUMNS: &[&str] = &[
+    "price",
+    "std_deviation",
+    "num_success",
+    "round_open_slot",
+    "round_open_timestamp",
+];
+
+fn pyth_status(status: u32) -> &'static str {
+    match status {
+        1 => "trading",
+        2 => "halted",
+        3 => "auction",
+        4 => "ignored",
+        _ => "unknown",
+    }
+}
+
+/// Pyth amounts are integers with a shared exponent, normally negative.
+fn pyth_decimal(value: i128, expo: i32) -> Result<String, DecodeError> {
+    if expo <= 0 {
+        return Ok(decimal(value, expo.unsigned_abs()));
+    }
+    let value = 10i128
+        .checked_pow(expo as u32)
+        .and_then(|scale| value.checked_mul(scale))
+        .ok_or(DecodeError::InvalidField("expo"))?;
+    Ok(value.to_string())
+}
+
+pub(super) fn decode_pyth_price(account: &StoredAccountMeta) -> Result<Vec<String>, DecodeError> {
+    // checked by header rather than owner: the oracle program differs across clusters
+    let data = Layout(account.data);
+    let header = (data.u32(0)?, data.u32(4)?, data.u32(8)?);
+    if header != (PYTH_MAGIC, PYTH_VERSION, PYTH_PRICE_ACCOUNT) {
+        return Err(DecodeError::WrongDiscriminator);
+    }
+    let expo = data.i32(PYTH_EXPO)?;
+    Ok(vec![
+        pyth_decimal(data.i64(PYTH_AGG_PRICE)? as i128, expo)?,
+        pyth_decimal(data.u64(PYTH_AGG_CONF)? as i128, expo)?,
+        pyth_status(data.u32(PYTH_AGG_STATUS)?).to_string(),
+        data.u64(PYTH_AGG_PUB_SLOT)?.to_string(),
+        data.i64(PYTH_TIMESTAMP)?.to_string(),
+    ])
+}
+
+/// `SwitchboardDecimal`: i128 mantissa followed by a u32 scale.
+fn switchboard_decimal(data: &Layout, offset: usize) -> Result<String, DecodeError> {
+    Ok(decimal(data.i128(offset)?, data.u32(offset + 16)?))
+}
+
+pub(super) fn decode_switchboard_aggregator(
+    account: &StoredAccountMeta,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[SWITCHBOARD_V2_PROGRAM_ID])?;
+    expect_anchor_account(account, "AggregatorAccountData")?;
+    let data = Layout(account.data);
+    Ok(vec![
+        switchboard_decimal(&data, SWITCHBOARD_ROUND_RESULT)?,
+        switchboard_decimal(&data, SWITCHBOARD_ROUND_STD_DEVIATION)?,
+        data.u32(SWITCHBOARD_ROUND_NUM_SUCCESS)?.to_string(),
+        data.u64(SWITCHBOARD_ROUND_OPEN_SLOT)?.to_string(),
+        data.i64(SWITCHBOARD_ROUND_OPEN_TIMESTAMP)?.to_string(),
+    ])
+}
+
+pub(super) fn validate_pyth_price(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let price = data.i64(PYTH_AGG_PRICE)?;
+    let conf = data.u64(PYTH_AGG_CONF)?;
+    let mut anomalies = vec![];
+    if data.u32(PYTH_AGG_STATUS)? == 1 && price <= 0 {
+        anomalies.push("non_positive_price");
+    }
+    if conf > price.unsigned_abs() {
+        anomalies.push("confidence_exceeds_price");
+    }
+    if !(-18..=18).contains(&data.i32(PYTH_EXPO)?) {
+        anomalies.push("unusual_exponent");
+    }
+    Ok(anomalies)
+}
+
+pub(super) fn validate_switchboard_aggregator(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let mut anomalies = vec![];
+    if data.u32(SWITCHBOARD_ROUND_NUM_SUCCESS)? == 0 {
+        anomalies.push("no_oracle_results");
+    }
+    if data.i128(SWITCHBOARD_ROUND_STD_DEVIATION)? < 0 {
+        anomalies.push("negative_std_deviation");
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::anchor_account_discriminator;
+    use crate::testing::{put, TestAccount};
+
+    const SWITCHBOARD_LEN: usize = SWITCHBOARD_ROUND_STD_DEVIATION + 20;
+
+    fn pyth_price(expo: i32, price: i64, conf: u64, status: u32) -> Vec<u8> {
+        let mut data = vec![0; 240];
+        put(&mut data, 0, &PYTH_MAGIC.to_le_bytes());
+        put(&mut data, 4, &PYTH_VERSION.to_le_bytes());
+        put(&mut data, 8, &PYTH_PRICE_ACCOUNT.to_le_bytes());
+        put(&mut data, PYTH_EXPO, &expo.to_le_bytes());
+        put(&mut data, PYTH_TIMESTAMP, &1_700_000_000i64.to_le_bytes());
+        put(&mut data, PYTH_AGG_PRICE, &price.to_le_bytes());
+        put(&mut data, PYTH_AGG_CONF, &conf.to_le_bytes());
+        put(&mut data, PYTH_AGG_STATUS, &status.to_le_bytes());
+        put(&mut data, PYTH_AGG_PUB_SLOT, &250_000_000u64.to_le_bytes());
+        data
+    }
+
+    fn switchboard_aggregator(result: i128, std_deviation: i128, num_success: u32) -> Vec<u8> {
+        let mut data = vec![0; SWITCHBOARD_LEN];
+        put(
+            &mut data,
+            0,
+            &anchor_account_discriminator("AggregatorAccountData"),
+        );
+        put(
+            &mut data,
+            SWITCHBOARD_ROUND_NUM_SUCCESS,
+            &num_success.to_le_bytes(),
+        );
+        put(&mut data, SWITCHBOARD_ROUND_OPEN_SLOT, &42u64.to_le_bytes());
+        put(
+            &mut data,
+            SWITCHBOARD_ROUND_OPEN_TIMESTAMP,
+            &(-5i64).to_le_bytes(),
+        );
+        put(&mut data, SWITCHBOARD_ROUND_RESULT, &result.to_le_bytes());
+        put(
+            &mut data,
+            SWITCHBOARD_ROUND_RESULT + 16,
+            &3u32.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            SWITCHBOARD_ROUND_STD_DEVIATION,
+            &std_deviation.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            SWITCHBOARD_ROUND_STD_DEVIATION + 16,
+            &4u32.to_le_bytes(),
+        );
+        data
+    }
+
+    fn pyth(data: Vec<u8>) -> TestAccount {
+        TestAccount::new(PYTH_ORACLE_PROGRAM_ID, data)
+    }
+
+    fn switchboard(data: Vec<u8>) -> TestAccount {
+        TestAccount::new(SWITCHBOARD_V2_PROGRAM_ID, data)
+    }
+
+    #[test]
+    fn decodes_pyth_prices() {
+        let account = pyth(pyth_price(-8, 123_456_789_000, 1_500_000, 1));
+        assert_eq!(
+            decode_pyth_price(&account.stored()),
+            Ok(vec![
+                "1234.56789000".to_string(),
+                "0.01500000".to_string(),
+                "trading".to_string(),
+                "250000000".to_string(),
+                "1700000000".to_string(),
+            ])
+        );
+
+        let account = pyth(pyth_price(2, -5, 1, 7));
+        let values = decode_pyth_price(&account.stored()).unwrap();
+        assert_eq!(values[..3], ["-500", "100", "unknown"]);
+
+        // any owner: the header identifies the account
+        let account = TestAccount::new(Pubkey::new_unique(), pyth_price(0, 5, 1, 2));
+        let values = decode_pyth_price(&account.stored()).unwrap();
+        assert_eq!(values[..3], ["5", "1", "halted"]);
+    }
+
+    #[test]
+    fn rejects_malformed_pyth_prices() {
+        let decode = |data| decode_pyth_price(&pyth(data).stored());
+        assert_eq!(
+            decode(pyth_price(39, 1, 1, 1)),
+            Err(DecodeError::InvalidField("expo"))
+        );
+        let mut data = pyth_price(-8, 1, 1, 1);
+        data[0] ^= 1;
+        assert_eq!(decode(data), Err(DecodeError::WrongDiscriminator));
+        let mut data = pyth_price(-8, 1, 1, 1);
+        put(&mut data, 8, &1u32.to_le_bytes());
+        assert_eq!(decode(data), Err(DecodeError::WrongDiscriminator));
+        let mut data = pyth_price(-8, 1, 1, 1);
+        data.truncate(239);
+        assert_eq!(decode(data), Err(DecodeError::Truncated(PYTH_AGG_PUB_SLOT)));
+        assert_eq!(decode(vec![]), Err(DecodeError::Truncated(0)));
+    }
+
+    #[test]
+    fn flags_pyth_price_anomalies() {
+        let validate = |data| validate_pyth_price(&pyth(data).stored());
+        assert_eq!(validate(pyth_price(-8, 100, 1, 1)), Ok(vec![]));
+        assert_eq!(
+            validate(pyth_price(-8, 0, 1, 1)),
+            Ok(vec!["non_positive_price", "confidence_exceeds_price"])
+        );
+        // only trading prices have to be positive
+        assert_eq!(validate(pyth_price(-8, -100, 1, 2)), Ok(vec![]));
+        assert_eq!(
+            validate(pyth_price(-19, 100, 1, 1)),
+            Ok(vec!["unusual_exponent"])
+        );
+    }
+
+    #[test]
+    fn decodes_switchboard_aggregators() {
+        let account = switchboard(switchboard_aggregator(-123_456, 25_000, 3));
+        assert_eq!(
+            decode_switchboard_aggregator(&account.stored()),
+            Ok(vec![
+                "-123.456".to_string(),
+                "2.5000".to_string(),
+                "3".to_string(),
+                "42".to_string(),
+                "-5".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_switchboard_aggregators() {
+        let data = switchboard_aggregator(1, 1, 1);
+        let account = pyth(data.clone());
+        assert_eq!(
+            decode_switchboard_aggregator(&account.stored()),
+            Err(DecodeError::WrongOwner(PYTH_ORACLE_PROGRAM_ID))
+        );
+        let decode = |data| decode_switchboard_aggregator(&switchboard(data).stored());
+        let mut wrong_discriminator = data.clone();
+        wrong_discriminator[..8].copy_from_slice(&anchor_account_discriminator("LeaseAccountData"));
+        assert_eq!(
+            decode(wrong_discriminator),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        assert_eq!(
+            decode(data[..7].to_vec()),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        assert_eq!(
+            decode(data[..SWITCHBOARD_LEN - 1].to_vec()),
+            Err(DecodeError::Truncated(SWITCHBOARD_ROUND_STD_DEVIATION + 16))
+        );
+    }
+
+    #[test]
+    fn flags_switchboard_aggregator_anomalies() {
+        let validate = |data| validate_switchboard_aggregator(&switchboard(data).stored());
+        assert_eq!(validate(switchboard_aggregator(1, 1, 1)), Ok(vec![]));
+        assert_eq!(
+            validate(switchboard_aggregator(1, -1, 0)),
+            Ok(vec!["no_oracle_results", "negative_std_deviation"])
+        );
+    }
+}
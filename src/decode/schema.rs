@@ -0,0 +1,817 @@
+use super::{DecodeError, Keys, Reader};
+use crate::filter::anchor_account_discriminator;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{Map, Value};
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// Deepest nesting of defined types followed while decoding, so a type containing itself
+/// without a length or tag in between can't recurse forever.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("Failed to read schema: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid schema JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid schema: {0}")]
+    Invalid(String),
+}
+
+/// A Borsh type, written as in Anchor IDLs: `"u64"`, `"pubkey"`, `{"vec": T}`,
+/// `{"array": [T, N]}`, `{"defined": "Name"}`, ...
+#[derive(Debug)]
+enum Type {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    U128,
+    I128,
+    F32,
+    F64,
+    String,
+    Bytes,
+    Pubkey,
+    Option(Box<Type>),
+    COption(Box<Type>),
+    Vec(Box<Type>),
+    Array(Box<Type>, usize),
+    Defined(String),
+}
+
+#[derive(Debug)]
+enum Fields {
+    Named(Vec<(String, Type)>),
+    Tuple(Vec<Type>),
+    Unit,
+}
+
+#[derive(Debug)]
+enum TypeDef {
+    Struct(Fields),
+    Enum(Vec<(String, Fields)>),
+}
+
+/// Named types that fields refer to with `{"defined": ...}`.
+#[derive(Debug, Default)]
+struct Types(HashMap<String, TypeDef>);
+
+/// Schema-driven `--decode` replacement, decoding account data into JSON.
+#[derive(Debug)]
+pub struct Schema {
+    root: Root,
+    types: Types,
+}
+
+#[derive(Debug)]
+enum Root {
+    /// `--borsh-schema`: `fields` read in order from `offset`.
+    Borsh { offset: usize, fields: Fields },
+    /// `--idl`: the account type whose discriminator the data starts with, read after it.
+    Idl { accounts: Vec<IdlAccount> },
+}
+
+#[derive(Debug)]
+struct IdlAccount {
+    name: String,
+    discriminator: Vec<u8>,
+    ty: Type,
+}
+
+fn invalid(message: impl std::fmt::Display) -> SchemaError {
+    SchemaError::Invalid(message.to_string())
+}
+
+fn read_json(path: &Path) -> Result<Value, SchemaError> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+impl Schema {
+    /// Reads a `{"offset": N, "fields": [...], "types": [...]}` layout; `offset` (default 0)
+    /// skips a discriminator or header, `types` are Anchor IDL type definitions.
+    pub fn borsh(path: &Path) -> Result<Self, SchemaError> {
+        let json = read_json(path)?;
+        let offset = match json.get("offset") {
+            Some(offset) => offset
+                .as_u64()
+                .ok_or_else(|| invalid("\"offset\" must be a byte count"))?
+                as usize,
+            None => 0,
+        };
+        let fields = match parse_fields(json.get("fields"))? {
+            fields @ Fields::Named(_) => fields,
+            _ => return Err(invalid("\"fields\" must list {\"name\", \"type\"} objects")),
+        };
+        let types = Types::parse(json.get("types"))?;
+        types.check_fields(&fields)?;
+        Ok(Schema {
+            root: Root::Borsh { offset, fields },
+            types,
+        })
+    }
+
+    /// Reads the account types of an Anchor IDL. Both IDL formats work: accounts with their
+    /// own `type` and 8 byte `account:<Name>` discriminators (before Anchor 0.30), and accounts
+    /// with a `discriminator` whose layout is in `types`.
+    pub fn idl(path: &Path) -> Result<Self, SchemaError> {
+        let json = read_json(path)?;
+        let accounts = match json.get("accounts") {
+            Some(Value::Array(accounts)) if !accounts.is_empty() => accounts,
+            _ => return Err(invalid("IDL has no \"accounts\"")),
+        };
+        let mut defs = match json.get("types") {
+            Some(Value::Array(defs)) => defs.clone(),
+            None => vec![],
+            Some(_) => return Err(invalid("\"types\" must be an array")),
+        };
+        defs.extend(
+            accounts
+                .iter()
+                .filter(|account| account.get("type").is_some())
+                .cloned(),
+        );
+        let types = Types::parse(Some(&Value::Array(defs)))?;
+
+        let accounts = accounts
+            .iter()
+            .map(|account| {
+                let name = account
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| invalid(format!("account without a name: {}", account)))?;
+                let discriminator = match account.get("discriminator") {
+                    None => anchor_account_discriminator(name).to_vec(),
+                    Some(Value::Array(bytes)) if !bytes.is_empty() => bytes
+                        .iter()
+                        .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| invalid(format!("invalid discriminator of {}", name)))?,
+                    Some(_) => return Err(invalid(format!("invalid discriminator of {}", name))),
+                };
+                let ty = Type::Defined(name.to_string());
+                types
+                    .check(&ty)
+                    .map_err(|_e| invalid(format!("account {} has no type definition", name)))?;
+                Ok(IdlAccount {
+                    name: name.to_string(),
+                    discriminator,
+                    ty,
+                })
+            })
+            .collect::<Result<_, SchemaError>>()?;
+        Ok(Schema {
+            root: Root::Idl { accounts },
+            types,
+        })
+    }
+
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self.root {
+            Root::Borsh { .. } => &["decoded"],
+            Root::Idl { .. } => &["account_type", "decoded"],
+        }
+    }
+
+    pub fn decode(
+        &self,
+        account: &StoredAccountMeta,
+        keys: &Keys,
+    ) -> Result<Vec<String>, DecodeError> {
+        match &self.root {
+            Root::Borsh { offset, fields } => {
+                let mut reader = Reader::new(account.data, *offset);
+                let decoded = self.types.decode_fields(fields, &mut reader, keys, 0)?;
+                Ok(vec![decoded.to_string()])
+            }
+            Root::Idl { accounts } => {
+                let idl_account = accounts
+                    .iter()
+                    .find(|idl_account| account.data.starts_with(&idl_account.discriminator))
+                    .ok_or(DecodeError::WrongDiscriminator)?;
+                let mut reader = Reader::new(account.data, idl_account.discriminator.len());
+                let decoded = self.types.decode(&idl_account.ty, &mut reader, keys, 0)?;
+                Ok(vec![idl_account.name.clone(), decoded.to_string()])
+            }
+        }
+    }
+}
+
+fn parse_type(json: &Value) -> Result<Type, SchemaError> {
+    let unsupported = || invalid(format!("unsupported type {}", json));
+    if let Some(name) = json.as_str() {
+        return Ok(match name {
+            "bool" => Type::Bool,
+            "u8" => Type::U8,
+            "i8" => Type::I8,
+            "u16" => Type::U16,
+            "i16" => Type::I16,
+            "u32" => Type::U32,
+            "i32" => Type::I32,
+            "u64" => Type::U64,
+            "i64" => Type::I64,
+            "u128" => Type::U128,
+            "i128" => Type::I128,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "string" => Type::String,
+            "bytes" => Type::Bytes,
+            // Anchor IDLs before 0.30 spell it publicKey
+            "pubkey" | "publicKey" => Type::Pubkey,
+            _ => return Err(unsupported()),
+        });
+    }
+    let (kind, inner) = json
+        .as_object()
+        .filter(|object| object.len() == 1)
+        .and_then(|object| object.iter().next())
+        .ok_or_else(unsupported)?;
+    let boxed = |inner: &Value| parse_type(inner).map(Box::new);
+    Ok(match kind.as_str() {
+        "option" => Type::Option(boxed(inner)?),
+        "coption" => Type::COption(boxed(inner)?),
+        "vec" => Type::Vec(boxed(inner)?),
+        "array" => match inner.as_array().map(Vec::as_slice) {
+            Some([item, Value::Number(len)]) => {
+                let len = len.as_u64().ok_or_else(unsupported)? as usize;
+                Type::Array(boxed(item)?, len)
+            }
+            _ => return Err(unsupported()),
+        },
+        "defined" => match inner {
+            Value::String(name) => Type::Defined(name.clone()),
+            // Anchor 0.30: {"defined": {"name": "Name"}}
+            Value::Object(defined) => match defined.get("name") {
+                Some(Value::String(name)) => Type::Defined(name.clone()),
+                _ => return Err(unsupported()),
+            },
+            _ => return Err(unsupported()),
+        },
+        _ => return Err(unsupported()),
+    })
+}
+
+/// `[{"name", "type"}, ...]` as named fields, `[TYPE, ...]` as a tuple, absent as unit.
+fn parse_fields(json: Option<&Value>) -> Result<Fields, SchemaError> {
+    let fields = match json {
+        None => return Ok(Fields::Unit),
+        Some(Value::Array(fields)) if fields.is_empty() => return Ok(Fields::Unit),
+        Some(Value::Array(fields)) => fields,
+        Some(_) => return Err(invalid("fields must be an array")),
+    };
+    if fields.iter().all(|field| field.get("name").is_some()) {
+        fields
+            .iter()
+            .map(|field| match (field.get("name"), field.get("type")) {
+                (Some(Value::String(name)), Some(ty)) => Ok((name.clone(), parse_type(ty)?)),
+                _ => Err(invalid(format!("invalid field {}", field))),
+            })
+            .collect::<Result<_, _>>()
+            .map(Fields::Named)
+    } else {
+        fields
+            .iter()
+            .map(parse_type)
+            .collect::<Result<_, _>>()
+            .map(Fields::Tuple)
+    }
+}
+
+impl Types {
+    /// Reads `[{"name", "type": {"kind": "struct", "fields"} | {"kind": "enum", "variants"}}]`.
+    fn parse(json: Option<&Value>) -> Result<Self, SchemaError> {
+        let mut types = Types::default();
+        let defs = match json {
+            None => return Ok(types),
+            Some(Value::Array(defs)) => defs,
+            Some(_) => return Err(invalid("\"types\" must be an array")),
+        };
+        for def in defs {
+            let name = def
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid(format!("type without a name: {}", def)))?;
+            let ty = def.get("type").unwrap_or(&Value::Null);
+            let type_def = match ty.get("kind").and_then(Value::as_str) {
+                Some("struct") => TypeDef::Struct(parse_fields(ty.get("fields"))?),
+                Some("enum") => {
+                    let variants = ty
+                        .get("variants")
+                        .and_then(Value::as_array)
+                        .ok_or_else(|| invalid(format!("enum {} has no variants", name)))?;
+                    let variants = variants
+                        .iter()
+                        .map(|variant| match variant.get("name") {
+                            Some(Value::String(name)) => {
+                                Ok((name.clone(), parse_fields(variant.get("fields"))?))
+                            }
+                            _ => Err(invalid(format!("invalid variant {}", variant))),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    TypeDef::Enum(variants)
+                }
+                _ => return Err(invalid(format!("type {} is not a struct or enum", name))),
+            };
+            types.0.insert(name.to_string(), type_def);
+        }
+        for type_def in types.0.values() {
+            match type_def {
+                TypeDef::Struct(fields) => types.check_fields(fields)?,
+                TypeDef::Enum(variants) => {
+                    for (_, fields) in variants {
+                        types.check_fields(fields)?;
+                    }
+                }
+            }
+        }
+        Ok(types)
+    }
+
+    /// Fails on references to types that aren't defined.
+    fn check_fields(&self, fields: &Fields) -> Result<(), SchemaError> {
+        match fields {
+            Fields::Named(fields) => fields.iter().try_for_each(|(_, ty)| self.check(ty)),
+            Fields::Tuple(types) => types.iter().try_for_each(|ty| self.check(ty)),
+            Fields::Unit => Ok(()),
+        }
+    }
+
+    fn check(&self, ty: &Type) -> Result<(), SchemaError> {
+        match ty {
+            Type::Option(inner)
+            | Type::COption(inner)
+            | Type::Vec(inner)
+            | Type::Array(inner, _) => self.check(inner),
+            Type::Defined(name) if !self.0.contains_key(name) => {
+                Err(invalid(format!("type {} is not defined", name)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn decode(
+        &self,
+        ty: &Type,
+        reader: &mut Reader,
+        keys: &Keys,
+        depth: usize,
+    ) -> Result<Value, DecodeError> {
+        if depth > MAX_DEPTH {
+            return Err(DecodeError::InvalidField("type nesting"));
+        }
+        Ok(match ty {
+            Type::Bool => match reader.bytes::<1>()? {
+                [0] => Value::Bool(false),
+                [1] => Value::Bool(true),
+                _ => return Err(DecodeError::InvalidField("bool")),
+            },
+            Type::U8 => u8::from_le_bytes(reader.bytes()?).into(),
+            Type::I8 => i8::from_le_bytes(reader.bytes()?).into(),
+            Type::U16 => u16::from_le_bytes(reader.bytes()?).into(),
+            Type::I16 => i16::from_le_bytes(reader.bytes()?).into(),
+            Type::U32 => u32::from_le_bytes(reader.bytes()?).into(),
+            Type::I32 => i32::from_le_bytes(reader.bytes()?).into(),
+            Type::U64 => u64::from_le_bytes(reader.bytes()?).into(),
+            Type::I64 => i64::from_le_bytes(reader.bytes()?).into(),
+            // JSON numbers lose precision past 2^53 in most readers, let alone 2^64
+            Type::U128 => u128::from_le_bytes(reader.bytes()?).to_string().into(),
+            Type::I128 => i128::from_le_bytes(reader.bytes()?).to_string().into(),
+            Type::F32 => f32::from_le_bytes(reader.bytes()?).into(),
+            Type::F64 => f64::from_le_bytes(reader.bytes()?).into(),
+            Type::String => reader.string()?.into(),
+            Type::Bytes => {
+                let len = reader.u32()? as usize;
+                STANDARD.encode(reader.take(len)?).into()
+            }
+            Type::Pubkey => keys.format(&reader.bytes()?).into(),
+            Type::Option(inner) => match reader.bytes::<1>()? {
+                [0] => Value::Null,
+                [1] => self.decode(inner, reader, keys, depth + 1)?,
+                _ => return Err(DecodeError::InvalidField("option tag")),
+            },
+            Type::COption(inner) => match reader.u32()? {
+                0 => Value::Null,
+                1 => self.decode(inner, reader, keys, depth + 1)?,
+                _ => return Err(DecodeError::InvalidField("coption tag")),
+            },
+            Type::Vec(inner) => {
+                let len = reader.u32()? as usize;
+                // every element takes at least a byte, so a garbage length fails fast
+                if len > reader.remaining() {
+                    return Err(DecodeError::InvalidField("vec length"));
+                }
+                self.decode_items(inner, len, reader, keys, depth)?
+            }
+            Type::Array(inner, len) => self.decode_items(inner, *len, reader, keys, depth)?,
+            Type::Defined(name) => match &self.0[name] {
+                TypeDef::Struct(fields) => self.decode_fields(fields, reader, keys, depth + 1)?,
+                TypeDef::Enum(variants) => {
+                    let [index] = reader.bytes::<1>()?;
+                    let (name, fields) = variants
+                        .get(index as usize)
+                        .ok_or(DecodeError::InvalidField("enum variant"))?;
+                    match fields {
+                        Fields::Unit => Value::from(name.as_str()),
+                        _ => {
+                            let value = self.decode_fields(fields, reader, keys, depth + 1)?;
+                            Value::Object(Map::from_iter([(name.clone(), value)]))
+                        }
+                    }
+                }
+            },
+        })
+    }
+
+    fn decode_items(
+        &self,
+        ty: &Type,
+        len: usize,
+        reader: &mut Reader,
+        keys: &Keys,
+        depth: usize,
+    ) -> Result<Value, DecodeError> {
+        (0..len)
+            .map(|_| self.decode(ty, reader, keys, depth + 1))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array)
+    }
+
+    /// Named fields as an object, tuple fields as an array.
+    fn decode_fields(
+        &self,
+        fields: &Fields,
+        reader: &mut Reader,
+        keys: &Keys,
+        depth: usize,
+    ) -> Result<Value, DecodeError> {
+        Ok(match fields {
+            Fields::Named(fields) => {
+                let mut object = Map::new();
+                for (name, ty) in fields {
+                    object.insert(name.clone(), self.decode(ty, reader, keys, depth)?);
+                }
+                Value::Object(object)
+            }
+            Fields::Tuple(types) => Value::Array(
+                types
+                    .iter()
+                    .map(|ty| self.decode(ty, reader, keys, depth))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Fields::Unit => Value::Object(Map::new()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::TokenAmounts;
+    use crate::testing::{TempPath, TestAccount};
+    use serde_json::json;
+    use solana_program::pubkey::Pubkey;
+
+    fn schema_file(name: &str, contents: &str) -> TempPath {
+        TempPath::file(&format!("{}.json", name), contents)
+    }
+
+    fn borsh(name: &str, schema: Value) -> Schema {
+        Schema::borsh(&schema_file(name, &schema.to_string())).unwrap()
+    }
+
+    fn idl(name: &str, idl: Value) -> Schema {
+        Schema::idl(&schema_file(name, &idl.to_string())).unwrap()
+    }
+
+    fn decode(schema: &Schema, data: Vec<u8>) -> Result<Vec<String>, DecodeError> {
+        let account = TestAccount::new(Pubkey::default(), data);
+        schema.decode(&account.stored(), &Keys::new(None, TokenAmounts::U64))
+    }
+
+    fn decoded(schema: &Schema, data: Vec<u8>) -> Value {
+        let values = decode(schema, data).unwrap();
+        serde_json::from_str(values.last().unwrap()).unwrap()
+    }
+
+    fn invalid_message(schema: Result<Schema, SchemaError>) -> String {
+        match schema {
+            Err(SchemaError::Invalid(message)) => message,
+            other => panic!("expected an invalid schema, got {:?}", other),
+        }
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(s.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decodes_borsh_layouts() {
+        let schema = borsh(
+            "layout",
+            json!({
+                "offset": 8,
+                "fields": [
+                    { "name": "authority", "type": "pubkey" },
+                    { "name": "amount", "type": "u64" },
+                    { "name": "big", "type": "u128" },
+                    { "name": "flag", "type": "bool" },
+                    { "name": "label", "type": "string" },
+                    { "name": "blob", "type": "bytes" },
+                    { "name": "maybe", "type": { "option": "u16" } },
+                    { "name": "none", "type": { "coption": "u64" } },
+                    { "name": "list", "type": { "vec": "i8" } },
+                    { "name": "pair", "type": { "array": ["u8", 2] } },
+                    { "name": "side", "type": { "defined": "Side" } },
+                    { "name": "point", "type": { "defined": "Point" } },
+                ],
+                "types": [
+                    {
+                        "name": "Side",
+                        "type": {
+                            "kind": "enum",
+                            "variants": [
+                                { "name": "Bid" },
+                                { "name": "Ask", "fields": [{ "name": "price", "type": "u32" }] },
+                            ],
+                        },
+                    },
+                    { "name": "Point", "type": { "kind": "struct", "fields": ["i16", "i16"] } },
+                ],
+            }),
+        );
+        assert_eq!(schema.columns(), ["decoded"]);
+
+        let mut data = vec![0xff; 8];
+        data.extend([7; 32]);
+        data.extend(42u64.to_le_bytes());
+        data.extend(u128::MAX.to_le_bytes());
+        data.push(1);
+        data.extend(string("hi"));
+        data.extend(string("\x01\x02\x03"));
+        data.push(1);
+        data.extend(500u16.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(2u32.to_le_bytes());
+        data.extend([0xff, 5]);
+        data.extend([9, 8]);
+        data.push(1);
+        data.extend(77u32.to_le_bytes());
+        data.extend((-3i16).to_le_bytes());
+        data.extend(4i16.to_le_bytes());
+        assert_eq!(
+            decoded(&schema, data),
+            json!({
+                "authority": Pubkey::new_from_array([7; 32]).to_string(),
+                "amount": 42,
+                "big": u128::MAX.to_string(),
+                "flag": true,
+                "label": "hi",
+                "blob": "AQID",
+                "maybe": 500,
+                "none": null,
+                "list": [-1, 5],
+                "pair": [9, 8],
+                "side": { "Ask": { "price": 77 } },
+                "point": [-3, 4],
+            })
+        );
+
+        let unit_variant = borsh(
+            "unit-variant",
+            json!({
+                "fields": [{ "name": "side", "type": { "defined": "Side" } }],
+                "types": [{
+                    "name": "Side",
+                    "type": { "kind": "enum", "variants": [{ "name": "Bid" }, { "name": "Ask" }] },
+                }],
+            }),
+        );
+        assert_eq!(decoded(&unit_variant, vec![0]), json!({ "side": "Bid" }));
+    }
+
+    #[test]
+    fn decodes_idl_accounts_of_both_formats() {
+        // before Anchor 0.30: the layout in the account, an `account:<Name>` discriminator
+        let legacy = idl(
+            "legacy",
+            json!({
+                "accounts": [{
+                    "name": "Counter",
+                    "type": {
+                        "kind": "struct",
+                        "fields": [
+                            { "name": "count", "type": "u64" },
+                            { "name": "owner", "type": "publicKey" },
+                        ],
+                    },
+                }],
+            }),
+        );
+        assert_eq!(legacy.columns(), ["account_type", "decoded"]);
+        let mut data = anchor_account_discriminator("Counter").to_vec();
+        data.extend(3u64.to_le_bytes());
+        data.extend([7; 32]);
+        let values = decode(&legacy, data).unwrap();
+        assert_eq!(values[0], "Counter");
+        assert_eq!(
+            serde_json::from_str::<Value>(&values[1]).unwrap(),
+            json!({ "count": 3, "owner": Pubkey::new_from_array([7; 32]).to_string() })
+        );
+
+        // Anchor 0.30: explicit discriminators, layouts in `types`
+        let current = idl(
+            "current",
+            json!({
+                "accounts": [
+                    { "name": "Counter", "discriminator": [1, 2, 3] },
+                    { "name": "Switch", "discriminator": [1, 2, 4] },
+                ],
+                "types": [
+                    {
+                        "name": "Counter",
+                        "type": { "kind": "struct", "fields": [{ "name": "count", "type": "u64" }] },
+                    },
+                    {
+                        "name": "Switch",
+                        "type": {
+                            "kind": "struct",
+                            "fields": [{ "name": "mode", "type": { "defined": { "name": "Mode" } } }],
+                        },
+                    },
+                    {
+                        "name": "Mode",
+                        "type": { "kind": "enum", "variants": [{ "name": "Off" }, { "name": "On" }] },
+                    },
+                ],
+            }),
+        );
+        let values = decode(&current, vec![1, 2, 4, 1]).unwrap();
+        assert_eq!(values, ["Switch", r#"{"mode":"On"}"#]);
+        let mut data = vec![1, 2, 3];
+        data.extend(9u64.to_le_bytes());
+        assert_eq!(decoded(&current, data), json!({ "count": 9 }));
+        assert_eq!(
+            decode(&current, vec![1, 2, 5, 0]),
+            Err(DecodeError::WrongDiscriminator)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_borsh_schemas() {
+        let missing = TempPath::new("missing.json");
+        assert!(matches!(Schema::borsh(&missing), Err(SchemaError::Io(_))));
+        let not_json = schema_file("not-json", "{\"fields\": [");
+        assert!(matches!(
+            Schema::borsh(&not_json),
+            Err(SchemaError::Json(_))
+        ));
+
+        let borsh = |name: &str, schema: Value| {
+            invalid_message(Schema::borsh(&schema_file(name, &schema.to_string())))
+        };
+        let field = |ty: Value| json!({ "fields": [{ "name": "x", "type": ty }] });
+        assert_eq!(
+            borsh("offset", json!({ "offset": -1, "fields": [] })),
+            "\"offset\" must be a byte count"
+        );
+        assert_eq!(
+            borsh("tuple", json!({ "fields": ["u8"] })),
+            "\"fields\" must list {\"name\", \"type\"} objects"
+        );
+        assert_eq!(
+            borsh("fields", json!({ "fields": {} })),
+            "fields must be an array"
+        );
+        assert_eq!(
+            borsh("field", json!({ "fields": [{ "name": "x" }] })),
+            r#"invalid field {"name":"x"}"#
+        );
+        assert_eq!(
+            borsh("u256", field(json!("u256"))),
+            r#"unsupported type "u256""#
+        );
+        assert_eq!(
+            borsh("array", field(json!({ "array": ["u8"] }))),
+            r#"unsupported type {"array":["u8"]}"#
+        );
+        assert_eq!(
+            borsh("undefined", field(json!({ "defined": "Missing" }))),
+            "type Missing is not defined"
+        );
+        let mut union = field(json!("u8"));
+        union["types"] = json!([{ "name": "U", "type": { "kind": "union" } }]);
+        assert_eq!(borsh("union", union), "type U is not a struct or enum");
+        let mut no_variants = field(json!("u8"));
+        no_variants["types"] = json!([{ "name": "E", "type": { "kind": "enum" } }]);
+        assert_eq!(borsh("no-variants", no_variants), "enum E has no variants");
+    }
+
+    #[test]
+    fn rejects_malformed_idls() {
+        let not_json = schema_file("idl-not-json", "{\"accounts\": [");
+        assert!(matches!(Schema::idl(&not_json), Err(SchemaError::Json(_))));
+
+        let idl = |name: &str, idl: Value| {
+            invalid_message(Schema::idl(&schema_file(name, &idl.to_string())))
+        };
+        assert_eq!(
+            idl("no-accounts", json!({ "accounts": [] })),
+            "IDL has no \"accounts\""
+        );
+        assert_eq!(
+            idl(
+                "discriminator",
+                json!({ "accounts": [{ "name": "A", "discriminator": [256] }] })
+            ),
+            "invalid discriminator of A"
+        );
+        assert_eq!(
+            idl("no-type", json!({ "accounts": [{ "name": "A" }] })),
+            "account A has no type definition"
+        );
+        assert_eq!(
+            idl("no-name", json!({ "accounts": [{ "discriminator": [1] }] })),
+            r#"account without a name: {"discriminator":[1]}"#
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let schema =
+            |name: &str, ty: Value| borsh(name, json!({ "fields": [{ "name": "x", "type": ty }] }));
+        let invalid =
+            |field| -> Result<Vec<String>, DecodeError> { Err(DecodeError::InvalidField(field)) };
+
+        assert_eq!(
+            decode(&schema("bool", json!("bool")), vec![2]),
+            invalid("bool")
+        );
+        let option = schema("option", json!({ "option": "u8" }));
+        assert_eq!(decode(&option, vec![2, 0]), invalid("option tag"));
+        let coption = schema("coption", json!({ "coption": "u8" }));
+        assert_eq!(
+            decode(&coption, vec![2, 0, 0, 0, 0]),
+            invalid("coption tag")
+        );
+        let utf8 = schema("string", json!("string"));
+        assert_eq!(decode(&utf8, vec![1, 0, 0, 0, 0xff]), invalid("string"));
+        // a length past the end of the data fails before reading any element
+        let length = schema("vec", json!({ "vec": "u64" }));
+        assert_eq!(
+            decode(&length, vec![100, 0, 0, 0, 1, 2, 3]),
+            invalid("vec length")
+        );
+
+        let truncated = schema("u64", json!("u64"));
+        assert_eq!(
+            decode(&truncated, vec![0; 7]),
+            Err(DecodeError::Truncated(0))
+        );
+        let offset = borsh(
+            "offset-truncated",
+            json!({ "offset": 8, "fields": [{ "name": "x", "type": "u8" }] }),
+        );
+        assert_eq!(decode(&offset, vec![0; 8]), Err(DecodeError::Truncated(8)));
+
+        let enum_types = json!([{
+            "name": "E",
+            "type": { "kind": "enum", "variants": [{ "name": "A" }] },
+        }]);
+        let variant = borsh(
+            "variant",
+            json!({
+                "fields": [{ "name": "x", "type": { "defined": "E" } }],
+                "types": enum_types,
+            }),
+        );
+        assert_eq!(decode(&variant, vec![1]), invalid("enum variant"));
+
+        // a struct containing itself directly never reads a byte
+        let nested = borsh(
+            "nested",
+            json!({
+                "fields": [{ "name": "x", "type": { "defined": "Node" } }],
+                "types": [{
+                    "name": "Node",
+                    "type": {
+                        "kind": "struct",
+                        "fields": [{ "name": "next", "type": { "defined": "Node" } }],
+                    },
+                }],
+            }),
+        );
+        assert_eq!(decode(&nested, vec![]), invalid("type nesting"));
+    }
+}
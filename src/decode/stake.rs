@@ -0,0 +1,267 @@
+use super::{expect_len, expect_owner, DecodeError, Keys, Layout};
+
+use solana_program::pubkey;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+pub(super) const STAKE_PROGRAM_ID: Pubkey = pubkey!("Stake11111111111111111111111111111111111111");
+
+/// Bincode `StakeStateV2`: a u32 tag, `Meta`, then `Stake` and flags for delegated accounts.
+const STAKE_LEN: usize = 200;
+const STATE_INITIALIZED: u32 = 1;
+const STATE_STAKE: u32 = 2;
+
+/// `deactivation_epoch` of a delegation that was never deactivated.
+const NOT_DEACTIVATED: u64 = u64::MAX;
+
+pub(super) const STAKE_COLUMNS: &[&str] = &[
+    "state",
+    "staker",
+    "withdrawer",
+    "rent_exempt_reserve",
+    "lockup_unix_timestamp",
+    "lockup_epoch",
+    "lockup_custodian",
+    "voter",
+    "stake",
+    "activation_epoch",
+    "deactivation_epoch",
+    "credits_observed",
+];
+
+/// The delegation of a stake account, as summed by `--summarize-stake`.
+pub struct Delegation {
+    pub voter: Pubkey,
+    pub stake: u64,
+    /// Epoch the stake was deactivated in, if it was.
+    pub deactivation_epoch: Option<u64>,
+}
+
+/// The delegation of `account`, or `None` for other accounts and undelegated stake accounts.
+pub fn delegation(account: &StoredAccountMeta) -> Option<Delegation> {
+    expect_owner(account, &[STAKE_PROGRAM_ID]).ok()?;
+    expect_len(account, STAKE_LEN).ok()?;
+    let data = Layout(account.data);
+    if data.u32(0).ok()? != STATE_STAKE {
+        return None;
+    }
+    let deactivation_epoch = data.u64(172).ok()?;
+    Some(Delegation {
+        voter: Pubkey::new_from_array(data.bytes(124).ok()?),
+        stake: data.u64(156).ok()?,
+        deactivation_epoch: (deactivation_epoch != NOT_DEACTIVATED).then_some(deactivation_epoch),
+    })
+}
+
+pub(super) fn decode_stake(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[STAKE_PROGRAM_ID])?;
+    expect_len(account, STAKE_LEN)?;
+    let data = Layout(account.data);
+    let state = match data.u32(0)? {
+        0 => return Err(DecodeError::Uninitialized),
+        STATE_INITIALIZED => "initialized",
+        STATE_STAKE => "delegated",
+        3 => return Err(DecodeError::WrongDiscriminator), // rewards pool
+        _ => return Err(DecodeError::InvalidField("state")),
+    };
+    let mut values = vec![
+        state.to_string(),
+        keys.format(&data.bytes(12)?),
+        keys.format(&data.bytes(44)?),
+        data.u64(4)?.to_string(),
+        data.i64(76)?.to_string(),
+        data.u64(84)?.to_string(),
+        keys.format(&data.bytes(92)?),
+    ];
+    if state == "delegated" {
+        let deactivation_epoch = data.u64(172)?;
+        values.extend([
+            keys.format(&data.bytes(124)?),
+            data.u64(156)?.to_string(),
+            data.u64(164)?.to_string(),
+            match deactivation_epoch {
+                NOT_DEACTIVATED => String::new(),
+                epoch => epoch.to_string(),
+            },
+            data.u64(188)?.to_string(),
+        ]);
+    } else {
+        values.resize(STAKE_COLUMNS.len(), String::new());
+    }
+    Ok(values)
+}
+
+pub(super) fn validate_stake(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let mut anomalies = vec![];
+    let rent_exempt_reserve = data.u64(4)?;
+    if account.account_meta.lamports < rent_exempt_reserve {
+        anomalies.push("below_rent_exempt_reserve");
+    }
+    if data.u32(0)? == STATE_STAKE {
+        let stake = data.u64(156)?;
+        let (activation_epoch, deactivation_epoch) = (data.u64(164)?, data.u64(172)?);
+        if deactivation_epoch != NOT_DEACTIVATED && deactivation_epoch < activation_epoch {
+            anomalies.push("deactivated_before_activation");
+        }
+        if account.account_meta.lamports < stake.saturating_add(rent_exempt_reserve) {
+            anomalies.push("stake_exceeds_balance");
+        }
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::TokenAmounts;
+    use crate::testing::{put, TestAccount};
+
+    struct Authorities {
+        staker: Pubkey,
+        withdrawer: Pubkey,
+        voter: Pubkey,
+    }
+
+    fn authorities() -> Authorities {
+        Authorities {
+            staker: Pubkey::new_unique(),
+            withdrawer: Pubkey::new_unique(),
+            voter: Pubkey::new_unique(),
+        }
+    }
+
+    fn stake_data(state: u32, keys: &Authorities, deactivation_epoch: u64) -> Vec<u8> {
+        let mut data = vec![0; STAKE_LEN];
+        put(&mut data, 0, &state.to_le_bytes());
+        put(&mut data, 4, &2_282_880u64.to_le_bytes());
+        put(&mut data, 12, &keys.staker.to_bytes());
+        put(&mut data, 44, &keys.withdrawer.to_bytes());
+        put(&mut data, 76, &(-1i64).to_le_bytes());
+        put(&mut data, 84, &9u64.to_le_bytes());
+        put(&mut data, 124, &keys.voter.to_bytes());
+        put(&mut data, 156, &500_000_000u64.to_le_bytes());
+        put(&mut data, 164, &400u64.to_le_bytes());
+        put(&mut data, 172, &deactivation_epoch.to_le_bytes());
+        put(&mut data, 188, &77u64.to_le_bytes());
+        data
+    }
+
+    fn decode(data: Vec<u8>) -> Result<Vec<String>, DecodeError> {
+        let keys = Keys::new(None, TokenAmounts::U64);
+        decode_stake(&TestAccount::new(STAKE_PROGRAM_ID, data).stored(), &keys)
+    }
+
+    #[test]
+    fn decodes_delegated_stake() {
+        let keys = authorities();
+        let values = decode(stake_data(STATE_STAKE, &keys, NOT_DEACTIVATED)).unwrap();
+        assert_eq!(
+            values,
+            [
+                "delegated".to_string(),
+                keys.staker.to_string(),
+                keys.withdrawer.to_string(),
+                "2282880".to_string(),
+                "-1".to_string(),
+                "9".to_string(),
+                Pubkey::default().to_string(),
+                keys.voter.to_string(),
+                "500000000".to_string(),
+                "400".to_string(),
+                String::new(),
+                "77".to_string(),
+            ]
+        );
+        let values = decode(stake_data(STATE_STAKE, &keys, 450)).unwrap();
+        assert_eq!(values[10], "450");
+    }
+
+    #[test]
+    fn decodes_initialized_stake_without_a_delegation() {
+        let keys = authorities();
+        let values = decode(stake_data(STATE_INITIALIZED, &keys, 450)).unwrap();
+        assert_eq!(values.len(), STAKE_COLUMNS.len());
+        assert_eq!(values[0], "initialized");
+        assert_eq!(values[1], keys.staker.to_string());
+        assert!(values[7..].iter().all(String::is_empty));
+    }
+
+    #[test]
+    fn rejects_malformed_stake_accounts() {
+        let keys = authorities();
+        assert_eq!(
+            decode(stake_data(0, &keys, 0)),
+            Err(DecodeError::Uninitialized)
+        );
+        assert_eq!(
+            decode(stake_data(3, &keys, 0)),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        assert_eq!(
+            decode(stake_data(4, &keys, 0)),
+            Err(DecodeError::InvalidField("state"))
+        );
+        assert_eq!(
+            decode(vec![0; STAKE_LEN - 1]),
+            Err(DecodeError::WrongSize {
+                expected: STAKE_LEN,
+                actual: STAKE_LEN - 1
+            })
+        );
+        let account = TestAccount::new(Pubkey::default(), stake_data(STATE_STAKE, &keys, 0));
+        assert_eq!(
+            decode_stake(&account.stored(), &Keys::new(None, TokenAmounts::U64)),
+            Err(DecodeError::WrongOwner(Pubkey::default()))
+        );
+    }
+
+    #[test]
+    fn reads_delegations() {
+        let keys = authorities();
+        let delegated = |data| delegation(&TestAccount::new(STAKE_PROGRAM_ID, data).stored());
+
+        let active = delegated(stake_data(STATE_STAKE, &keys, NOT_DEACTIVATED)).unwrap();
+        assert_eq!(active.voter, keys.voter);
+        assert_eq!(active.stake, 500_000_000);
+        assert_eq!(active.deactivation_epoch, None);
+        let deactivated = delegated(stake_data(STATE_STAKE, &keys, 450)).unwrap();
+        assert_eq!(deactivated.deactivation_epoch, Some(450));
+
+        assert!(delegated(stake_data(STATE_INITIALIZED, &keys, 0)).is_none());
+        assert!(delegated(vec![0; 100]).is_none());
+        let other = TestAccount::new(Pubkey::default(), stake_data(STATE_STAKE, &keys, 0));
+        assert!(delegation(&other.stored()).is_none());
+    }
+
+    #[test]
+    fn flags_stake_anomalies() {
+        let keys = authorities();
+        let validate = |data, lamports| {
+            let mut account = TestAccount::new(STAKE_PROGRAM_ID, data);
+            account.account_meta.lamports = lamports;
+            validate_stake(&account.stored())
+        };
+        let balance = 2_282_880 + 500_000_000;
+        assert_eq!(
+            validate(stake_data(STATE_STAKE, &keys, NOT_DEACTIVATED), balance),
+            Ok(vec![])
+        );
+        assert_eq!(
+            validate(stake_data(STATE_STAKE, &keys, 399), balance - 1),
+            Ok(vec![
+                "deactivated_before_activation",
+                "stake_exceeds_balance"
+            ])
+        );
+        assert_eq!(
+            validate(stake_data(STATE_INITIALIZED, &keys, 0), 2_282_879),
+            Ok(vec!["below_rent_exempt_reserve"])
+        );
+    }
+}
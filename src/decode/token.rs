@@ -0,0 +1,326 @@
+use super::{expect_len, expect_owner, DecodeError, Keys, Layout};
+
+use solana_program::pubkey;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+pub(super) const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub(super) const TOKEN_2022_PROGRAM_ID: Pubkey =
+    pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// spl-token `Multisig`: m, n, is_initialized, then 11 signer slots.
+pub(super) const MULTISIG_LEN: usize = 355;
+const MULTISIG_MAX_SIGNERS: usize = 11;
+
+pub(super) const MULTISIG_COLUMNS: &[&str] = &["m", "n", "signers"];
+
+pub(super) fn decode_multisig(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID])?;
+    expect_len(account, MULTISIG_LEN)?;
+    let data = Layout(account.data);
+    let (m, n, is_initialized) = (data.u8(0)?, data.u8(1)?, data.u8(2)?);
+    if is_initialized != 1 {
+        return Err(DecodeError::Uninitialized);
+    }
+    if n as usize > MULTISIG_MAX_SIGNERS {
+        return Err(DecodeError::InvalidField("n"));
+    }
+    // only the first n slots are in use, the rest are zeroed
+    let signers = (0..n as usize)
+        .map(|i| data.bytes::<32>(3 + 32 * i).map(|key| keys.format(&key)))
+        .collect::<Result<Vec<String>, DecodeError>>()?;
+    Ok(vec![m.to_string(), n.to_string(), signers.join(" ")])
+}
+
+pub(super) fn validate_multisig(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = account.data;
+    let (m, n) = (data[0], data[1] as usize);
+    let slots: Vec<&[u8]> = data[3..].chunks_exact(32).collect();
+    let (used, unused) = slots.split_at(n);
+    let mut anomalies = vec![];
+    if m == 0 {
+        anomalies.push("m_is_zero");
+    }
+    if m as usize > n {
+        anomalies.push("m_exceeds_n");
+    }
+    if used.iter().any(|key| key.iter().all(|b| *b == 0)) {
+        anomalies.push("empty_signer");
+    }
+    if (1..used.len()).any(|i| used[..i].contains(&used[i])) {
+        anomalies.push("duplicate_signer");
+    }
+    if unused.iter().any(|key| key.iter().any(|b| *b != 0)) {
+        anomalies.push("unused_signer_slot_set");
+    }
+    Ok(anomalies)
+}
+
+/// spl-token `Account`; Token-2022 accounts with extensions are longer and carry the
+/// `AccountType::Account` byte right after it.
+pub(super) const ACCOUNT_LEN: usize = 165;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+pub(super) const ACCOUNT_COLUMNS: &[&str] = &[
+    "mint",
+    "token_owner",
+    "amount",
+    "delegate",
+    "delegated_amount",
+    "state",
+    "is_native",
+    "close_authority",
+];
+
+pub(super) fn decode_account(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID])?;
+    if account.data.len() > ACCOUNT_LEN && account.account_meta.owner == TOKEN_2022_PROGRAM_ID {
+        if account.data[ACCOUNT_LEN] != ACCOUNT_TYPE_ACCOUNT {
+            return Err(DecodeError::WrongDiscriminator);
+        }
+    } else {
+        expect_len(account, ACCOUNT_LEN)?;
+    }
+    let data = Layout(account.data);
+    let state = match data.u8(108)? {
+        0 => return Err(DecodeError::Uninitialized),
+        1 => "initialized",
+        2 => "frozen",
+        _ => return Err(DecodeError::InvalidField("state")),
+    };
+    let delegate = coption(&data, 72, "delegate")?.map(|key: [u8; 32]| keys.format(&key));
+    let is_native = coption(&data, 109, "is_native")?.map(u64::from_le_bytes);
+    let close_authority =
+        coption(&data, 129, "close_authority")?.map(|key: [u8; 32]| keys.format(&key));
+    Ok(vec![
+        keys.format(&data.bytes(0)?),
+        keys.format(&data.bytes(32)?),
+        data.u64(64)?.to_string(),
+        delegate.unwrap_or_default(),
+        data.u64(121)?.to_string(),
+        state.to_string(),
+        is_native.map(|rent| rent.to_string()).unwrap_or_default(),
+        close_authority.unwrap_or_default(),
+    ])
+}
+
+/// `COption<T>`: a u32 tag, then the value's bytes whether or not it is set.
+fn coption<const N: usize>(
+    data: &Layout,
+    offset: usize,
+    field: &'static str,
+) -> Result<Option<[u8; N]>, DecodeError> {
+    match data.u32(offset)? {
+        0 => Ok(None),
+        1 => data.bytes(offset + 4).map(Some),
+        _ => Err(DecodeError::InvalidField(field)),
+    }
+}
+
+pub(super) fn validate_account(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let data = Layout(account.data);
+    let has_delegate = data.u32(72)? == 1;
+    let delegated_amount = data.u64(121)?;
+    let mut anomalies = vec![];
+    // revoking a delegate, or spending its whole allowance, clears both
+    if has_delegate && delegated_amount == 0 {
+        anomalies.push("delegate_without_allowance");
+    }
+    if !has_delegate && delegated_amount != 0 {
+        anomalies.push("allowance_without_delegate");
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::TokenAmounts;
+    use crate::testing::{put, TestAccount};
+
+    fn keys() -> Keys<'static> {
+        Keys::new(None, TokenAmounts::U64)
+    }
+
+    /// An initialized token account holding 42 with a native rent reserve and no delegate.
+    fn token_account(owner: Pubkey, mint: &Pubkey, token_owner: &Pubkey) -> TestAccount {
+        let mut data = vec![0; ACCOUNT_LEN];
+        put(&mut data, 0, &mint.to_bytes());
+        put(&mut data, 32, &token_owner.to_bytes());
+        put(&mut data, 64, &42u64.to_le_bytes());
+        data[108] = 1;
+        put(&mut data, 109, &1u32.to_le_bytes());
+        put(&mut data, 113, &2_039_280u64.to_le_bytes());
+        TestAccount::new(owner, data)
+    }
+
+    #[test]
+    fn decodes_token_accounts() {
+        let (mint, token_owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut account = token_account(TOKEN_PROGRAM_ID, &mint, &token_owner);
+        let expected = [
+            mint.to_string(),
+            token_owner.to_string(),
+            "42".to_string(),
+            String::new(),
+            "0".to_string(),
+            "initialized".to_string(),
+            "2039280".to_string(),
+            String::new(),
+        ];
+        assert_eq!(
+            decode_account(&account.stored(), &keys()),
+            Ok(expected.to_vec())
+        );
+        assert_eq!(validate_account(&account.stored()), Ok(vec![]));
+
+        let delegate = Pubkey::new_unique();
+        account.data[108] = 2;
+        put(&mut account.data, 72, &1u32.to_le_bytes());
+        put(&mut account.data, 76, &delegate.to_bytes());
+        put(&mut account.data, 121, &7u64.to_le_bytes());
+        let values = decode_account(&account.stored(), &keys()).unwrap();
+        assert_eq!(values[3], delegate.to_string());
+        assert_eq!(values[4], "7");
+        assert_eq!(values[5], "frozen");
+
+        put(&mut account.data, 121, &0u64.to_le_bytes());
+        assert_eq!(
+            validate_account(&account.stored()),
+            Ok(vec!["delegate_without_allowance"])
+        );
+    }
+
+    #[test]
+    fn decodes_token_2022_accounts_with_extensions() {
+        let (mint, token_owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut account = token_account(TOKEN_2022_PROGRAM_ID, &mint, &token_owner);
+        account.data.extend([ACCOUNT_TYPE_ACCOUNT, 7, 0, 0, 0]);
+        assert!(decode_account(&account.stored(), &keys()).is_ok());
+
+        account.data[ACCOUNT_LEN] = 1;
+        assert_eq!(
+            decode_account(&account.stored(), &keys()),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        // spl-token accounts never carry extensions
+        let mut legacy = token_account(TOKEN_PROGRAM_ID, &mint, &token_owner);
+        legacy.data.push(ACCOUNT_TYPE_ACCOUNT);
+        assert_eq!(
+            decode_account(&legacy.stored(), &keys()),
+            Err(DecodeError::WrongSize {
+                expected: ACCOUNT_LEN,
+                actual: ACCOUNT_LEN + 1
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_token_accounts() {
+        let (mint, token_owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let decode = |account: &TestAccount| decode_account(&account.stored(), &keys());
+
+        let other = Pubkey::new_unique();
+        let account = token_account(other, &mint, &token_owner);
+        assert_eq!(decode(&account), Err(DecodeError::WrongOwner(other)));
+
+        let mut account = token_account(TOKEN_PROGRAM_ID, &mint, &token_owner);
+        account.data.pop();
+        assert_eq!(
+            decode(&account),
+            Err(DecodeError::WrongSize {
+                expected: ACCOUNT_LEN,
+                actual: ACCOUNT_LEN - 1
+            })
+        );
+
+        for (state, error) in [
+            (0, DecodeError::Uninitialized),
+            (3, DecodeError::InvalidField("state")),
+        ] {
+            let mut account = token_account(TOKEN_PROGRAM_ID, &mint, &token_owner);
+            account.data[108] = state;
+            assert_eq!(decode(&account), Err(error));
+        }
+        for (offset, field) in [
+            (72, "delegate"),
+            (109, "is_native"),
+            (129, "close_authority"),
+        ] {
+            let mut account = token_account(TOKEN_PROGRAM_ID, &mint, &token_owner);
+            put(&mut account.data, offset, &2u32.to_le_bytes());
+            assert_eq!(decode(&account), Err(DecodeError::InvalidField(field)));
+        }
+    }
+
+    fn multisig(m: u8, signers: &[Pubkey]) -> TestAccount {
+        let mut data = vec![0; MULTISIG_LEN];
+        data[0] = m;
+        data[1] = signers.len() as u8;
+        data[2] = 1;
+        for (i, signer) in signers.iter().enumerate() {
+            put(&mut data, 3 + 32 * i, &signer.to_bytes());
+        }
+        TestAccount::new(TOKEN_PROGRAM_ID, data)
+    }
+
+    #[test]
+    fn decodes_multisigs() {
+        let signers = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let account = multisig(2, &signers);
+        let joined = signers.map(|signer| signer.to_string()).join(" ");
+        assert_eq!(
+            decode_multisig(&account.stored(), &keys()),
+            Ok(vec!["2".to_string(), "3".to_string(), joined])
+        );
+        assert_eq!(validate_multisig(&account.stored()), Ok(vec![]));
+
+        let account = multisig(0, &[signers[0], signers[0]]);
+        assert_eq!(
+            validate_multisig(&account.stored()),
+            Ok(vec!["m_is_zero", "duplicate_signer"])
+        );
+        let mut account = multisig(3, &signers[..2]);
+        put(&mut account.data, 3 + 32 * 5, &[1]);
+        assert_eq!(
+            validate_multisig(&account.stored()),
+            Ok(vec!["m_exceeds_n", "unused_signer_slot_set"])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_multisigs() {
+        let decode = |account: &TestAccount| decode_multisig(&account.stored(), &keys());
+        let mut account = multisig(1, &[Pubkey::new_unique()]);
+        account.data[2] = 0;
+        assert_eq!(decode(&account), Err(DecodeError::Uninitialized));
+
+        let mut account = multisig(1, &[Pubkey::new_unique()]);
+        account.data[1] = MULTISIG_MAX_SIGNERS as u8 + 1;
+        assert_eq!(decode(&account), Err(DecodeError::InvalidField("n")));
+
+        let mut account = multisig(1, &[Pubkey::new_unique()]);
+        account.data.push(0);
+        assert_eq!(
+            decode(&account),
+            Err(DecodeError::WrongSize {
+                expected: MULTISIG_LEN,
+                actual: MULTISIG_LEN + 1
+            })
+        );
+    }
+}
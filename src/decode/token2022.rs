@@ -0,0 +1,495 @@
+use super::token::{
+    decode_account, ACCOUNT_COLUMNS, ACCOUNT_LEN, MULTISIG_LEN, TOKEN_2022_PROGRAM_ID,
+};
+use super::{expect_owner, json_object, DecodeError, Keys, Layout, Reader};
+
+use serde_json::{json, Value};
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+
+/// spl-token `Mint`; Token-2022 mints with extensions are padded to `ACCOUNT_LEN` before
+/// their `AccountType::Mint` byte.
+const MINT_LEN: usize = 82;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+const MINT_COLUMNS: &[&str] = &["mint_authority", "supply", "decimals", "freeze_authority"];
+
+/// `ExtensionType` names, indexed by their TLV type.
+const EXTENSION_NAMES: &[&str] = &[
+    "uninitialized",
+    "transfer_fee_config",
+    "transfer_fee_amount",
+    "mint_close_authority",
+    "confidential_transfer_mint",
+    "confidential_transfer_account",
+    "default_account_state",
+    "immutable_owner",
+    "memo_transfer",
+    "non_transferable",
+    "interest_bearing_config",
+    "cpi_guard",
+    "permanent_delegate",
+    "non_transferable_account",
+    "transfer_hook",
+    "transfer_hook_account",
+    "confidential_transfer_fee_config",
+    "confidential_transfer_fee_amount",
+    "metadata_pointer",
+    "token_metadata",
+    "group_pointer",
+    "token_group",
+    "group_member_pointer",
+    "token_group_member",
+    "confidential_mint_burn",
+    "scaled_ui_amount",
+    "pausable",
+    "pausable_account",
+];
+
+const TRANSFER_FEE_CONFIG: u16 = 1;
+const MAX_FEE_BASIS_POINTS: u16 = 10_000;
+
+pub(super) const TOKEN_2022_COLUMNS: &[&str] = &["account_type", "base", "extensions"];
+
+pub(super) fn decode_token_2022(
+    account: &StoredAccountMeta,
+    keys: &Keys,
+) -> Result<Vec<String>, DecodeError> {
+    expect_owner(account, &[TOKEN_2022_PROGRAM_ID])?;
+    let data = account.data;
+    let account_type = match data.len() {
+        MINT_LEN => ACCOUNT_TYPE_MINT,
+        ACCOUNT_LEN => ACCOUNT_TYPE_ACCOUNT,
+        // the program pads extended accounts so they never collide with a multisig
+        MULTISIG_LEN => return Err(DecodeError::WrongDiscriminator),
+        len if len > ACCOUNT_LEN => data[ACCOUNT_LEN],
+        actual => {
+            return Err(DecodeError::WrongSize {
+                expected: ACCOUNT_LEN,
+                actual,
+            })
+        }
+    };
+    let (account_type, base) = match account_type {
+        ACCOUNT_TYPE_MINT => ("mint", json_object(MINT_COLUMNS, &decode_mint(data, keys)?)),
+        ACCOUNT_TYPE_ACCOUNT => (
+            "account",
+            json_object(ACCOUNT_COLUMNS, &decode_account(account, keys)?),
+        ),
+        _ => return Err(DecodeError::WrongDiscriminator),
+    };
+    let extensions = extensions(data)?
+        .into_iter()
+        .map(|(extension_type, value)| decode_extension(extension_type, value, keys))
+        .collect::<Result<Vec<Value>, DecodeError>>()?;
+    Ok(vec![
+        account_type.to_string(),
+        base,
+        Value::Array(extensions).to_string(),
+    ])
+}
+
+fn decode_mint(data: &[u8], keys: &Keys) -> Result<Vec<String>, DecodeError> {
+    let data = Layout(data);
+    if data.u8(45)? != 1 {
+        return Err(DecodeError::Uninitialized);
+    }
+    let authority = |offset: usize, field: &'static str| match data.u32(offset)? {
+        0 => Ok(String::new()),
+        1 => Ok(keys.format(&data.bytes(offset + 4)?)),
+        _ => Err(DecodeError::InvalidField(field)),
+    };
+    Ok(vec![
+        authority(0, "mint_authority")?,
+        keys.amount(data.u64(36)?, data.u8(44)?),
+        data.u8(44)?.to_string(),
+        authority(46, "freeze_authority")?,
+    ])
+}
+
+/// The `(type, value)` TLV entries after the account type byte, up to the first
+/// uninitialized one.
+fn extensions(data: &[u8]) -> Result<Vec<(u16, &[u8])>, DecodeError> {
+    let mut entries = vec![];
+    let layout = Layout(data);
+    let mut offset = ACCOUNT_LEN + 1;
+    while offset + 4 <= data.len() {
+        let extension_type = layout.u16(offset)?;
+        if extension_type == 0 {
+            break;
+        }
+        let start = offset + 4;
+        let end = start + layout.u16(offset + 2)? as usize;
+        let value = data.get(start..end).ok_or(DecodeError::Truncated(end))?;
+        entries.push((extension_type, value));
+        offset = end;
+    }
+    Ok(entries)
+}
+
+/// `{"type": NAME, ...fields}`; extensions without a readable layout (the confidential
+/// transfer ones hold ElGamal ciphertexts) only report their length.
+fn decode_extension(extension_type: u16, value: &[u8], keys: &Keys) -> Result<Value, DecodeError> {
+    let name = EXTENSION_NAMES
+        .get(extension_type as usize)
+        .copied()
+        .unwrap_or("unknown");
+    let invalid = |_e: DecodeError| DecodeError::InvalidField("extension");
+    let data = Layout(value);
+    let key = |offset: usize| {
+        data.bytes(offset)
+            .map(|key| optional_key(keys, key))
+            .map_err(invalid)
+    };
+    let u64 = |offset: usize| data.u64(offset).map_err(invalid);
+    let i64 = |offset: usize| data.i64(offset).map_err(invalid);
+    let u16 = |offset: usize| data.u16(offset).map_err(invalid);
+    let i16 = |offset: usize| data.i16(offset).map_err(invalid);
+    let bool = |offset: usize| data.u8(offset).map(|b| b != 0).map_err(invalid);
+    let f64 = |offset: usize| data.u64(offset).map(f64::from_bits).map_err(invalid);
+    let transfer_fee = |offset: usize| -> Result<Value, DecodeError> {
+        Ok(json!({
+            "epoch": u64(offset)?,
+            "maximum_fee": u64(offset + 8)?,
+            "transfer_fee_basis_points": u16(offset + 16)?,
+        }))
+    };
+    let mut fields = match extension_type {
+        1 => json!({
+            "transfer_fee_config_authority": key(0)?,
+            "withdraw_withheld_authority": key(32)?,
+            "withheld_amount": u64(64)?,
+            "older_transfer_fee": transfer_fee(72)?,
+            "newer_transfer_fee": transfer_fee(90)?,
+        }),
+        2 => json!({ "withheld_amount": u64(0)? }),
+        3 => json!({ "close_authority": key(0)? }),
+        6 => {
+            let state = match data.u8(0).map_err(invalid)? {
+                0 => "uninitialized",
+                1 => "initialized",
+                2 => "frozen",
+                _ => return Err(DecodeError::InvalidField("default_account_state")),
+            };
+            json!({ "state": state })
+        }
+        8 => json!({ "require_incoming_transfer_memos": bool(0)? }),
+        10 => json!({
+            "rate_authority": key(0)?,
+            "initialization_timestamp": i64(32)?,
+            "pre_update_average_rate": i16(40)?,
+            "last_update_timestamp": i64(42)?,
+            "current_rate": i16(50)?,
+        }),
+        11 => json!({ "lock_cpi": bool(0)? }),
+        12 => json!({ "delegate": key(0)? }),
+        14 => json!({ "authority": key(0)?, "program_id": key(32)? }),
+        15 => json!({ "transferring": bool(0)? }),
+        18 => json!({ "authority": key(0)?, "metadata_address": key(32)? }),
+        19 => {
+            token_metadata(value, keys).map_err(|_e| DecodeError::InvalidField("token_metadata"))?
+        }
+        20 => json!({ "authority": key(0)?, "group_address": key(32)? }),
+        21 => json!({
+            "update_authority": key(0)?,
+            "mint": key(32)?,
+            "size": u64(64)?,
+            "max_size": u64(72)?,
+        }),
+        22 => json!({ "authority": key(0)?, "member_address": key(32)? }),
+        23 => json!({ "mint": key(0)?, "group": key(32)?, "member_number": u64(64)? }),
+        25 => json!({
+            "authority": key(0)?,
+            "multiplier": f64(32)?,
+            "new_multiplier_effective_timestamp": i64(40)?,
+            "new_multiplier": f64(48)?,
+        }),
+        26 => json!({ "authority": key(0)?, "paused": bool(32)? }),
+        7 | 9 | 13 | 27 => json!({}),
+        _ => json!({ "length": value.len() }),
+    };
+    fields["type"] = Value::from(name);
+    if name == "unknown" {
+        fields["extension_type"] = Value::from(extension_type);
+    }
+    Ok(fields)
+}
+
+/// The variable-length `TokenMetadata` extension, Borsh-encoded.
+fn token_metadata(value: &[u8], keys: &Keys) -> Result<Value, DecodeError> {
+    let mut reader = Reader::new(value, 0);
+    let update_authority = optional_key(keys, reader.bytes()?);
+    let mint = optional_key(keys, reader.bytes()?);
+    let (name, symbol, uri) = (reader.string()?, reader.string()?, reader.string()?);
+    let mut additional_metadata = vec![];
+    for _ in 0..reader.u32()? {
+        additional_metadata.push(json!([reader.string()?, reader.string()?]));
+    }
+    Ok(json!({
+        "update_authority": update_authority,
+        "mint": mint,
+        "name": name,
+        "symbol": symbol,
+        "uri": uri,
+        "additional_metadata": additional_metadata,
+    }))
+}
+
+/// `OptionalNonZeroPubkey`: all zeroes for none.
+fn optional_key(keys: &Keys, key: [u8; 32]) -> Value {
+    match key.iter().all(|b| *b == 0) {
+        true => Value::Null,
+        false => Value::from(keys.format(&key)),
+    }
+}
+
+pub(super) fn validate_token_2022(
+    account: &StoredAccountMeta,
+) -> Result<Vec<&'static str>, DecodeError> {
+    let mut anomalies = vec![];
+    for (extension_type, value) in extensions(account.data)? {
+        if extension_type == TRANSFER_FEE_CONFIG {
+            let data = Layout(value);
+            let over = [72 + 16, 90 + 16].iter().any(|offset| {
+                data.u16(*offset)
+                    .is_ok_and(|bps| bps > MAX_FEE_BASIS_POINTS)
+            });
+            if over {
+                anomalies.push("transfer_fee_above_100_percent");
+            }
+        }
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::token::TOKEN_PROGRAM_ID;
+    use crate::decode::TokenAmounts;
+    use crate::testing::{put, TestAccount};
+    use solana_program::pubkey::Pubkey;
+
+    fn keys() -> Keys<'static> {
+        Keys::new(None, TokenAmounts::Decimal)
+    }
+
+    /// An initialized mint of 1.5 tokens with 6 decimals and a mint authority.
+    fn mint_data(authority: &Pubkey) -> Vec<u8> {
+        let mut data = vec![0; MINT_LEN];
+        put(&mut data, 0, &1u32.to_le_bytes());
+        put(&mut data, 4, &authority.to_bytes());
+        put(&mut data, 36, &1_500_000u64.to_le_bytes());
+        data[44] = 6;
+        data[45] = 1;
+        data
+    }
+
+    /// `data` padded to `ACCOUNT_LEN`, the account type byte, then `(type, value)` entries.
+    fn with_extensions(mut data: Vec<u8>, account_type: u8, entries: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        data.resize(ACCOUNT_LEN, 0);
+        data.push(account_type);
+        for (extension_type, value) in entries {
+            data.extend(extension_type.to_le_bytes());
+            data.extend((value.len() as u16).to_le_bytes());
+            data.extend(value);
+        }
+        data
+    }
+
+    fn decode(data: Vec<u8>) -> Result<Vec<String>, DecodeError> {
+        decode_token_2022(
+            &TestAccount::new(TOKEN_2022_PROGRAM_ID, data).stored(),
+            &keys(),
+        )
+    }
+
+    fn transfer_fee_config(authority: &Pubkey, newer_bps: u16) -> Vec<u8> {
+        let mut value = vec![0; 108];
+        put(&mut value, 0, &authority.to_bytes());
+        put(&mut value, 64, &9u64.to_le_bytes());
+        put(&mut value, 72, &100u64.to_le_bytes());
+        put(&mut value, 80, &5000u64.to_le_bytes());
+        put(&mut value, 88, &25u16.to_le_bytes());
+        put(&mut value, 90, &101u64.to_le_bytes());
+        put(&mut value, 98, &6000u64.to_le_bytes());
+        put(&mut value, 106, &newer_bps.to_le_bytes());
+        value
+    }
+
+    fn token_metadata_value(mint: &Pubkey) -> Vec<u8> {
+        let mut value = vec![0; 32];
+        value.extend(mint.to_bytes());
+        for s in ["Token", "TKN", "https://example.com/t.json"] {
+            value.extend((s.len() as u32).to_le_bytes());
+            value.extend(s.as_bytes());
+        }
+        value.extend(1u32.to_le_bytes());
+        for s in ["key", "value"] {
+            value.extend((s.len() as u32).to_le_bytes());
+            value.extend(s.as_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn decodes_plain_mints() {
+        let authority = Pubkey::new_unique();
+        let values = decode(mint_data(&authority)).unwrap();
+        assert_eq!(values[0], "mint");
+        let base: Value = serde_json::from_str(&values[1]).unwrap();
+        assert_eq!(
+            base,
+            json!({
+                "mint_authority": authority.to_string(),
+                "supply": "1.500000",
+                "decimals": "6",
+                "freeze_authority": "",
+            })
+        );
+        assert_eq!(values[2], "[]");
+    }
+
+    #[test]
+    fn decodes_mint_extensions() {
+        let (authority, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let data = with_extensions(
+            mint_data(&authority),
+            ACCOUNT_TYPE_MINT,
+            &[
+                (1, transfer_fee_config(&authority, 30)),
+                (6, vec![2]),
+                (12, vec![0; 32]),
+                (19, token_metadata_value(&mint)),
+                (9, vec![]),
+                (200, vec![1, 2, 3]),
+            ],
+        );
+        let values = decode(data).unwrap();
+        assert_eq!(values[0], "mint");
+        let extensions: Value = serde_json::from_str(&values[2]).unwrap();
+        assert_eq!(
+            extensions,
+            json!([
+                {
+                    "type": "transfer_fee_config",
+                    "transfer_fee_config_authority": authority.to_string(),
+                    "withdraw_withheld_authority": null,
+                    "withheld_amount": 9,
+                    "older_transfer_fee": {
+                        "epoch": 100,
+                        "maximum_fee": 5000,
+                        "transfer_fee_basis_points": 25,
+                    },
+                    "newer_transfer_fee": {
+                        "epoch": 101,
+                        "maximum_fee": 6000,
+                        "transfer_fee_basis_points": 30,
+                    },
+                },
+                { "type": "default_account_state", "state": "frozen" },
+                { "type": "permanent_delegate", "delegate": null },
+                {
+                    "type": "token_metadata",
+                    "update_authority": null,
+                    "mint": mint.to_string(),
+                    "name": "Token",
+                    "symbol": "TKN",
+                    "uri": "https://example.com/t.json",
+                    "additional_metadata": [["key", "value"]],
+                },
+                { "type": "non_transferable" },
+                { "type": "unknown", "extension_type": 200, "length": 3 },
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_extended_token_accounts() {
+        let mut data = vec![0; ACCOUNT_LEN];
+        put(&mut data, 64, &77u64.to_le_bytes());
+        data[108] = 1;
+        let data = with_extensions(data, ACCOUNT_TYPE_ACCOUNT, &[(7, vec![]), (0, vec![])]);
+        let values = decode(data).unwrap();
+        assert_eq!(values[0], "account");
+        let base: Value = serde_json::from_str(&values[1]).unwrap();
+        assert_eq!(base["amount"], "77");
+        assert_eq!(values[2], r#"[{"type":"immutable_owner"}]"#);
+    }
+
+    #[test]
+    fn flags_transfer_fees_above_100_percent() {
+        let authority = Pubkey::new_unique();
+        let validate = |bps| {
+            let data = with_extensions(
+                mint_data(&authority),
+                ACCOUNT_TYPE_MINT,
+                &[(1, transfer_fee_config(&authority, bps))],
+            );
+            validate_token_2022(&TestAccount::new(TOKEN_2022_PROGRAM_ID, data).stored())
+        };
+        assert_eq!(validate(MAX_FEE_BASIS_POINTS), Ok(vec![]));
+        assert_eq!(
+            validate(MAX_FEE_BASIS_POINTS + 1),
+            Ok(vec!["transfer_fee_above_100_percent"])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_token_2022_accounts() {
+        let mint = mint_data(&Pubkey::new_unique());
+        let extended = |entries: &[(u16, Vec<u8>)]| {
+            decode(with_extensions(mint.clone(), ACCOUNT_TYPE_MINT, entries))
+        };
+
+        let account = TestAccount::new(TOKEN_PROGRAM_ID, mint.clone());
+        assert_eq!(
+            decode_token_2022(&account.stored(), &keys()),
+            Err(DecodeError::WrongOwner(TOKEN_PROGRAM_ID))
+        );
+        assert_eq!(
+            decode(vec![0; MULTISIG_LEN]),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        assert_eq!(
+            decode(vec![0; 100]),
+            Err(DecodeError::WrongSize {
+                expected: ACCOUNT_LEN,
+                actual: 100
+            })
+        );
+        assert_eq!(
+            decode(with_extensions(mint.clone(), 3, &[])),
+            Err(DecodeError::WrongDiscriminator)
+        );
+        let mut uninitialized = mint.clone();
+        uninitialized[45] = 0;
+        assert_eq!(decode(uninitialized), Err(DecodeError::Uninitialized));
+        let mut freeze_authority = mint.clone();
+        put(&mut freeze_authority, 46, &2u32.to_le_bytes());
+        assert_eq!(
+            decode(freeze_authority),
+            Err(DecodeError::InvalidField("freeze_authority"))
+        );
+
+        // an entry claiming more bytes than the account has
+        let mut truncated = with_extensions(mint.clone(), ACCOUNT_TYPE_MINT, &[(3, vec![0; 32])]);
+        truncated.truncate(truncated.len() - 1);
+        let end = ACCOUNT_LEN + 1 + 4 + 32;
+        assert_eq!(decode(truncated), Err(DecodeError::Truncated(end)));
+        assert_eq!(
+            extended(&[(3, vec![0; 31])]),
+            Err(DecodeError::InvalidField("extension"))
+        );
+        assert_eq!(
+            extended(&[(6, vec![3])]),
+            Err(DecodeError::InvalidField("default_account_state"))
+        );
+        let mut metadata = token_metadata_value(&Pubkey::new_unique());
+        metadata.truncate(metadata.len() - 2);
+        assert_eq!(
+            extended(&[(19, metadata)]),
+            Err(DecodeError::InvalidField("token_metadata"))
+        );
+    }
+}
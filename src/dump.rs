@@ -0,0 +1,1029 @@
+use crate::anonymize::Anonymizer;
+use crate::append_vec::{append_vec_iter, AppendVec};
+use crate::columnar::ParquetDumper;
+use crate::csv::CsvDumper;
+use crate::decode::{DecodeErrors, DecodeFailure, DecodeMode, Decoded, Keys, TokenAmounts};
+use crate::encoding::{Base58TooLong, DataEncoding, EncodedData, LamportsFormat, MAX_BASE58_LEN};
+use crate::filter::{AccountFilter, MatchedBy};
+use crate::geyser::GeyserPluginSink;
+use crate::json::{JsonlDumper, LedgerToolJsonDumper};
+use crate::labels::Labels;
+use crate::output::{Encryption, Output};
+use crate::parallel::Candidates;
+use crate::pg::PostgresSink;
+use crate::project::Projection;
+use crate::registry::ProgramRegistry;
+use crate::stake_summary::StakeSummary;
+use crate::stats::Stats;
+
+use crossbeam::channel::{bounded, unbounded, Sender};
+use log::warn;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// Same JSON document as `agave-ledger-tool accounts --output json`
+    LedgerToolJson,
+    /// One JSON object per account and line
+    Jsonl,
+    /// Snappy-compressed Parquet with the default columns; needs --output
+    Parquet,
+}
+
+#[derive(Default)]
+pub struct DumpOptions {
+    pub noheader: bool,
+    /// Stop after this many matches.
+    pub first: Option<u64>,
+    /// Emit the first N data bytes as hex in `data_preview` instead of the full `data`.
+    pub preview_bytes: Option<usize>,
+    /// Emit only `(offset, length)` of the data, like RPC `dataSlice`.
+    pub data_slice: Option<(usize, usize)>,
+    /// Text encoding of the data in CSV and JSON records.
+    pub data_encoding: DataEncoding,
+    /// Lamports in CSV and JSONL records, stats and stake summaries.
+    pub lamports_as: LamportsFormat,
+    /// Custom output columns replacing the default record.
+    pub projection: Option<Projection>,
+    /// Tokenizes account pubkeys in the output.
+    pub anonymizer: Option<Anonymizer>,
+    /// Flush buffered output after every N records.
+    pub flush_every: Option<u64>,
+    /// Stop emitting accounts for an owner filter once it has matched this many.
+    pub limit_per_owner: Option<u64>,
+    /// Adds `owner_label` and `pubkey_label` columns.
+    pub labels: Option<Labels>,
+    /// Adds an `owner_name` column for well-known programs.
+    pub owner_names: Option<ProgramRegistry>,
+    /// Adds a `uri` column of `snap://` coordinates for this snapshot id.
+    pub uri_snapshot: Option<String>,
+    /// Adds the decoder's columns, empty for accounts without its layout.
+    pub decoder: Option<DecodeMode>,
+    /// Token amounts in the decoded columns, where the decoder knows the mint's decimals.
+    pub token_amounts: TokenAmounts,
+    /// Adds a `decode_anomalies` column of the decoder's invariant checks.
+    pub decode_validate: bool,
+    /// Handling of accounts the decoder rejects; `EmitRaw` adds a `decode_error` column.
+    pub decode_errors: DecodeErrors,
+    /// Worker threads decoding the matches of each append vec; 0 or 1 decodes inline.
+    pub decode_threads: usize,
+    /// Newest `(slot, write_version)` per pubkey; other versions are not emitted.
+    pub newest_versions: Option<HashMap<Pubkey, (u64, u64)>>,
+    /// Full snapshot slot and the pubkeys an `--incremental` snapshot stores newer versions
+    /// of; their versions up to that slot are not emitted.
+    pub superseded: Option<(u64, HashSet<Pubkey>)>,
+}
+
+impl DumpOptions {
+    pub fn format_pubkey(&self, account: &StoredAccountMeta) -> String {
+        match &self.anonymizer {
+            Some(anonymizer) => anonymizer.pubkey(&account.meta.pubkey),
+            None => account.meta.pubkey.to_string(),
+        }
+    }
+
+    /// False for versions older than the newest one when deduplicating.
+    pub fn is_newest(&self, slot: u64, account: &StoredAccountMeta) -> bool {
+        self.newest_versions.as_ref().is_none_or(|newest| {
+            newest.get(&account.meta.pubkey) == Some(&(slot, account.meta.write_version))
+        })
+    }
+
+    /// True for full snapshot versions of accounts the incremental snapshot stores again.
+    pub fn is_superseded(&self, slot: u64, account: &StoredAccountMeta) -> bool {
+        self.superseded
+            .as_ref()
+            .is_some_and(|(base_slot, pubkeys)| {
+                slot <= *base_slot && pubkeys.contains(&account.meta.pubkey)
+            })
+    }
+
+    /// Account data as written out, cut to `data_slice`; empty past the end of the data.
+    pub fn data<'a>(&self, account: &StoredAccountMeta<'a>) -> &'a [u8] {
+        match self.data_slice {
+            Some((offset, length)) => {
+                let start = offset.min(account.data.len());
+                let end = start.saturating_add(length).min(account.data.len());
+                &account.data[start..end]
+            }
+            None => account.data,
+        }
+    }
+
+    /// Formatting of values the decoder reads out of account data.
+    pub fn keys(&self) -> Keys<'_> {
+        Keys::new(self.anonymizer.as_ref(), self.token_amounts)
+    }
+
+    /// Data longer than base58 encodes under `--data-encoding=base58`, which `encoded_data`
+    /// leaves empty.
+    fn base58_too_long(&self, account: &StoredAccountMeta) -> Option<Base58TooLong> {
+        let len = self.data(account).len();
+        (self.data_encoding == DataEncoding::Base58 && len > MAX_BASE58_LEN).then(|| {
+            Base58TooLong {
+                pubkey: self.format_pubkey(account),
+                len,
+            }
+        })
+    }
+
+    fn warn_unencodable(&self, account: &StoredAccountMeta) {
+        if let Some(too_long) = self.base58_too_long(account) {
+            warn!("{}; writing its data empty", too_long);
+        }
+    }
+
+    /// `data` in `data_encoding`; empty for data too long for base58.
+    pub fn encoded_data<'a>(&self, account: &StoredAccountMeta<'a>) -> EncodedData<'a> {
+        let data = self.data(account);
+        EncodedData {
+            encoding: self.data_encoding,
+            data: match self.data_encoding {
+                DataEncoding::Base58 if data.len() > MAX_BASE58_LEN => &[],
+                _ => data,
+            },
+        }
+    }
+
+    /// Bytes behind `format_pubkey`, before base58 encoding.
+    pub fn pubkey_bytes(&self, account: &StoredAccountMeta) -> [u8; 32] {
+        let pubkey = account.meta.pubkey.to_bytes();
+        match &self.anonymizer {
+            Some(anonymizer) => anonymizer.digest(&pubkey),
+            None => pubkey,
+        }
+    }
+}
+
+/// Destination of matched accounts.
+pub enum Sink {
+    Csv(CsvDumper),
+    LedgerToolJson(LedgerToolJsonDumper),
+    Jsonl(JsonlDumper),
+    Parquet(Box<ParquetDumper>),
+    Geyser(GeyserPluginSink),
+    Postgres(PostgresSink),
+    StakeSummary(StakeSummary),
+    Stats(Stats),
+}
+
+impl Sink {
+    /// Opens `format` output at `path` (stdout when `None`). Parquet is written straight to
+    /// a file, since its footer can't be streamed through a pipe or an encryption process.
+    pub fn open(
+        format: Format,
+        path: Option<&str>,
+        encryption: Option<&Encryption>,
+    ) -> std::io::Result<Self> {
+        if format == Format::Parquet {
+            return match (path, encryption) {
+                (Some(path), None) => Ok(Sink::Parquet(Box::new(ParquetDumper::new(
+                    File::create(path)?,
+                )?))),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--format=parquet needs --output and doesn't support --encrypt",
+                )),
+            };
+        }
+        Ok(Self::streamed(format, Output::open(path, encryption)?))
+    }
+
+    /// Continues the `format` output of an interrupted dump at `path` after its first `len`
+    /// bytes. Only formats without a header or footer to rewrite can be resumed.
+    pub fn resume(format: Format, path: &str, len: u64) -> std::io::Result<Self> {
+        match format {
+            Format::Csv | Format::Jsonl => Ok(Self::streamed(format, Output::resume(path, len)?)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--checkpoint needs --format=csv or jsonl",
+            )),
+        }
+    }
+
+    fn streamed(format: Format, output: Output) -> Self {
+        match format {
+            Format::Csv => Sink::Csv(CsvDumper::new(output)),
+            Format::LedgerToolJson => Sink::LedgerToolJson(LedgerToolJsonDumper::new(output)),
+            Format::Jsonl => Sink::Jsonl(JsonlDumper::new(output)),
+            Format::Parquet => unreachable!("written to a file"),
+        }
+    }
+}
+
+/// Applies the account filter to append vecs and hands matches to the selected output format.
+pub struct Dumper {
+    sink: Sink,
+    filter: AccountFilter,
+    options: DumpOptions,
+    accounts_count: u64,
+    /// Accounts checked against the filter, here or by scan workers.
+    scanned_count: u64,
+    owner_counts: Vec<u64>,
+    /// Receives `--decode=auto` matches whose owner has no registered decoder.
+    unknown_owner: Option<CsvDumper>,
+    unknown_owner_count: u64,
+    /// Decodes matches with more than one `decode_threads`.
+    decode_pool: Option<DecodePool>,
+}
+
+impl Dumper {
+    pub fn new(
+        sink: Sink,
+        filter: AccountFilter,
+        options: DumpOptions,
+        unknown_owner_output: Option<Output>,
+    ) -> Self {
+        let owner_counts = vec![0; filter.owner_filter_count()];
+        let decode_pool = match &options.decoder {
+            Some(decoder) if options.decode_threads > 1 => Some(DecodePool::new(
+                options.decode_threads,
+                decoder.clone(),
+                options.anonymizer.clone(),
+                options.token_amounts,
+            )),
+            _ => None,
+        };
+        Self {
+            sink,
+            filter,
+            options,
+            accounts_count: 0,
+            scanned_count: 0,
+            owner_counts,
+            unknown_owner: unknown_owner_output.map(CsvDumper::new),
+            unknown_owner_count: 0,
+            decode_pool,
+        }
+    }
+
+    /// Dumps the matching accounts of one append vec. `candidates` are the accounts a
+    /// parallel scan worker found to pass the filter; without them every account is checked
+    /// here.
+    ///
+    /// Fails on the first output write error, or with a [`DecodeFailure`] under
+    /// `--decode-errors=fail`.
+    pub fn dump_append_vec(
+        &mut self,
+        slot: u64,
+        id: u64,
+        append_vec: AppendVec,
+        candidates: Option<Candidates>,
+    ) -> io::Result<()> {
+        let append_vec = Rc::new(append_vec);
+        // candidates were counted and traced by the scan worker that picked them
+        let scanning = candidates.is_none();
+        let candidates = candidates.map(|candidates| {
+            self.scanned_count += candidates.scanned;
+            candidates.offsets
+        });
+        if self.decode_pool.is_some() {
+            return self.dump_append_vec_pooled(slot, id, append_vec, candidates);
+        }
+        visit_accounts(&append_vec, candidates, |account| {
+            if self.is_done() {
+                return Ok(false);
+            }
+            if scanning {
+                self.scan(slot, id, account);
+            }
+            if let Some(matched) = self.accept(slot, account) {
+                let decoded = self.decode(account);
+                self.dump_match(slot, id, account, matched, decoded)?;
+            }
+            Ok(true)
+        })
+    }
+
+    /// Counts an account checked here rather than by a scan worker, tracing it.
+    fn scan(&mut self, slot: u64, id: u64, account: &StoredAccountMeta) {
+        self.scanned_count += 1;
+        self.filter.trace(slot, id, account);
+    }
+
+    /// Applies the filter with per-owner caps, `--dedup` and `--incremental`.
+    fn accept(&self, slot: u64, account: &StoredAccountMeta) -> Option<MatchedBy> {
+        let limit = self.options.limit_per_owner;
+        let matched = self.filter.matched_by(account, |i| {
+            limit.is_some_and(|limit| self.owner_counts[i] >= limit)
+        });
+        matched.filter(|_| {
+            self.options.is_newest(slot, account) && !self.options.is_superseded(slot, account)
+        })
+    }
+
+    /// Writes an accepted match, counting it toward `--limit-per-owner` only once it made
+    /// it into the output rather than being skipped or sent to `--unknown-owner-output`.
+    fn dump_match(
+        &mut self,
+        slot: u64,
+        id: u64,
+        account: &StoredAccountMeta,
+        matched: MatchedBy,
+        decoded: Option<Decoded>,
+    ) -> io::Result<()> {
+        if self.dump_account(slot, id, account, decoded)? {
+            if let MatchedBy::Owner(i) = matched {
+                self.owner_counts[i] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scan, decode and write stages over one append vec: matches are collected first,
+    /// decoded by the [`DecodePool`], then written in storage order.
+    ///
+    /// With `--first`, matches are decoded a chunk of the rows still missing at a time, so a
+    /// match that is skipped or sent to `--unknown-owner-output` is made up for by the next
+    /// one, as when decoding inline.
+    fn dump_append_vec_pooled(
+        &mut self,
+        slot: u64,
+        id: u64,
+        append_vec: Rc<AppendVec>,
+        candidates: Option<Vec<usize>>,
+    ) -> io::Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        let mut offsets = Vec::new();
+        let scanning = candidates.is_none();
+        visit_accounts(&append_vec, candidates, |account| {
+            if scanning {
+                self.scan(slot, id, account);
+            }
+            if self.accept(slot, account).is_some() {
+                offsets.push(account.offset);
+            }
+            Ok::<_, io::Error>(true)
+        })?;
+
+        let append_vec = match Rc::try_unwrap(append_vec) {
+            Ok(append_vec) => Arc::new(append_vec),
+            Err(_) => unreachable!("account iterator dropped"),
+        };
+        let mut offsets = &offsets[..];
+        while !offsets.is_empty() && !self.is_done() {
+            let missing = self.options.first.map_or(offsets.len(), |first| {
+                (first - self.accounts_count) as usize
+            });
+            let (chunk, rest) = offsets.split_at(missing.min(offsets.len()));
+            offsets = rest;
+            let pool = self
+                .decode_pool
+                .as_ref()
+                .expect("pooled decoding has a pool");
+            let decoded = pool.decode(&append_vec, chunk);
+            for (&offset, decoded) in chunk.iter().zip(decoded) {
+                let (account, _) = append_vec.get_account(offset).unwrap();
+                // owners may have filled up since the scan, with matches written ahead of this one
+                if let Some(matched) = self.accept(slot, &account) {
+                    self.dump_match(slot, id, &account, matched, decoded)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(&self, account: &StoredAccountMeta) -> Option<Decoded> {
+        self.options
+            .decoder
+            .as_ref()
+            .map(|mode| mode.decode(account, &self.options.keys()))
+    }
+
+    /// Writes `account` to the output, or to `--unknown-owner-output`, and returns whether
+    /// it went to the output.
+    fn dump_account(
+        &mut self,
+        slot: u64,
+        id: u64,
+        account: &StoredAccountMeta,
+        decoded: Option<Decoded>,
+    ) -> io::Result<bool> {
+        let owner = &account.account_meta.owner;
+        if let (Some(dumper), Some(mode)) = (&mut self.unknown_owner, &self.options.decoder) {
+            if mode.is_unknown_owner(owner) {
+                self.options.warn_unencodable(account);
+                dumper
+                    .dump_account(&self.options, slot, id, account, decoded.as_ref())
+                    .map_err(csv_io_error)?;
+                self.unknown_owner_count += 1;
+                return Ok(false);
+            }
+        }
+
+        if let Some(Err(error)) = &decoded {
+            match self.options.decode_errors {
+                DecodeErrors::Skip => return Ok(false),
+                DecodeErrors::Fail => {
+                    return Err(io::Error::other(DecodeFailure {
+                        pubkey: self.options.format_pubkey(account),
+                        error: error.clone(),
+                    }))
+                }
+                DecodeErrors::EmitRaw => {}
+            }
+        }
+
+        // the other sinks don't encode data as text
+        if matches!(
+            self.sink,
+            Sink::Csv(_) | Sink::LedgerToolJson(_) | Sink::Jsonl(_)
+        ) {
+            self.options.warn_unencodable(account);
+        }
+        match &mut self.sink {
+            Sink::Csv(dumper) => dumper
+                .dump_account(&self.options, slot, id, account, decoded.as_ref())
+                .map_err(csv_io_error)?,
+            Sink::LedgerToolJson(dumper) => dumper.dump_account(&self.options, account)?,
+            Sink::Jsonl(dumper) => dumper.dump_account(&self.options, slot, account)?,
+            Sink::Parquet(dumper) => dumper.dump_account(&self.options, slot, id, account)?,
+            Sink::Geyser(plugin) => plugin.dump_account(slot, account).map_err(|e| {
+                let pubkey = self.options.format_pubkey(account);
+                io::Error::other(format!("Geyser plugin rejected {}: {}", pubkey, e))
+            })?,
+            Sink::Postgres(sink) => {
+                sink.dump_account(&self.options, slot, account)
+                    .map_err(|e| {
+                        let pubkey = self.options.format_pubkey(account);
+                        let message = format!("Failed to write {} to Postgres: {}", pubkey, e);
+                        io::Error::other(message)
+                    })?
+            }
+            Sink::StakeSummary(summary) => summary.dump_account(account),
+            Sink::Stats(stats) => stats.dump_account(account),
+        }
+        self.accounts_count += 1;
+
+        if let Some(n) = self.options.flush_every {
+            if self.accounts_count.is_multiple_of(n) {
+                self.flush()?;
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(dumper) = &mut self.unknown_owner {
+            dumper.flush()?;
+        }
+        match &mut self.sink {
+            Sink::Csv(dumper) => dumper.flush(),
+            Sink::LedgerToolJson(dumper) => dumper.flush(),
+            Sink::Jsonl(dumper) => dumper.flush(),
+            Sink::Parquet(dumper) => dumper.flush(),
+            Sink::Geyser(_) => Ok(()),
+            Sink::Postgres(sink) => sink.flush().map_err(std::io::Error::other),
+            Sink::StakeSummary(_) | Sink::Stats(_) => Ok(()),
+        }
+    }
+
+    /// True once `--first` matches have been written and scanning can stop.
+    pub fn is_done(&self) -> bool {
+        self.options
+            .first
+            .is_some_and(|first| self.accounts_count >= first)
+    }
+
+    pub fn accounts_count(&self) -> u64 {
+        self.accounts_count
+    }
+
+    pub fn scanned_count(&self) -> u64 {
+        self.scanned_count
+    }
+
+    pub fn unknown_owner_count(&self) -> u64 {
+        self.unknown_owner_count
+    }
+
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(dumper) = &mut self.unknown_owner {
+            dumper.finish()?;
+        }
+        match &mut self.sink {
+            Sink::Csv(dumper) => dumper.finish(),
+            Sink::LedgerToolJson(dumper) => dumper.finish(),
+            Sink::Jsonl(dumper) => dumper.finish(),
+            Sink::Parquet(dumper) => dumper.finish(),
+            Sink::Geyser(plugin) => plugin.finish().map_err(std::io::Error::other),
+            Sink::Postgres(sink) => sink.finish().map_err(std::io::Error::other),
+            Sink::StakeSummary(summary) => summary.finish(),
+            Sink::Stats(stats) => stats.finish(),
+        }
+    }
+}
+
+/// A CSV write error as the I/O error behind it, so a closed stdout stays a `BrokenPipe`.
+fn csv_io_error(e: ::csv::Error) -> io::Error {
+    if !e.is_io_error() {
+        return io::Error::other(e);
+    }
+    match e.into_kind() {
+        ::csv::ErrorKind::Io(e) => e,
+        _ => unreachable!("checked is_io_error"),
+    }
+}
+
+/// Calls `f` with every account of `append_vec`, or only those at `candidates`, until it
+/// returns false.
+fn visit_accounts<E>(
+    append_vec: &Rc<AppendVec>,
+    candidates: Option<Vec<usize>>,
+    mut f: impl FnMut(&StoredAccountMeta) -> Result<bool, E>,
+) -> Result<(), E> {
+    match candidates {
+        None => {
+            for account in append_vec_iter(append_vec.clone()) {
+                if !f(&account.access().unwrap())? {
+                    break;
+                }
+            }
+        }
+        Some(offsets) => {
+            for offset in offsets {
+                let (account, _) = append_vec.get_account(offset).unwrap();
+                if !f(&account)? {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A chunk of the matches of one append vec: its place among the chunks, the offsets to
+/// decode and where the results go.
+type DecodeJob = (
+    Arc<AppendVec>,
+    usize,
+    Vec<usize>,
+    Sender<(usize, Vec<Option<Decoded>>)>,
+);
+
+/// `--decode-threads` workers started once per dump and fed chunks of matches through a
+/// bounded channel, like the scan workers of [`crate::parallel::scan`].
+struct DecodePool {
+    threads: usize,
+    work_tx: Option<Sender<DecodeJob>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl DecodePool {
+    fn new(
+        threads: usize,
+        decoder: DecodeMode,
+        anonymizer: Option<Anonymizer>,
+        token_amounts: TokenAmounts,
+    ) -> Self {
+        let (work_tx, work_rx) = bounded::<DecodeJob>(threads);
+        let decoder = Arc::new(decoder);
+        let anonymizer = Arc::new(anonymizer);
+        let workers = (0..threads)
+            .map(|_| {
+                let work_rx = work_rx.clone();
+                let decoder = decoder.clone();
+                let anonymizer = anonymizer.clone();
+                std::thread::spawn(move || {
+                    let keys = Keys::new(Option::as_ref(&anonymizer), token_amounts);
+                    for (append_vec, chunk, offsets, done_tx) in work_rx {
+                        let decoded = offsets
+                            .iter()
+                            .map(|offset| {
+                                let (account, _) = append_vec.get_account(*offset).unwrap();
+                                Some(decoder.decode(&account, &keys))
+                            })
+                            .collect();
+                        // nobody waits for the results once another chunk's worker panicked
+                        let _ = done_tx.send((chunk, decoded));
+                    }
+                })
+            })
+            .collect();
+        Self {
+            threads,
+            work_tx: Some(work_tx),
+            workers,
+        }
+    }
+
+    /// Decodes the accounts at `offsets` in order, splitting them evenly across the workers.
+    fn decode(&self, append_vec: &Arc<AppendVec>, offsets: &[usize]) -> Vec<Option<Decoded>> {
+        let work_tx = self.work_tx.as_ref().expect("pool not shut down");
+        let chunk_size = offsets.len().div_ceil(self.threads).max(1);
+        let (done_tx, done_rx) = unbounded();
+        let mut chunks = 0;
+        for (chunk, offsets) in offsets.chunks(chunk_size).enumerate() {
+            let job = (append_vec.clone(), chunk, offsets.to_vec(), done_tx.clone());
+            work_tx.send(job).expect("decode worker panicked");
+            chunks += 1;
+        }
+        drop(done_tx);
+        let mut decoded: Vec<Vec<Option<Decoded>>> = (0..chunks).map(|_| vec![]).collect();
+        for _ in 0..chunks {
+            let (chunk, values) = done_rx.recv().expect("decode worker panicked");
+            decoded[chunk] = values;
+        }
+        decoded.into_iter().flatten().collect()
+    }
+}
+
+impl Drop for DecodePool {
+    fn drop(&mut self) {
+        // workers exit once the channel is closed
+        self.work_tx = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MatchMode;
+    use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+    use crate::testing::{TempPath, TestAccount, TestSnapshot};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use std::process::{Command, Stdio};
+
+    /// Output into a process that exited without reading, like `head` closing the pipe.
+    fn closed_pipe() -> Output {
+        let mut child = Command::new("true").stdin(Stdio::piped()).spawn().unwrap();
+        child.wait().unwrap();
+        Output::Encrypted(child)
+    }
+
+    fn dumper(sink: Sink, options: DumpOptions, unknown_owner: Option<Output>) -> Dumper {
+        let filter = AccountFilter::new(&[], None, &[], &[], None, MatchMode::Any).unwrap();
+        Dumper::new(sink, filter, options, unknown_owner)
+    }
+
+    /// Pubkeys of the CSV rows a dumper over `owners` (every account if empty) writes for
+    /// `snapshot`, in output order.
+    fn dumped_pubkeys(
+        owners: &[Pubkey],
+        options: DumpOptions,
+        unknown_owner: Option<&TempPath>,
+        snapshot: &mut TestSnapshot,
+    ) -> Vec<Pubkey> {
+        pubkeys(&dumped_rows(owners, options, unknown_owner, snapshot))
+    }
+
+    /// CSV rows, without the header, a dumper over `owners` writes for `snapshot`.
+    fn dumped_rows(
+        owners: &[Pubkey],
+        options: DumpOptions,
+        unknown_owner: Option<&TempPath>,
+        snapshot: &mut TestSnapshot,
+    ) -> Vec<Vec<String>> {
+        let path = TempPath::new("dump.csv");
+        let owners: Vec<_> = owners.iter().map(Pubkey::to_string).collect();
+        let filter = AccountFilter::new(&[], None, &owners, &[], None, MatchMode::Any).unwrap();
+        let output = Output::open(path.to_str(), None).unwrap();
+        let unknown_owner = unknown_owner.map(|path| Output::open(path.to_str(), None).unwrap());
+        let sink = Sink::Csv(CsvDumper::new(output));
+        let mut dumper = Dumper::new(sink, filter, options, unknown_owner);
+        for append_vec in snapshot.iter() {
+            let (slot, id, append_vec) = append_vec.unwrap();
+            dumper.dump_append_vec(slot, id, append_vec, None).unwrap();
+        }
+        dumper.finish().unwrap();
+        csv_rows(&path)
+    }
+
+    fn csv_rows(path: &TempPath) -> Vec<Vec<String>> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').map(str::to_string).collect())
+            .collect()
+    }
+
+    fn pubkeys(rows: &[Vec<String>]) -> Vec<Pubkey> {
+        rows.iter().map(|row| row[0].parse().unwrap()).collect()
+    }
+
+    /// A stake account the stake decoder reads, or rejects as too short.
+    fn stake_account(decodes: bool) -> TestAccount {
+        let mut data = vec![0; if decodes { 200 } else { 3 }];
+        if decodes {
+            data[..4].copy_from_slice(&2u32.to_le_bytes());
+        }
+        TestAccount::new(solana_program::stake::program::id(), data)
+    }
+
+    #[test]
+    fn returns_sink_write_errors() {
+        let options = DumpOptions {
+            flush_every: Some(1),
+            ..Default::default()
+        };
+        let mut dumper = dumper(Sink::Csv(CsvDumper::new(closed_pipe())), options, None);
+        let account = TestAccount::new(Pubkey::new_unique(), vec![1, 2, 3]);
+        let error = dumper
+            .dump_account(1, 0, &account.stored(), None)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert!(dumper.finish().is_err());
+    }
+
+    #[test]
+    fn returns_unknown_owner_write_errors() {
+        let options = DumpOptions {
+            decoder: Some(DecodeMode::Auto),
+            ..Default::default()
+        };
+        let sink = Sink::Stats(Stats::new(closed_pipe(), LamportsFormat::U64));
+        let mut dumper = dumper(sink, options, Some(closed_pipe()));
+        // large enough that the data is written through rather than buffered
+        let account = TestAccount::new(Pubkey::new_unique(), vec![7; 1 << 16]);
+        let decoded = dumper.decode(&account.stored());
+        let error = dumper
+            .dump_account(1, 0, &account.stored(), decoded)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(dumper.unknown_owner_count(), 0);
+    }
+
+    #[test]
+    fn returns_decode_failures() {
+        let options = DumpOptions {
+            decoder: Some(DecodeMode::One(crate::decode::Decoder::Stake)),
+            decode_errors: DecodeErrors::Fail,
+            ..Default::default()
+        };
+        let sink = Sink::Stats(Stats::new(closed_pipe(), LamportsFormat::U64));
+        let mut dumper = dumper(sink, options, None);
+        let account = TestAccount::new(Pubkey::new_unique(), vec![1, 2, 3]);
+        let decoded = dumper.decode(&account.stored());
+        let error = dumper
+            .dump_account(1, 0, &account.stored(), decoded)
+            .unwrap_err();
+        let failure = error.get_ref().unwrap().downcast_ref::<DecodeFailure>();
+        assert_eq!(
+            failure.map(|failure| failure.pubkey.clone()),
+            Some(account.meta.pubkey.to_string())
+        );
+        assert_eq!(dumper.accounts_count(), 0);
+    }
+
+    #[test]
+    fn decode_pool_writes_what_inline_decoding_does() {
+        // decodable and rejected stake accounts, with one of an owner without a decoder
+        let accounts = || {
+            (0..6u8)
+                .map(|i| {
+                    let mut account = match i {
+                        4 => TestAccount::new(Pubkey::new_unique(), vec![i; 8]),
+                        _ => stake_account(i % 3 != 1),
+                    };
+                    account.meta.pubkey = Pubkey::new_from_array([i; 32]);
+                    account
+                })
+                .collect::<Vec<_>>()
+        };
+        let dump = |decode_threads: usize, first: Option<u64>, decode_errors| {
+            let unknown_owner = TempPath::new("unknown-owner.csv");
+            let options = DumpOptions {
+                first,
+                decoder: Some(DecodeMode::Auto),
+                decode_errors,
+                decode_threads,
+                ..Default::default()
+            };
+            let mut snapshot = TestSnapshot(vec![(1, accounts()), (2, vec![]), (3, accounts())]);
+            let written = dumped_pubkeys(&[], options, Some(&unknown_owner), &mut snapshot);
+            (written, pubkeys(&csv_rows(&unknown_owner)))
+        };
+
+        let (inline, unknown_owner) = dump(1, None, DecodeErrors::EmitRaw);
+        assert_eq!((inline.len(), unknown_owner.len()), (10, 2));
+        assert_eq!(
+            dump(4, None, DecodeErrors::EmitRaw),
+            (inline, unknown_owner)
+        );
+        for first in [1, 3, 4, 7, 100] {
+            let inline = dump(1, Some(first), DecodeErrors::Skip);
+            assert_eq!(
+                inline.0.len() as u64,
+                first.min(8),
+                "--first={} writes as many decodable accounts",
+                first
+            );
+            assert_eq!(
+                dump(4, Some(first), DecodeErrors::Skip),
+                inline,
+                "{}",
+                first
+            );
+        }
+    }
+
+    #[test]
+    fn writes_base58_past_the_rpc_limit_empty() {
+        let options = DumpOptions {
+            data_encoding: DataEncoding::Base58,
+            ..Default::default()
+        };
+        let path = TempPath::new("base58.csv");
+        let output = Output::open(path.to_str(), None).unwrap();
+        let mut dumper = dumper(Sink::Csv(CsvDumper::new(output)), options, None);
+        let fits = TestAccount::new(Pubkey::new_unique(), vec![1; MAX_BASE58_LEN]);
+        dumper.dump_account(1, 0, &fits.stored(), None).unwrap();
+        let too_long = TestAccount::new(Pubkey::new_unique(), vec![1; MAX_BASE58_LEN + 1]);
+        let stored = too_long.stored();
+        assert_eq!(
+            dumper
+                .options
+                .base58_too_long(&stored)
+                .map(|too_long| too_long.len),
+            Some(MAX_BASE58_LEN + 1)
+        );
+        dumper.dump_account(1, 0, &stored, None).unwrap();
+        assert_eq!(dumper.accounts_count(), 2);
+        dumper.finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let data: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.rsplit(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            data,
+            [
+                bs58::encode([1; MAX_BASE58_LEN]).into_string(),
+                String::new()
+            ]
+        );
+    }
+
+    #[test]
+    fn skipped_matches_leave_room_under_the_owner_limit() {
+        for decode_threads in [1, 4] {
+            let accounts: Vec<_> = [false, true, false, true, true]
+                .into_iter()
+                .map(stake_account)
+                .collect();
+            let decoded: Vec<_> = accounts
+                .iter()
+                .filter(|account| account.data.len() == 200)
+                .map(|account| account.meta.pubkey)
+                .collect();
+            let options = DumpOptions {
+                limit_per_owner: Some(2),
+                decoder: Some(DecodeMode::One(crate::decode::Decoder::Stake)),
+                decode_errors: DecodeErrors::Skip,
+                decode_threads,
+                ..Default::default()
+            };
+            let mut snapshot = TestSnapshot(vec![(1, accounts)]);
+            let owners = [solana_program::stake::program::id()];
+            assert_eq!(
+                dumped_pubkeys(&owners, options, None, &mut snapshot),
+                decoded[..2],
+                "{} decode threads",
+                decode_threads
+            );
+        }
+    }
+
+    #[test]
+    fn stops_emitting_an_owner_at_its_limit() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let accounts = |owners: &[Pubkey]| -> Vec<_> {
+            owners
+                .iter()
+                .map(|owner| TestAccount::new(*owner, vec![]))
+                .collect()
+        };
+        let mut snapshot = TestSnapshot(vec![
+            (1, accounts(&[a, a, b, a])),
+            (2, accounts(&[b, a, b, b])),
+        ]);
+        let pubkey = |vec: usize, account: usize| snapshot.0[vec].1[account].meta.pubkey;
+        let expected = [pubkey(0, 0), pubkey(0, 1), pubkey(0, 2), pubkey(1, 0)];
+        let options = DumpOptions {
+            limit_per_owner: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            dumped_pubkeys(&[a, b], options, None, &mut snapshot),
+            expected
+        );
+    }
+
+    #[test]
+    fn stops_after_the_first_matches() {
+        let owner = Pubkey::new_unique();
+        let accounts = || {
+            vec![
+                TestAccount::new(owner, vec![]),
+                TestAccount::new(Pubkey::new_unique(), vec![]),
+                TestAccount::new(owner, vec![]),
+            ]
+        };
+        let mut snapshot = TestSnapshot(vec![(1, accounts()), (2, accounts())]);
+        let matches: Vec<_> = snapshot
+            .0
+            .iter()
+            .flat_map(|(_, accounts)| accounts.iter())
+            .filter(|account| account.account_meta.owner == owner)
+            .map(|account| account.meta.pubkey)
+            .collect();
+        for first in [1, 3, 10] {
+            let options = DumpOptions {
+                first: Some(first),
+                ..Default::default()
+            };
+            assert_eq!(
+                dumped_pubkeys(&[owner], options, None, &mut snapshot),
+                matches[..matches.len().min(first as usize)]
+            );
+        }
+    }
+
+    #[test]
+    fn drops_versions_an_incremental_snapshot_supersedes() {
+        let (updated, unchanged) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let version = |pubkey: Pubkey| {
+            let mut account = TestAccount::new(Pubkey::new_unique(), vec![]);
+            account.meta.pubkey = pubkey;
+            account
+        };
+        // the full snapshot at slot 10 and an incremental one after it
+        let mut snapshot = TestSnapshot(vec![
+            (9, vec![version(updated)]),
+            (10, vec![version(updated), version(unchanged)]),
+            (12, vec![version(updated)]),
+        ]);
+        let options = DumpOptions {
+            superseded: Some((10, HashSet::from([updated]))),
+            ..Default::default()
+        };
+        let rows = dumped_rows(&[], options, None, &mut snapshot);
+        let versions: Vec<_> = rows
+            .iter()
+            .map(|row| (row[0].parse::<Pubkey>().unwrap(), row[4].as_str()))
+            .collect();
+        assert_eq!(versions, [(unchanged, "10"), (updated, "12")]);
+    }
+
+    #[test]
+    fn writes_the_data_slice() {
+        let data: Vec<u8> = (0..10).collect();
+        let mut snapshot = TestSnapshot(vec![(
+            1,
+            vec![TestAccount::new(Pubkey::new_unique(), data)],
+        )]);
+        for (data_slice, sliced) in [
+            ((2, 3), &[2u8, 3, 4][..]),
+            ((8, 5), &[8, 9]),
+            ((20, 1), &[]),
+        ] {
+            let options = DumpOptions {
+                data_slice: Some(data_slice),
+                ..Default::default()
+            };
+            let rows = dumped_rows(&[], options, None, &mut snapshot);
+            // data_len stays the length of the whole data
+            assert_eq!(
+                (rows[0][2].as_str(), rows[0][8].as_str()),
+                ("10", STANDARD.encode(sliced).as_str()),
+                "{:?}",
+                data_slice
+            );
+        }
+    }
+
+    #[test]
+    fn writes_lamports_as_requested() {
+        let mut account = TestAccount::new(Pubkey::new_unique(), vec![]);
+        account.account_meta.lamports = 1_500_000_001;
+        let mut snapshot = TestSnapshot(vec![(1, vec![account])]);
+        for (lamports_as, lamports) in [
+            (LamportsFormat::U64, "1500000001"),
+            (LamportsFormat::SolDecimal, "1.500000001"),
+        ] {
+            let options = DumpOptions {
+                lamports_as,
+                ..Default::default()
+            };
+            let rows = dumped_rows(&[], options, None, &mut snapshot);
+            assert_eq!(rows[0][3], lamports);
+        }
+    }
+}
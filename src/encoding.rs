@@ -0,0 +1,246 @@
+use crate::decode::decimal;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::write::EncoderWriter;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io::{self, Write};
+use thiserror::Error;
+
+/// Chunk of data hex-encoded at a time, so huge accounts are streamed through a small buffer.
+const HEX_CHUNK: usize = 4096;
+
+/// Longest data encoded as base58, RPC's limit too: base58 is a big-number conversion of the
+/// whole input, quadratic in its length.
+pub const MAX_BASE58_LEN: usize = 128;
+
+/// Data of a matched account too long for `--data-encoding=base58`.
+#[derive(Error, Debug)]
+#[error(
+    "{pubkey} has {len} bytes of data, but base58 is limited to {max} bytes as in RPC; \
+     use --data-encoding=base64 or cut the data with --data-slice",
+    max = MAX_BASE58_LEN
+)]
+pub struct Base58TooLong {
+    /// As written to the output, so tokenized when anonymizing.
+    pub pubkey: String,
+    pub len: usize,
+}
+
+/// Text encoding of account data in the CSV and JSON outputs.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataEncoding {
+    #[default]
+    Base64,
+    Base58,
+    Hex,
+    /// Zstd-compressed, then base64, as RPC `base64+zstd`
+    #[clap(name = "base64+zstd", alias = "zstd+base64")]
+    Base64Zstd,
+    /// No data, only its length
+    None,
+}
+
+impl DataEncoding {
+    /// Label of the encoding in `agave-ledger-tool` JSON, or `None` if it has none.
+    pub fn ledger_tool_name(self) -> Option<&'static str> {
+        match self {
+            DataEncoding::Base64 => Some("base64"),
+            DataEncoding::Base58 => Some("base58"),
+            DataEncoding::Base64Zstd => Some("base64+zstd"),
+            DataEncoding::Hex | DataEncoding::None => None,
+        }
+    }
+}
+
+/// SOL has 9 decimals: 1 SOL is 10^9 lamports.
+const SOL_DECIMALS: u32 = 9;
+
+/// How lamport balances are written.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LamportsFormat {
+    /// Integer lamports
+    #[default]
+    U64,
+    /// SOL with all 9 decimals, e.g. 1.500000000
+    SolDecimal,
+}
+
+impl LamportsFormat {
+    pub fn format(self, lamports: u128) -> String {
+        match self {
+            LamportsFormat::U64 => lamports.to_string(),
+            LamportsFormat::SolDecimal => decimal(lamports as i128, SOL_DECIMALS),
+        }
+    }
+}
+
+/// A lamport balance in JSON: a number, or a string in SOL so no decimal is lost to a
+/// reader's floating point.
+pub struct Lamports {
+    pub format: LamportsFormat,
+    pub lamports: u64,
+}
+
+impl Serialize for Lamports {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format {
+            LamportsFormat::U64 => serializer.serialize_u64(self.lamports),
+            format => serializer.serialize_str(&format.format(self.lamports as u128)),
+        }
+    }
+}
+
+/// Account data in a `DataEncoding`, written out as it is encoded. None of the encodings
+/// produce characters that need CSV quoting or JSON escaping.
+pub struct EncodedData<'a> {
+    pub encoding: DataEncoding,
+    pub data: &'a [u8],
+}
+
+impl EncodedData<'_> {
+    /// Writes the encoding to `out` as it is produced, a few KiB at a time, so huge accounts
+    /// never have their whole encoding in memory. Base58 is the exception: it is a
+    /// big-number conversion of the whole input and can't be chunked, which is why
+    /// [`MAX_BASE58_LEN`] bounds it.
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        match self.encoding {
+            DataEncoding::Base64 => {
+                let mut encoder = EncoderWriter::new(out, &STANDARD);
+                encoder.write_all(self.data)?;
+                encoder.finish()?;
+            }
+            DataEncoding::Base58 => {
+                out.write_all(bs58::encode(self.data).into_string().as_bytes())?
+            }
+            DataEncoding::Hex => {
+                let mut buf = [0u8; HEX_CHUNK * 2];
+                for chunk in self.data.chunks(HEX_CHUNK) {
+                    let hex = &mut buf[..chunk.len() * 2];
+                    hex::encode_to_slice(chunk, hex).expect("buffer fits the chunk");
+                    out.write_all(hex)?;
+                }
+            }
+            DataEncoding::Base64Zstd => {
+                let base64 = EncoderWriter::new(out, &STANDARD);
+                let mut encoder = zstd::stream::write::Encoder::new(base64, 0)?;
+                // the frame records the data length, as `zstd::bulk::compress` does
+                encoder.set_pledged_src_size(Some(self.data.len() as u64))?;
+                encoder.write_all(self.data)?;
+                encoder.finish()?.finish()?;
+            }
+            DataEncoding::None => {}
+        }
+        Ok(())
+    }
+}
+
+/// `io::Write` onto a formatter, for encodings, which are ASCII.
+struct FmtWriter<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl Write for FmtWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = std::str::from_utf8(buf).expect("encodings are ASCII");
+        self.0.write_str(text).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Display for EncodedData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_to(FmtWriter(f)).map_err(|_e| fmt::Error)
+    }
+}
+
+impl Serialize for EncodedData<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    /// Collects what is written and the largest single write.
+    #[derive(Default)]
+    struct Recorder {
+        bytes: Vec<u8>,
+        largest_write: usize,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.largest_write = self.largest_write.max(buf.len());
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn encode(encoding: DataEncoding, data: &[u8]) -> Recorder {
+        let mut recorder = Recorder::default();
+        EncodedData { encoding, data }
+            .write_to(&mut recorder)
+            .unwrap();
+        recorder
+    }
+
+    #[test]
+    fn streams_megabytes_through_small_writes() {
+        let data = (0..8 << 20)
+            .map(|i: u32| (i.wrapping_mul(7919) >> 5) as u8)
+            .collect::<Vec<_>>();
+
+        let base64 = encode(DataEncoding::Base64, &data);
+        assert_eq!(STANDARD.decode(&base64.bytes).unwrap(), data);
+        assert!(base64.largest_write <= 1024, "{}", base64.largest_write);
+
+        let hex = encode(DataEncoding::Hex, &data);
+        assert_eq!(hex::decode(&hex.bytes).unwrap(), data);
+        assert!(hex.largest_write <= HEX_CHUNK * 2, "{}", hex.largest_write);
+
+        let zstd = encode(DataEncoding::Base64Zstd, &data);
+        let compressed = STANDARD.decode(&zstd.bytes).unwrap();
+        assert_eq!(
+            zstd::bulk::decompress(&compressed, data.len()).unwrap(),
+            data
+        );
+        assert!(zstd.largest_write <= 1024, "{}", zstd.largest_write);
+    }
+
+    #[test]
+    fn display_matches_the_streamed_encoding() {
+        let data = [0u8, 1, 2, 0xfe, 0xff, 0x10, 0x20];
+        for encoding in [
+            DataEncoding::Base64,
+            DataEncoding::Base58,
+            DataEncoding::Hex,
+            DataEncoding::Base64Zstd,
+            DataEncoding::None,
+        ] {
+            let displayed = EncodedData {
+                encoding,
+                data: &data,
+            }
+            .to_string();
+            assert_eq!(displayed.as_bytes(), encode(encoding, &data).bytes);
+        }
+        assert_eq!(
+            EncodedData {
+                encoding: DataEncoding::Base58,
+                data: &data
+            }
+            .to_string(),
+            bs58::encode(data).into_string()
+        );
+    }
+}
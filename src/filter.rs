@@ -1,11 +1,19 @@
+use crate::filter_json::FilterExpr;
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
 use bs58;
 use hex;
+use log::info;
+use memchr::memmem;
 use regex::Regex;
+use solana_program::hash::{hash, hashv};
 use solana_program::pubkey::Pubkey;
 use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::str::FromStr;
+use std::sync::Arc;
 use thiserror::Error;
 
 use solana_snapshot_etl::append_vec::StoredAccountMeta;
@@ -17,10 +25,8 @@ pub enum FilterParseError {
     #[error("Invalid owner pubkey")]
     InvalidOwnerPubkey,
 
-    #[error("Invalid size filter")]
+    #[error("Invalid size filter (expected N, MIN..MAX, >N or <N)")]
     InvalidSizeFilter,
-    #[error("Multiple size filter")]
-    MultipleSizeFilter,
 
     #[error("Invalid memcmp filter (bytes)")]
     InvalidBytesMemcmpFilter,
@@ -29,124 +35,427 @@ pub enum FilterParseError {
     #[error("Invalid memcmpfile filter")]
     InvalidMemcmpFileFilter,
 
+    #[error("Invalid range filter (expected MIN..MAX@OFFSET)")]
+    InvalidRangeFilter,
+
+    #[error("Invalid lamports filter (expected N, MIN..MAX, >N or <N)")]
+    InvalidLamportsFilter,
+
+    #[error("Invalid references filter (expected a base58 pubkey)")]
+    InvalidReferencesFilter,
+    #[error("Invalid referencesfile filter")]
+    InvalidReferencesFileFilter,
+
+    #[error("Invalid pubkeyfile (can't be read)")]
+    InvalidPubkeyFile,
+
+    #[error("Invalid --filter-json: {0}")]
+    InvalidFilterJson(String),
+    #[error("Invalid --rpc-filters: {0}")]
+    InvalidRpcFilters(String),
+
     #[error("Unknown filter")]
     UnknownFilter,
 }
 
+#[derive(Clone)]
 enum MemCmpBytes {
     One(Vec<u8>),
-    AnyOf32(std::collections::HashSet<[u8; 32]>),
+    /// `None` bytes (`??` in the filter) match anything.
+    Masked(Vec<Option<u8>>),
+    AnyOf32(Arc<HashSet<[u8; 32]>>),
 }
 
+#[derive(Clone)]
 pub struct MemCmp {
     offset: usize,
     bytes: MemCmpBytes,
 }
 
+/// Little-endian integer at `offset` within an inclusive range.
+#[derive(Clone)]
+pub enum IntRange {
+    U64Le { offset: usize, min: u64, max: u64 },
+    I64Le { offset: usize, min: i64, max: i64 },
+}
+
+/// Data length or balance within an inclusive range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct U64Range {
+    min: u64,
+    max: u64,
+}
+
+/// Matches account data containing a 32 byte key (or any of a set of keys) at any offset.
+#[derive(Clone)]
+pub enum References {
+    One(memmem::Finder<'static>),
+    AnyOf {
+        automaton: Arc<AhoCorasick>,
+        /// `key_set_digest` of the keys, as the automaton can't list them.
+        digest: String,
+    },
+}
+
+#[derive(Clone)]
 pub struct OwnerFilter {
     owner: Pubkey,
-    size_filter: Option<u64>,
+    /// Data length ranges, any of which may match.
+    size_filters: Vec<U64Range>,
+    lamports_filter: Option<U64Range>,
     memcmp_filters: Vec<MemCmp>,
+    range_filters: Vec<IntRange>,
+    references_filters: Vec<References>,
+    /// `not:` options, each kept as a filter of the same owner that must not match.
+    negated_filters: Vec<OwnerFilter>,
+    /// `--rpc-filters` entries, all of which must match.
+    rpc_filters: Vec<FilterExpr>,
+}
+
+/// How pubkey filters and owner filters combine when both are given.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchMode {
+    /// An account matches if it is in the pubkey list or matches any owner filter
+    Any,
+    /// An account must be in the pubkey list and also match an owner filter
+    All,
+}
+
+/// The filter that accepted an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchedBy {
+    /// No filters were given
+    Everything,
+    Pubkey,
+    /// Index of the `--owner` filter, in command line order
+    Owner(usize),
+    References,
+    OwnerSet,
+    /// A `--filter-json` expression
+    Expression,
 }
 
+/// Pubkey filters beyond this many are hashed in `canonical_string` instead of listed.
+const MAX_LISTED_PUBKEYS: usize = 16;
+
+/// The filters of one scan. Cloning is cheap: the filters are shared behind `Arc`s, so
+/// parallel scan workers and reloaded daemon jobs can each hold one without copying key sets
+/// or automatons. The builder methods copy shared parts on write, so a clone handed out
+/// earlier never sees filters added later.
+#[derive(Clone)]
 pub struct AccountFilter {
-    pubkey_filters: HashSet<String>,
-    owner_filters: Vec<OwnerFilter>,
+    pubkey_filters: Arc<HashSet<String>>,
+    owner_filters: Arc<Vec<OwnerFilter>>,
+    references_filters: Arc<Vec<References>>,
+    owner_sets: Arc<Vec<HashSet<Pubkey>>>,
+    expressions: Arc<Vec<FilterExpr>>,
+    /// `--lamports`, required of every match on top of the other filters.
+    lamports: Option<U64Range>,
+    /// `--exclude-owner` filters; an account matching any of them is never a match.
+    exclude_filters: Arc<Vec<OwnerFilter>>,
+    match_mode: MatchMode,
+    /// `--trace-pubkey`, the account whose filter evaluation is logged.
+    trace: Option<Pubkey>,
 }
 
 impl MemCmp {
+    pub fn new(offset: usize, bytes: Vec<u8>) -> Self {
+        MemCmp {
+            offset,
+            bytes: MemCmpBytes::One(bytes),
+        }
+    }
+
+    /// `memcmp:0xHEX@OFFSET`, the same for hex, base58 and `anchor:` filters of the same bytes.
+    pub(crate) fn canonical(&self) -> String {
+        match &self.bytes {
+            MemCmpBytes::One(bytes) => format!("memcmp:0x{}@{}", hex::encode(bytes), self.offset),
+            MemCmpBytes::Masked(pattern) => {
+                let pattern: String = pattern
+                    .iter()
+                    .map(|byte| {
+                        byte.map_or_else(|| "??".to_string(), |byte| format!("{:02x}", byte))
+                    })
+                    .collect();
+                format!("memcmp:0x{}@{}", pattern, self.offset)
+            }
+            MemCmpBytes::AnyOf32(set) => format!(
+                "memcmpfile:{}@{}",
+                key_set_digest(set.iter().map(|key| key.as_slice())),
+                self.offset
+            ),
+        }
+    }
+
     pub fn is_match(&self, data: &[u8]) -> bool {
         match &self.bytes {
-            MemCmpBytes::One(bytes) => {
-                if self.offset + bytes.len() > data.len() {
+            MemCmpBytes::One(bytes) => self.window(data, bytes.len()) == Some(&bytes[..]),
+            MemCmpBytes::Masked(pattern) => {
+                let Some(window) = self.window(data, pattern.len()) else {
                     return false;
-                }
+                };
+                pattern
+                    .iter()
+                    .zip(window)
+                    .all(|(expected, byte)| expected.is_none_or(|expected| expected == *byte))
+            }
+            MemCmpBytes::AnyOf32(set) => match self.window(data, 32) {
+                Some(window) => set.contains(<&[u8; 32]>::try_from(window).expect("32 bytes")),
+                None => false,
+            },
+        }
+    }
 
-                for i in 0..bytes.len() {
-                    if data[self.offset + i] != bytes[i] {
-                        return false;
-                    }
-                }
+    /// `len` bytes of `data` at the offset, or `None` when they run past its end (or past
+    /// `usize::MAX` for a huge offset).
+    fn window<'a>(&self, data: &'a [u8], len: usize) -> Option<&'a [u8]> {
+        data.get(self.offset..self.offset.checked_add(len)?)
+    }
+}
+
+/// `N:HASH` for a set of keys too large to list: the count and the sha256 of the sorted keys.
+fn key_set_digest<'a>(keys: impl Iterator<Item = &'a [u8]>) -> String {
+    let mut keys: Vec<&[u8]> = keys.collect();
+    keys.sort_unstable();
+    format!("{}:{}", keys.len(), hashv(&keys))
+}
+
+/// Sorted, deduplicated and joined, for option lists whose order doesn't change what matches.
+fn canonical_list(mut items: Vec<String>, separator: &str) -> String {
+    items.sort_unstable();
+    items.dedup();
+    items.join(separator)
+}
+
+/// Anchor accounts start with the first 8 bytes of `sha256("account:<Name>")`.
+pub fn anchor_account_discriminator(name: &str) -> [u8; 8] {
+    let digest = hash(format!("account:{}", name).as_bytes()).to_bytes();
+    digest[..8].try_into().unwrap()
+}
+
+/// Reads a file of 32 byte keys, one per line as base58 or `0x`-hex; blank lines are skipped.
+fn read_32_byte_keys(path: &str) -> Option<HashSet<[u8; 32]>> {
+    let f = File::open(path).ok()?;
+    let reader = BufReader::new(f);
+
+    let mut set: HashSet<[u8; 32]> = HashSet::new();
+    for line in reader.lines() {
+        let line = line.ok()?;
+        let t = line.trim();
+        if t.is_empty() {
+            continue;
+        }
 
-                true
+        let bytes: Vec<u8> = if let Some(hex) = t.strip_prefix("0x") {
+            hex::decode(hex).ok()?
+        } else {
+            bs58::decode(t).into_vec().ok()?
+        };
+        set.insert(bytes.as_slice().try_into().ok()?);
+    }
+    Some(set)
+}
+
+impl IntRange {
+    /// Parses `MIN..MAX@OFFSET` for the given integer type (`u64le` or `i64le`).
+    fn parse(kind: &str, range: &str, offset: &str) -> Result<Self, FilterParseError> {
+        let invalid = |_e| FilterParseError::InvalidRangeFilter;
+        let (min, max) = range
+            .split_once("..")
+            .ok_or(FilterParseError::InvalidRangeFilter)?;
+        let offset = offset
+            .parse::<usize>()
+            .map_err(|_e| FilterParseError::InvalidOffsetMemcmpFilter)?;
+        match kind {
+            "u64le" => Ok(IntRange::U64Le {
+                offset,
+                min: min.parse().map_err(invalid)?,
+                max: max.parse().map_err(invalid)?,
+            }),
+            _ => Ok(IntRange::I64Le {
+                offset,
+                min: min.parse().map_err(invalid)?,
+                max: max.parse().map_err(invalid)?,
+            }),
+        }
+    }
+
+    fn canonical(&self) -> String {
+        match self {
+            IntRange::U64Le { offset, min, max } => {
+                format!("u64le-between:{}..{}@{}", min, max, offset)
             }
-            MemCmpBytes::AnyOf32(set) => {
-                if self.offset + 32 > data.len() {
-                    return false;
-                }
-                let slice: [u8; 32] = match data[self.offset..self.offset + 32].try_into() {
-                    Ok(v) => v,
-                    Err(_) => return false,
-                };
-                set.contains(&slice)
+            IntRange::I64Le { offset, min, max } => {
+                format!("i64le-between:{}..{}@{}", min, max, offset)
+            }
+        }
+    }
+
+    pub fn is_match(&self, data: &[u8]) -> bool {
+        let read = |offset: usize| -> Option<[u8; 8]> {
+            data.get(offset..offset.checked_add(8)?)?.try_into().ok()
+        };
+        match *self {
+            IntRange::U64Le { offset, min, max } => {
+                read(offset).is_some_and(|bytes| (min..=max).contains(&u64::from_le_bytes(bytes)))
+            }
+            IntRange::I64Le { offset, min, max } => {
+                read(offset).is_some_and(|bytes| (min..=max).contains(&i64::from_le_bytes(bytes)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for U64Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.min, self.max)
+    }
+}
+
+impl U64Range {
+    /// Parses `N`, `MIN..MAX` (inclusive), `>N` or `<N`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (min, max) = if let Some(n) = s.strip_prefix('>') {
+            (n.parse::<u64>().ok()?.checked_add(1)?, u64::MAX)
+        } else if let Some(n) = s.strip_prefix('<') {
+            (0, n.parse::<u64>().ok()?.checked_sub(1)?)
+        } else if let Some((min, max)) = s.split_once("..") {
+            (min.parse().ok()?, max.parse().ok()?)
+        } else {
+            let n = s.parse().ok()?;
+            (n, n)
+        };
+        (min <= max).then_some(U64Range { min, max })
+    }
+
+    pub fn contains(&self, lamports: u64) -> bool {
+        (self.min..=self.max).contains(&lamports)
+    }
+}
+
+impl References {
+    pub fn new(pubkey: &str) -> Result<Self, FilterParseError> {
+        let pubkey =
+            Pubkey::from_str(pubkey).map_err(|_e| FilterParseError::InvalidReferencesFilter)?;
+        // memmem picks a SIMD substring search where the CPU supports it
+        let finder = memmem::Finder::new(&pubkey.to_bytes()).into_owned();
+        Ok(References::One(finder))
+    }
+
+    /// Searches for every key in the file in a single pass over the data.
+    pub fn from_file(path: &str) -> Result<Self, FilterParseError> {
+        let keys = read_32_byte_keys(path).ok_or(FilterParseError::InvalidReferencesFileFilter)?;
+        let automaton = AhoCorasickBuilder::new().dfa(true).build(keys.iter());
+        Ok(References::AnyOf {
+            automaton: Arc::new(automaton),
+            digest: key_set_digest(keys.iter().map(|key| key.as_slice())),
+        })
+    }
+
+    pub fn is_match(&self, data: &[u8]) -> bool {
+        match self {
+            References::One(finder) => finder.find(data).is_some(),
+            References::AnyOf { automaton, .. } => automaton.is_match(data),
+        }
+    }
+
+    fn canonical(&self) -> String {
+        match self {
+            References::One(finder) => {
+                format!("references:{}", bs58::encode(finder.needle()).into_string())
             }
+            References::AnyOf { digest, .. } => format!("referencesfile:{}", digest),
         }
     }
 }
 
 impl OwnerFilter {
-    pub fn new(owner_with_opts: &String) -> Result<Self, FilterParseError> {
+    pub fn new(owner_with_opts: &str) -> Result<Self, FilterParseError> {
         let re_owner_filter = Regex::new(
             r"^([abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ123456789]+)((?:,[^,]+)*)$",
         )
         .unwrap();
-        let re_size_filter = Regex::new(r"^size:(\d+)$").unwrap();
+        let re_size_filter = Regex::new(r"^size:(.+)$").unwrap();
         let re_memcmp_hex_filter =
             Regex::new(r"memcmp:0x((?:[0-9a-fA-F][0-9a-fA-F])+)@(\d+)$").unwrap();
+        let re_memcmp_masked_filter =
+            Regex::new(r"^memcmp:0x((?:[0-9a-fA-F][0-9a-fA-F]|\?\?)+)@(\d+)$").unwrap();
         let re_memcmp_base58_filter = Regex::new(
             r"memcmp:([abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ123456789]+)@(\d+)$",
         )
         .unwrap();
         let re_memcmp_file_filter = Regex::new(r"^memcmpfile:([^@]+)@(\d+)$").unwrap();
+        let re_anchor_filter = Regex::new(r"^anchor:([A-Za-z_][A-Za-z0-9_]*)$").unwrap();
+        let re_range_filter = Regex::new(r"^(u64le|i64le)-between:([^@]+)@(\d+)$").unwrap();
+        let re_lamports_filter = Regex::new(r"^lamports:(.+)$").unwrap();
+        let re_references_filter = Regex::new(r"^references:([^,]+)$").unwrap();
+        let re_references_file_filter = Regex::new(r"^referencesfile:([^,]+)$").unwrap();
 
-        if !re_owner_filter.is_match(&owner_with_opts) {
+        if !re_owner_filter.is_match(owner_with_opts) {
             return Err(FilterParseError::InvalidOwnerFilterSyntax);
         }
 
-        let caps = re_owner_filter.captures(&owner_with_opts).unwrap();
+        let caps = re_owner_filter.captures(owner_with_opts).unwrap();
         let owner_base58 = &caps[1];
         let opts = &caps[2];
 
-        let owner = Pubkey::from_str(owner_base58)
-            .or_else(|_e| Err(FilterParseError::InvalidOwnerPubkey))?;
+        let owner =
+            Pubkey::from_str(owner_base58).map_err(|_e| FilterParseError::InvalidOwnerPubkey)?;
 
-        let mut size_filter: Option<u64> = None;
+        let mut size_filters: Vec<U64Range> = vec![];
+        let mut lamports_filter: Option<U64Range> = None;
         let mut memcmp_filters: Vec<MemCmp> = vec![];
+        let mut range_filters: Vec<IntRange> = vec![];
+        let mut references_filters: Vec<References> = vec![];
+        let mut negated_filters: Vec<OwnerFilter> = vec![];
         for opt in opts.split(',') {
             if opt.is_empty() {
                 continue;
             }
 
-            if re_size_filter.is_match(opt) {
+            if let Some(negated) = opt.strip_prefix("not:") {
+                negated_filters.push(OwnerFilter::new(&format!("{},{}", owner_base58, negated))?);
+            } else if re_size_filter.is_match(opt) {
                 let caps = re_size_filter.captures(opt).unwrap();
-                let size = caps[1]
-                    .parse::<u64>()
-                    .or_else(|_e| Err(FilterParseError::InvalidSizeFilter))?;
-                match size_filter {
-                    Some(_size) => {
-                        return Err(FilterParseError::MultipleSizeFilter);
-                    }
-                    None => size_filter = Some(size),
-                }
+                let size = U64Range::parse(&caps[1]).ok_or(FilterParseError::InvalidSizeFilter)?;
+                size_filters.push(size);
             } else if re_memcmp_hex_filter.is_match(opt) {
                 let caps = re_memcmp_hex_filter.captures(opt).unwrap();
                 let bytes = hex::decode(&caps[1])
-                    .or_else(|_e| Err(FilterParseError::InvalidBytesMemcmpFilter))?;
+                    .map_err(|_e| FilterParseError::InvalidBytesMemcmpFilter)?;
                 let offset = caps[2]
                     .parse::<usize>()
-                    .or_else(|_e| Err(FilterParseError::InvalidOffsetMemcmpFilter))?;
+                    .map_err(|_e| FilterParseError::InvalidOffsetMemcmpFilter)?;
                 memcmp_filters.push(MemCmp {
                     bytes: MemCmpBytes::One(bytes),
                     offset,
                 });
+            } else if re_memcmp_masked_filter.is_match(opt) {
+                let caps = re_memcmp_masked_filter.captures(opt).unwrap();
+                let pattern = caps[1]
+                    .as_bytes()
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        b"??" => Ok(None),
+                        _ => hex::decode(pair).map(|byte| Some(byte[0])),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_e| FilterParseError::InvalidBytesMemcmpFilter)?;
+                let offset = caps[2]
+                    .parse::<usize>()
+                    .map_err(|_e| FilterParseError::InvalidOffsetMemcmpFilter)?;
+                memcmp_filters.push(MemCmp {
+                    bytes: MemCmpBytes::Masked(pattern),
+                    offset,
+                });
             } else if re_memcmp_base58_filter.is_match(opt) {
                 let caps = re_memcmp_base58_filter.captures(opt).unwrap();
                 let bytes = bs58::decode(&caps[1])
                     .into_vec()
-                    .or_else(|_e| Err(FilterParseError::InvalidBytesMemcmpFilter))?;
+                    .map_err(|_e| FilterParseError::InvalidBytesMemcmpFilter)?;
                 let offset = caps[2]
                     .parse::<usize>()
-                    .or_else(|_e| Err(FilterParseError::InvalidOffsetMemcmpFilter))?;
+                    .map_err(|_e| FilterParseError::InvalidOffsetMemcmpFilter)?;
                 memcmp_filters.push(MemCmp {
                     offset,
                     bytes: MemCmpBytes::One(bytes),
@@ -156,43 +465,37 @@ impl OwnerFilter {
                 let path = &caps[1];
                 let offset = caps[2]
                     .parse::<usize>()
-                    .or_else(|_e| Err(FilterParseError::InvalidOffsetMemcmpFilter))?;
-
-                let f = File::open(path)
-                    .or_else(|_e| Err(FilterParseError::InvalidMemcmpFileFilter))?;
-                let reader = BufReader::new(f);
-
-                let mut set: HashSet<[u8; 32]> = HashSet::new();
-                for line in reader.lines() {
-                    let line = line.or_else(|_e| Err(FilterParseError::InvalidMemcmpFileFilter))?;
-                    let t = line.trim();
-                    if t.is_empty() {
-                        continue;
-                    }
+                    .map_err(|_e| FilterParseError::InvalidOffsetMemcmpFilter)?;
 
-                    let bytes: Vec<u8> = if t.starts_with("0x") {
-                        hex::decode(&t[2..])
-                            .or_else(|_e| Err(FilterParseError::InvalidMemcmpFileFilter))?
-                    } else {
-                        bs58::decode(t)
-                            .into_vec()
-                            .or_else(|_e| Err(FilterParseError::InvalidMemcmpFileFilter))?
-                    };
-
-                    if bytes.len() != 32 {
-                        return Err(FilterParseError::InvalidMemcmpFileFilter);
-                    }
-                    let arr: [u8; 32] = bytes
-                        .as_slice()
-                        .try_into()
-                        .or_else(|_e| Err(FilterParseError::InvalidMemcmpFileFilter))?;
-                    set.insert(arr);
-                }
+                let set =
+                    read_32_byte_keys(path).ok_or(FilterParseError::InvalidMemcmpFileFilter)?;
 
                 memcmp_filters.push(MemCmp {
                     offset,
-                    bytes: MemCmpBytes::AnyOf32(set),
+                    bytes: MemCmpBytes::AnyOf32(Arc::new(set)),
+                });
+            } else if re_anchor_filter.is_match(opt) {
+                let caps = re_anchor_filter.captures(opt).unwrap();
+                memcmp_filters.push(MemCmp {
+                    offset: 0,
+                    bytes: MemCmpBytes::One(anchor_account_discriminator(&caps[1]).to_vec()),
                 });
+            } else if re_range_filter.is_match(opt) {
+                let caps = re_range_filter.captures(opt).unwrap();
+                range_filters.push(IntRange::parse(&caps[1], &caps[2], &caps[3])?);
+            } else if re_lamports_filter.is_match(opt) {
+                let caps = re_lamports_filter.captures(opt).unwrap();
+                if lamports_filter.is_some() {
+                    return Err(FilterParseError::InvalidLamportsFilter);
+                }
+                lamports_filter =
+                    Some(U64Range::parse(&caps[1]).ok_or(FilterParseError::InvalidLamportsFilter)?);
+            } else if re_references_filter.is_match(opt) {
+                let caps = re_references_filter.captures(opt).unwrap();
+                references_filters.push(References::new(&caps[1])?);
+            } else if re_references_file_filter.is_match(opt) {
+                let caps = re_references_file_filter.captures(opt).unwrap();
+                references_filters.push(References::from_file(&caps[1])?);
             } else {
                 return Err(FilterParseError::UnknownFilter);
             }
@@ -200,43 +503,161 @@ impl OwnerFilter {
 
         Ok(OwnerFilter {
             owner,
-            size_filter,
+            size_filters,
+            lamports_filter,
             memcmp_filters,
+            range_filters,
+            references_filters,
+            negated_filters,
+            rpc_filters: vec![],
         })
     }
 
+    /// The program whose accounts the filter selects.
+    pub fn owner(&self) -> Pubkey {
+        self.owner
+    }
+
     pub fn is_match(&self, account: &StoredAccountMeta) -> bool {
-        match self.size_filter {
-            Some(size) => {
-                if account.meta.data_len != size {
-                    return false;
-                }
-            }
-            None => {}
+        if !self.size_filters.is_empty()
+            && !self
+                .size_filters
+                .iter()
+                .any(|size| size.contains(account.meta.data_len))
+        {
+            return false;
         }
 
         if !account.account_meta.owner.eq(&self.owner) {
             return false;
         }
 
+        if let Some(lamports) = &self.lamports_filter {
+            if !lamports.contains(account.account_meta.lamports) {
+                return false;
+            }
+        }
+
         for memcmp in self.memcmp_filters.iter() {
             if !memcmp.is_match(account.data) {
                 return false;
             }
         }
 
-        return true;
+        for range in self.range_filters.iter() {
+            if !range.is_match(account.data) {
+                return false;
+            }
+        }
+
+        for references in self.references_filters.iter() {
+            if !references.is_match(account.data) {
+                return false;
+            }
+        }
+
+        for negated in self.negated_filters.iter() {
+            if negated.is_match(account) {
+                return false;
+            }
+        }
+
+        for rpc_filter in self.rpc_filters.iter() {
+            if !rpc_filter.is_match(account) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// The options of the filter in a fixed order, `--rpc-filters` memcmps merged with the
+    /// filter's own since both must match.
+    fn canonical_options(&self) -> Vec<String> {
+        let mut options = vec![];
+        if !self.size_filters.is_empty() {
+            let sizes = self.size_filters.iter().map(U64Range::to_string).collect();
+            options.push(format!("size:{}", canonical_list(sizes, "/")));
+        }
+        if let Some(lamports) = &self.lamports_filter {
+            options.push(format!("lamports:{}", lamports));
+        }
+        options.extend(self.memcmp_filters.iter().map(MemCmp::canonical));
+        options.extend(self.range_filters.iter().map(IntRange::canonical));
+        options.extend(self.references_filters.iter().map(References::canonical));
+        for negated in self.negated_filters.iter() {
+            options.push(format!("not:[{}]", negated.canonical_options().join(",")));
+        }
+        options.extend(self.rpc_filters.iter().map(|filter| match filter {
+            FilterExpr::Memcmp(memcmp) => memcmp.canonical(),
+            filter => filter.canonical(),
+        }));
+        options.sort_unstable();
+        options.dedup();
+        options
+    }
+
+    /// `OWNER,OPTION,...` with options in a fixed order, so the same filter written with its
+    /// options in another order, or with an equivalent memcmp encoding, reads the same.
+    pub fn canonical(&self) -> String {
+        std::iter::once(self.owner.to_string())
+            .chain(self.canonical_options())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Every condition of the filter with whether `account` meets it, including those
+    /// `is_match` never gets to.
+    fn explain(&self, account: &StoredAccountMeta) -> Vec<String> {
+        let mut conditions = vec![format!(
+            "owner {}: {}",
+            self.owner,
+            account.account_meta.owner == self.owner
+        )];
+        if !self.size_filters.is_empty() {
+            let size = account.meta.data_len;
+            let matched = self.size_filters.iter().any(|range| range.contains(size));
+            conditions.push(format!("size {}: {}", size, matched));
+        }
+        if let Some(lamports) = &self.lamports_filter {
+            let matched = lamports.contains(account.account_meta.lamports);
+            conditions.push(format!("lamports {}: {}", lamports, matched));
+        }
+        for (i, memcmp) in self.memcmp_filters.iter().enumerate() {
+            conditions.push(format!("memcmp[{}]: {}", i, memcmp.is_match(account.data)));
+        }
+        for (i, range) in self.range_filters.iter().enumerate() {
+            conditions.push(format!("range[{}]: {}", i, range.is_match(account.data)));
+        }
+        for (i, references) in self.references_filters.iter().enumerate() {
+            let matched = references.is_match(account.data);
+            conditions.push(format!("references[{}]: {}", i, matched));
+        }
+        for (i, negated) in self.negated_filters.iter().enumerate() {
+            conditions.push(format!("not[{}]: {}", i, !negated.is_match(account)));
+        }
+        for (i, rpc_filter) in self.rpc_filters.iter().enumerate() {
+            let matched = rpc_filter.is_match(account);
+            conditions.push(format!("rpc filter[{}]: {}", i, matched));
+        }
+        conditions
     }
 }
 
 impl AccountFilter {
+    /// Filter from the `--pubkey`, `--pubkeyfile`, `--owner`, `--references` and
+    /// `--referencesfile` values; empty slices and `None` leave a kind of filter out.
     pub fn new(
-        pubkeys: &Vec<String>,
-        pubkeyfile: &Option<String>,
-        owners: &Vec<String>,
+        pubkeys: &[String],
+        pubkeyfile: Option<&str>,
+        owners: &[String],
+        references: &[String],
+        referencesfile: Option<&str>,
+        match_mode: MatchMode,
     ) -> Result<Self, FilterParseError> {
         let mut pubkey_filters: HashSet<String> = HashSet::new();
         let mut owner_filters: Vec<OwnerFilter> = vec![];
+        let mut references_filters: Vec<References> = vec![];
 
         // --pubkey=pk1
         // --pubkey=pk1,pk2,pk3,...
@@ -250,12 +671,12 @@ impl AccountFilter {
         match pubkeyfile {
             None => {}
             Some(file) => {
-                let f = File::open(file).unwrap();
+                let f = File::open(file).map_err(|_e| FilterParseError::InvalidPubkeyFile)?;
                 let reader = BufReader::new(f);
                 for line in reader.lines() {
-                    let line = line.unwrap();
+                    let line = line.map_err(|_e| FilterParseError::InvalidPubkeyFile)?;
                     let trimed = line.trim();
-                    if trimed.len() == 0 {
+                    if trimed.is_empty() {
                         continue;
                     }
 
@@ -272,30 +693,658 @@ impl AccountFilter {
             owner_filters.push(owner_filter);
         }
 
+        // --references=pk1
+        for pubkey in references.iter() {
+            references_filters.push(References::new(pubkey)?);
+        }
+
+        // --referencesfile=file (1 pubkey per line)
+        if let Some(file) = referencesfile {
+            references_filters.push(References::from_file(file)?);
+        }
+
         Ok(AccountFilter {
-            pubkey_filters,
-            owner_filters,
+            pubkey_filters: Arc::new(pubkey_filters),
+            owner_filters: Arc::new(owner_filters),
+            references_filters: Arc::new(references_filters),
+            owner_sets: Arc::default(),
+            expressions: Arc::default(),
+            lamports: None,
+            exclude_filters: Arc::default(),
+            match_mode,
+            trace: None,
         })
     }
 
-    pub fn is_match(&self, account: &StoredAccountMeta) -> bool {
-        if self.pubkey_filters.is_empty() && self.owner_filters.is_empty() {
-            return true;
+    /// Adds a data filter accepting accounts owned by any program in `owners`, for owner
+    /// sets computed by a preliminary scan.
+    pub fn add_owner_set(&mut self, owners: HashSet<Pubkey>) {
+        Arc::make_mut(&mut self.owner_sets).push(owners);
+    }
+
+    /// Adds a data filter accepting accounts for which `expr` holds.
+    pub fn add_expression(&mut self, expr: FilterExpr) {
+        Arc::make_mut(&mut self.expressions).push(expr);
+    }
+
+    /// Adds RPC `getProgramAccounts` filters to every `--owner` filter.
+    pub fn add_rpc_filters(&mut self, filters: Vec<FilterExpr>) {
+        for owner_filter in Arc::make_mut(&mut self.owner_filters).iter_mut() {
+            owner_filter.rpc_filters.extend(filters.iter().cloned());
+        }
+    }
+
+    /// Restricts every match, whichever filter accepted it, to balances within `range`.
+    pub fn set_lamports(&mut self, range: U64Range) {
+        self.lamports = Some(range);
+    }
+
+    /// Never matches accounts accepted by `filter`, whichever other filter accepts them.
+    pub fn exclude(&mut self, filter: OwnerFilter) {
+        Arc::make_mut(&mut self.exclude_filters).push(filter);
+    }
+
+    /// The filters in a fixed order, independent of the order of the options and flags they
+    /// came from and of how memcmp bytes were written. Large key sets are listed as a count
+    /// and hash. Empty without filters.
+    pub fn canonical_string(&self) -> String {
+        let mut sections = vec![];
+        if !self.pubkey_filters.is_empty() {
+            let pubkeys = if self.pubkey_filters.len() <= MAX_LISTED_PUBKEYS {
+                canonical_list(self.pubkey_filters.iter().cloned().collect(), "|")
+            } else {
+                key_set_digest(self.pubkey_filters.iter().map(|key| key.as_bytes()))
+            };
+            sections.push(format!("pubkey={}", pubkeys));
         }
+        let mut list = |name: &str, items: Vec<String>| {
+            if !items.is_empty() {
+                sections.push(format!("{}={}", name, canonical_list(items, "|")));
+            }
+        };
+        list(
+            "owner",
+            self.owner_filters
+                .iter()
+                .map(OwnerFilter::canonical)
+                .collect(),
+        );
+        list(
+            "references",
+            self.references_filters
+                .iter()
+                .map(References::canonical)
+                .collect(),
+        );
+        list(
+            "owner-set",
+            self.owner_sets
+                .iter()
+                .map(|owners| key_set_digest(owners.iter().map(|owner| owner.as_ref())))
+                .collect(),
+        );
+        list(
+            "filter-json",
+            self.expressions.iter().map(FilterExpr::canonical).collect(),
+        );
+        list(
+            "exclude-owner",
+            self.exclude_filters
+                .iter()
+                .map(OwnerFilter::canonical)
+                .collect(),
+        );
+        if let Some(lamports) = &self.lamports {
+            sections.push(format!("lamports={}", lamports));
+        }
+        // the mode only changes anything with both kinds of filters
+        let data_filters = !self.owner_filters.is_empty()
+            || !self.references_filters.is_empty()
+            || !self.owner_sets.is_empty()
+            || !self.expressions.is_empty();
+        if !self.pubkey_filters.is_empty() && data_filters {
+            let mode = match self.match_mode {
+                MatchMode::Any => "any",
+                MatchMode::All => "all",
+            };
+            sections.push(format!("match={}", mode));
+        }
+        sections.join(";")
+    }
 
+    /// Hex sha256 of `canonical_string`, identifying the filters in manifests and checkpoints.
+    pub fn filter_hash(&self) -> String {
+        hex::encode(hash(self.canonical_string().as_bytes()).to_bytes())
+    }
+
+    /// Logs how every filter evaluates for `pubkey` each time it is passed to `trace`.
+    pub fn set_trace(&mut self, pubkey: Pubkey) {
+        self.trace = Some(pubkey);
+    }
+
+    /// With `--trace-pubkey` naming `account`, logs where it was found, whether it matches and
+    /// the result of each filter, all of them evaluated. Per-owner caps are not applied.
+    pub fn trace(&self, slot: u64, id: u64, account: &StoredAccountMeta) {
+        if self.trace != Some(account.meta.pubkey) {
+            return;
+        }
+        let owner_filter = |name: &str, i: usize, filter: &OwnerFilter| {
+            format!(
+                "{}[{}] ({}): {}",
+                name,
+                i,
+                filter.explain(account).join(", "),
+                filter.is_match(account)
+            )
+        };
+        let mut filters = vec![];
+        if let Some(lamports) = &self.lamports {
+            let matched = lamports.contains(account.account_meta.lamports);
+            filters.push(format!("--lamports {}: {}", lamports, matched));
+        }
+        for (i, exclude) in self.exclude_filters.iter().enumerate() {
+            filters.push(owner_filter("--exclude-owner", i, exclude));
+        }
+        if !self.pubkey_filters.is_empty() {
+            let listed = self
+                .pubkey_filters
+                .contains(&account.meta.pubkey.to_string());
+            filters.push(format!("--pubkey: {}", listed));
+        }
+        for (i, owner) in self.owner_filters.iter().enumerate() {
+            filters.push(owner_filter("--owner", i, owner));
+        }
+        for (i, references) in self.references_filters.iter().enumerate() {
+            let matched = references.is_match(account.data);
+            filters.push(format!("--references[{}]: {}", i, matched));
+        }
+        for (i, owners) in self.owner_sets.iter().enumerate() {
+            let matched = owners.contains(&account.account_meta.owner);
+            filters.push(format!("owner set[{}]: {}", i, matched));
+        }
+        for (i, expr) in self.expressions.iter().enumerate() {
+            filters.push(format!("--filter-json[{}]: {}", i, expr.is_match(account)));
+        }
+        let matched = match self.matched_by(account, |_| false) {
+            Some(matched_by) => format!("matches ({:?})", matched_by),
+            None => "doesn't match".to_string(),
+        };
+        info!(
+            "Trace {} in append vec {}.{} at offset {}: {} with match mode {:?}; {}",
+            account.meta.pubkey,
+            slot,
+            id,
+            account.offset,
+            matched,
+            self.match_mode,
+            filters.join("; ")
+        );
+    }
+
+    pub fn owner_filter_count(&self) -> usize {
+        self.owner_filters.len()
+    }
+
+    /// True without pubkey or data filters, when only a `--lamports` range and
+    /// `--exclude-owner` filters can narrow the scan down.
+    pub fn matches_everything(&self) -> bool {
+        self.pubkey_filters.is_empty()
+            && self.owner_filters.is_empty()
+            && self.references_filters.is_empty()
+            && self.owner_sets.is_empty()
+            && self.expressions.is_empty()
+    }
+
+    /// With no filters every account matches. Owner, references, owner set and
+    /// `--filter-json` filters are data filters; with only pubkey filters or only data filters the match mode makes
+    /// no difference. With both, `Any` accepts an account matching either kind and `All`
+    /// requires the pubkey to be listed and a data filter to match. A `--lamports` range
+    /// and `--exclude-owner` filters apply on top, in either mode.
+    pub fn is_match(&self, account: &StoredAccountMeta) -> bool {
+        self.matched_by(account, |_| false).is_some()
+    }
+
+    /// Like `is_match`, but reports which filter matched. Owner filters for which
+    /// `is_full` returns true are skipped, so per-owner caps fall through to the next
+    /// matching owner filter.
+    pub fn matched_by(
+        &self,
+        account: &StoredAccountMeta,
+        is_full: impl Fn(usize) -> bool,
+    ) -> Option<MatchedBy> {
+        if let Some(lamports) = &self.lamports {
+            if !lamports.contains(account.account_meta.lamports) {
+                return None;
+            }
+        }
         if self
-            .pubkey_filters
-            .contains(&account.meta.pubkey.to_string())
+            .exclude_filters
+            .iter()
+            .any(|exclude| exclude.is_match(account))
         {
-            return true;
+            return None;
         }
 
-        for owner_filter in self.owner_filters.iter() {
-            if owner_filter.is_match(account) {
-                return true;
+        let no_data_filters = self.owner_filters.is_empty()
+            && self.references_filters.is_empty()
+            && self.owner_sets.is_empty()
+            && self.expressions.is_empty();
+        if self.pubkey_filters.is_empty() && no_data_filters {
+            return Some(MatchedBy::Everything);
+        }
+
+        let pubkey_match = || {
+            self.pubkey_filters
+                .contains(&account.meta.pubkey.to_string())
+        };
+        let data_match = || {
+            let owner = self
+                .owner_filters
+                .iter()
+                .enumerate()
+                .position(|(i, owner_filter)| !is_full(i) && owner_filter.is_match(account))
+                .map(MatchedBy::Owner);
+            owner
+                .or_else(|| {
+                    self.references_filters
+                        .iter()
+                        .any(|references| references.is_match(account.data))
+                        .then_some(MatchedBy::References)
+                })
+                .or_else(|| {
+                    self.owner_sets
+                        .iter()
+                        .any(|owners| owners.contains(&account.account_meta.owner))
+                        .then_some(MatchedBy::OwnerSet)
+                })
+                .or_else(|| {
+                    self.expressions
+                        .iter()
+                        .any(|expr| expr.is_match(account))
+                        .then_some(MatchedBy::Expression)
+                })
+        };
+
+        match self.match_mode {
+            MatchMode::Any => {
+                if pubkey_match() {
+                    Some(MatchedBy::Pubkey)
+                } else {
+                    data_match()
+                }
             }
+            MatchMode::All => {
+                if !self.pubkey_filters.is_empty() && !pubkey_match() {
+                    None
+                } else if no_data_filters {
+                    Some(MatchedBy::Pubkey)
+                } else {
+                    data_match()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{put, TempPath, TestAccount};
+
+    const OWNER: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+    fn owner_filter(options: &str) -> Result<OwnerFilter, FilterParseError> {
+        OwnerFilter::new(&format!("{}{}", OWNER, options))
+    }
+
+    /// A 165 byte account with `01 02` at 4, the u64 500 at 64 and `key` at 100.
+    fn account(key: &Pubkey) -> TestAccount {
+        let mut data = vec![0; 165];
+        put(&mut data, 4, &[1, 2]);
+        put(&mut data, 64, &500u64.to_le_bytes());
+        put(&mut data, 100, &key.to_bytes());
+        let mut account = TestAccount::new(Pubkey::from_str(OWNER).unwrap(), data);
+        account.account_meta.lamports = 2_039_280;
+        account
+    }
+
+    #[test]
+    fn parses_u64_ranges() {
+        let range = |s| U64Range::parse(s).map(|range| (range.min, range.max));
+        assert_eq!(range("165"), Some((165, 165)));
+        assert_eq!(range("1..10"), Some((1, 10)));
+        assert_eq!(range(">7"), Some((8, u64::MAX)));
+        assert_eq!(range("<7"), Some((0, 6)));
+        for invalid in [
+            "",
+            "x",
+            "-1",
+            "10..1",
+            "1..",
+            "..5",
+            "1...5",
+            "<0",
+            ">18446744073709551615",
+            "18446744073709551616",
+        ] {
+            assert_eq!(range(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn u64_ranges_round_trip_through_display() {
+        for s in ["0", "165", "1..10", ">7", "<7", "0..18446744073709551615"] {
+            let range = U64Range::parse(s).unwrap();
+            assert_eq!(U64Range::parse(&range.to_string()), Some(range), "{}", s);
+        }
+        let range = U64Range::parse("10..20").unwrap();
+        assert!(!range.contains(9));
+        assert!(range.contains(10));
+        assert!(range.contains(20));
+        assert!(!range.contains(21));
+    }
+
+    #[test]
+    fn owner_filter_options_match_account_data() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(""));
+        assert!(matches(",size:165"));
+        assert!(matches(",size:82,size:165"));
+        assert!(matches(",size:100..200"));
+        assert!(!matches(",size:82"));
+        assert!(matches(",memcmp:0x0102@4"));
+        assert!(!matches(",memcmp:0x0102@5"));
+        assert!(matches(&format!(
+            ",memcmp:{}@4",
+            bs58::encode([1u8, 2]).into_string()
+        )));
+        // reads past the end of the data never match, even at offsets that overflow
+        assert!(!matches(",memcmp:0x00@165"));
+        assert!(!matches(",memcmp:0x00@18446744073709551615"));
+
+        let other_owner = OwnerFilter::new(&Pubkey::new_unique().to_string()).unwrap();
+        assert!(!other_owner.is_match(&account));
+    }
+
+    #[test]
+    fn lamports_option_matches_a_range() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(",lamports:>2000000"));
+        assert!(matches(",lamports:2039280"));
+        assert!(!matches(",lamports:<2000000"));
+        for invalid in [",lamports:x", ",lamports:1,lamports:2"] {
+            assert!(
+                matches!(
+                    owner_filter(invalid),
+                    Err(FilterParseError::InvalidLamportsFilter)
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn masked_memcmp_skips_wildcard_bytes() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(",memcmp:0x01??@4"));
+        assert!(matches(",memcmp:0x????@4"));
+        assert!(!matches(",memcmp:0x??01@4"));
+        assert!(!matches(",memcmp:0x01??@164"));
+    }
+
+    #[test]
+    fn integer_ranges_compare_little_endian_fields() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(",u64le-between:500..500@64"));
+        assert!(!matches(",u64le-between:0..499@64"));
+        assert!(matches(",i64le-between:-1..500@64"));
+        assert!(!matches(",i64le-between:-10..-1@64"));
+        assert!(!matches(",u64le-between:0..1@18446744073709551615"));
+        for invalid in [
+            ",u64le-between:5@0",
+            ",u64le-between:a..5@0",
+            ",i64le-between:0..9223372036854775808@0",
+        ] {
+            assert!(
+                matches!(
+                    owner_filter(invalid),
+                    Err(FilterParseError::InvalidRangeFilter)
+                ),
+                "{}",
+                invalid
+            );
         }
+    }
+
+    #[test]
+    fn references_match_a_key_at_any_offset() {
+        let key = Pubkey::new_unique();
+        let account = account(&key);
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(&format!(",references:{}", key)));
+        assert!(!matches(&format!(",references:{}", Pubkey::new_unique())));
+        assert!(matches!(
+            owner_filter(",references:0x01"),
+            Err(FilterParseError::InvalidReferencesFilter)
+        ));
+    }
+
+    #[test]
+    fn owner_filter_key_files_accept_base58_and_hex() {
+        let key = Pubkey::new_unique();
+        let keys = TempPath::file(
+            "keys",
+            format!(
+                "{}\n\n  0x{}  \n",
+                Pubkey::new_unique(),
+                hex::encode(key.to_bytes())
+            ),
+        );
+        let account = account(&key);
+        let account = account.stored();
+        assert!(owner_filter(&format!(",memcmpfile:{}@100", keys.display()))
+            .unwrap()
+            .is_match(&account));
+        assert!(!owner_filter(&format!(",memcmpfile:{}@4", keys.display()))
+            .unwrap()
+            .is_match(&account));
+        assert!(owner_filter(&format!(",referencesfile:{}", keys.display()))
+            .unwrap()
+            .is_match(&account));
+
+        let short = TempPath::file("short-key", format!("0x{}\n", hex::encode([7u8; 31])));
+        assert!(matches!(
+            owner_filter(&format!(",memcmpfile:{}@0", short.display())),
+            Err(FilterParseError::InvalidMemcmpFileFilter)
+        ));
+        assert!(matches!(
+            owner_filter(&format!(",referencesfile:{}", short.display())),
+            Err(FilterParseError::InvalidReferencesFileFilter)
+        ));
+        assert!(matches!(
+            owner_filter(",memcmpfile:/nonexistent/keys@0"),
+            Err(FilterParseError::InvalidMemcmpFileFilter)
+        ));
+    }
+
+    #[test]
+    fn not_inverts_an_option() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(",not:size:82"));
+        assert!(!matches(",not:memcmp:0x0102@4"));
+        assert!(matches(",size:165,not:lamports:<5"));
+        assert!(matches!(
+            owner_filter(",not:size:x"),
+            Err(FilterParseError::InvalidSizeFilter)
+        ));
+    }
+
+    #[test]
+    fn anchor_option_matches_the_account_discriminator() {
+        let mut data = vec![0; 16];
+        put(&mut data, 0, &anchor_account_discriminator("Whirlpool"));
+        let account = TestAccount::new(Pubkey::from_str(OWNER).unwrap(), data);
+        let account = account.stored();
+        let matches = |options: &str| owner_filter(options).unwrap().is_match(&account);
+
+        assert!(matches(",anchor:Whirlpool"));
+        assert!(!matches(",anchor:Position"));
+        assert!(matches!(
+            owner_filter(",anchor:1Name"),
+            Err(FilterParseError::UnknownFilter)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_owner_filters() {
+        assert!(matches!(
+            OwnerFilter::new("0wner"),
+            Err(FilterParseError::InvalidOwnerFilterSyntax)
+        ));
+        assert!(matches!(
+            OwnerFilter::new(&format!("{},", OWNER)),
+            Err(FilterParseError::InvalidOwnerFilterSyntax)
+        ));
+        assert!(matches!(
+            OwnerFilter::new("abc"),
+            Err(FilterParseError::InvalidOwnerPubkey)
+        ));
+        let cases = [
+            (",size:10..1", "size"),
+            (",size:", "unknown"),
+            (",memcmp:0x01@99999999999999999999999", "offset"),
+            (",memcmp:0x0@1", "unknown"),
+            (",bogus:1", "unknown"),
+        ];
+        for (options, expected) in cases {
+            let kind = match owner_filter(options) {
+                Err(FilterParseError::InvalidSizeFilter) => "size",
+                Err(FilterParseError::InvalidOffsetMemcmpFilter) => "offset",
+                Err(FilterParseError::UnknownFilter) => "unknown",
+                Err(e) => panic!("{}: {}", options, e),
+                Ok(_) => panic!("{} parsed", options),
+            };
+            assert_eq!(kind, expected, "{}", options);
+        }
+    }
+
+    #[test]
+    fn canonical_owner_filters_round_trip() {
+        let key = Pubkey::new_unique();
+        let a = owner_filter(&format!(
+            ",size:165,size:82,memcmp:0x0102@4,lamports:1..5,u64le-between:1..2@64,references:{}",
+            key
+        ))
+        .unwrap();
+        let b = owner_filter(&format!(
+            ",references:{},u64le-between:1..2@64,lamports:1..5,memcmp:{}@4,size:82,size:165",
+            key,
+            bs58::encode([1u8, 2]).into_string()
+        ))
+        .unwrap();
+        assert_eq!(a.canonical(), b.canonical());
+
+        // single options read back as themselves; size lists are joined with `/`
+        let c = owner_filter(&format!(
+            ",size:>100,memcmp:0x01??@4,lamports:<5,i64le-between:-1..2@64,references:{}",
+            key
+        ))
+        .unwrap();
+        let reparsed = OwnerFilter::new(&c.canonical()).unwrap();
+        assert_eq!(reparsed.canonical(), c.canonical());
+
+        let anchor = owner_filter(",anchor:Whirlpool").unwrap();
+        let hex = hex::encode(anchor_account_discriminator("Whirlpool"));
+        let memcmp = owner_filter(&format!(",memcmp:0x{}@0", hex)).unwrap();
+        assert_eq!(anchor.canonical(), memcmp.canonical());
+    }
+
+    #[test]
+    fn match_modes_combine_pubkey_and_owner_filters() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let filter = |pubkey: &Pubkey, owner: &str, mode| {
+            AccountFilter::new(
+                &[pubkey.to_string()],
+                None,
+                &[owner.to_string()],
+                &[],
+                None,
+                mode,
+            )
+            .unwrap()
+            .matched_by(&account, |_| false)
+        };
+        let listed = account.meta.pubkey;
+        let other = Pubkey::new_unique();
+        let other_owner = Pubkey::new_unique().to_string();
+
+        assert_eq!(
+            filter(&listed, &other_owner, MatchMode::Any),
+            Some(MatchedBy::Pubkey)
+        );
+        assert_eq!(
+            filter(&other, OWNER, MatchMode::Any),
+            Some(MatchedBy::Owner(0))
+        );
+        assert_eq!(filter(&other, &other_owner, MatchMode::Any), None);
+        assert_eq!(
+            filter(&listed, OWNER, MatchMode::All),
+            Some(MatchedBy::Owner(0))
+        );
+        assert_eq!(filter(&listed, &other_owner, MatchMode::All), None);
+        assert_eq!(filter(&other, OWNER, MatchMode::All), None);
+
+        let everything = AccountFilter::new(&[], None, &[], &[], None, MatchMode::Any).unwrap();
+        assert!(everything.matches_everything());
+        assert_eq!(
+            everything.matched_by(&account, |_| false),
+            Some(MatchedBy::Everything)
+        );
+    }
+
+    #[test]
+    fn reads_pubkey_files() {
+        let account = account(&Pubkey::new_unique());
+        let account = account.stored();
+        let pubkeys = TempPath::file(
+            "pubkeys",
+            format!("{}\n\n  {}  \n", Pubkey::new_unique(), account.meta.pubkey),
+        );
+        let filter =
+            AccountFilter::new(&[], pubkeys.to_str(), &[], &[], None, MatchMode::Any).unwrap();
+        assert_eq!(
+            filter.matched_by(&account, |_| false),
+            Some(MatchedBy::Pubkey)
+        );
 
-        return false;
+        let missing = TempPath::new("missing-pubkeys");
+        assert!(matches!(
+            AccountFilter::new(&[], missing.to_str(), &[], &[], None, MatchMode::Any),
+            Err(FilterParseError::InvalidPubkeyFile)
+        ));
+        let not_utf8 = TempPath::file("binary-pubkeys", [0xff, 0xfe, b'\n']);
+        assert!(matches!(
+            AccountFilter::new(&[], not_utf8.to_str(), &[], &[], None, MatchMode::Any),
+            Err(FilterParseError::InvalidPubkeyFile)
+        ));
     }
 }
@@ -0,0 +1,295 @@
+use crate::filter::{FilterParseError, MemCmp};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::str::FromStr;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum BytesEncoding {
+    #[default]
+    Base58,
+    Base64,
+}
+
+/// `memcmp` as in the RPC `getProgramAccounts` filters.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMemcmp {
+    offset: usize,
+    bytes: String,
+    #[serde(default)]
+    encoding: BytesEncoding,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum RawExpr {
+    And(Vec<RawExpr>),
+    Or(Vec<RawExpr>),
+    Not(Box<RawExpr>),
+    Owner(String),
+    Pubkey(String),
+    DataSize(u64),
+    Memcmp(RawMemcmp),
+}
+
+/// A top-level array is an implicit `and`, like the RPC `filters` list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTopLevel {
+    All(Vec<RawExpr>),
+    One(RawExpr),
+}
+
+/// Boolean combination of account conditions read from `--filter-json`.
+#[derive(Clone)]
+pub enum FilterExpr {
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Owner(Pubkey),
+    Pubkey(Pubkey),
+    DataSize(u64),
+    Memcmp(MemCmp),
+}
+
+impl FromStr for FilterExpr {
+    type Err = FilterParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw: RawTopLevel = read_json(s).map_err(FilterParseError::InvalidFilterJson)?;
+        match raw {
+            RawTopLevel::All(exprs) => Ok(FilterExpr::And(compile_all(exprs)?)),
+            RawTopLevel::One(expr) => compile(expr),
+        }
+    }
+}
+
+/// Deserializes inline JSON, or the contents of the file named by `@PATH`.
+fn read_json<T: serde::de::DeserializeOwned>(s: &str) -> Result<T, String> {
+    let json = match s.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| e.to_string())?,
+        None => s.to_string(),
+    };
+    serde_json::from_str(&json).map_err(|e| e.to_string())
+}
+
+/// Parses the `filters` array of an RPC `getProgramAccounts` call (`dataSize` and `memcmp`
+/// entries, all of which must match), inline or from `@PATH`.
+pub fn rpc_filters(s: &str) -> Result<Vec<FilterExpr>, FilterParseError> {
+    let filters: Vec<RawExpr> = read_json(s).map_err(FilterParseError::InvalidRpcFilters)?;
+    filters
+        .into_iter()
+        .map(|filter| match filter {
+            RawExpr::DataSize(_) | RawExpr::Memcmp(_) => compile(filter),
+            _ => Err(FilterParseError::InvalidRpcFilters(
+                "only dataSize and memcmp filters are supported".to_string(),
+            )),
+        })
+        .collect()
+}
+
+fn compile_all(exprs: Vec<RawExpr>) -> Result<Vec<FilterExpr>, FilterParseError> {
+    exprs.into_iter().map(compile).collect()
+}
+
+fn compile(expr: RawExpr) -> Result<FilterExpr, FilterParseError> {
+    let pubkey = |s: &str| {
+        Pubkey::from_str(s)
+            .map_err(|_e| FilterParseError::InvalidFilterJson(format!("invalid pubkey {}", s)))
+    };
+    Ok(match expr {
+        RawExpr::And(exprs) => FilterExpr::And(compile_all(exprs)?),
+        RawExpr::Or(exprs) => FilterExpr::Or(compile_all(exprs)?),
+        RawExpr::Not(expr) => FilterExpr::Not(Box::new(compile(*expr)?)),
+        RawExpr::Owner(owner) => FilterExpr::Owner(pubkey(&owner)?),
+        RawExpr::Pubkey(key) => FilterExpr::Pubkey(pubkey(&key)?),
+        RawExpr::DataSize(size) => FilterExpr::DataSize(size),
+        RawExpr::Memcmp(memcmp) => {
+            let bytes = match memcmp.encoding {
+                BytesEncoding::Base58 => bs58::decode(&memcmp.bytes).into_vec().ok(),
+                BytesEncoding::Base64 => STANDARD.decode(&memcmp.bytes).ok(),
+            };
+            let bytes = bytes.ok_or(FilterParseError::InvalidBytesMemcmpFilter)?;
+            FilterExpr::Memcmp(MemCmp::new(memcmp.offset, bytes))
+        }
+    })
+}
+
+impl FilterExpr {
+    /// The expression with `and`/`or` operands sorted, so their order doesn't matter.
+    pub(crate) fn canonical(&self) -> String {
+        let operands = |exprs: &[FilterExpr]| {
+            let mut operands: Vec<String> = exprs.iter().map(FilterExpr::canonical).collect();
+            operands.sort_unstable();
+            operands.dedup();
+            operands.join(",")
+        };
+        match self {
+            FilterExpr::And(exprs) => format!("and({})", operands(exprs)),
+            FilterExpr::Or(exprs) => format!("or({})", operands(exprs)),
+            FilterExpr::Not(expr) => format!("not({})", expr.canonical()),
+            FilterExpr::Owner(owner) => format!("owner({})", owner),
+            FilterExpr::Pubkey(pubkey) => format!("pubkey({})", pubkey),
+            FilterExpr::DataSize(size) => format!("data-size({})", size),
+            FilterExpr::Memcmp(memcmp) => memcmp.canonical(),
+        }
+    }
+
+    pub fn is_match(&self, account: &StoredAccountMeta) -> bool {
+        match self {
+            FilterExpr::And(exprs) => exprs.iter().all(|expr| expr.is_match(account)),
+            FilterExpr::Or(exprs) => exprs.iter().any(|expr| expr.is_match(account)),
+            FilterExpr::Not(expr) => !expr.is_match(account),
+            FilterExpr::Owner(owner) => account.account_meta.owner == *owner,
+            FilterExpr::Pubkey(pubkey) => account.meta.pubkey == *pubkey,
+            FilterExpr::DataSize(size) => account.meta.data_len == *size,
+            FilterExpr::Memcmp(memcmp) => memcmp.is_match(account.data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{put, TempPath, TestAccount};
+
+    fn parse(s: &str) -> FilterExpr {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    #[test]
+    fn matches_boolean_combinations() {
+        let owner = Pubkey::new_unique();
+        let mut data = vec![0; 100];
+        put(&mut data, 10, &[0xde, 0xad]);
+        let account = TestAccount::new(owner, data);
+        let account = account.stored();
+        let memcmp = format!(
+            r#"{{"memcmp": {{"offset": 10, "bytes": "{}"}}}}"#,
+            bs58::encode([0xde, 0xad]).into_string()
+        );
+        let matches = |s: &str| parse(s).is_match(&account);
+
+        assert!(matches(&format!(r#"{{"owner": "{}"}}"#, owner)));
+        assert!(matches(&format!(
+            r#"{{"pubkey": "{}"}}"#,
+            account.meta.pubkey
+        )));
+        assert!(matches(r#"{"dataSize": 100}"#));
+        assert!(!matches(r#"{"dataSize": 99}"#));
+        assert!(matches(&memcmp));
+        assert!(matches(
+            r#"{"memcmp": {"offset": 10, "bytes": "3q0=", "encoding": "base64"}}"#
+        ));
+        assert!(!matches(
+            r#"{"memcmp": {"offset": 99, "bytes": "3q0=", "encoding": "base64"}}"#
+        ));
+        assert!(matches(&format!(r#"[{}, {{"dataSize": 100}}]"#, memcmp)));
+        assert!(!matches(&format!(r#"[{}, {{"dataSize": 99}}]"#, memcmp)));
+        assert!(matches(&format!(
+            r#"{{"or": [{{"dataSize": 99}}, {}]}}"#,
+            memcmp
+        )));
+        assert!(!matches(&format!(r#"{{"not": {}}}"#, memcmp)));
+        assert!(matches(r#"{"and": []}"#));
+        assert!(!matches(r#"{"or": []}"#));
+    }
+
+    #[test]
+    fn canonical_form_ignores_operand_order() {
+        let a = parse(r#"{"or": [{"dataSize": 82}, {"dataSize": 165}]}"#);
+        let b = parse(r#"{"or": [{"dataSize": 165}, {"dataSize": 82}, {"dataSize": 165}]}"#);
+        assert_eq!(a.canonical(), "or(data-size(165),data-size(82))");
+        assert_eq!(a.canonical(), b.canonical());
+
+        let base58 = parse(&format!(
+            r#"{{"memcmp": {{"offset": 1, "bytes": "{}"}}}}"#,
+            bs58::encode([0xde, 0xad]).into_string()
+        ));
+        let base64 = parse(r#"{"memcmp": {"offset": 1, "bytes": "3q0=", "encoding": "base64"}}"#);
+        assert_eq!(base58.canonical(), "memcmp:0xdead@1");
+        assert_eq!(base58.canonical(), base64.canonical());
+    }
+
+    #[test]
+    fn reads_filters_from_a_file() {
+        let path = TempPath::file("filters.json", r#"[{"dataSize": 165}]"#);
+        let arg = format!("@{}", path.display());
+        assert_eq!(parse(&arg).canonical(), "and(data-size(165))");
+        assert_eq!(rpc_filters(&arg).unwrap().len(), 1);
+        drop(path);
+        assert!(matches!(
+            arg.parse::<FilterExpr>(),
+            Err(FilterParseError::InvalidFilterJson(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        for s in [
+            "",
+            "{",
+            "{}",
+            r#"{"dataSize": -1}"#,
+            r#"{"dataSize": 1, "owner": "x"}"#,
+            r#"{"size": 1}"#,
+            r#"{"owner": "not a pubkey"}"#,
+            r#"{"pubkey": "11"}"#,
+            r#"{"not": [{"dataSize": 1}]}"#,
+            r#"{"memcmp": {"offset": 0}}"#,
+            r#"{"memcmp": {"offset": 0, "bytes": "1", "extra": 1}}"#,
+            r#"{"memcmp": {"offset": 0, "bytes": "1", "encoding": "hex"}}"#,
+        ] {
+            assert!(
+                matches!(
+                    s.parse::<FilterExpr>(),
+                    Err(FilterParseError::InvalidFilterJson(_))
+                ),
+                "{}",
+                s
+            );
+        }
+        for s in [
+            r#"{"memcmp": {"offset": 0, "bytes": "0OIl"}}"#,
+            r#"{"memcmp": {"offset": 0, "bytes": "***", "encoding": "base64"}}"#,
+        ] {
+            assert!(
+                matches!(
+                    s.parse::<FilterExpr>(),
+                    Err(FilterParseError::InvalidBytesMemcmpFilter)
+                ),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn rpc_filters_take_only_data_size_and_memcmp() {
+        let filters = rpc_filters(
+            r#"[{"dataSize": 165}, {"memcmp": {"offset": 32, "bytes": "3q0=", "encoding": "base64"}}]"#,
+        )
+        .unwrap();
+        let canonical: Vec<String> = filters.iter().map(FilterExpr::canonical).collect();
+        assert_eq!(canonical, ["data-size(165)", "memcmp:0xdead@32"]);
+
+        assert!(rpc_filters("[]").unwrap().is_empty());
+        for s in [
+            r#"{"dataSize": 165}"#,
+            r#"[{"owner": "11111111111111111111111111111111"}]"#,
+            r#"[{"not": {"dataSize": 1}}]"#,
+            "[",
+        ] {
+            assert!(
+                matches!(rpc_filters(s), Err(FilterParseError::InvalidRpcFilters(_))),
+                "{}",
+                s
+            );
+        }
+    }
+}
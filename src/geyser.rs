@@ -0,0 +1,371 @@
+use libloading::{Library, Symbol};
+use log::{info, warn};
+use solana_geyser_plugin_interface::geyser_plugin_interface::{
+    GeyserPlugin, GeyserPluginError, ReplicaAccountInfo, ReplicaAccountInfoVersions, SlotStatus,
+};
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GeyserLoadError {
+    #[error("Failed to read Geyser plugin config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid Geyser plugin config: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Geyser plugin config has no \"libpath\"")]
+    MissingLibpath,
+    #[error("Failed to load Geyser plugin library: {0}")]
+    Library(#[from] libloading::Error),
+    #[error("Geyser plugin failed to load: {0}")]
+    Plugin(#[from] GeyserPluginError),
+}
+
+/// Order in which matched accounts reach the plugin.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayOrder {
+    /// As stored in the archive
+    #[default]
+    Archive,
+    /// By ascending slot, then write version, as the validator restores storages
+    Slot,
+}
+
+/// Pacing of account notifications.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Replay {
+    pub order: ReplayOrder,
+    /// Send a `Processed` slot status heartbeat after every N accounts.
+    pub batch: Option<u64>,
+}
+
+/// Account data `--replay-order=slot` keeps in memory; the rest goes to a temp file.
+const REPLAY_MEMORY: usize = 1 << 30;
+
+/// A matched account kept until its slot is replayed with `--replay-order=slot`.
+struct BufferedAccount {
+    pubkey: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    executable: bool,
+    rent_epoch: u64,
+    write_version: u64,
+    data: BufferedData,
+}
+
+enum BufferedData {
+    Memory(Vec<u8>),
+    /// Offset and length in the spill file
+    Spilled(u64, usize),
+}
+
+/// Temp file for account data past `REPLAY_MEMORY`, removed on drop.
+struct SpillFile {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    len: u64,
+}
+
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "solana-snapshot-gpa-replay-{}.tmp",
+            std::process::id()
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path,
+            len: 0,
+        })
+    }
+
+    fn append(&mut self, data: &[u8]) -> io::Result<BufferedData> {
+        self.writer.write_all(data)?;
+        let offset = self.len;
+        self.len += data.len() as u64;
+        Ok(BufferedData::Spilled(offset, data.len()))
+    }
+
+    /// Reads back appended data; the first call flushes the writer.
+    fn read(&mut self, offset: u64, len: usize, buf: &mut Vec<u8>) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(offset))?;
+        buf.resize(len, 0);
+        file.read_exact(buf)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Matched accounts held back by `--replay-order=slot` until the scan ends, with data past
+/// `memory` bytes spilled to a temp file.
+struct ReplayBuffer {
+    memory: usize,
+    accounts: BTreeMap<u64, Vec<BufferedAccount>>,
+    memory_bytes: usize,
+    spill: Option<SpillFile>,
+}
+
+impl ReplayBuffer {
+    fn new(memory: usize) -> Self {
+        Self {
+            memory,
+            accounts: BTreeMap::new(),
+            memory_bytes: 0,
+            spill: None,
+        }
+    }
+
+    fn push(&mut self, slot: u64, account: &StoredAccountMeta) -> io::Result<()> {
+        let data = self.buffer_data(account.data)?;
+        self.accounts
+            .entry(slot)
+            .or_default()
+            .push(BufferedAccount {
+                pubkey: account.meta.pubkey,
+                owner: account.account_meta.owner,
+                lamports: account.account_meta.lamports,
+                executable: account.account_meta.executable,
+                rent_epoch: account.account_meta.rent_epoch,
+                write_version: account.meta.write_version,
+                data,
+            });
+        Ok(())
+    }
+
+    /// Keeps `data` in memory while the buffered data fits in `memory`, and appends it to
+    /// the spill file past that.
+    fn buffer_data(&mut self, data: &[u8]) -> io::Result<BufferedData> {
+        if self.memory_bytes + data.len() <= self.memory {
+            self.memory_bytes += data.len();
+            return Ok(BufferedData::Memory(data.to_vec()));
+        }
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => {
+                let spill = SpillFile::create()?;
+                info!(
+                    "Over {} bytes of buffered account data, spilling to {}",
+                    self.memory,
+                    spill.path.display()
+                );
+                self.spill.insert(spill)
+            }
+        };
+        spill.append(data)
+    }
+
+    /// Calls `f(slot, Some(account))` for the buffered accounts by ascending slot, then
+    /// write version, and `f(slot, None)` after the last account of each slot.
+    fn replay(
+        mut self,
+        mut f: impl FnMut(u64, Option<&ReplicaAccountInfo>) -> Result<(), GeyserPluginError>,
+    ) -> Result<(), GeyserPluginError> {
+        let mut spilled = Vec::new();
+        for (slot, mut accounts) in std::mem::take(&mut self.accounts) {
+            accounts.sort_by_key(|account| account.write_version);
+            for account in &accounts {
+                let data = match &account.data {
+                    BufferedData::Memory(data) => data,
+                    BufferedData::Spilled(offset, len) => {
+                        self.spill
+                            .as_mut()
+                            .expect("spilled data has a spill file")
+                            .read(*offset, *len, &mut spilled)
+                            .map_err(spill_error)?;
+                        &spilled
+                    }
+                };
+                let info = ReplicaAccountInfo {
+                    pubkey: account.pubkey.as_ref(),
+                    lamports: account.lamports,
+                    owner: account.owner.as_ref(),
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                    data,
+                    write_version: account.write_version,
+                };
+                f(slot, Some(&info))?;
+            }
+            f(slot, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Signature of the `_create_plugin` symbol every Geyser plugin library exports.
+type PluginConstructor = unsafe fn() -> *mut dyn GeyserPlugin;
+
+/// Feeds matched accounts to a Geyser plugin as the validator does while loading a
+/// snapshot at startup: `update_account` with `is_startup`, then `notify_end_of_startup`.
+pub struct GeyserPluginSink {
+    // declared before the library so the plugin is dropped while its code is still mapped
+    plugin: Box<dyn GeyserPlugin>,
+    _library: Library,
+    replay: Replay,
+    buffered: ReplayBuffer,
+    notified: u64,
+}
+
+impl GeyserPluginSink {
+    /// Loads the library named by `libpath` in the validator-style JSON config (relative to
+    /// the config file) and calls `on_load` with the config path.
+    pub fn load(config_path: &Path, replay: Replay) -> Result<Self, GeyserLoadError> {
+        let config: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(config_path)?)?;
+        let libpath = config["libpath"]
+            .as_str()
+            .ok_or(GeyserLoadError::MissingLibpath)?;
+        let libpath = match config_path.parent() {
+            Some(dir) => dir.join(libpath),
+            None => libpath.into(),
+        };
+
+        // SAFETY: the plugin is trusted like any validator plugin; its ABI must match the
+        // solana-geyser-plugin-interface version this binary is built with
+        #[allow(unsafe_code)]
+        let (library, plugin) = unsafe {
+            let library = Library::new(&libpath)?;
+            let constructor: Symbol<PluginConstructor> = library.get(b"_create_plugin")?;
+            let plugin = Box::from_raw(constructor());
+            (library, plugin)
+        };
+        let mut sink = Self {
+            plugin,
+            _library: library,
+            replay,
+            buffered: ReplayBuffer::new(REPLAY_MEMORY),
+            notified: 0,
+        };
+        let config_path = config_path.to_string_lossy();
+        sink.plugin.on_load(&config_path)?;
+        info!("Loaded Geyser plugin {}", sink.plugin.name());
+        if !sink.plugin.account_data_notifications_enabled() {
+            warn!(
+                "Geyser plugin {} has account notifications disabled",
+                sink.plugin.name()
+            );
+        }
+        Ok(sink)
+    }
+
+    pub fn dump_account(
+        &mut self,
+        slot: u64,
+        account: &StoredAccountMeta,
+    ) -> Result<(), GeyserPluginError> {
+        if self.replay.order == ReplayOrder::Slot {
+            return self.buffered.push(slot, account).map_err(spill_error);
+        }
+        let info = ReplicaAccountInfo {
+            pubkey: account.meta.pubkey.as_ref(),
+            lamports: account.account_meta.lamports,
+            owner: account.account_meta.owner.as_ref(),
+            executable: account.account_meta.executable,
+            rent_epoch: account.account_meta.rent_epoch,
+            data: account.data,
+            write_version: account.meta.write_version,
+        };
+        self.notify(slot, &info)
+    }
+
+    fn notify(&mut self, slot: u64, info: &ReplicaAccountInfo) -> Result<(), GeyserPluginError> {
+        self.plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_1(info), slot, true)?;
+        self.notified += 1;
+        if self
+            .replay
+            .batch
+            .is_some_and(|batch| batch > 0 && self.notified.is_multiple_of(batch))
+        {
+            self.plugin
+                .update_slot_status(slot, None, SlotStatus::Processed)?;
+        }
+        Ok(())
+    }
+
+    /// Replays buffered slots, committing each with a `Rooted` slot status, then ends the
+    /// startup phase and unloads the plugin.
+    pub fn finish(&mut self) -> Result<(), GeyserPluginError> {
+        let buffered = std::mem::replace(&mut self.buffered, ReplayBuffer::new(REPLAY_MEMORY));
+        buffered.replay(|slot, account| match account {
+            Some(info) => self.notify(slot, info),
+            None => self
+                .plugin
+                .update_slot_status(slot, None, SlotStatus::Rooted),
+        })?;
+        self.plugin.notify_end_of_startup()?;
+        self.plugin.on_unload();
+        Ok(())
+    }
+}
+
+fn spill_error(err: io::Error) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestAccount;
+
+    #[test]
+    fn replays_by_slot_and_write_version_past_the_memory_limit() {
+        // [slot, write version, data]; the first two and the empty one fit in memory, the rest spills
+        let pushed: [(u64, u64, &[u8]); 5] = [
+            (5, 3, &[1; 4]),
+            (5, 1, &[2; 4]),
+            (3, 2, &[3; 6]),
+            (5, 2, &[4]),
+            (3, 1, &[]),
+        ];
+        let mut buffer = ReplayBuffer::new(8);
+        for (slot, write_version, data) in pushed {
+            let mut account = TestAccount::new(Pubkey::new_unique(), data.to_vec());
+            account.meta.write_version = write_version;
+            buffer.push(slot, &account.stored()).unwrap();
+        }
+        let spill = buffer.spill.as_ref().expect("a spill file");
+        assert_eq!(spill.len, 7);
+        let spill = spill.path.clone();
+        assert!(spill.exists());
+
+        let mut replayed = vec![];
+        buffer
+            .replay(|slot, account| {
+                replayed.push((
+                    slot,
+                    account.map(|info| (info.write_version, info.data.to_vec())),
+                ));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            replayed,
+            [
+                (3, Some((1, vec![]))),
+                (3, Some((2, vec![3; 6]))),
+                (3, None),
+                (5, Some((1, vec![2; 4]))),
+                (5, Some((2, vec![4]))),
+                (5, Some((3, vec![1; 4]))),
+                (5, None),
+            ]
+        );
+        assert!(!spill.exists());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::dump::DumpOptions;
+use crate::encoding::{DataEncoding, EncodedData, Lamports};
+use crate::output::Output;
+
+use serde::Serialize;
+use solana_program::rent::Rent;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::io::{self, Write};
+
+/// Writes the `{"accounts": [...], "summary": {...}}` document produced by
+/// `agave-ledger-tool accounts --output json`, keeping its field names and ordering.
+pub struct LedgerToolJsonDumper {
+    writer: Output,
+    summary: TotalAccountsStats,
+    rent: Rent,
+}
+
+#[derive(Serialize)]
+struct KeyedAccount<'a> {
+    pubkey: String,
+    account: UiAccount<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UiAccount<'a> {
+    lamports: u64,
+    data: (EncodedData<'a>, &'static str),
+    owner: String,
+    executable: bool,
+    rent_epoch: u64,
+    space: u64,
+}
+
+/// One `--format=jsonl` line.
+#[derive(Serialize)]
+struct JsonlRecord<'a> {
+    pubkey: String,
+    owner: String,
+    lamports: Lamports,
+    /// Left out with `--data-encoding=none`, which reports `data_len` instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<EncodedData<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_len: Option<u64>,
+    slot: u64,
+    write_version: u64,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct TotalAccountsStats {
+    num_accounts: u64,
+    data_len: u64,
+    num_executable_accounts: u64,
+    executable_data_len: u64,
+    num_rent_exempt_accounts: u64,
+    num_rent_paying_accounts: u64,
+    num_rent_paying_accounts_without_data: u64,
+    lamports_in_rent_paying_accounts: u64,
+}
+
+impl LedgerToolJsonDumper {
+    pub fn new(writer: Output) -> Self {
+        Self {
+            writer,
+            summary: TotalAccountsStats::default(),
+            rent: Rent::default(),
+        }
+    }
+
+    pub fn dump_account(
+        &mut self,
+        options: &DumpOptions,
+        account: &StoredAccountMeta,
+    ) -> io::Result<()> {
+        let separator: &[u8] = if self.summary.num_accounts == 0 {
+            b"{\"accounts\":["
+        } else {
+            b","
+        };
+        self.writer.write_all(separator)?;
+
+        let record = KeyedAccount {
+            pubkey: options.format_pubkey(account),
+            account: UiAccount {
+                lamports: account.account_meta.lamports,
+                data: (
+                    options.encoded_data(account),
+                    options
+                        .data_encoding
+                        .ledger_tool_name()
+                        .expect("checked when parsing arguments"),
+                ),
+                owner: account.account_meta.owner.to_string(),
+                executable: account.account_meta.executable,
+                rent_epoch: account.account_meta.rent_epoch,
+                space: account.meta.data_len,
+            },
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+
+        self.update_summary(account);
+        Ok(())
+    }
+
+    fn update_summary(&mut self, account: &StoredAccountMeta) {
+        let data_len = account.meta.data_len;
+        let lamports = account.account_meta.lamports;
+        let summary = &mut self.summary;
+        summary.num_accounts += 1;
+        summary.data_len += data_len;
+        if account.account_meta.executable {
+            summary.num_executable_accounts += 1;
+            summary.executable_data_len += data_len;
+        }
+        if self.rent.is_exempt(lamports, data_len as usize) {
+            summary.num_rent_exempt_accounts += 1;
+        } else {
+            summary.num_rent_paying_accounts += 1;
+            summary.lamports_in_rent_paying_accounts += lamports;
+            if data_len == 0 {
+                summary.num_rent_paying_accounts_without_data += 1;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        let prefix: &[u8] = if self.summary.num_accounts == 0 {
+            b"{\"accounts\":[],\"summary\":"
+        } else {
+            b"],\"summary\":"
+        };
+        self.writer.write_all(prefix)?;
+        serde_json::to_writer(&mut self.writer, &self.summary)?;
+        self.writer.write_all(b"}\n")?;
+        self.writer.finish()
+    }
+}
+
+/// Writes one JSON object per matched account and line, for jq and Spark style ingestion.
+pub struct JsonlDumper {
+    writer: Output,
+}
+
+impl JsonlDumper {
+    pub fn new(writer: Output) -> Self {
+        Self { writer }
+    }
+
+    pub fn dump_account(
+        &mut self,
+        options: &DumpOptions,
+        slot: u64,
+        account: &StoredAccountMeta,
+    ) -> io::Result<()> {
+        let record = JsonlRecord {
+            pubkey: options.format_pubkey(account),
+            owner: account.account_meta.owner.to_string(),
+            lamports: Lamports {
+                format: options.lamports_as,
+                lamports: account.account_meta.lamports,
+            },
+            data: (options.data_encoding != DataEncoding::None)
+                .then(|| options.encoded_data(account)),
+            data_len: (options.data_encoding == DataEncoding::None)
+                .then_some(account.meta.data_len),
+            slot,
+            write_version: account.meta.write_version,
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempPath, TestAccount};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde_json::Value;
+    use solana_program::pubkey::Pubkey;
+
+    /// A rent-exempt account with data and an executable rent-paying one without.
+    fn accounts() -> [TestAccount; 2] {
+        let mut exempt = TestAccount::new(Pubkey::new_from_array([2; 32]), vec![1, 2, 3]);
+        exempt.meta.pubkey = Pubkey::new_from_array([1; 32]);
+        exempt.account_meta.rent_epoch = 361;
+        let mut paying = TestAccount::new(Pubkey::new_from_array([4; 32]), vec![]);
+        paying.meta.pubkey = Pubkey::new_from_array([3; 32]);
+        paying.account_meta.lamports = 5;
+        paying.account_meta.executable = true;
+        [exempt, paying]
+    }
+
+    fn dump(data_encoding: DataEncoding, accounts: &[TestAccount]) -> String {
+        let options = DumpOptions {
+            data_encoding,
+            ..Default::default()
+        };
+        let path = TempPath::new("ledger-tool.json");
+        let mut dumper = LedgerToolJsonDumper::new(Output::open(path.to_str(), None).unwrap());
+        for account in accounts {
+            dumper.dump_account(&options, &account.stored()).unwrap();
+        }
+        dumper.finish().unwrap();
+        std::fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn writes_the_ledger_tool_document() {
+        assert_eq!(
+            dump(DataEncoding::Base64, &accounts()),
+            concat!(
+                r#"{"accounts":["#,
+                r#"{"pubkey":"4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi","account":{"#,
+                r#""lamports":1000000000,"data":["AQID","base64"],"#,
+                r#""owner":"8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR","#,
+                r#""executable":false,"rentEpoch":361,"space":3}},"#,
+                r#"{"pubkey":"CktRuQ2mttgRGkXJtyksdKHjUdc2C4TgDzyB98oEzy8","account":{"#,
+                r#""lamports":5,"data":["","base64"],"#,
+                r#""owner":"GgBaCs3NCBuZN12kCJgAW63ydqohFkHEdfdEXBPzLHq","#,
+                r#""executable":true,"rentEpoch":0,"space":0}}],"#,
+                r#""summary":{"numAccounts":2,"dataLen":3,"#,
+                r#""numExecutableAccounts":1,"executableDataLen":0,"#,
+                r#""numRentExemptAccounts":1,"numRentPayingAccounts":1,"#,
+                r#""numRentPayingAccountsWithoutData":1,"lamportsInRentPayingAccounts":5}}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn writes_an_empty_document_without_accounts() {
+        assert_eq!(
+            dump(DataEncoding::Base64, &[]),
+            concat!(
+                r#"{"accounts":[],"summary":{"numAccounts":0,"dataLen":0,"#,
+                r#""numExecutableAccounts":0,"executableDataLen":0,"#,
+                r#""numRentExemptAccounts":0,"numRentPayingAccounts":0,"#,
+                r#""numRentPayingAccountsWithoutData":0,"lamportsInRentPayingAccounts":0}}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn labels_data_with_its_encoding() {
+        let data = |data_encoding| {
+            let json: Value = serde_json::from_str(&dump(data_encoding, &accounts())).unwrap();
+            json["accounts"][0]["account"]["data"].clone()
+        };
+        assert_eq!(
+            data(DataEncoding::Base58),
+            serde_json::json!(["Ldp", "base58"])
+        );
+
+        let zstd = data(DataEncoding::Base64Zstd);
+        assert_eq!(zstd[1], "base64+zstd");
+        let compressed = STANDARD.decode(zstd[0].as_str().unwrap()).unwrap();
+        assert_eq!(zstd::bulk::decompress(&compressed, 3).unwrap(), [1, 2, 3]);
+    }
+}
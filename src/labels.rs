@@ -0,0 +1,68 @@
+use crate::registry::WELL_KNOWN_PROGRAMS;
+
+use rusqlite::{params, Connection, OpenFlags};
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LabelsError {
+    #[error("Label database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Invalid pubkey in label database: {0}")]
+    InvalidPubkey(String),
+}
+
+/// Pubkey names read from a `labels(pubkey, name, category)` SQLite table.
+///
+/// The whole table is loaded up front so output never waits on a query.
+#[derive(Debug, Default)]
+pub struct Labels {
+    names: HashMap<Pubkey, String>,
+}
+
+impl Labels {
+    pub fn open(path: &Path) -> Result<Self, LabelsError> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut stmt = conn.prepare("SELECT pubkey, name FROM labels")?;
+        let mut names = HashMap::new();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let pubkey: String = row.get(0)?;
+            let name: String = row.get(1)?;
+            let pubkey =
+                Pubkey::from_str(&pubkey).map_err(|_e| LabelsError::InvalidPubkey(pubkey))?;
+            names.insert(pubkey, name);
+        }
+        Ok(Labels { names })
+    }
+
+    /// Name for `pubkey`, or an empty string when it isn't labelled.
+    pub fn get(&self, pubkey: &Pubkey) -> &str {
+        self.names.get(pubkey).map(String::as_str).unwrap_or("")
+    }
+}
+
+/// Creates the labels table at `path` if needed and adds the starter set of well-known
+/// program ids, keeping any existing rows. Returns the number of rows added.
+pub fn init(path: &Path) -> Result<usize, LabelsError> {
+    let conn = Connection::open(path)?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS labels (
+            pubkey TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            category TEXT NOT NULL DEFAULT ''
+        )",
+        [],
+    )?;
+    let mut added = 0;
+    for (pubkey, name) in WELL_KNOWN_PROGRAMS {
+        added += conn.execute(
+            "INSERT OR IGNORE INTO labels (pubkey, name, category) VALUES (?1, ?2, ?3)",
+            params![pubkey, name, "program"],
+        )?;
+    }
+    Ok(added)
+}
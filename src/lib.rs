@@ -0,0 +1,67 @@
+//! Account filtering, snapshot scanning and the output sinks of `solana-snapshot-gpa`, for
+//! programs that embed the scan instead of running the CLI and parsing its CSV.
+//!
+//! [`accounts::SnapshotAccounts`] collects the newest versions of given pubkeys to seed
+//! `solana-program-test` with. [`SnapshotScanner`] yields the accounts matching a
+//! [`filter::AccountFilter`]; the
+//! [`csv::CsvDumper`], [`json::JsonlDumper`], [`json::LedgerToolJsonDumper`] and
+//! [`columnar::ParquetDumper`] write them as the CLI does, configured by
+//! [`dump::DumpOptions`].
+//!
+//! ```no_run
+//! use solana_snapshot_gpa::csv::CsvDumper;
+//! use solana_snapshot_gpa::dump::DumpOptions;
+//! use solana_snapshot_gpa::filter::{AccountFilter, MatchMode};
+//! use solana_snapshot_gpa::modified_solana_snapshot_etl::ArchiveSnapshotExtractor;
+//! use solana_snapshot_gpa::output::Output;
+//! use solana_snapshot_gpa::SnapshotScanner;
+//! use std::path::Path;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut extractor = ArchiveSnapshotExtractor::open(Path::new("snapshot.tar.zst"))?;
+//! let owners = ["TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string()];
+//! let filter = AccountFilter::new(&[], None, &owners, &[], None, MatchMode::Any)?;
+//! let options = DumpOptions::default();
+//! let mut csv = CsvDumper::new(Output::open(None, None)?);
+//! for matches in SnapshotScanner::new(&mut extractor, filter) {
+//!     let matches = matches?;
+//!     for account in matches.accounts() {
+//!         csv.dump_account(&options, matches.slot, matches.id, &account, None)?;
+//!     }
+//! }
+//! csv.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+
+// unsafe blocks need a local #[allow(unsafe_code)] and a SAFETY comment saying why they hold
+#![deny(unsafe_code)]
+
+pub mod accounts;
+pub mod anonymize;
+pub mod append_vec;
+pub mod catalog;
+pub mod columnar;
+pub mod csv;
+pub mod cursor;
+pub mod decode;
+pub mod dump;
+pub mod encoding;
+pub mod filter;
+pub mod filter_json;
+pub mod geyser;
+pub mod json;
+pub mod labels;
+pub mod modified_solana_snapshot_etl;
+pub mod output;
+pub mod parallel;
+pub mod pg;
+pub mod project;
+pub mod registry;
+mod scanner;
+pub mod stake_summary;
+pub mod stats;
+#[doc(hidden)]
+pub mod testing;
+
+pub use scanner::{Matches, SnapshotScanner};
@@ -1,21 +1,277 @@
+#![deny(unsafe_code)]
+
+use crate::anonymize::Anonymizer;
+use crate::append_vec::AppendVec;
+use crate::cache::{CachedArchive, CachedDownload, CachedSource};
+use crate::catalog::{parse_byte_size, parse_incremental_base_slot, parse_snapshot_name, Catalog};
+use crate::checkpoint::Checkpoint;
 use crate::csv::CsvDumper;
-use crate::filter::AccountFilter;
+use crate::cursor::{snapshot_id, AppendVecCursor, Coordinate};
+use crate::daemon::DaemonConfig;
+use crate::decode::{DecodeErrors, DecodeMode, Schema, TokenAmounts};
+use crate::dump::{DumpOptions, Dumper, Format, Sink};
+use crate::encoding::{DataEncoding, LamportsFormat, MAX_BASE58_LEN};
+use crate::filter::{AccountFilter, MatchMode, OwnerFilter, U64Range};
+use crate::filter_json::FilterExpr;
+use crate::geyser::{GeyserPluginSink, Replay, ReplayOrder};
+use crate::labels::Labels;
+use crate::notify::{Notify, RunManifest, RunSummary};
+use crate::output::{write_checksum, Encryption, Output};
+use crate::parallel::Candidates;
+use crate::pg::PostgresSink;
+use crate::prepass::OwnerQuery;
+use crate::progress::{Progress, ProgressFormat};
+use crate::project::Projection;
+use crate::registry::ProgramRegistry;
+use crate::stake_summary::StakeSummary;
+use crate::stats::Stats;
 
-use clap::Parser;
-use log::{error, info};
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
+use modified_solana_snapshot_etl::{
+    AppendVecIterator, ArchiveSnapshotExtractor, SnapshotExtractor,
+};
 use reqwest::blocking::Response;
-use modified_solana_snapshot_etl::{AppendVecIterator, SnapshotExtractor, ArchiveSnapshotExtractor};
-use std::fs::{File};
-use std::path::{Path};
+use solana_program::hash::hash;
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
-mod modified_solana_snapshot_etl;
+#[cfg(test)]
+use solana_snapshot_gpa::testing;
+use solana_snapshot_gpa::{
+    anonymize, append_vec, catalog, csv, cursor, decode, dump, encoding, filter, filter_json,
+    geyser, labels, modified_solana_snapshot_etl, output, parallel, pg, project, registry,
+    stake_summary, stats,
+};
 
-mod csv;
-mod filter;
+mod cache;
+mod checkpoint;
+mod closed;
+mod daemon;
+mod notify;
+mod patch;
+mod prepass;
+mod progress;
+mod upgradeable;
+mod verify;
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Snapshot catalog file (default: ~/.solana-snapshot-gpa/catalog.json)
+    #[clap(long = "catalog", global = true)]
+    catalog_path: Option<String>,
+
+    #[clap(flatten)]
+    dump: DumpArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the local catalog of snapshot archives
+    #[clap(subcommand)]
+    Catalog(CatalogCommand),
+    /// Delete old catalogued snapshots to stay within count and disk budgets
+    Gc(GcArgs),
+    /// Write a copy of a snapshot archive with account overrides applied
+    Patch(PatchArgs),
+    /// Report accounts alive in BEFORE but missing or zero-lamport in AFTER
+    Closed(ClosedArgs),
+    /// Re-read single accounts by the slot, id and offset columns of a previous dump
+    Get(GetArgs),
+    /// Manage pubkey label databases
+    #[clap(subcommand)]
+    Labels(LabelsCommand),
+    /// List upgradeable programs with their upgrade authority
+    Programs(ProgramsArgs),
+    /// Watch a directory for new snapshots and run the configured dump jobs on each
+    Daemon(DaemonArgs),
+    /// Compare the accounts an --owner filter selects in a snapshot with getProgramAccounts
+    VerifyFilters(VerifyFiltersArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum CatalogCommand {
+    /// Record snapshot archives in the catalog
+    Add {
+        #[clap(help = "Snapshot archive files", required = true)]
+        paths: Vec<String>,
+    },
+    /// List catalogued snapshots
+    List,
+    /// Forget the snapshot recorded for a slot (the archive is left on disk)
+    Remove {
+        #[clap(help = "Snapshot slot")]
+        slot: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LabelsCommand {
+    /// Create a label database (or extend an existing one) with well-known program ids
+    Init {
+        #[clap(help = "Label database file")]
+        path: String,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct GcArgs {
+    /// Keep only the newest N snapshots
+    #[clap(long)]
+    keep_last: Option<usize>,
+
+    /// Keep the newest snapshots whose total size fits into this budget (e.g. 2TB)
+    #[clap(long, value_parser = parse_byte_size)]
+    max_disk: Option<u64>,
+
+    /// Also collect the downloads cached by --cache-dir in DIR, by the same budgets
+    #[clap(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Only print what would be deleted
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DaemonArgs {
+    /// JSON5 file with watch_dir, output_dir, jobs and retention settings
+    #[clap(long)]
+    config: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct PatchArgs {
+    /// JSON list of overrides: [{"pubkey": .., "lamports": .., "owner": .., "data": base64}]
+    #[clap(long)]
+    overrides: String,
+
+    /// Patched snapshot archive to write
+    #[clap(long)]
+    output: String,
+
+    #[clap(flatten)]
+    snapshot: SourceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ClosedArgs {
+    /// Only track accounts owned by the specified program id (same syntax as the dump --owner)
+    #[clap(short, long)]
+    owner: Vec<String>,
+
+    /// Write the report to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Use the catalogued snapshot for this slot as BEFORE
+    #[clap(long)]
+    before_slot: Option<u64>,
+
+    /// Use the catalogued snapshot for this slot as AFTER
+    #[clap(long)]
+    after_slot: Option<u64>,
+
+    #[clap(
+        value_name = "BEFORE AFTER",
+        help = "Earlier and later snapshot archive files, leaving out those given by slot"
+    )]
+    snapshots: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ProgramsArgs {
+    /// Only list programs upgradeable by this authority
+    #[clap(long)]
+    authority: Option<Pubkey>,
+
+    /// Write the report to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+
+    #[clap(flatten)]
+    snapshot: SourceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyFiltersArgs {
+    /// Owner filter to check against the snapshot (same syntax as the dump --owner)
+    #[clap(short, long)]
+    owner: String,
+
+    /// JSON-RPC endpoint to call getProgramAccounts on
+    #[clap(long)]
+    rpc_url: String,
+
+    /// getProgramAccounts filters JSON (or @FILE) sent for the program of --owner
+    #[clap(long)]
+    rpc_filters: Option<String>,
+
+    /// Fetch the RPC results in pages of N with getProgramAccountsV2, where the provider has it
+    #[clap(long)]
+    page_size: Option<usize>,
+
+    /// Write the differences to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+
+    #[clap(flatten)]
+    snapshot: SourceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct GetArgs {
+    /// Account coordinate: SLOT:ID:OFFSET (the slot, id and offset CSV columns) or a snap:// URI
+    #[clap(long = "at", required = true)]
+    coordinates: Vec<Coordinate>,
+
+    /// Suppress output of header line
+    #[clap(short, long)]
+    noheader: bool,
+
+    /// Write output to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+
+    #[clap(flatten)]
+    snapshot: SourceArgs,
+}
+
+/// The snapshot a command reads: an archive, or the catalogued one of a slot.
+#[derive(clap::Args, Debug)]
+struct SourceArgs {
+    /// Use the catalogued snapshot for this slot instead of SOURCE
+    #[clap(long)]
+    slot: Option<u64>,
+
+    #[clap(help = "Snapshot archive file")]
+    source: Option<String>,
+}
+
+impl SourceArgs {
+    fn resolve(&self, catalog_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+        catalogued_source(
+            self.source.as_deref(),
+            self.slot,
+            ("SOURCE", "--slot"),
+            catalog_path,
+        )
+    }
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(group(clap::ArgGroup::new("decoding").args(&["decode", "borsh-schema", "idl"])))]
+struct DumpArgs {
     /// Fetch the account for the specified public key
     #[clap(short, long)]
     pubkey: Vec<String>,
@@ -28,12 +284,307 @@ struct Args {
     #[clap(short, long)]
     owner: Vec<String>,
 
+    /// Fetch the accounts matching a JSON filter expression with and/or/not groups (or @FILE)
+    #[clap(long)]
+    filter_json: Vec<String>,
+
+    /// Print the filters and passes over the source this dump would run, then exit
+    #[clap(long)]
+    explain_plan: bool,
+
+    /// RPC getProgramAccounts filters JSON (or @FILE) added to every --owner filter
+    #[clap(long, requires = "owner")]
+    rpc_filters: Option<String>,
+
+    /// Never emit accounts matching this owner filter (same syntax as --owner)
+    #[clap(long)]
+    exclude_owner: Vec<String>,
+
+    /// Fetch all the accounts whose data contains this public key at any offset
+    #[clap(long)]
+    references: Vec<String>,
+
+    /// Fetch all the accounts whose data contains any public key in the file
+    #[clap(long)]
+    referencesfile: Option<String>,
+
+    /// Fetch all the accounts whose owner is a program owned by this loader (two passes)
+    #[clap(long)]
+    owner_owned_by: Option<Pubkey>,
+
+    /// Fetch all the accounts owned by accounts matching this pre-pass filter
+    /// ('owner=<OWNER_OPTS>' or 'references=<PUBKEY>'; two passes)
+    #[clap(long)]
+    owners_from_query: Vec<OwnerQuery>,
+
+    /// Emit only the newest version of each account, dropping closed ones (three passes)
+    #[clap(long)]
+    dedup: bool,
+
+    /// How pubkey filters and --owner/--references filters combine when both are given
+    #[clap(long, value_enum, default_value = "any")]
+    match_mode: MatchMode,
+
+    /// Only emit accounts holding MIN..MAX (inclusive), >N or <N lamports
+    #[clap(long, value_parser = parse_lamports)]
+    lamports: Option<U64Range>,
+
+    /// Log how every filter evaluates for this account each time the scan reaches a version of it
+    #[clap(long)]
+    trace_pubkey: Option<Pubkey>,
+
     /// Suppress output of header line
     #[clap(short, long)]
     noheader: bool,
 
-    #[clap(help = "Snapshot archive file")]
-    source: String,
+    /// Output format
+    #[clap(long, value_enum, default_value = "csv")]
+    format: Format,
+
+    /// Write output to this file instead of stdout
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Send matched accounts to the Geyser plugin described by this validator-style config
+    #[clap(long, conflicts_with_all = &["output", "encrypt", "checksum"])]
+    geyser_plugin: Option<PathBuf>,
+
+    /// With --geyser-plugin, send a Processed slot status heartbeat after every N accounts
+    #[clap(long, requires = "geyser-plugin")]
+    replay_batch: Option<u64>,
+
+    /// With --geyser-plugin, order of account notifications [default: archive]
+    #[clap(long, value_enum, requires = "geyser-plugin")]
+    replay_order: Option<ReplayOrder>,
+
+    /// COPY matched accounts into a Postgres table at this postgres:// URL
+    #[clap(long, conflicts_with_all = &["output", "encrypt", "checksum", "geyser-plugin"])]
+    postgres: Option<String>,
+
+    /// With --postgres, table to write, created if missing
+    #[clap(long, requires = "postgres", default_value = "accounts")]
+    postgres_table: String,
+
+    /// With --postgres, key the table on pubkey and keep only the newest version of each account
+    #[clap(long, requires = "postgres")]
+    postgres_upsert: bool,
+
+    /// With --postgres, rows per COPY batch
+    #[clap(long, requires = "postgres", default_value_t = 10_000)]
+    postgres_batch: usize,
+
+    /// Write total delegated stake per vote account instead of one record per account
+    /// (implies --dedup)
+    #[clap(long, conflicts_with_all = &["geyser-plugin", "postgres", "decoding"])]
+    summarize_stake: bool,
+
+    /// Write per-owner counts, lamports, data bytes and size histograms instead of accounts
+    #[clap(
+        long,
+        conflicts_with_all = &["geyser-plugin", "postgres", "summarize-stake", "decoding"]
+    )]
+    stats: bool,
+
+    /// Encrypt output with age or gpg (age:RECIPIENT, gpg:RECIPIENT)
+    #[clap(long)]
+    encrypt: Option<Encryption>,
+
+    /// Write a <OUTPUT>.sha256 sidecar once the output file is complete, and one for
+    /// --unknown-owner-output
+    #[clap(long, requires = "output")]
+    checksum: bool,
+
+    /// Record finished append vecs in this file, and resume after them when it exists
+    #[clap(
+        long,
+        requires = "output",
+        conflicts_with_all = &[
+            "encrypt",
+            "geyser-plugin",
+            "postgres",
+            "summarize-stake",
+            "stats",
+            "unknown-owner-output",
+            "first",
+            "limit-per-owner",
+            "expect-matches",
+        ]
+    )]
+    checkpoint: Option<PathBuf>,
+
+    /// Emit only the first N data bytes (hex) in a data_preview column instead of data
+    #[clap(long)]
+    preview_bytes: Option<usize>,
+
+    /// Emit only LEN data bytes starting at OFFSET, like RPC dataSlice
+    #[clap(
+        long,
+        value_parser = parse_data_slice,
+        conflicts_with_all = &["preview-bytes", "project", "fields"]
+    )]
+    data_slice: Option<(usize, usize)>,
+
+    /// Encoding of the data column [default: base64]
+    #[clap(long, value_enum, conflicts_with_all = &["preview-bytes", "project", "fields"])]
+    data_encoding: Option<DataEncoding>,
+
+    /// Write lamports as integers or as SOL with 9 decimals [default: u64]
+    #[clap(long, value_enum, conflicts_with_all = &["project", "fields"])]
+    lamports_as: Option<LamportsFormat>,
+
+    /// Custom output columns, e.g. '{pk: .pubkey, amt: u64le(.data, 64)}'
+    #[clap(long, conflicts_with = "preview-bytes")]
+    project: Option<Projection>,
+
+    /// Output only these record fields, e.g. pubkey,owner,lamports
+    #[clap(
+        long,
+        value_parser = Projection::from_fields,
+        conflicts_with_all = &["preview-bytes", "project"]
+    )]
+    fields: Option<Projection>,
+
+    /// Add columns decoded from a known account layout (a decoder name, or auto to pick by owner)
+    #[clap(long)]
+    decode: Option<DecodeMode>,
+
+    /// Add a decoded JSON column read with this Borsh layout (see README)
+    #[clap(long)]
+    borsh_schema: Option<PathBuf>,
+
+    /// Add account_type and decoded JSON columns for the account types of this Anchor IDL
+    #[clap(long)]
+    idl: Option<PathBuf>,
+
+    /// Add a decode_anomalies column flagging decoded accounts that break layout invariants
+    #[clap(long, requires = "decoding")]
+    decode_validate: bool,
+
+    /// Write decoded amounts of mints with known decimals as integers or decimals [default: u64]
+    #[clap(long, value_enum, requires = "decoding")]
+    token_amounts_as: Option<TokenAmounts>,
+
+    /// With --decode=auto, write matches whose owner has no registered decoder to this file
+    #[clap(long, requires = "decode")]
+    unknown_owner_output: Option<String>,
+
+    /// What to do with matches the decoder rejects [default: emit-raw]
+    #[clap(long, value_enum, requires = "decoding")]
+    decode_errors: Option<DecodeErrors>,
+
+    /// Threads decoding matched accounts, separate from the scan [default: number of CPUs]
+    #[clap(long, requires = "decoding")]
+    decode_threads: Option<usize>,
+
+    /// Worker threads filtering append vecs in parallel; output order is unchanged [default: 1]
+    #[clap(long)]
+    threads: Option<usize>,
+
+    /// Replace account pubkeys with keyed hashes (hmac:KEY)
+    #[clap(long)]
+    anonymize: Option<Anonymizer>,
+
+    /// Flush buffered output every N records (default: only when the buffer fills)
+    #[clap(long, value_parser = parse_flush_every)]
+    flush_every: Option<u64>,
+
+    /// Emit at most N accounts per --owner filter
+    #[clap(long)]
+    limit_per_owner: Option<u64>,
+
+    /// Add a uri column of snap://SNAPSHOT/SLOT.ID/OFFSET coordinates accepted by get
+    #[clap(long)]
+    uri_column: bool,
+
+    /// Add an owner_name column naming well-known programs (Token, Stake, Vote, ...)
+    #[clap(long)]
+    resolve_owner_names: bool,
+
+    /// Add owner_label and pubkey_label columns from this SQLite label database
+    #[clap(long, conflicts_with = "anonymize")]
+    labels: Option<String>,
+
+    /// Stop the whole scan as soon as N accounts have matched
+    #[clap(long)]
+    first: Option<u64>,
+
+    /// Fail the run if fewer than N accounts matched
+    #[clap(long)]
+    expect_matches: Option<u64>,
+
+    /// Abort if nothing matched after scanning this many GB of account data
+    #[clap(long, value_parser = parse_gigabytes)]
+    abort_if_zero_after: Option<u64>,
+
+    /// On completion or failure, POST the run manifest to this URL or pipe it to cmd:COMMAND
+    #[clap(long)]
+    notify: Option<Notify>,
+
+    /// How to report scan progress on stderr [default: bar on a terminal, log otherwise]
+    #[clap(long, value_enum)]
+    progress_format: Option<ProgressFormat>,
+
+    /// Don't report scan progress
+    #[clap(short, long, conflicts_with = "progress-format")]
+    quiet: bool,
+
+    /// Keep fetched bytes of http(s) sources here so re-runs and retries don't re-download
+    #[clap(long)]
+    cache_dir: Option<String>,
+
+    /// Incremental snapshot archive whose newer account versions replace those of SOURCE
+    #[clap(long)]
+    incremental: Option<String>,
+
+    #[clap(flatten)]
+    snapshot: SourceArgs,
+}
+
+fn parse_gigabytes(s: &str) -> Result<u64, String> {
+    let bytes = s
+        .parse::<f64>()
+        .map(|gb| gb * 1_000_000_000f64)
+        .map_err(|_e| format!("Invalid GB value: {}", s))?;
+    // `as` would saturate NaN, negative and huge values instead of rejecting them
+    if !bytes.is_finite() || bytes <= 0.0 || bytes >= u64::MAX as f64 {
+        return Err(format!(
+            "Invalid GB value: {} (expected a positive number)",
+            s
+        ));
+    }
+    Ok(bytes as u64)
+}
+
+fn parse_flush_every(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(0) | Err(_) => Err(format!("Invalid row count: {}", s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+fn parse_lamports(s: &str) -> Result<U64Range, String> {
+    U64Range::parse(s).ok_or_else(|| format!("Invalid lamports range: {}", s))
+}
+
+fn parse_data_slice(s: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid data slice (expected OFFSET:LEN): {}", s);
+    let (offset, length) = s.split_once(':').ok_or_else(invalid)?;
+    match (offset.parse::<usize>(), length.parse::<usize>()) {
+        (Ok(offset), Ok(length)) => Ok((offset, length)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Set by the Ctrl-C handler installed by `watch_interrupts`.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the Ctrl-C handler once per process; later calls (daemon jobs) reuse it.
+fn watch_interrupts() -> Result<(), ctrlc::Error> {
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    if !INSTALLED.swap(true, Ordering::SeqCst) {
+        ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst))?;
+    }
+    Ok(())
 }
 
 fn main() {
@@ -41,51 +592,1056 @@ fn main() {
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
     );
     if let Err(e) = _main() {
-        error!("{}", e);
+        // stdout closed by the reader, e.g. piped into head: exit without an error message
+        let broken_pipe = e
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe);
+        if !broken_pipe {
+            error!("{}", e);
+        }
         std::process::exit(1);
     }
 }
 
 fn _main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    let catalog_path = args
+        .catalog_path
+        .map(PathBuf::from)
+        .unwrap_or_else(Catalog::default_path);
+
+    match args.command {
+        Some(Command::Catalog(command)) => run_catalog(command, &catalog_path),
+        Some(Command::Gc(gc)) => run_gc(gc, &catalog_path),
+        Some(Command::Patch(patch)) => run_patch(patch, &catalog_path),
+        Some(Command::Closed(closed)) => run_closed(closed, &catalog_path),
+        Some(Command::Get(get)) => run_get(get, &catalog_path),
+        Some(Command::Labels(LabelsCommand::Init { path })) => {
+            let added = labels::init(path.as_ref())?;
+            info!("Added {} labels to {}", added, path);
+            Ok(())
+        }
+        Some(Command::Programs(programs)) => run_programs(programs, &catalog_path),
+        Some(Command::Daemon(daemon)) => run_daemon(daemon, &catalog_path),
+        Some(Command::VerifyFilters(verify)) => run_verify_filters(verify, &catalog_path),
+        None => run_dump(args.dump, &catalog_path),
+    }
+}
+
+fn run_catalog(
+    command: CatalogCommand,
+    catalog_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut catalog = Catalog::open(catalog_path)?;
+    match command {
+        CatalogCommand::Add { paths } => {
+            for path in paths.iter() {
+                let entry = catalog.add(path.as_ref())?;
+                info!("Added snapshot for slot {}: {}", entry.slot, entry.path);
+            }
+            catalog.save()?;
+        }
+        CatalogCommand::List => {
+            println!("slot\thash\tsize\tpath");
+            for entry in catalog.entries() {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    entry.slot, entry.hash, entry.size, entry.path
+                );
+            }
+        }
+        CatalogCommand::Remove { slot } => {
+            let entry = catalog.remove(slot)?;
+            catalog.save()?;
+            info!("Removed snapshot for slot {}: {}", entry.slot, entry.path);
+        }
+    }
+    Ok(())
+}
 
-    let filter = AccountFilter::new(&args.pubkey, &args.pubkeyfile, &args.owner)?;
-    let mut loader = SupportedLoader::new(&args.source)?;
+fn run_gc(args: GcArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if args.keep_last.is_none() && args.max_disk.is_none() {
+        return Err("gc needs --keep-last and/or --max-disk".into());
+    }
+
+    let mut catalog = Catalog::open(catalog_path)?;
+    let evicted = catalog
+        .gc_candidates(args.keep_last, args.max_disk)
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    let cached = match &args.cache_dir {
+        Some(cache_dir) => cache::gc_candidates(cache_dir, args.keep_last, args.max_disk)?,
+        None => vec![],
+    };
+    if evicted.is_empty() && cached.is_empty() {
+        info!("Nothing to collect");
+        return Ok(());
+    }
+
+    let mut freed = 0u64;
+    for entry in evicted {
+        let slot = entry.slot;
+        if args.dry_run {
+            info!("Would delete snapshot for slot {}: {}", slot, entry.path);
+            freed += entry.size;
+            continue;
+        }
+
+        catalog.remove_path(&entry.path);
+        match std::fs::remove_file(&entry.path) {
+            Ok(()) => info!("Deleted snapshot for slot {}: {}", slot, entry.path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!(
+                    "Snapshot for slot {} was already gone: {}",
+                    slot, entry.path
+                )
+            }
+            Err(e) => return Err(e.into()),
+        }
+        freed += entry.size;
+        // persist after every deletion so an error midway leaves an accurate catalog
+        catalog.save()?;
+    }
+    for (path, size) in cached {
+        if args.dry_run {
+            info!("Would delete cached download {}", path.display());
+        } else {
+            std::fs::remove_file(&path)?;
+            info!("Deleted cached download {}", path.display());
+        }
+        freed += size;
+    }
+    info!("Freed {} bytes", freed);
+    Ok(())
+}
+
+/// Parses a daemon job as the dump command line `ARGS --output OUTPUT SOURCE`.
+fn job_dump_args(
+    args: &[String],
+    source: &Path,
+    output: &Path,
+) -> Result<DumpArgs, Box<dyn std::error::Error>> {
+    let command_line = std::iter::once(OsStr::new("solana-snapshot-gpa"))
+        .chain(args.iter().map(OsStr::new))
+        .chain([
+            OsStr::new("--output"),
+            output.as_os_str(),
+            source.as_os_str(),
+        ]);
+    let parsed = Args::try_parse_from(command_line)?;
+    if parsed.command.is_some() {
+        return Err("daemon jobs must be dumps, not subcommands".into());
+    }
+    Ok(parsed.dump)
+}
+
+fn run_daemon(args: DaemonArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let config = DaemonConfig::open(&args.config)?;
+    // reject malformed jobs at startup rather than on the first snapshot
+    for job in config.jobs.iter() {
+        job_dump_args(&job.args, Path::new("snapshot.tar.zst"), Path::new("out"))
+            .map_err(|e| format!("Invalid arguments for job {}: {}", job.name, e))?;
+    }
+    watch_interrupts()?;
+    daemon::run(
+        &config,
+        |args, source, output| run_dump(job_dump_args(args, source, output)?, catalog_path),
+        || INTERRUPTED.load(Ordering::SeqCst),
+    )?;
+    Ok(())
+}
+
+fn run_patch(args: PatchArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let source = args.snapshot.resolve(catalog_path)?;
+    let overrides = patch::load_overrides(args.overrides.as_ref())?;
+    info!("Loaded {} account overrides", overrides.len());
+    patch::patch_snapshot(source.as_ref(), args.output.as_ref(), &overrides)?;
+    info!("Done!");
+    Ok(())
+}
+
+fn run_closed(args: ClosedArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    // archive files fill the places --before-slot and --after-slot leave
+    let mut files = args.snapshots.iter().map(String::as_str);
+    let mut resolve = |slot: Option<u64>, names| {
+        let file = if slot.is_none() { files.next() } else { None };
+        catalogued_source(file, slot, names, catalog_path)
+    };
+    let before_source = resolve(args.before_slot, ("BEFORE", "--before-slot"))?;
+    let after_source = resolve(args.after_slot, ("AFTER", "--after-slot"))?;
+    if files.next().is_some() {
+        return Err("closed takes two snapshots, counting those given by slot".into());
+    }
+
+    let filter = AccountFilter::new(&[], None, &args.owner, &[], None, MatchMode::Any)?;
+    let mut before = SupportedLoader::new(&before_source, None)?;
+    let mut before_again = SupportedLoader::new(&before_source, None)?;
+    let mut after = SupportedLoader::new(&after_source, None)?;
+    let closed = closed::closed_accounts(&mut before, &mut before_again, &mut after, &filter)?;
+
+    let mut writer = ::csv::Writer::from_writer(Output::open(args.output.as_deref(), None)?);
+    writer.write_record(["owner", "pubkey", "lamports", "slot"])?;
+    for account in closed.iter() {
+        writer.write_record([
+            account.owner.to_string(),
+            account.pubkey.to_string(),
+            account.lamports.to_string(),
+            account.slot.to_string(),
+        ])?;
+    }
+    for (owner, count, lamports) in closed::owner_totals(&closed) {
+        info!(
+            "{}: {} accounts closed, {} lamports",
+            owner, count, lamports
+        );
+    }
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+    info!("{} closed accounts", closed.len());
+    Ok(())
+}
+
+fn run_programs(args: ProgramsArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let source = args.snapshot.resolve(catalog_path)?;
+    let mut loader = SupportedLoader::new(&source, None)?;
+    let mut programs = upgradeable::upgradeable_programs(&mut loader)?;
+    if let Some(authority) = args.authority {
+        programs.retain(|program| {
+            program
+                .programdata
+                .as_ref()
+                .is_some_and(|programdata| programdata.upgrade_authority == Some(authority))
+        });
+    }
+
+    let mut writer = ::csv::Writer::from_writer(Output::open(args.output.as_deref(), None)?);
+    writer.write_record([
+        "program_id",
+        "programdata_address",
+        "upgrade_authority",
+        "last_deploy_slot",
+        "programdata_size",
+    ])?;
+    for program in programs.iter() {
+        let (upgrade_authority, last_deploy_slot, size) = match &program.programdata {
+            Some(programdata) => (
+                programdata
+                    .upgrade_authority
+                    .map_or_else(|| "none".to_string(), |authority| authority.to_string()),
+                programdata.last_deploy_slot.to_string(),
+                programdata.size.to_string(),
+            ),
+            // programdata closed: the program can no longer be invoked
+            None => (String::new(), String::new(), String::new()),
+        };
+        writer.write_record([
+            program.program_id.to_string(),
+            program.programdata_address.to_string(),
+            upgrade_authority,
+            last_deploy_slot,
+            size,
+        ])?;
+    }
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+    info!("{} upgradeable programs", programs.len());
+    Ok(())
+}
+
+fn run_verify_filters(
+    args: VerifyFiltersArgs,
+    catalog_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let source = args.snapshot.resolve(catalog_path)?;
+    let owners = [args.owner.clone()];
+    let filter = AccountFilter::new(&[], None, &owners, &[], None, MatchMode::Any)?;
+    let program = OwnerFilter::new(&args.owner)?.owner();
+    let rpc_filters = match &args.rpc_filters {
+        Some(filters) => verify::read_rpc_filters(filters)?,
+        None => serde_json::Value::Array(vec![]),
+    };
+
+    info!("getProgramAccounts for {} on {}", program, args.rpc_url);
+    let rpc = verify::rpc_pubkeys(&args.rpc_url, &program, &rpc_filters, args.page_size)?;
+    let at_slot = rpc.slot.map(|slot| format!(" at slot {}", slot));
+    info!(
+        "RPC returned {} accounts{}",
+        rpc.pubkeys.len(),
+        at_slot.unwrap_or_default()
+    );
+    let mut first = SupportedLoader::new(&source, None)?;
+    let mut second = SupportedLoader::new(&source, None)?;
+    let mut third = SupportedLoader::new(&source, None)?;
+    let snapshot = verify::snapshot_pubkeys(&mut first, &mut second, &mut third, &filter)?;
+    info!("Snapshot filter matched {} accounts", snapshot.len());
+
+    let only_in_rpc = rpc.pubkeys.difference(&snapshot);
+    let mut differences: Vec<(&Pubkey, &str)> = snapshot
+        .difference(&rpc.pubkeys)
+        .map(|pubkey| (pubkey, "snapshot"))
+        .chain(only_in_rpc.map(|pubkey| (pubkey, "rpc")))
+        .collect();
+    differences.sort();
+    let mut writer = ::csv::Writer::from_writer(Output::open(args.output.as_deref(), None)?);
+    writer.write_record(["pubkey", "only_in"])?;
+    for (pubkey, only_in) in differences.iter() {
+        writer.write_record([pubkey.to_string().as_str(), only_in])?;
+    }
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    let only_in_snapshot = differences
+        .iter()
+        .filter(|(_, only_in)| *only_in == "snapshot")
+        .count();
+    let summary = format!(
+        "{} accounts in both, {} only in the snapshot, {} only in RPC",
+        snapshot.len() - only_in_snapshot,
+        only_in_snapshot,
+        differences.len() - only_in_snapshot
+    );
+    if differences.is_empty() {
+        info!("Filters agree: {}", summary);
+    } else {
+        warn!("Filters disagree: {}", summary);
+    }
+    Ok(())
+}
+
+fn run_get(args: GetArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let source = args.snapshot.resolve(catalog_path)?;
+    let source_snapshot = snapshot_id(&source);
+    // group by append vec so every append vec is visited once, in archive order
+    let mut wanted: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    for coordinate in args.coordinates.iter() {
+        if let Some(actual) = &source_snapshot {
+            if !coordinate.points_into(actual) {
+                return Err(
+                    format!("{} does not point into snapshot {}", coordinate, actual).into(),
+                );
+            }
+        }
+        wanted
+            .entry((coordinate.slot, coordinate.id))
+            .or_default()
+            .push(coordinate.offset);
+    }
+
+    let mut loader = SupportedLoader::new(&source, None)?;
+    let mut cursor = AppendVecCursor::new(loader.iter());
+    let mut dumper = CsvDumper::new(Output::open(args.output.as_deref(), None)?);
+    let options = DumpOptions {
+        noheader: args.noheader,
+        ..Default::default()
+    };
+    while !wanted.is_empty() {
+        let (slot, id) = match cursor.seek(|slot, id| wanted.contains_key(&(slot, id)))? {
+            Some(found) => found,
+            None => break,
+        };
+        for offset in wanted.remove(&(slot, id)).unwrap_or_default() {
+            let written = cursor.with_account(offset, |slot, id, account| {
+                dumper.dump_account(&options, slot, id, account, None)
+            });
+            match written {
+                Some(result) => result?,
+                None => warn!("No account starts at {}:{}:{}", slot, id, offset),
+            }
+        }
+    }
+    dumper.finish()?;
+
+    for (slot, id) in wanted.keys() {
+        warn!("Append vec {}.{} is not in the snapshot", slot, id);
+    }
+    Ok(())
+}
+
+/// `source`, or the catalogued snapshot of `slot`; `names` are the argument and the flag
+/// giving them, for errors.
+fn catalogued_source(
+    source: Option<&str>,
+    slot: Option<u64>,
+    names: (&str, &str),
+    catalog_path: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (source_name, slot_flag) = names;
+    match (source, slot) {
+        (Some(source), None) => Ok(source.to_string()),
+        (None, Some(slot)) => {
+            let catalog = Catalog::open(catalog_path)?;
+            let entry = catalog.find_slot(slot)?;
+            info!(
+                "Using catalogued snapshot for slot {}: {}",
+                slot, entry.path
+            );
+            Ok(entry.path.clone())
+        }
+        (Some(_), Some(_)) => {
+            Err(format!("{} and {} are mutually exclusive", source_name, slot_flag).into())
+        }
+        (None, None) => Err(format!("Either {} or {} is required", source_name, slot_flag).into()),
+    }
+}
+
+/// Checks that `incremental` builds on the full snapshot `source` and returns its base slot.
+fn incremental_base_slot(
+    incremental: &str,
+    source: &str,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let name = incremental.rsplit('/').next().unwrap_or_default();
+    let base_slot = parse_incremental_base_slot(name).ok_or(
+        "--incremental needs an archive named incremental-snapshot-<BASE_SLOT>-<SLOT>-<HASH>.tar.zst",
+    )?;
+    let full_slot = source
+        .rsplit('/')
+        .next()
+        .and_then(parse_snapshot_name)
+        .map(|(slot, _)| slot);
+    if let Some(full_slot) = full_slot {
+        if full_slot != base_slot {
+            return Err(format!(
+                "The incremental snapshot builds on slot {}, but SOURCE is a snapshot of slot {}",
+                base_slot, full_slot
+            )
+            .into());
+        }
+    }
+    Ok(base_slot)
+}
+
+/// What a dump with `args` would do, without scanning: the filters, every pass over the
+/// source and how much account data each pass reads.
+fn explain_plan(args: &DumpArgs, owner_queries: &[OwnerQuery], total_bytes: u64) -> String {
+    let mut plan = vec![];
+    plan.push("Filters:".to_string());
+    let pubkeys: usize = args
+        .pubkey
+        .iter()
+        .map(|pubkey| pubkey.split(',').count())
+        .sum();
+    if pubkeys > 0 {
+        plan.push(format!("  pubkey: {} listed", pubkeys));
+    }
+    if let Some(path) = &args.pubkeyfile {
+        plan.push(format!("  pubkeyfile: {}", path));
+    }
+    for owner in args.owner.iter() {
+        plan.push(format!("  owner: {}", owner));
+    }
+    for references in args.references.iter() {
+        plan.push(format!("  references: {}", references));
+    }
+    if let Some(path) = &args.referencesfile {
+        plan.push(format!("  referencesfile: {}", path));
+    }
+    if !owner_queries.is_empty() {
+        plan.push(format!("  owned by matches of {:?}", owner_queries));
+    }
+    for expr in args.filter_json.iter() {
+        plan.push(format!("  filter-json: {}", expr));
+    }
+    if let Some(filters) = &args.rpc_filters {
+        plan.push(format!("  rpc-filters on every owner: {}", filters));
+    }
+    if plan.len() == 1 {
+        plan.push("  none, every account matches".to_string());
+    } else if args.match_mode == MatchMode::All {
+        plan.push("  pubkeys must also match a data filter (--match-mode=all)".to_string());
+    }
+    if let Some(range) = &args.lamports {
+        plan.push(format!("  and lamports within {}", range));
+    }
+    for exclude in args.exclude_owner.iter() {
+        plan.push(format!("  except owner: {}", exclude));
+    }
+
+    // the tool keeps no index, so every pass reads every append vec
+    plan.push(format!(
+        "Passes over the source ({} bytes of account data, all read by every pass):",
+        total_bytes
+    ));
+    if !owner_queries.is_empty() {
+        plan.push("  2 pre-passes collecting live owners".to_string());
+    }
+    if args.dedup {
+        plan.push("  2 pre-passes finding the newest version of matches (--dedup)".to_string());
+    } else if args.incremental.is_some() {
+        plan.push(
+            "  pre-pass over the incremental snapshot only, finding superseded accounts"
+                .to_string(),
+        );
+    }
+    plan.push(format!(
+        "  main scan filtering on {} thread(s)",
+        args.threads.unwrap_or(1).max(1)
+    ));
+    if let Some(first) = args.first {
+        plan.push(format!("  stops after the first {} matches", first));
+    }
+    if let Some(abort_after) = args.abort_if_zero_after {
+        plan.push(format!(
+            "  fails if nothing matched after {} bytes",
+            abort_after
+        ));
+    }
+    plan.push(String::new());
+    plan.join("\n")
+}
+
+/// Hash of the options shaping the records a dump writes, so `--checkpoint` refuses to
+/// append records of another shape to the output of an earlier run.
+fn output_options_hash(args: &DumpArgs) -> String {
+    // the anonymizer's Debug output leaves the key out; a token tells keys apart instead
+    let anonymize = args
+        .anonymize
+        .as_ref()
+        .map(|anonymizer| anonymizer.bytes32(&[0; 32]));
+    let options: [&dyn std::fmt::Debug; 19] = [
+        &args.format,
+        &args.noheader,
+        &args.preview_bytes,
+        &args.data_slice,
+        &args.data_encoding,
+        &args.lamports_as,
+        &args.project,
+        &args.decode,
+        &args.borsh_schema,
+        &args.idl,
+        &args.decode_validate,
+        &args.token_amounts_as,
+        &args.decode_errors,
+        &anonymize,
+        &args.labels,
+        &args.resolve_owner_names,
+        &args.uri_column,
+        &args.dedup,
+        &args.incremental,
+    ];
+    hex::encode(hash(format!("{:?}", options).as_bytes()).to_bytes())
+}
+
+/// Runs a dump and sends its manifest to `--notify`, whether it succeeded or not.
+fn run_dump(args: DumpArgs, catalog_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let notify = args.notify.clone();
+    let started = Instant::now();
+    let mut summary = RunSummary {
+        output: args.output.clone(),
+        ..Default::default()
+    };
+    let result = dump(args, catalog_path, &mut summary);
+    if let Some(notify) = notify {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let manifest = RunManifest::new(&summary, started.elapsed(), error);
+        if let Err(e) = notify.send(&manifest) {
+            warn!("Failed to send the run manifest: {}", e);
+        }
+    }
+    result
+}
+
+fn dump(
+    mut args: DumpArgs,
+    catalog_path: &Path,
+    summary: &mut RunSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // --fields is shorthand for a projection of plain fields
+    if let Some(fields) = args.fields.take() {
+        args.project = Some(fields);
+    }
+    // totals count each stake account once, as of its newest version, and not once closed
+    args.dedup |= args.summarize_stake;
+    let source = args.snapshot.resolve(catalog_path)?;
+    summary.source = Some(source.clone());
+
+    let decoder = match (&args.borsh_schema, &args.idl) {
+        (Some(path), _) => Some(DecodeMode::Schema(Arc::new(Schema::borsh(path)?))),
+        (None, Some(path)) => Some(DecodeMode::Schema(Arc::new(Schema::idl(path)?))),
+        (None, None) => args.decode.clone(),
+    };
+    if args.format != Format::Csv
+        && (args.preview_bytes.is_some()
+            || args.project.is_some()
+            || decoder.is_some()
+            || args.labels.is_some()
+            || args.resolve_owner_names
+            || args.uri_column)
+    {
+        return Err("--preview-bytes, --project, --fields, --decode, --labels, --resolve-owner-names and --uri-column only apply to CSV output".into());
+    }
+    if args.geyser_plugin.is_some()
+        && (args.format != Format::Csv
+            || args.preview_bytes.is_some()
+            || args.data_slice.is_some()
+            || args.project.is_some()
+            || decoder.is_some()
+            || args.labels.is_some()
+            || args.resolve_owner_names
+            || args.uri_column)
+    {
+        return Err("--geyser-plugin replaces the output, so --format, --data-slice and the CSV column options don't apply".into());
+    }
+    if args.postgres.is_some()
+        && (args.format != Format::Csv
+            || args.preview_bytes.is_some()
+            || args.project.is_some()
+            || decoder.is_some()
+            || args.labels.is_some()
+            || args.resolve_owner_names
+            || args.uri_column)
+    {
+        return Err(
+            "--postgres replaces the output, so --format and the CSV column options don't apply"
+                .into(),
+        );
+    }
+    if args.summarize_stake
+        && (args.format != Format::Csv
+            || args.preview_bytes.is_some()
+            || args.data_slice.is_some()
+            || args.project.is_some()
+            || args.labels.is_some()
+            || args.resolve_owner_names
+            || args.uri_column)
+    {
+        return Err("--summarize-stake writes its own CSV, so --format, --data-slice and the column options don't apply".into());
+    }
+    if args.stats
+        && (args.format != Format::Csv
+            || args.preview_bytes.is_some()
+            || args.data_slice.is_some()
+            || args.project.is_some()
+            || args.labels.is_some()
+            || args.resolve_owner_names
+            || args.uri_column)
+    {
+        return Err("--stats writes its own JSON, so --format, --data-slice and the column options don't apply".into());
+    }
+    if args.data_encoding.is_some()
+        && (args.format == Format::Parquet
+            || args.geyser_plugin.is_some()
+            || args.postgres.is_some()
+            || args.summarize_stake
+            || args.stats)
+    {
+        return Err(
+            "--data-encoding only applies to the csv, jsonl and ledger-tool-json data".into(),
+        );
+    }
+    let data_encoding = args.data_encoding.unwrap_or_default();
+    // a longer account would otherwise surface hours into the scan
+    if data_encoding == DataEncoding::Base58
+        && args
+            .data_slice
+            .is_none_or(|(_, length)| length > MAX_BASE58_LEN)
+    {
+        return Err(format!(
+            "--data-encoding=base58 is limited to {} bytes of data as in RPC; cut the data to that with --data-slice or use another encoding",
+            MAX_BASE58_LEN
+        )
+        .into());
+    }
+    if args.format == Format::LedgerToolJson && data_encoding.ledger_tool_name().is_none() {
+        return Err(format!(
+            "agave-ledger-tool JSON has no {:?} data encoding, use base64, base58 or base64+zstd",
+            data_encoding
+        )
+        .into());
+    }
+    if args.anonymize.is_some() {
+        if args.geyser_plugin.is_some()
+            || args.postgres.is_some()
+            || args.format == Format::Parquet
+            || args.summarize_stake
+            || args.uri_column
+        {
+            return Err("--anonymize can't tokenize what Geyser plugins, Postgres, Parquet, --summarize-stake and --uri-column write".into());
+        }
+        let raw_data = match &args.project {
+            Some(projection) => projection.exposes_data(),
+            None => {
+                !args.stats && (args.preview_bytes.is_some() || data_encoding != DataEncoding::None)
+            }
+        };
+        if raw_data {
+            return Err("--anonymize doesn't tokenize pubkeys embedded in account data; leave the raw data out with --data-encoding=none or --fields/--project, and add --decode for decoded, tokenized keys".into());
+        }
+    }
+    if args.lamports_as.is_some()
+        && (matches!(args.format, Format::LedgerToolJson | Format::Parquet)
+            || args.geyser_plugin.is_some()
+            || args.postgres.is_some())
+    {
+        return Err("--lamports-as applies to csv and jsonl records, --stats and --summarize-stake; ledger-tool-json, Parquet, Postgres and Geyser plugins keep integer lamports".into());
+    }
+    let lamports_as = args.lamports_as.unwrap_or_default();
+    if args.checkpoint.is_some() && !matches!(args.format, Format::Csv | Format::Jsonl) {
+        return Err("--checkpoint needs --format=csv or jsonl, which can be appended to".into());
+    }
+    if args.unknown_owner_output.is_some() && !matches!(decoder, Some(DecodeMode::Auto)) {
+        return Err("--unknown-owner-output needs --decode=auto".into());
+    }
+    if args.uri_column && args.incremental.is_some() {
+        return Err("--uri-column can't point into two snapshots, drop --incremental".into());
+    }
+    let incremental = match &args.incremental {
+        Some(incremental) => Some((
+            incremental.as_str(),
+            incremental_base_slot(incremental, &source)?,
+        )),
+        None => None,
+    };
+    let uri_snapshot = if args.uri_column {
+        Some(
+            snapshot_id(&source)
+                .ok_or("--uri-column needs a source named snapshot-<SLOT>-<HASH>.tar.zst")?,
+        )
+    } else {
+        None
+    };
+
+    let mut filter = AccountFilter::new(
+        &args.pubkey,
+        args.pubkeyfile.as_deref(),
+        &args.owner,
+        &args.references,
+        args.referencesfile.as_deref(),
+        args.match_mode,
+    )?;
+    if let Some(range) = args.lamports {
+        filter.set_lamports(range);
+    }
+    if let Some(filters) = &args.rpc_filters {
+        filter.add_rpc_filters(filter_json::rpc_filters(filters)?);
+    }
+    for expr in args.filter_json.iter() {
+        filter.add_expression(expr.parse::<FilterExpr>()?);
+    }
+    for exclude in args.exclude_owner.iter() {
+        filter.exclude(OwnerFilter::new(exclude)?);
+    }
+    if let Some(pubkey) = args.trace_pubkey {
+        filter.set_trace(pubkey);
+    }
+    let cache_dir = args.cache_dir.as_deref().map(Path::new);
+    let open_loader =
+        || SupportedLoader::layered(&source, incremental.map(|(path, _)| path), cache_dir);
+
+    let mut owner_queries = args.owners_from_query.clone();
+    if let Some(loader_id) = args.owner_owned_by {
+        owner_queries.push(OwnerQuery::Owner(loader_id.to_string()));
+    }
+    if args.replay_order == Some(ReplayOrder::Slot)
+        && filter.matches_everything()
+        && owner_queries.is_empty()
+    {
+        return Err("--replay-order=slot holds every match until the scan ends; narrow it down with a pubkey, owner or data filter".into());
+    }
+    if args.explain_plan {
+        let total_bytes = open_loader()?.total_append_vec_bytes();
+        print!("{}", explain_plan(&args, &owner_queries, total_bytes));
+        return Ok(());
+    }
+    if !owner_queries.is_empty() {
+        info!("Pre-pass: collecting owners for {:?}", owner_queries);
+        let query_filter = prepass::query_filter(&owner_queries)?;
+        let mut first_loader = open_loader()?;
+        let mut second_loader = open_loader()?;
+        let owners = prepass::alive_pubkeys(&mut first_loader, &mut second_loader, |account| {
+            query_filter.is_match(account)
+        })?;
+        info!("Pre-pass found {} owners", owners.len());
+        filter.add_owner_set(owners);
+    }
+
+    let newest_versions = if args.dedup {
+        info!("Pre-pass: finding the newest version of matching accounts");
+        let mut first_loader = open_loader()?;
+        let mut second_loader = open_loader()?;
+        let newest = prepass::newest_versions(&mut first_loader, &mut second_loader, |account| {
+            filter.is_match(account)
+        })?;
+        info!("Pre-pass found {} live accounts", newest.len());
+        Some(newest)
+    } else {
+        None
+    };
+
+    // --dedup already drops every version but the newest
+    let superseded = match incremental {
+        Some((path, base_slot)) if !args.dedup => {
+            info!("Pre-pass: collecting accounts stored in the incremental snapshot");
+            let mut incremental_loader = SupportedLoader::new(path, cache_dir)?;
+            let pubkeys = prepass::stored_pubkeys(&mut incremental_loader)?;
+            info!(
+                "Pre-pass found {} accounts updated since slot {}",
+                pubkeys.len(),
+                base_slot
+            );
+            Some((base_slot, pubkeys))
+        }
+        _ => None,
+    };
+
+    let filter_hash = filter.filter_hash();
+    info!("Filter hash {}", filter_hash);
+    summary.filter = Some(filter.canonical_string());
+    summary.filter_hash = Some(filter_hash.clone());
+
+    let mut loader = open_loader()?;
+
+    let progress_format = match args.quiet {
+        true => None,
+        false => Some(args.progress_format.unwrap_or_else(ProgressFormat::detect)),
+    };
+    let mut progress = Progress::new(progress_format, loader.total_append_vec_bytes());
+    let (mut checkpoint, resume) = match &args.checkpoint {
+        Some(path) => {
+            let options_hash = output_options_hash(&args);
+            let (checkpoint, resume) =
+                Checkpoint::open(path, &source, &filter_hash, &options_hash)?;
+            (Some(checkpoint), resume)
+        }
+        None => (None, None),
+    };
+    if let Some(resume) = &resume {
+        info!(
+            "Resuming from checkpoint: {} append vecs done, {} bytes of output kept",
+            resume.done.len(),
+            resume.output_len
+        );
+    }
+    let sink = match (&args.geyser_plugin, &args.postgres) {
+        (Some(config), _) => {
+            let replay = Replay {
+                order: args.replay_order.unwrap_or_default(),
+                batch: args.replay_batch,
+            };
+            Sink::Geyser(GeyserPluginSink::load(config, replay)?)
+        }
+        (None, Some(url)) => {
+            info!("Dumping to Postgres table {}", args.postgres_table);
+            Sink::Postgres(PostgresSink::connect(
+                url,
+                &args.postgres_table,
+                args.postgres_upsert,
+                args.postgres_batch,
+            )?)
+        }
+        (None, None) if args.summarize_stake => {
+            info!("Summarizing stake per vote account");
+            let output = Output::open(args.output.as_deref(), args.encrypt.as_ref())?;
+            Sink::StakeSummary(StakeSummary::new(output, lamports_as))
+        }
+        (None, None) if args.stats => {
+            info!("Collecting statistics of the matches");
+            let output = Output::open(args.output.as_deref(), args.encrypt.as_ref())?;
+            Sink::Stats(Stats::new(output, lamports_as))
+        }
+        (None, None) => {
+            info!("Dumping to {:?}", args.format);
+            match (&resume, &args.output) {
+                (Some(resume), Some(path)) => Sink::resume(args.format, path, resume.output_len)?,
+                _ => Sink::open(args.format, args.output.as_deref(), args.encrypt.as_ref())?,
+            }
+        }
+    };
+    let options = DumpOptions {
+        // the kept output starts with the header already
+        noheader: args.noheader || resume.as_ref().is_some_and(|resume| resume.output_len > 0),
+        first: args.first,
+        preview_bytes: args.preview_bytes,
+        data_slice: args.data_slice,
+        data_encoding,
+        lamports_as,
+        projection: args.project,
+        anonymizer: args.anonymize,
+        flush_every: args.flush_every,
+        limit_per_owner: args.limit_per_owner,
+        labels: match &args.labels {
+            Some(path) => Some(Labels::open(path.as_ref())?),
+            None => None,
+        },
+        owner_names: args.resolve_owner_names.then(ProgramRegistry::default),
+        uri_snapshot,
+        decoder,
+        decode_validate: args.decode_validate,
+        token_amounts: args.token_amounts_as.unwrap_or_default(),
+        decode_errors: args.decode_errors.unwrap_or_default(),
+        decode_threads: args.decode_threads.unwrap_or_else(num_cpus::get),
+        newest_versions,
+        superseded,
+    };
+    let unknown_owner_output = match &args.unknown_owner_output {
+        Some(path) => Some(Output::open(Some(path), args.encrypt.as_ref())?),
+        None => None,
+    };
+    let mut writer = Dumper::new(sink, filter.clone(), options, unknown_owner_output);
+
+    // on Ctrl-C stop after the current append vec so buffered records still reach the output
+    watch_interrupts()?;
+
+    // dumps one append vec, returning false once the scan should stop
+    let mut dump_append_vec = |slot: u64,
+                               id: u64,
+                               append_vec: AppendVec,
+                               candidates: Option<Candidates>|
+     -> Result<bool, Box<dyn std::error::Error>> {
+        let append_vec_bytes = append_vec.len() as u64;
+        if let Err(e) = writer.dump_append_vec(slot, id, append_vec, candidates) {
+            // keep what was written so far, but report the error that stopped the dump
+            let _ = writer.finish();
+            return Err(e.into());
+        }
+        if let (Some(checkpoint), Some(path)) = (&mut checkpoint, &args.output) {
+            writer.flush()?;
+            checkpoint.record(slot, id, std::fs::metadata(path)?.len())?;
+        }
+        progress.append_vec(
+            append_vec_bytes,
+            writer.scanned_count(),
+            writer.accounts_count(),
+        );
+        summary.matched = writer.accounts_count();
+        summary.scanned_bytes = progress.scanned_bytes();
+
+        if writer.is_done() {
+            info!(
+                "Found the first {} matches, stopping",
+                writer.accounts_count()
+            );
+            return Ok(false);
+        }
 
-    info!("Dumping to CSV");
-    let mut processed = 0;
-    let mut writer = CsvDumper::new(filter, args.noheader);
-    for append_vec in loader.iter() {
-        let (slot, id, append_vec) = append_vec?;
-        writer.dump_append_vec(slot, id, append_vec);
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            writer.finish()?;
+            return Err(format!(
+                "Interrupted after {} matching accounts, output flushed",
+                writer.accounts_count()
+            )
+            .into());
+        }
 
-        processed += 1;
-        if processed % 100 == 0 {
-            info!("AppendVec processed: {}", processed);
+        if let Some(abort_after) = args.abort_if_zero_after {
+            if writer.accounts_count() == 0 && progress.scanned_bytes() >= abort_after {
+                writer.finish()?;
+                return Err(format!(
+                    "No accounts matched after scanning {} bytes, check the filters",
+                    progress.scanned_bytes()
+                )
+                .into());
+            }
+        }
+        Ok(true)
+    };
+    let done = resume.map(|resume| resume.done).unwrap_or_default();
+    let append_vecs = loader.iter().filter(
+        |append_vec| !matches!(append_vec, Ok((slot, id, _)) if done.contains(&(*slot, *id))),
+    );
+    match args.threads {
+        Some(threads) if threads > 1 => {
+            parallel::scan(
+                append_vecs,
+                threads,
+                &filter,
+                |slot, id, append_vec, candidates| {
+                    dump_append_vec(slot, id, append_vec, Some(candidates))
+                },
+            )?;
+        }
+        _ => {
+            for append_vec in append_vecs {
+                let (slot, id, append_vec) = append_vec?;
+                if !dump_append_vec(slot, id, append_vec, None)? {
+                    break;
+                }
+            }
+        }
+    }
+    writer.finish()?;
+    progress.finish(writer.accounts_count());
+    if let Some(path) = &args.checkpoint {
+        // a finished dump shouldn't be skipped by the next run writing the same output
+        std::fs::remove_file(path)?;
+    }
+    if let Some(path) = &args.unknown_owner_output {
+        info!(
+            "{} accounts without a registered decoder written to {}",
+            writer.unknown_owner_count(),
+            path
+        );
+    }
+
+    if let Some(expected) = args.expect_matches {
+        if writer.accounts_count() < expected {
+            return Err(format!(
+                "Expected at least {} matching accounts, found {}",
+                expected,
+                writer.accounts_count()
+            )
+            .into());
+        }
+    }
+    if args.checksum {
+        for sidecar in write_checksums(&args.output, &args.unknown_owner_output)? {
+            info!("Checksum written to {}", sidecar.display());
         }
     }
-    drop(writer);
     info!("Done!");
 
     Ok(())
 }
 
-pub enum SupportedLoader {
+/// Writes the sha256 sidecars of every file a finished dump wrote.
+fn write_checksums(
+    output: &Option<String>,
+    unknown_owner_output: &Option<String>,
+) -> std::io::Result<Vec<PathBuf>> {
+    output
+        .iter()
+        .chain(unknown_owner_output.iter())
+        .map(|path| write_checksum(path.as_ref()))
+        .collect()
+}
+
+pub(crate) enum SupportedLoader {
     ArchiveFile(ArchiveSnapshotExtractor<File>),
     ArchiveDownload(ArchiveSnapshotExtractor<Response>),
+    ArchiveCachedDownload(ArchiveSnapshotExtractor<CachedDownload>),
+    ArchiveCached(ArchiveSnapshotExtractor<CachedArchive>),
+    /// A full snapshot followed by an incremental snapshot built on it.
+    Layered(Box<SupportedLoader>, Box<SupportedLoader>),
 }
 
 impl SupportedLoader {
-    fn new(
-        source: &str,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    fn new(source: &str, cache_dir: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
         if source.starts_with("http://") || source.starts_with("https://") {
-            Self::new_download(source)
+            match cache_dir {
+                Some(cache_dir) => Self::new_cached_download(source, cache_dir),
+                None => Self::new_download(source),
+            }
         } else {
             Self::new_file(source.as_ref()).map_err(Into::into)
         }
     }
 
+    /// `source`, followed by the append vecs of `incremental` when given.
+    fn layered(
+        source: &str,
+        incremental: Option<&str>,
+        cache_dir: Option<&Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let full = Self::new(source, cache_dir)?;
+        match incremental {
+            Some(incremental) => Ok(Self::Layered(
+                Box::new(full),
+                Box::new(Self::new(incremental, cache_dir)?),
+            )),
+            None => Ok(full),
+        }
+    }
+
     fn new_download(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let resp = reqwest::blocking::get(url)?;
         let loader = ArchiveSnapshotExtractor::from_reader(resp)?;
@@ -93,12 +1649,41 @@ impl SupportedLoader {
         Ok(Self::ArchiveDownload(loader))
     }
 
-    fn new_file(
-        path: &Path,
-    ) -> solana_snapshot_etl::Result<Self> {
-        Ok(
-            Self::ArchiveFile(ArchiveSnapshotExtractor::open(path)?)
-        )
+    fn new_cached_download(
+        url: &str,
+        cache_dir: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match cache::open(url, cache_dir)? {
+            CachedSource::Complete(archive) => Ok(Self::ArchiveCached(
+                ArchiveSnapshotExtractor::from_reader(archive)?,
+            )),
+            CachedSource::Download(download) => {
+                let loader = ArchiveSnapshotExtractor::from_reader(*download)?;
+                info!(
+                    "Streaming snapshot from HTTP through {}",
+                    cache_dir.display()
+                );
+                Ok(Self::ArchiveCachedDownload(loader))
+            }
+        }
+    }
+
+    fn new_file(path: &Path) -> solana_snapshot_etl::Result<Self> {
+        Ok(Self::ArchiveFile(ArchiveSnapshotExtractor::open(path)?))
+    }
+}
+
+impl SupportedLoader {
+    fn total_append_vec_bytes(&self) -> u64 {
+        match self {
+            SupportedLoader::ArchiveFile(loader) => loader.total_append_vec_bytes(),
+            SupportedLoader::ArchiveDownload(loader) => loader.total_append_vec_bytes(),
+            SupportedLoader::ArchiveCachedDownload(loader) => loader.total_append_vec_bytes(),
+            SupportedLoader::ArchiveCached(loader) => loader.total_append_vec_bytes(),
+            SupportedLoader::Layered(full, incremental) => {
+                full.total_append_vec_bytes() + incremental.total_append_vec_bytes()
+            }
+        }
     }
 }
 
@@ -107,6 +1692,82 @@ impl SnapshotExtractor for SupportedLoader {
         match self {
             SupportedLoader::ArchiveFile(loader) => Box::new(loader.iter()),
             SupportedLoader::ArchiveDownload(loader) => Box::new(loader.iter()),
+            SupportedLoader::ArchiveCachedDownload(loader) => Box::new(loader.iter()),
+            SupportedLoader::ArchiveCached(loader) => Box::new(loader.iter()),
+            SupportedLoader::Layered(full, incremental) => {
+                Box::new(full.iter().chain(incremental.iter()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Checkpoint;
+    use crate::testing::TempPath;
+    use clap::CommandFactory;
+
+    #[test]
+    fn arguments_name_existing_ids() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn checkpoints_refuse_another_anonymize_key() {
+        let options_hash = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let args = job_dump_args(&args, Path::new("snapshot.tar.zst"), Path::new("out"));
+            output_options_hash(&args.unwrap())
+        };
+        let key_a = options_hash(&["--fields=owner", "--anonymize=hmac:a"]);
+        let key_b = options_hash(&["--fields=owner", "--anonymize=hmac:b"]);
+        assert_eq!(
+            key_a,
+            options_hash(&["--fields=owner", "--anonymize=hmac:a"])
+        );
+        assert_ne!(key_a, key_b);
+        assert_ne!(key_a, options_hash(&["--fields=owner"]));
+
+        let path = TempPath::new("anonymize.checkpoint");
+        drop(Checkpoint::open(&path, "snapshot.tar.zst", "filter", &key_a).unwrap());
+        let error = Checkpoint::open(&path, "snapshot.tar.zst", "filter", &key_b)
+            .err()
+            .expect("a checkpoint of another key");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn checksums_cover_the_unknown_owner_output() {
+        let output = TempPath::file("checksum.csv", "pubkey\n");
+        let unknown_owner_output = TempPath::file("checksum-unknown.csv", "pubkey\n");
+        let args: Vec<String> = [
+            "--checksum".to_string(),
+            "--decode=auto".to_string(),
+            format!("--unknown-owner-output={}", unknown_owner_output.display()),
+        ]
+        .into();
+        let args = job_dump_args(&args, Path::new("snapshot.tar.zst"), &output).unwrap();
+
+        let sidecars = write_checksums(&args.output, &args.unknown_owner_output).unwrap();
+        let contents: Vec<_> = sidecars
+            .iter()
+            .map(|sidecar| std::fs::read_to_string(sidecar).unwrap())
+            .collect();
+        for sidecar in sidecars.iter() {
+            std::fs::remove_file(sidecar).unwrap();
         }
+        let names: Vec<_> = contents
+            .iter()
+            .map(|line| line.trim_end().split_once("  ").unwrap().1)
+            .collect();
+        assert_eq!(
+            names,
+            [&output, &unknown_owner_output].map(|path| path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap())
+        );
     }
 }
@@ -2,21 +2,25 @@
 // Source: https://github.com/riptl/solana-snapshot-etl/blob/d1f569b3e9384ba54948d59875da08dfbec4559e/src/archived.rs
 // Source: https://github.com/riptl/solana-snapshot-etl/blob/d1f569b3e9384ba54948d59875da08dfbec4559e/src/lib.rs
 
+use crate::append_vec::AppendVec;
+use log::info;
 use solana_snapshot_etl::{
-    solana::{deserialize_from, AccountsDbFields, DeserializableVersionedBank, SerializableAccountStorageEntry},
-    append_vec::AppendVec, Result, SnapshotError,
+    solana::{
+        deserialize_from, AccountsDbFields, DeserializableVersionedBank,
+        SerializableAccountStorageEntry,
+    },
+    Result, SnapshotError,
 };
-use log::info;
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::{Component, Path};
 use std::pin::Pin;
+use std::str::FromStr;
 use std::time::Instant;
 use tar::{Archive, Entries, Entry};
-use std::ffi::OsStr;
-use std::str::FromStr;
 
-fn parse_append_vec_name(name: &OsStr) -> Option<(u64, u64)> {
+pub fn parse_append_vec_name(name: &OsStr) -> Option<(u64, u64)> {
     let name = name.to_str()?;
     let mut parts = name.splitn(2, '.');
     let slot = u64::from_str(parts.next().unwrap_or(""));
@@ -39,8 +43,10 @@ where
     Source: Read + Unpin + 'static,
 {
     accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
-    _archive: Pin<Box<Archive<zstd::Decoder<'static, BufReader<Source>>>>>,
+    // borrows `_archive` under a faked 'static lifetime, so it must be declared (and thus
+    // dropped) before it
     entries: Option<Entries<'static, zstd::Decoder<'static, BufReader<Source>>>>,
+    _archive: Pin<Box<Archive<zstd::Decoder<'static, BufReader<Source>>>>>,
 }
 
 impl<Source> SnapshotExtractor for ArchiveSnapshotExtractor<Source>
@@ -60,7 +66,10 @@ where
         let tar_stream = zstd::stream::read::Decoder::new(source)?;
         let mut archive = Box::pin(Archive::new(tar_stream));
 
-        // This is safe as long as we guarantee that entries never gets accessed past drop.
+        // SAFETY: the archive is pinned on the heap and owned by the extractor next to
+        // `entries`, which is dropped first and never handed out beyond `&mut self`, so the
+        // reference never outlives it. Nothing else touches the archive once entries exist.
+        #[allow(unsafe_code)]
         let archive_static = unsafe { &mut *((&mut *archive) as *mut Archive<_>) };
         let mut entries = archive_static.entries()?;
 
@@ -110,6 +119,16 @@ where
         })
     }
 
+    /// Sum of the account data lengths of all append vecs listed in the manifest.
+    pub fn total_append_vec_bytes(&self) -> u64 {
+        self.accounts_db_fields
+            .0
+            .values()
+            .flatten()
+            .map(|entry| entry.accounts_current_len as u64)
+            .sum()
+    }
+
     fn unboxed_iter(&mut self) -> impl Iterator<Item = Result<(u64, u64, AppendVec)>> + '_ {
         self.entries
             .take()
@@ -146,14 +165,11 @@ where
             None => return Err(SnapshotError::UnexpectedAppendVec),
             Some(v) => v,
         };
-        let append_vec = AppendVec::new_from_reader(
-            entry,
-            known_vec.accounts_current_len,
-        )?;
+        let append_vec = AppendVec::new_from_reader(entry, known_vec.accounts_current_len)?;
         Ok((slot, id, append_vec))
     }
 
-    fn is_snapshot_manifest_file(path: &Path) -> bool {
+    pub fn is_snapshot_manifest_file(path: &Path) -> bool {
         let mut components = path.components();
         if components.next() != Some(Component::Normal("snapshots".as_ref())) {
             return false;
@@ -177,7 +193,7 @@ where
         components.next().is_none() && slot_number_str_1 == slot_number_str_2
     }
 
-    fn is_appendvec_file(path: &Path) -> bool {
+    pub fn is_appendvec_file(path: &Path) -> bool {
         let mut components = path.components();
         if components.next() != Some(Component::Normal("accounts".as_ref())) {
             return false;
@@ -0,0 +1,212 @@
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum NotifyError {
+    #[error("Invalid --notify target (expected http(s)://URL or cmd:COMMAND)")]
+    InvalidTarget,
+    #[error("Webhook request failed: {0}")]
+    Webhook(#[from] reqwest::Error),
+    #[error("Notify command failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Notify command exited with {0}")]
+    CommandStatus(std::process::ExitStatus),
+}
+
+/// Where the run manifest goes once a dump finishes or fails.
+#[derive(Debug, Clone)]
+pub(crate) enum Notify {
+    /// POSTed as `application/json`.
+    Webhook(String),
+    /// Run with `sh -c`, manifest on stdin.
+    Command(String),
+}
+
+impl FromStr for Notify {
+    type Err = NotifyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = s.strip_prefix("cmd:") {
+            return Ok(Notify::Command(command.to_string()));
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Notify::Webhook(s.to_string()));
+        }
+        Err(NotifyError::InvalidTarget)
+    }
+}
+
+/// What a dump run saw, filled in as it goes so failed runs report how far they got.
+#[derive(Debug, Default)]
+pub(crate) struct RunSummary {
+    pub(crate) source: Option<String>,
+    pub(crate) output: Option<String>,
+    /// `AccountFilter::canonical_string` and `filter_hash` of the main scan.
+    pub(crate) filter: Option<String>,
+    pub(crate) filter_hash: Option<String>,
+    pub(crate) matched: u64,
+    pub(crate) scanned_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RunManifest<'a> {
+    status: &'static str,
+    source: Option<&'a str>,
+    output: Option<&'a str>,
+    filter: Option<&'a str>,
+    filter_hash: Option<&'a str>,
+    matched: u64,
+    scanned_bytes: u64,
+    elapsed_secs: f64,
+    error: Option<String>,
+}
+
+impl<'a> RunManifest<'a> {
+    pub(crate) fn new(summary: &'a RunSummary, elapsed: Duration, error: Option<String>) -> Self {
+        let status = match error {
+            None => "success",
+            Some(_) => "failure",
+        };
+        Self {
+            status,
+            source: summary.source.as_deref(),
+            output: summary.output.as_deref(),
+            filter: summary.filter.as_deref(),
+            filter_hash: summary.filter_hash.as_deref(),
+            matched: summary.matched,
+            scanned_bytes: summary.scanned_bytes,
+            elapsed_secs: elapsed.as_secs_f64(),
+            error,
+        }
+    }
+}
+
+impl Notify {
+    pub(crate) fn send(&self, manifest: &RunManifest) -> Result<(), NotifyError> {
+        let body = serde_json::to_vec(manifest).expect("manifest serializes");
+        match self {
+            Notify::Webhook(url) => {
+                reqwest::blocking::Client::builder()
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .build()?
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .send()?
+                    .error_for_status()?;
+            }
+            Notify::Command(command) => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                // a hook that ignores stdin may close it early; its exit status is what counts
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = stdin.write_all(&body);
+                }
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(NotifyError::CommandStatus(status));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+    use serde_json::json;
+
+    fn summary() -> RunSummary {
+        RunSummary {
+            source: Some("snapshot-100-hash.tar.zst".to_string()),
+            output: Some("out.csv".to_string()),
+            filter: Some("owner=Stake11111111111111111111111111111111111".to_string()),
+            filter_hash: Some("abcd".to_string()),
+            matched: 7,
+            scanned_bytes: 1024,
+        }
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert!(matches!(
+            "cmd:cat > /dev/null".parse(),
+            Ok(Notify::Command(command)) if command == "cat > /dev/null"
+        ));
+        for url in ["http://localhost:8080/hook", "https://example.com/hook"] {
+            assert!(matches!(url.parse(), Ok(Notify::Webhook(parsed)) if parsed == url));
+        }
+        for invalid in ["ftp://example.com", "example.com", ""] {
+            assert!(
+                matches!(invalid.parse::<Notify>(), Err(NotifyError::InvalidTarget)),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn writes_the_manifest_of_successes_and_failures() {
+        let summary = summary();
+        let manifest = |error: Option<&str>| {
+            let manifest = RunManifest::new(
+                &summary,
+                Duration::from_millis(1500),
+                error.map(str::to_string),
+            );
+            serde_json::to_value(manifest).unwrap()
+        };
+        let expected = |status: &str, error: Option<&str>| {
+            json!({
+                "status": status,
+                "source": "snapshot-100-hash.tar.zst",
+                "output": "out.csv",
+                "filter": "owner=Stake11111111111111111111111111111111111",
+                "filter_hash": "abcd",
+                "matched": 7,
+                "scanned_bytes": 1024,
+                "elapsed_secs": 1.5,
+                "error": error,
+            })
+        };
+        assert_eq!(manifest(None), expected("success", None));
+        assert_eq!(
+            manifest(Some("Interrupted")),
+            expected("failure", Some("Interrupted"))
+        );
+
+        let empty = RunSummary::default();
+        let manifest = serde_json::to_value(RunManifest::new(&empty, Duration::ZERO, None));
+        assert_eq!(manifest.unwrap()["source"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn pipes_the_manifest_to_commands() {
+        let summary = summary();
+        let manifest = RunManifest::new(&summary, Duration::from_secs(2), None);
+        let path = TempPath::new("notify.json");
+        let notify: Notify = format!("cmd:cat > {}", path.display()).parse().unwrap();
+        notify.send(&manifest).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            serde_json::to_vec(&manifest).unwrap()
+        );
+
+        let notify: Notify = "cmd:exit 3".parse().unwrap();
+        assert!(matches!(
+            notify.send(&manifest),
+            Err(NotifyError::CommandStatus(status)) if status.code() == Some(3)
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+use solana_program::hash::Hasher;
+use std::cell::{RefCell, RefMut};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, StdoutLock, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EncryptionParseError {
+    #[error("Invalid encryption spec (expected age:RECIPIENT or gpg:RECIPIENT)")]
+    InvalidEncryptionSpec,
+}
+
+/// External tool used to encrypt output before it touches the disk.
+#[derive(Debug, Clone)]
+pub enum Encryption {
+    Age(String),
+    Gpg(String),
+}
+
+impl FromStr for Encryption {
+    type Err = EncryptionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tool, recipient) = s
+            .split_once(':')
+            .ok_or(EncryptionParseError::InvalidEncryptionSpec)?;
+        if recipient.is_empty() {
+            return Err(EncryptionParseError::InvalidEncryptionSpec);
+        }
+        match tool {
+            "age" => Ok(Encryption::Age(recipient.to_string())),
+            "gpg" => Ok(Encryption::Gpg(recipient.to_string())),
+            _ => Err(EncryptionParseError::InvalidEncryptionSpec),
+        }
+    }
+}
+
+impl Encryption {
+    fn command(&self) -> Command {
+        match self {
+            Encryption::Age(recipient) => {
+                let mut command = Command::new("age");
+                command.arg("--recipient").arg(recipient);
+                command
+            }
+            Encryption::Gpg(recipient) => {
+                let mut command = Command::new("gpg");
+                command
+                    .arg("--batch")
+                    .arg("--encrypt")
+                    .arg("--recipient")
+                    .arg(recipient);
+                command
+            }
+        }
+    }
+}
+
+/// Destination of dumped records: stdout, a file, or an encryption process writing to either.
+///
+/// Stdout is locked once and buffered, so records don't pay for a lock per write.
+pub enum Output {
+    Stdout(BufWriter<StdoutLock<'static>>),
+    File(BufWriter<File>),
+    Encrypted(Child),
+}
+
+impl Output {
+    pub fn open(path: Option<&str>, encryption: Option<&Encryption>) -> io::Result<Self> {
+        match (path, encryption) {
+            (None, None) => Ok(Output::Stdout(BufWriter::new(io::stdout().lock()))),
+            (Some(path), None) => Ok(Output::File(BufWriter::new(File::create(path)?))),
+            (path, Some(encryption)) => {
+                let stdout = match path {
+                    Some(path) => Stdio::from(File::create(path)?),
+                    None => Stdio::inherit(),
+                };
+                let child = encryption
+                    .command()
+                    .stdin(Stdio::piped())
+                    .stdout(stdout)
+                    .spawn()?;
+                Ok(Output::Encrypted(child))
+            }
+        }
+    }
+
+    /// Reopens the output file of an interrupted dump, dropping anything past its first `len`
+    /// bytes, to write the rest after them.
+    pub fn resume(path: &str, len: u64) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Output::File(BufWriter::new(file)))
+    }
+
+    /// Flushes everything and, for encrypted output, waits for the encryption process.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+            Output::Encrypted(child) => {
+                // closing stdin lets the encryption tool write its trailer and exit
+                drop(child.stdin.take());
+                let status = child.wait()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(format!(
+                        "Encryption process failed: {}",
+                        status
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+            Output::Encrypted(child) => match child.stdin.as_mut() {
+                Some(stdin) => stdin.write(buf),
+                None => Err(io::ErrorKind::BrokenPipe.into()),
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+            Output::Encrypted(child) => match child.stdin.as_mut() {
+                Some(stdin) => stdin.flush(),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// An [`Output`] that stays reachable behind a writer wrapping it: `csv::Writer` only hands
+/// out a shared reference to what it writes to, which is enough to borrow this mutably.
+pub struct OutputCell(RefCell<Output>);
+
+impl OutputCell {
+    pub fn new(output: Output) -> Self {
+        Self(RefCell::new(output))
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, Output> {
+        self.0.borrow_mut()
+    }
+}
+
+impl Write for OutputCell {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+/// Writes a `sha256sum`-compatible `<path>.sha256` sidecar next to a finished output file.
+pub fn write_checksum(path: &Path) -> io::Result<PathBuf> {
+    let mut file = File::open(path)?;
+    let mut hasher = Hasher::default();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.hash(&buf[..n]);
+    }
+    let digest = hex::encode(hasher.result().to_bytes());
+
+    let mut sidecar = OsString::from(path.as_os_str());
+    sidecar.push(".sha256");
+    let sidecar = PathBuf::from(sidecar);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut writer = File::create(&sidecar)?;
+    writeln!(writer, "{}  {}", digest, name)?;
+    Ok(sidecar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn resume_drops_the_unfinished_tail() {
+        let path = TempPath::file("resume.csv", "pubkey\nrow 1\nrow 2 cut sh");
+        let mut output = Output::resume(path.to_str().unwrap(), 13).unwrap();
+        output.write_all(b"row 2\n").unwrap();
+        output.finish().unwrap();
+        drop(output);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "pubkey\nrow 1\nrow 2\n"
+        );
+    }
+
+    #[test]
+    fn writes_a_sha256sum_sidecar() {
+        let path = TempPath::file("checksum.csv", "abc");
+        let sidecar = write_checksum(&path).unwrap();
+        let contents = std::fs::read_to_string(&sidecar);
+        std::fs::remove_file(&sidecar).unwrap();
+
+        assert_eq!(sidecar, PathBuf::from(format!("{}.sha256", path.display())));
+        assert_eq!(
+            contents.unwrap(),
+            format!(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  {}\n",
+                path.file_name().unwrap().to_string_lossy()
+            )
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use crate::append_vec::{append_vec_iter, AppendVec};
+use crate::filter::AccountFilter;
+
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+/// An append vec as `(slot, id, append_vec)`, numbered in archive order.
+type Work = (u64, (u64, u64, AppendVec));
+
+/// A scanned append vec with the accounts that passed the filter, or the worker's panic.
+type Scanned = (u64, std::thread::Result<(u64, u64, AppendVec, Candidates)>);
+
+/// The accounts of an append vec a scan worker found to pass the filter.
+pub struct Candidates {
+    pub offsets: Vec<usize>,
+    /// Number of accounts the worker checked.
+    pub scanned: u64,
+}
+
+/// Runs `filter` over append vecs on `threads` workers and hands each one with the offsets
+/// of its matching accounts to `handle`, in archive order, until `handle` returns false.
+///
+/// The archive is still read on the calling thread; workers get append vecs through a
+/// bounded channel and at most `2 * threads` are in flight, which caps the memory held by
+/// append vecs waiting for a slower one ahead of them.
+pub fn scan<S, E: From<S>>(
+    append_vecs: impl Iterator<Item = Result<(u64, u64, AppendVec), S>>,
+    threads: usize,
+    filter: &AccountFilter,
+    handle: impl FnMut(u64, u64, AppendVec, Candidates) -> Result<bool, E>,
+) -> Result<(), E> {
+    scan_with(
+        append_vecs,
+        threads,
+        |slot, id, append_vec| matching_offsets(filter, slot, id, append_vec),
+        handle,
+    )
+}
+
+/// [`scan`] with `work` finding the candidates of each append vec on the workers.
+///
+/// A panic in `work` is handed to the calling thread and resumed there, since the scan
+/// would otherwise wait for the append vec of the dead worker forever.
+fn scan_with<S, E: From<S>>(
+    append_vecs: impl Iterator<Item = Result<(u64, u64, AppendVec), S>>,
+    threads: usize,
+    work: impl Fn(u64, u64, AppendVec) -> (AppendVec, Candidates) + Sync,
+    handle: impl FnMut(u64, u64, AppendVec, Candidates) -> Result<bool, E>,
+) -> Result<(), E> {
+    let (work_tx, work_rx) = bounded::<Work>(threads);
+    let (done_tx, done_rx) = unbounded::<Scanned>();
+    crossbeam::thread::scope(|scope| {
+        for _ in 0..threads {
+            let work_rx = work_rx.clone();
+            let done_tx = done_tx.clone();
+            let work = &work;
+            scope.spawn(move |_| {
+                for (seq, (slot, id, append_vec)) in work_rx {
+                    let scanned = panic::catch_unwind(AssertUnwindSafe(|| {
+                        let (append_vec, candidates) = work(slot, id, append_vec);
+                        (slot, id, append_vec, candidates)
+                    }));
+                    if done_tx.send((seq, scanned)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done_tx);
+        // feed takes the only sender, so workers exit once it returns, even on an early stop
+        feed(append_vecs, threads * 2, work_tx, done_rx, handle)
+    })
+    .expect("scan worker panicked")
+}
+
+fn feed<S, E: From<S>>(
+    append_vecs: impl Iterator<Item = Result<(u64, u64, AppendVec), S>>,
+    in_flight: usize,
+    work_tx: Sender<Work>,
+    done_rx: Receiver<Scanned>,
+    mut handle: impl FnMut(u64, u64, AppendVec, Candidates) -> Result<bool, E>,
+) -> Result<(), E> {
+    let mut pending = BTreeMap::new();
+    let mut sent = 0;
+    let mut next = 0;
+    // receives one result, then hands over every result that is next in archive order
+    let mut advance = |next: &mut u64| -> Result<bool, E> {
+        let (seq, scanned) = done_rx.recv().expect("scan workers alive");
+        let scanned = scanned.unwrap_or_else(|panic| panic::resume_unwind(panic));
+        pending.insert(seq, scanned);
+        while let Some((slot, id, append_vec, candidates)) = pending.remove(next) {
+            *next += 1;
+            if !handle(slot, id, append_vec, candidates)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+    for append_vec in append_vecs {
+        work_tx
+            .send((sent, append_vec?))
+            .expect("scan workers alive");
+        sent += 1;
+        while sent - next >= in_flight as u64 {
+            if !advance(&mut next)? {
+                return Ok(());
+            }
+        }
+    }
+    drop(work_tx);
+    while next < sent {
+        if !advance(&mut next)? {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// The accounts in `append_vec` that pass `filter`, handing the append vec back.
+fn matching_offsets(
+    filter: &AccountFilter,
+    slot: u64,
+    id: u64,
+    append_vec: AppendVec,
+) -> (AppendVec, Candidates) {
+    let append_vec = Rc::new(append_vec);
+    let mut scanned = 0;
+    let offsets = append_vec_iter(append_vec.clone())
+        .filter_map(|account| {
+            let account = account.access().unwrap();
+            scanned += 1;
+            filter.trace(slot, id, &account);
+            filter.is_match(&account).then_some(account.offset)
+        })
+        .collect();
+    match Rc::try_unwrap(append_vec) {
+        Ok(append_vec) => (append_vec, Candidates { offsets, scanned }),
+        Err(_) => unreachable!("account iterator dropped"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv::CsvDumper;
+    use crate::dump::{DumpOptions, Dumper, Sink};
+    use crate::filter::MatchMode;
+    use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+    use crate::output::Output;
+    use crate::testing::{TempPath, TestAccount, TestSnapshot};
+    use solana_program::pubkey::Pubkey;
+    use std::error::Error;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// 40 append vecs of 1 to 5 accounts, every other one owned by `owner`.
+    fn snapshot(owner: Pubkey) -> TestSnapshot {
+        let other = Pubkey::new_unique();
+        TestSnapshot(
+            (0..40)
+                .map(|slot| {
+                    let accounts = (0..slot % 5 + 1)
+                        .map(|i| {
+                            let owner = if (slot + i) % 2 == 0 { owner } else { other };
+                            TestAccount::new(owner, vec![i as u8; (slot * 7 + i) as usize % 200])
+                        })
+                        .collect();
+                    (slot, accounts)
+                })
+                .collect(),
+        )
+    }
+
+    fn owner_filter(owner: &Pubkey) -> AccountFilter {
+        AccountFilter::new(&[], None, &[owner.to_string()], &[], None, MatchMode::Any).unwrap()
+    }
+
+    /// Runs `f` on its own thread, failing instead of hanging the test run if it never returns.
+    fn without_hanging<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || tx.send(f()).unwrap());
+        rx.recv_timeout(Duration::from_secs(60))
+            .expect("scan did not return")
+    }
+
+    #[test]
+    fn threads_write_the_same_output_as_a_serial_scan() {
+        let owner = Pubkey::new_unique();
+        let mut snapshot = snapshot(owner);
+        let dump = |threads: usize, snapshot: &mut TestSnapshot| -> Vec<u8> {
+            let path = TempPath::new("scan.csv");
+            let output = Output::open(path.to_str(), None).unwrap();
+            let mut dumper = Dumper::new(
+                Sink::Csv(CsvDumper::new(output)),
+                owner_filter(&owner),
+                DumpOptions::default(),
+                None,
+            );
+            if threads == 1 {
+                for append_vec in snapshot.iter() {
+                    let (slot, id, append_vec) = append_vec.unwrap();
+                    dumper.dump_append_vec(slot, id, append_vec, None).unwrap();
+                }
+            } else {
+                scan::<_, Box<dyn Error>>(
+                    snapshot.iter(),
+                    threads,
+                    &owner_filter(&owner),
+                    |slot, id, append_vec, candidates| {
+                        dumper.dump_append_vec(slot, id, append_vec, Some(candidates))?;
+                        Ok(true)
+                    },
+                )
+                .unwrap();
+            }
+            dumper.finish().unwrap();
+            std::fs::read(&path).unwrap()
+        };
+
+        let serial = dump(1, &mut snapshot);
+        assert_eq!(
+            String::from_utf8_lossy(&serial).lines().count(),
+            1 + 60,
+            "header and every account of the owner"
+        );
+        for _ in 0..3 {
+            assert_eq!(dump(4, &mut snapshot), serial);
+        }
+    }
+
+    #[test]
+    fn stops_when_the_handler_does() {
+        let handled = without_hanging(|| {
+            let owner = Pubkey::new_unique();
+            let mut snapshot = snapshot(owner);
+            let mut handled = vec![];
+            scan::<_, Box<dyn Error>>(
+                snapshot.iter(),
+                4,
+                &owner_filter(&owner),
+                |slot, _, _, _| {
+                    handled.push(slot);
+                    Ok(handled.len() < 3)
+                },
+            )
+            .unwrap();
+            handled
+        });
+        assert_eq!(handled, [0, 1, 2]);
+    }
+
+    #[test]
+    fn returns_handler_errors() {
+        let (error, handled) = without_hanging(|| {
+            let owner = Pubkey::new_unique();
+            let mut snapshot = snapshot(owner);
+            let mut handled = 0;
+            let error = scan::<_, Box<dyn Error>>(
+                snapshot.iter(),
+                4,
+                &owner_filter(&owner),
+                |slot, _, _, _| {
+                    handled += 1;
+                    match slot {
+                        5 => Err("sink closed".into()),
+                        _ => Ok(true),
+                    }
+                },
+            )
+            .unwrap_err();
+            (error.to_string(), handled)
+        });
+        assert_eq!(error, "sink closed");
+        assert_eq!(handled, 6);
+    }
+
+    #[test]
+    fn fails_when_a_worker_panics() {
+        let (panic, handled) = without_hanging(|| {
+            let owner = Pubkey::new_unique();
+            let mut snapshot = snapshot(owner);
+            let mut handled = 0;
+            let filter = owner_filter(&owner);
+            let scanned = panic::catch_unwind(AssertUnwindSafe(|| {
+                scan_with::<_, Box<dyn Error>>(
+                    snapshot.iter(),
+                    4,
+                    |slot, id, append_vec| match slot {
+                        5 => panic!("corrupt append vec"),
+                        _ => matching_offsets(&filter, slot, id, append_vec),
+                    },
+                    |_, _, _, _| {
+                        handled += 1;
+                        Ok(true)
+                    },
+                )
+            }));
+            let panic = scanned.unwrap_err();
+            (panic.downcast_ref::<&str>().map(|s| s.to_string()), handled)
+        });
+        assert_eq!(panic.as_deref(), Some("corrupt append vec"));
+        assert!(handled <= 5, "{}", handled);
+    }
+
+    #[test]
+    fn returns_archive_errors() {
+        let (error, handled) = without_hanging(|| {
+            let owner = Pubkey::new_unique();
+            let mut snapshot = snapshot(owner);
+            let append_vecs = snapshot
+                .iter()
+                .take(10)
+                .map(|append_vec| append_vec.map_err(|e| e.to_string()))
+                .chain(std::iter::once(Err("archive cut short".to_string())));
+            let mut handled = 0;
+            let error = scan::<_, String>(append_vecs, 4, &owner_filter(&owner), |_, _, _, _| {
+                handled += 1;
+                Ok(true)
+            })
+            .unwrap_err();
+            (error, handled)
+        });
+        assert_eq!(error, "archive cut short");
+        assert!(handled <= 10, "{}", handled);
+    }
+}
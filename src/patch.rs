@@ -0,0 +1,288 @@
+use crate::append_vec::{append_vec_iter, AppendVec};
+use crate::modified_solana_snapshot_etl::{parse_append_vec_name, ArchiveSnapshotExtractor};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{info, warn};
+use serde::Deserialize;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::hash::Hash;
+use solana_snapshot_etl::append_vec::{AccountMeta, StoredMeta};
+use solana_snapshot_etl::solana::{
+    deserialize_from, AccountsDbFields, DeserializableVersionedBank,
+    SerializableAccountStorageEntry,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::mem::{offset_of, size_of};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use tar::{Archive, Builder};
+use thiserror::Error;
+
+// Layout of a stored account: StoredMeta, AccountMeta, hash, data.
+const LAMPORTS_OFFSET: usize = size_of::<StoredMeta>() + offset_of!(AccountMeta, lamports);
+const OWNER_OFFSET: usize = size_of::<StoredMeta>() + offset_of!(AccountMeta, owner);
+const DATA_OFFSET: usize = size_of::<StoredMeta>() + size_of::<AccountMeta>() + size_of::<Hash>();
+
+#[derive(Error, Debug)]
+pub enum PatchError {
+    #[error("Invalid account override: {0}")]
+    InvalidOverride(String),
+    #[error("Override for {0} changes the data length, only in-place patches are supported")]
+    DataLengthMismatch(Pubkey),
+    #[error("Output {0} is the snapshot being patched")]
+    OverwritesSource(PathBuf),
+    #[error("AppendVec precedes the snapshot manifest")]
+    UnexpectedAppendVec,
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Snapshot deserialization error: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("Override file error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Deserialize)]
+struct RawAccountOverride {
+    pubkey: String,
+    lamports: Option<u64>,
+    owner: Option<String>,
+    /// base64, as emitted in the `data` column
+    data: Option<String>,
+}
+
+pub struct AccountOverride {
+    lamports: Option<u64>,
+    owner: Option<Pubkey>,
+    data: Option<Vec<u8>>,
+}
+
+/// Reads a JSON list of `{"pubkey", "lamports"?, "owner"?, "data"?}` overrides.
+pub fn load_overrides(path: &Path) -> Result<HashMap<Pubkey, AccountOverride>, PatchError> {
+    let raw: Vec<RawAccountOverride> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let mut overrides = HashMap::new();
+    for raw in raw {
+        let pubkey = Pubkey::from_str(&raw.pubkey)
+            .map_err(|_e| PatchError::InvalidOverride(format!("invalid pubkey {}", raw.pubkey)))?;
+        let owner = match raw.owner {
+            Some(owner) => Some(Pubkey::from_str(&owner).map_err(|_e| {
+                PatchError::InvalidOverride(format!("invalid owner for {}", pubkey))
+            })?),
+            None => None,
+        };
+        let data = match raw.data {
+            Some(data) => Some(STANDARD.decode(data).map_err(|_e| {
+                PatchError::InvalidOverride(format!("invalid base64 data for {}", pubkey))
+            })?),
+            None => None,
+        };
+        let account_override = AccountOverride {
+            lamports: raw.lamports,
+            owner,
+            data,
+        };
+        if overrides.insert(pubkey, account_override).is_some() {
+            return Err(PatchError::InvalidOverride(format!(
+                "duplicate override for {}",
+                pubkey
+            )));
+        }
+    }
+    Ok(overrides)
+}
+
+/// Copies a snapshot archive, rewriting every stored version of the overridden accounts.
+///
+/// The archive is streamed entry by entry; only AppendVecs are buffered. Account hashes are not
+/// recomputed, so validators must skip snapshot hash verification when loading the result.
+pub fn patch_snapshot(
+    source: &Path,
+    destination: &Path,
+    overrides: &HashMap<Pubkey, AccountOverride>,
+) -> Result<(), PatchError> {
+    // the destination is created while the source is still being read
+    if destination.exists() && destination.canonicalize()? == source.canonicalize()? {
+        return Err(PatchError::OverwritesSource(destination.to_path_buf()));
+    }
+    let decoder = zstd::stream::read::Decoder::new(File::open(source)?)?;
+    let mut archive = Archive::new(decoder);
+    let encoder = zstd::stream::write::Encoder::new(BufWriter::new(File::create(destination)?), 0)?;
+    let mut builder = Builder::new(encoder);
+
+    let mut accounts_db_fields: Option<AccountsDbFields<SerializableAccountStorageEntry>> = None;
+    let mut found: HashSet<Pubkey> = HashSet::new();
+    let mut patched = 0u64;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut header = entry.header().clone();
+
+        if ArchiveSnapshotExtractor::<File>::is_snapshot_manifest_file(&path) {
+            let mut buf = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf)?;
+            let mut reader = &buf[..];
+            let _versioned_bank: DeserializableVersionedBank = deserialize_from(&mut reader)?;
+            accounts_db_fields = Some(deserialize_from(&mut reader)?);
+            builder.append_data(&mut header, &path, &buf[..])?;
+        } else if ArchiveSnapshotExtractor::<File>::is_appendvec_file(&path) {
+            let fields = accounts_db_fields
+                .as_ref()
+                .ok_or(PatchError::UnexpectedAppendVec)?;
+            let (slot, id) = path
+                .file_name()
+                .and_then(parse_append_vec_name)
+                .ok_or(PatchError::UnexpectedAppendVec)?;
+            let current_len = fields
+                .0
+                .get(&slot)
+                .and_then(|vecs| vecs.iter().find(|v| v.id == (id as usize)))
+                .map(|v| v.accounts_current_len)
+                .ok_or(PatchError::UnexpectedAppendVec)?;
+
+            let mut buf = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf)?;
+            patched += patch_append_vec(&mut buf, current_len, overrides, &mut found)?;
+            builder.append_data(&mut header, &path, &buf[..])?;
+        } else {
+            builder.append_data(&mut header, &path, &mut entry)?;
+        }
+    }
+    builder.into_inner()?.finish()?;
+
+    info!("Patched {} stored account versions", patched);
+    for pubkey in overrides.keys().filter(|pubkey| !found.contains(pubkey)) {
+        warn!("Account {} not found in snapshot, override ignored", pubkey);
+    }
+    Ok(())
+}
+
+fn patch_append_vec(
+    buf: &mut [u8],
+    current_len: usize,
+    overrides: &HashMap<Pubkey, AccountOverride>,
+    found: &mut HashSet<Pubkey>,
+) -> Result<u64, PatchError> {
+    let append_vec = AppendVec::new_from_reader(&mut &buf[..], current_len)?;
+    let mut targets = vec![];
+    for account in append_vec_iter(Rc::new(append_vec)) {
+        let account = account.access().unwrap();
+        let pubkey = account.meta.pubkey;
+        if let Some(account_override) = overrides.get(&pubkey) {
+            let data_len = account.meta.data_len as usize;
+            if let Some(data) = &account_override.data {
+                if data.len() != data_len {
+                    return Err(PatchError::DataLengthMismatch(pubkey));
+                }
+            }
+            targets.push((account.offset, data_len, pubkey));
+        }
+    }
+
+    for (offset, data_len, pubkey) in targets.iter() {
+        let account_override = &overrides[pubkey];
+        if let Some(lamports) = account_override.lamports {
+            buf[offset + LAMPORTS_OFFSET..offset + LAMPORTS_OFFSET + 8]
+                .copy_from_slice(&lamports.to_le_bytes());
+        }
+        if let Some(owner) = &account_override.owner {
+            buf[offset + OWNER_OFFSET..offset + OWNER_OFFSET + 32].copy_from_slice(owner.as_ref());
+        }
+        if let Some(data) = &account_override.data {
+            buf[offset + DATA_OFFSET..offset + DATA_OFFSET + data_len].copy_from_slice(data);
+        }
+        found.insert(*pubkey);
+    }
+    Ok(targets.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{append_vec_bytes, TempPath, TestAccount};
+
+    fn patched(
+        accounts: &[TestAccount],
+        overrides: &HashMap<Pubkey, AccountOverride>,
+    ) -> Result<(u64, Vec<u8>), PatchError> {
+        let mut bytes = append_vec_bytes(accounts);
+        let len = bytes.len();
+        let patched = patch_append_vec(&mut bytes, len, overrides, &mut HashSet::new())?;
+        Ok((patched, bytes))
+    }
+
+    #[test]
+    fn rewrites_lamports_owner_and_data_in_place() {
+        let accounts = [
+            TestAccount::new(Pubkey::new_unique(), vec![1; 10]),
+            TestAccount::new(Pubkey::new_unique(), vec![2; 10]),
+        ];
+        let target = accounts[1].meta.pubkey;
+        let owner = Pubkey::new_unique();
+        let overrides = HashMap::from([(
+            target,
+            AccountOverride {
+                lamports: Some(42),
+                owner: Some(owner),
+                data: Some(vec![7; 10]),
+            },
+        )]);
+
+        let (count, bytes) = patched(&accounts, &overrides).unwrap();
+        assert_eq!(count, 1);
+        let append_vec = AppendVec::new_from_reader(&mut &bytes[..], bytes.len()).unwrap();
+        let stored: Vec<_> = append_vec_iter(Rc::new(append_vec))
+            .map(|account| {
+                let account = account.access().unwrap();
+                (
+                    account.meta.pubkey,
+                    account.account_meta.lamports,
+                    account.account_meta.owner,
+                    account.data.to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                (
+                    accounts[0].meta.pubkey,
+                    accounts[0].account_meta.lamports,
+                    accounts[0].account_meta.owner,
+                    vec![1; 10],
+                ),
+                (target, 42, owner, vec![7; 10]),
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_data_of_another_length() {
+        let account = TestAccount::new(Pubkey::new_unique(), vec![1; 10]);
+        let overrides = HashMap::from([(
+            account.meta.pubkey,
+            AccountOverride {
+                lamports: None,
+                owner: None,
+                data: Some(vec![1; 11]),
+            },
+        )]);
+
+        let error = patched(&[account], &overrides).unwrap_err();
+        assert!(matches!(error, PatchError::DataLengthMismatch(_)));
+    }
+
+    #[test]
+    fn refuses_to_overwrite_the_source() {
+        let dir = TempPath::dir("patch");
+        let source = dir.join("snapshot.tar.zst");
+        std::fs::write(&source, b"archive").unwrap();
+
+        let same = dir.join(".").join("snapshot.tar.zst");
+        let error = patch_snapshot(&source, &same, &HashMap::new()).unwrap_err();
+        assert!(matches!(error, PatchError::OverwritesSource(_)));
+        assert_eq!(std::fs::read(&source).unwrap(), b"archive");
+    }
+}
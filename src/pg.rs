@@ -0,0 +1,162 @@
+use crate::dump::DumpOptions;
+
+use postgres::{Client, NoTls};
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::io::Write;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PostgresSinkError {
+    #[error("Invalid --postgres-table {0:?} (letters, digits and '_', as TABLE or SCHEMA.TABLE)")]
+    InvalidTable(String),
+    #[error("Postgres error: {0}")]
+    Postgres(#[from] postgres::Error),
+    #[error("Postgres COPY failed: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+const COLUMNS: &str = "pubkey, owner, lamports, executable, rent_epoch, data, slot, write_version";
+
+/// Bytes of pending rows that end a batch early, so huge accounts don't pile up in memory.
+const MAX_BATCH_BYTES: usize = 64 << 20;
+
+/// Session-local table each upsert batch is copied into before it is merged.
+const STAGING_TABLE: &str = "solana_snapshot_gpa_staging";
+
+/// Streams matched accounts into a Postgres table, one `COPY` per batch of rows.
+pub struct PostgresSink {
+    client: Client,
+    table: String,
+    upsert: bool,
+    batch_size: usize,
+    rows: Vec<u8>,
+    batched: usize,
+}
+
+impl PostgresSink {
+    /// Connects to `url` and creates `table` if it doesn't exist. With `upsert` the table is
+    /// keyed on pubkey and rows replace older versions of the same account instead of adding to
+    /// them.
+    pub fn connect(
+        url: &str,
+        table: &str,
+        upsert: bool,
+        batch_size: usize,
+    ) -> Result<Self, PostgresSinkError> {
+        let valid = table.split('.').count() <= 2
+            && table.split('.').all(|part| {
+                part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            });
+        if !valid {
+            return Err(PostgresSinkError::InvalidTable(table.to_string()));
+        }
+        let mut client = Client::connect(url, NoTls)?;
+        // rent_epoch is u64::MAX for rent-exempt accounts in recent snapshots, past bigint
+        client.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                pubkey text NOT NULL{},
+                owner text NOT NULL,
+                lamports bigint NOT NULL,
+                executable boolean NOT NULL,
+                rent_epoch numeric(20, 0) NOT NULL,
+                data bytea NOT NULL,
+                slot bigint NOT NULL,
+                write_version bigint NOT NULL
+            )",
+            table,
+            if upsert { " PRIMARY KEY" } else { "" },
+        ))?;
+        if upsert {
+            client.batch_execute(&format!(
+                "CREATE TEMP TABLE {} (LIKE {} INCLUDING DEFAULTS) ON COMMIT DELETE ROWS",
+                STAGING_TABLE, table,
+            ))?;
+        }
+        Ok(Self {
+            client,
+            table: table.to_string(),
+            upsert,
+            batch_size: batch_size.max(1),
+            rows: Vec::new(),
+            batched: 0,
+        })
+    }
+
+    pub fn dump_account(
+        &mut self,
+        options: &DumpOptions,
+        slot: u64,
+        account: &StoredAccountMeta,
+    ) -> Result<(), PostgresSinkError> {
+        // CSV rows for COPY; nothing but the bytea hex needs quoting or escaping
+        writeln!(
+            self.rows,
+            "{},{},{},{},{},\\x{},{},{}",
+            options.format_pubkey(account),
+            account.account_meta.owner,
+            account.account_meta.lamports,
+            account.account_meta.executable,
+            account.account_meta.rent_epoch,
+            hex::encode(options.data(account)),
+            slot,
+            account.meta.write_version,
+        )
+        .expect("writing to a Vec");
+        self.batched += 1;
+        if self.batched >= self.batch_size || self.rows.len() >= MAX_BATCH_BYTES {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Copies the pending batch in one transaction.
+    pub fn flush(&mut self) -> Result<(), PostgresSinkError> {
+        if self.batched == 0 {
+            return Ok(());
+        }
+        let target = if self.upsert {
+            STAGING_TABLE
+        } else {
+            &self.table
+        };
+        let mut transaction = self.client.transaction()?;
+        let mut writer = transaction.copy_in(&format!(
+            "COPY {} ({}) FROM STDIN (FORMAT csv)",
+            target, COLUMNS
+        ))?;
+        // dropping the writer on a failed write aborts the COPY and the transaction
+        writer.write_all(&self.rows)?;
+        writer.finish()?;
+        if self.upsert {
+            // a batch may hold several versions of an account; only the newest is merged,
+            // and only over an older stored one, so batch order doesn't matter
+            transaction.batch_execute(&format!(
+                "INSERT INTO {table} ({columns})
+                 SELECT DISTINCT ON (pubkey) {columns} FROM {staging}
+                 ORDER BY pubkey, slot DESC, write_version DESC
+                 ON CONFLICT (pubkey) DO UPDATE SET
+                    owner = EXCLUDED.owner,
+                    lamports = EXCLUDED.lamports,
+                    executable = EXCLUDED.executable,
+                    rent_epoch = EXCLUDED.rent_epoch,
+                    data = EXCLUDED.data,
+                    slot = EXCLUDED.slot,
+                    write_version = EXCLUDED.write_version
+                 WHERE ({table}.slot, {table}.write_version)
+                    <= (EXCLUDED.slot, EXCLUDED.write_version)",
+                table = self.table,
+                columns = COLUMNS,
+                staging = STAGING_TABLE,
+            ))?;
+        }
+        transaction.commit()?;
+        self.rows.clear();
+        self.batched = 0;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), PostgresSinkError> {
+        self.flush()
+    }
+}
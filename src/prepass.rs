@@ -0,0 +1,139 @@
+use crate::append_vec::append_vec_iter;
+use crate::closed::{latest_versions, AccountVersion};
+use crate::filter::{AccountFilter, FilterParseError, MatchMode, OwnerFilter, References};
+use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Filter of a preliminary scan whose matches become owners for the main scan:
+/// `owner=<--owner syntax>` or `references=<pubkey>`.
+#[derive(Debug, Clone)]
+pub(crate) enum OwnerQuery {
+    Owner(String),
+    References(String),
+}
+
+impl FromStr for OwnerQuery {
+    type Err = FilterParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // parse the filter now so mistakes surface before any scanning starts
+        match s.split_once('=') {
+            Some(("owner", spec)) => {
+                OwnerFilter::new(spec)?;
+                Ok(OwnerQuery::Owner(spec.to_string()))
+            }
+            Some(("references", pubkey)) => {
+                References::new(pubkey)?;
+                Ok(OwnerQuery::References(pubkey.to_string()))
+            }
+            _ => Err(FilterParseError::UnknownFilter),
+        }
+    }
+}
+
+/// Single filter matching any of `queries`, so all owner sets come out of one pass.
+pub(crate) fn query_filter(queries: &[OwnerQuery]) -> Result<AccountFilter, FilterParseError> {
+    let mut owners = vec![];
+    let mut references = vec![];
+    for query in queries {
+        match query {
+            OwnerQuery::Owner(spec) => owners.push(spec.clone()),
+            OwnerQuery::References(pubkey) => references.push(pubkey.clone()),
+        }
+    }
+    AccountFilter::new(&[], None, &owners, &references, None, MatchMode::Any)
+}
+
+/// Preliminary scans collecting the pubkeys of accounts accepted by `keep` whose newest
+/// version still holds lamports, as [`newest_accounts`] finds them. Used to build owner
+/// sets for the main scan.
+pub(crate) fn alive_pubkeys<A: SnapshotExtractor, B: SnapshotExtractor>(
+    first: &mut A,
+    second: &mut B,
+    keep: impl Fn(&StoredAccountMeta) -> bool,
+) -> solana_snapshot_etl::Result<HashSet<Pubkey>> {
+    Ok(newest_accounts(first, second, keep)?.into_keys().collect())
+}
+
+/// Pubkeys of every account stored in `loader`, whatever its state.
+pub(crate) fn stored_pubkeys<L: SnapshotExtractor>(
+    loader: &mut L,
+) -> solana_snapshot_etl::Result<HashSet<Pubkey>> {
+    let mut pubkeys = HashSet::new();
+    for append_vec in loader.iter() {
+        let (_slot, _id, append_vec) = append_vec?;
+        for account in append_vec_iter(Rc::new(append_vec)) {
+            pubkeys.insert(account.access().unwrap().meta.pubkey);
+        }
+    }
+    Ok(pubkeys)
+}
+
+/// Newest version of every account with at least one version accepted by `keep`, skipping
+/// accounts whose newest version holds no lamports.
+///
+/// Takes two preliminary scans: the newest version may itself no longer be accepted (a
+/// closed or reassigned account), and may come before the accepted ones in the archive.
+pub(crate) fn newest_accounts<A: SnapshotExtractor, B: SnapshotExtractor>(
+    first: &mut A,
+    second: &mut B,
+    keep: impl Fn(&StoredAccountMeta) -> bool,
+) -> solana_snapshot_etl::Result<HashMap<Pubkey, AccountVersion>> {
+    let candidates = latest_versions(first, keep)?;
+    let mut versions = latest_versions(second, |account| {
+        candidates.contains_key(&account.meta.pubkey)
+    })?;
+    versions.retain(|_, version| version.lamports > 0);
+    Ok(versions)
+}
+
+/// `(slot, write_version)` of every account [`newest_accounts`] keeps.
+pub(crate) fn newest_versions<A: SnapshotExtractor, B: SnapshotExtractor>(
+    first: &mut A,
+    second: &mut B,
+    keep: impl Fn(&StoredAccountMeta) -> bool,
+) -> solana_snapshot_etl::Result<HashMap<Pubkey, (u64, u64)>> {
+    Ok(newest_accounts(first, second, keep)?
+        .into_iter()
+        .map(|(pubkey, version)| (pubkey, (version.slot, version.write_version)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestAccount, TestSnapshot};
+    use solana_program::system_program;
+
+    fn account(pubkey: Pubkey, owner: Pubkey, lamports: u64) -> TestAccount {
+        let mut account = TestAccount::new(owner, vec![0; 8]);
+        account.meta.pubkey = pubkey;
+        account.account_meta.lamports = lamports;
+        account
+    }
+
+    #[test]
+    fn alive_pubkeys_skip_accounts_whose_newest_version_is_a_tombstone() {
+        let loader = Pubkey::new_unique();
+        let (alive, closed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        // the tombstone of `closed` comes first in the archive and no longer matches
+        let snapshot = || {
+            TestSnapshot(vec![
+                (20, vec![account(closed, system_program::id(), 0)]),
+                (
+                    10,
+                    vec![account(alive, loader, 500), account(closed, loader, 700)],
+                ),
+            ])
+        };
+        let keep = |account: &StoredAccountMeta| account.account_meta.owner == loader;
+
+        let pubkeys = alive_pubkeys(&mut snapshot(), &mut snapshot(), keep).unwrap();
+        assert_eq!(pubkeys, HashSet::from([alive]));
+    }
+}
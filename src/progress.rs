@@ -0,0 +1,140 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two JSON progress events.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ProgressFormat {
+    /// A progress bar with throughput and ETA
+    Bar,
+    /// Human readable log lines every 100 append vecs
+    Log,
+    /// One JSON object per line on stderr, at start, every few seconds and at the end
+    Json,
+}
+
+impl ProgressFormat {
+    /// The bar on a terminal, log lines when stderr goes to a file or a pipe.
+    pub(crate) fn detect() -> Self {
+        if std::io::stderr().is_terminal() {
+            ProgressFormat::Bar
+        } else {
+            ProgressFormat::Log
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ProgressEvent {
+    event: &'static str,
+    append_vecs: u64,
+    scanned_bytes: u64,
+    total_bytes: u64,
+    matched: u64,
+    elapsed_secs: f64,
+    eta_secs: Option<f64>,
+}
+
+/// Reports scan progress in the selected format, or not at all without one (`--quiet`).
+pub(crate) struct Progress {
+    format: Option<ProgressFormat>,
+    bar: Option<ProgressBar>,
+    total_bytes: u64,
+    started: Instant,
+    last_event: Instant,
+    append_vecs: u64,
+    scanned_bytes: u64,
+}
+
+impl Progress {
+    /// `total_bytes` is the account data listed in the snapshot manifest, used for the ETA.
+    pub(crate) fn new(format: Option<ProgressFormat>, total_bytes: u64) -> Self {
+        let now = Instant::now();
+        let bar = (format == Some(ProgressFormat::Bar)).then(|| {
+            let bar = ProgressBar::with_draw_target(Some(total_bytes), ProgressDrawTarget::stderr());
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} {bytes_per_sec}, ETA {eta} | {msg}",
+                )
+                .expect("valid progress template"),
+            );
+            bar
+        });
+        let progress = Self {
+            format,
+            bar,
+            total_bytes,
+            started: now,
+            last_event: now,
+            append_vecs: 0,
+            scanned_bytes: 0,
+        };
+        progress.emit("start", 0);
+        progress
+    }
+
+    pub(crate) fn scanned_bytes(&self) -> u64 {
+        self.scanned_bytes
+    }
+
+    /// Records one processed append vec of `bytes` account data. `scanned` and `matched` are
+    /// the accounts checked and matched so far.
+    pub(crate) fn append_vec(&mut self, bytes: u64, scanned: u64, matched: u64) {
+        self.append_vecs += 1;
+        self.scanned_bytes += bytes;
+        match self.format {
+            None => {}
+            Some(ProgressFormat::Bar) => {
+                if let Some(bar) = &self.bar {
+                    bar.set_position(self.scanned_bytes);
+                    bar.set_message(format!(
+                        "{} append vecs, {} accounts scanned, {} matched",
+                        self.append_vecs, scanned, matched
+                    ));
+                }
+            }
+            Some(ProgressFormat::Log) if self.append_vecs.is_multiple_of(100) => {
+                info!("AppendVec processed: {}", self.append_vecs);
+            }
+            Some(ProgressFormat::Json) if self.last_event.elapsed() >= HEARTBEAT_INTERVAL => {
+                self.last_event = Instant::now();
+                self.emit("progress", matched);
+            }
+            Some(ProgressFormat::Log | ProgressFormat::Json) => {}
+        }
+    }
+
+    pub(crate) fn finish(&self, matched: u64) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+        self.emit("done", matched);
+    }
+
+    fn emit(&self, event: &'static str, matched: u64) {
+        if self.format != Some(ProgressFormat::Json) {
+            return;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        // account data scanned so far is the best proxy for work done
+        let remaining = self.total_bytes.saturating_sub(self.scanned_bytes);
+        let eta_secs = (self.scanned_bytes > 0 && remaining > 0)
+            .then(|| elapsed * remaining as f64 / self.scanned_bytes as f64);
+        let event = ProgressEvent {
+            event,
+            append_vecs: self.append_vecs,
+            scanned_bytes: self.scanned_bytes,
+            total_bytes: self.total_bytes,
+            matched,
+            elapsed_secs: elapsed,
+            eta_secs,
+        };
+        if let Ok(line) = serde_json::to_string(&event) {
+            eprintln!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,690 @@
+use crate::anonymize::Anonymizer;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProjectionParseError {
+    #[error("Invalid projection syntax at position {0}")]
+    InvalidSyntax(usize),
+    #[error("Unknown projection field: .{0}")]
+    UnknownField(String),
+    #[error("Unknown projection function: {0}")]
+    UnknownFunction(String),
+    #[error("Wrong number of arguments for {0}")]
+    InvalidArguments(String),
+}
+
+/// A record field, `.pubkey` through `.data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Pubkey,
+    Owner,
+    DataLen,
+    Lamports,
+    Slot,
+    Id,
+    Offset,
+    WriteVersion,
+    Executable,
+    RentEpoch,
+    Data,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Func {
+    U8,
+    U16Le,
+    U32Le,
+    U64Le,
+    I64Le,
+    U128Le,
+    Pubkey,
+    Slice,
+    Hex,
+    Base64,
+    Len,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Field(Field),
+    Call(Func, Box<Expr>, Vec<usize>),
+}
+
+/// Bytes `start..start + len` of `.data` (to the end when `len` is `None`), valid for data
+/// of at least `min_len` bytes: what a chain of constant `slice()` calls resolves to.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: usize,
+    len: Option<usize>,
+    min_len: usize,
+}
+
+/// A fixed-width read whose offset into `.data` is known at parse time.
+#[derive(Debug, Clone)]
+struct Extractor {
+    func: Func,
+    offset: usize,
+    min_len: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Column {
+    Expr(Expr),
+    /// Read directly from the account data, skipping expression evaluation.
+    Fixed(Extractor),
+    /// Reads out of range for every account, e.g. past the end of a constant slice.
+    Empty,
+}
+
+enum Value<'a> {
+    Text(String),
+    Bytes(&'a [u8]),
+    Null,
+}
+
+/// Output shape built from `{name: expr, ...}` mini-expressions, e.g.
+/// `{pk: .pubkey, amt: u64le(.data, 64)}`.
+#[derive(Debug, Clone)]
+pub struct Projection {
+    fields: Vec<(String, Column)>,
+    /// The fields of a `--fields` list without `.data`, which can be written straight from
+    /// the account header.
+    header_fields: Option<Vec<Field>>,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pubkey" => Some(Field::Pubkey),
+            "owner" => Some(Field::Owner),
+            "data_len" => Some(Field::DataLen),
+            "lamports" => Some(Field::Lamports),
+            "slot" => Some(Field::Slot),
+            "id" => Some(Field::Id),
+            "offset" => Some(Field::Offset),
+            "write_version" => Some(Field::WriteVersion),
+            "executable" => Some(Field::Executable),
+            "rent_epoch" => Some(Field::RentEpoch),
+            "data" => Some(Field::Data),
+            _ => None,
+        }
+    }
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "u8" => Some(Func::U8),
+            "u16le" => Some(Func::U16Le),
+            "u32le" => Some(Func::U32Le),
+            "u64le" => Some(Func::U64Le),
+            "i64le" => Some(Func::I64Le),
+            "u128le" => Some(Func::U128Le),
+            "pubkey" => Some(Func::Pubkey),
+            "slice" => Some(Func::Slice),
+            "hex" => Some(Func::Hex),
+            "base64" => Some(Func::Base64),
+            "len" => Some(Func::Len),
+            _ => None,
+        }
+    }
+
+    /// Bytes read by fixed-width functions.
+    fn width(&self) -> Option<usize> {
+        match self {
+            Func::U8 => Some(1),
+            Func::U16Le => Some(2),
+            Func::U32Le => Some(4),
+            Func::U64Le | Func::I64Le => Some(8),
+            Func::U128Le => Some(16),
+            Func::Pubkey => Some(32),
+            Func::Slice | Func::Hex | Func::Base64 | Func::Len => None,
+        }
+    }
+
+    /// Accepted numbers of integer arguments after the bytes argument.
+    fn arities(&self) -> &'static [usize] {
+        match self {
+            Func::U8 | Func::U16Le | Func::U32Le | Func::U64Le | Func::I64Le | Func::U128Le => &[1],
+            Func::Pubkey => &[1],
+            Func::Slice => &[2],
+            Func::Hex | Func::Base64 => &[0, 2],
+            Func::Len => &[0],
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            input,
+            chars: input.char_indices().peekable(),
+        }
+    }
+
+    fn position(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|(i, _)| *i)
+            .unwrap_or(self.input.len())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ProjectionParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(ProjectionParseError::InvalidSyntax(self.position())),
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, ProjectionParseError> {
+        self.skip_whitespace();
+        let start = self.position();
+        while self
+            .chars
+            .peek()
+            .is_some_and(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            self.chars.next();
+        }
+        let end = self.position();
+        if start == end {
+            return Err(ProjectionParseError::InvalidSyntax(start));
+        }
+        Ok(&self.input[start..end])
+    }
+
+    fn number(&mut self) -> Result<usize, ProjectionParseError> {
+        let position = self.position();
+        self.ident()?
+            .parse::<usize>()
+            .map_err(|_e| ProjectionParseError::InvalidSyntax(position))
+    }
+
+    fn expr(&mut self) -> Result<Expr, ProjectionParseError> {
+        if self.peek() == Some('.') {
+            self.chars.next();
+            let name = self.ident()?;
+            let field = Field::from_name(name)
+                .ok_or_else(|| ProjectionParseError::UnknownField(name.to_string()))?;
+            return Ok(Expr::Field(field));
+        }
+
+        let name = self.ident()?;
+        let func = Func::from_name(name)
+            .ok_or_else(|| ProjectionParseError::UnknownFunction(name.to_string()))?;
+        self.expect('(')?;
+        let arg = self.expr()?;
+        let mut params = vec![];
+        while self.peek() == Some(',') {
+            self.chars.next();
+            params.push(self.number()?);
+        }
+        self.expect(')')?;
+        if !func.arities().contains(&params.len()) {
+            return Err(ProjectionParseError::InvalidArguments(name.to_string()));
+        }
+        Ok(Expr::Call(func, Box::new(arg), params))
+    }
+
+    fn projection(&mut self) -> Result<Projection, ProjectionParseError> {
+        let mut fields = vec![];
+        self.expect('{')?;
+        loop {
+            let name = self.ident()?.to_string();
+            self.expect(':')?;
+            let expr = self.expr()?;
+            fields.push((name, expr.compile()));
+            match self.peek() {
+                Some(',') => {
+                    self.chars.next();
+                }
+                Some('}') => {
+                    self.chars.next();
+                    break;
+                }
+                _ => return Err(ProjectionParseError::InvalidSyntax(self.position())),
+            }
+        }
+        if self.peek().is_some() {
+            return Err(ProjectionParseError::InvalidSyntax(self.position()));
+        }
+        Ok(Projection {
+            fields,
+            header_fields: None,
+        })
+    }
+}
+
+impl FromStr for Projection {
+    type Err = ProjectionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser::new(s).projection()
+    }
+}
+
+fn read_le<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+impl Window {
+    /// `slice(self, offset, len)`, or `None` when it is out of range for every account.
+    fn slice(self, offset: usize, len: usize) -> Option<Window> {
+        let end = offset.checked_add(len)?;
+        if self.len.is_some_and(|window_len| end > window_len) {
+            return None;
+        }
+        let start = self.start.checked_add(offset)?;
+        Some(Window {
+            start,
+            len: Some(len),
+            min_len: self.min_len.max(start.checked_add(len)?),
+        })
+    }
+}
+
+impl Expr {
+    /// The constant window of `.data` this expression evaluates to, if it is `.data` or a
+    /// chain of `slice()` calls on it. `Some(None)` when it is out of range for every account.
+    fn window(&self) -> Option<Option<Window>> {
+        match self {
+            Expr::Field(Field::Data) => Some(Some(Window {
+                start: 0,
+                len: None,
+                min_len: 0,
+            })),
+            Expr::Call(Func::Slice, arg, params) => Some(
+                arg.window()?
+                    .and_then(|window| window.slice(params[0], params[1])),
+            ),
+            _ => None,
+        }
+    }
+
+    /// True if the value carries raw `.data` bytes: `.data` itself, or `slice()`, `hex()` or
+    /// `base64()` of it. Numbers read out of the data and `pubkey()` (tokenized when
+    /// anonymizing) don't.
+    fn exposes_data(&self) -> bool {
+        match self {
+            Expr::Field(field) => matches!(field, Field::Data),
+            Expr::Call(Func::Slice | Func::Hex | Func::Base64, arg, _) => arg.exposes_data(),
+            Expr::Call(..) => false,
+        }
+    }
+
+    /// Turns fixed-width reads at constant offsets into extractors.
+    fn compile(self) -> Column {
+        let Expr::Call(func, arg, params) = &self else {
+            return Column::Expr(self);
+        };
+        let (Some(width), Some(window)) = (func.width(), arg.window()) else {
+            return Column::Expr(self);
+        };
+        match window.and_then(|window| window.slice(params[0], width)) {
+            Some(read) => Column::Fixed(Extractor {
+                func: *func,
+                offset: read.start,
+                min_len: read.min_len,
+            }),
+            None => Column::Empty,
+        }
+    }
+
+    fn eval<'a>(
+        &self,
+        slot: u64,
+        id: u64,
+        account: &StoredAccountMeta<'a>,
+        anonymizer: Option<&Anonymizer>,
+    ) -> Value<'a> {
+        match self {
+            Expr::Field(field) => match field {
+                Field::Pubkey => Value::Text(match anonymizer {
+                    Some(anonymizer) => anonymizer.pubkey(&account.meta.pubkey),
+                    None => account.meta.pubkey.to_string(),
+                }),
+                Field::Owner => Value::Text(account.account_meta.owner.to_string()),
+                Field::DataLen => Value::Text(account.meta.data_len.to_string()),
+                Field::Lamports => Value::Text(account.account_meta.lamports.to_string()),
+                Field::Slot => Value::Text(slot.to_string()),
+                Field::Id => Value::Text(id.to_string()),
+                Field::Offset => Value::Text(account.offset.to_string()),
+                Field::WriteVersion => Value::Text(account.meta.write_version.to_string()),
+                Field::Executable => Value::Text(account.account_meta.executable.to_string()),
+                Field::RentEpoch => Value::Text(account.account_meta.rent_epoch.to_string()),
+                Field::Data => Value::Bytes(account.data),
+            },
+            Expr::Call(func, arg, params) => {
+                let bytes = match arg.eval(slot, id, account, anonymizer) {
+                    Value::Bytes(bytes) => bytes,
+                    _ => return Value::Null,
+                };
+                Self::call(*func, bytes, params, anonymizer).unwrap_or(Value::Null)
+            }
+        }
+    }
+
+    fn call<'a>(
+        func: Func,
+        bytes: &'a [u8],
+        params: &[usize],
+        anonymizer: Option<&Anonymizer>,
+    ) -> Option<Value<'a>> {
+        let offset = params.first().copied().unwrap_or(0);
+        let text = |s: String| Some(Value::Text(s));
+        match func {
+            Func::U8 => text(bytes.get(offset)?.to_string()),
+            Func::U16Le => text(u16::from_le_bytes(read_le(bytes, offset)?).to_string()),
+            Func::U32Le => text(u32::from_le_bytes(read_le(bytes, offset)?).to_string()),
+            Func::U64Le => text(u64::from_le_bytes(read_le(bytes, offset)?).to_string()),
+            Func::I64Le => text(i64::from_le_bytes(read_le(bytes, offset)?).to_string()),
+            Func::U128Le => text(u128::from_le_bytes(read_le(bytes, offset)?).to_string()),
+            Func::Pubkey => {
+                let key = read_le::<32>(bytes, offset)?;
+                text(match anonymizer {
+                    Some(anonymizer) => anonymizer.bytes32(&key),
+                    None => bs58::encode(key).into_string(),
+                })
+            }
+            Func::Slice => {
+                let end = offset.checked_add(params[1])?;
+                Some(Value::Bytes(bytes.get(offset..end)?))
+            }
+            Func::Hex | Func::Base64 => {
+                let bytes = match params.len() {
+                    0 => bytes,
+                    _ => bytes.get(offset..offset.checked_add(params[1])?)?,
+                };
+                match func {
+                    Func::Hex => text(hex::encode(bytes)),
+                    _ => text(STANDARD.encode(bytes)),
+                }
+            }
+            Func::Len => text(bytes.len().to_string()),
+        }
+    }
+}
+
+impl Extractor {
+    fn eval(&self, account: &StoredAccountMeta, anonymizer: Option<&Anonymizer>) -> String {
+        let data = account.data;
+        if data.len() < self.min_len {
+            return String::new();
+        }
+        match Expr::call(self.func, data, &[self.offset], anonymizer) {
+            Some(Value::Text(text)) => text,
+            _ => String::new(),
+        }
+    }
+}
+
+impl Projection {
+    /// `--fields`: comma-separated record fields, each a column named after the field,
+    /// e.g. `pubkey,owner,lamports`.
+    pub fn from_fields(s: &str) -> Result<Self, ProjectionParseError> {
+        let mut fields = vec![];
+        let mut position = 0;
+        for name in s.split(',') {
+            let trimmed = name.trim();
+            if trimmed.is_empty() {
+                return Err(ProjectionParseError::InvalidSyntax(position));
+            }
+            let field = Field::from_name(trimmed)
+                .ok_or_else(|| ProjectionParseError::UnknownField(trimmed.to_string()))?;
+            fields.push((trimmed.to_string(), field));
+            position += name.len() + 1;
+        }
+        let header_fields = fields
+            .iter()
+            .all(|(_, field)| *field != Field::Data)
+            .then(|| fields.iter().map(|(_, field)| *field).collect());
+        Ok(Projection {
+            fields: fields
+                .into_iter()
+                .map(|(name, field)| (name, Expr::Field(field).compile()))
+                .collect(),
+            header_fields,
+        })
+    }
+
+    /// The fields of a `--fields` list that leaves `.data` out, so that metadata-only
+    /// exports skip expression evaluation and never read the data.
+    pub fn header_fields(&self) -> Option<&[Field]> {
+        self.header_fields.as_deref()
+    }
+
+    /// True if a column writes raw account data, which `--anonymize` leaves untouched.
+    pub fn exposes_data(&self) -> bool {
+        self.fields
+            .iter()
+            .any(|(_, column)| matches!(column, Column::Expr(expr) if expr.exposes_data()))
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Evaluates every column; `.pubkey` and `pubkey()` values are tokenized when an
+    /// anonymizer is given.
+    pub fn eval(
+        &self,
+        slot: u64,
+        id: u64,
+        account: &StoredAccountMeta,
+        anonymizer: Option<&Anonymizer>,
+    ) -> Vec<String> {
+        self.fields
+            .iter()
+            .map(|(_, column)| match column {
+                Column::Expr(expr) => match expr.eval(slot, id, account, anonymizer) {
+                    Value::Text(text) => text,
+                    Value::Bytes(bytes) => STANDARD.encode(bytes),
+                    Value::Null => String::new(),
+                },
+                Column::Fixed(extractor) => extractor.eval(account, anonymizer),
+                Column::Empty => String::new(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{put, TestAccount};
+    use solana_program::pubkey::Pubkey;
+
+    /// 132 bytes: 0..4 are `01 02 03 04`, the u64 500 at 64 and a key at 100.
+    fn account(key: &Pubkey) -> TestAccount {
+        let mut data = vec![0; 132];
+        put(&mut data, 0, &[1, 2, 3, 4]);
+        put(&mut data, 64, &500u64.to_le_bytes());
+        put(&mut data, 100, &key.to_bytes());
+        TestAccount::new(Pubkey::new_unique(), data)
+    }
+
+    fn parse(s: &str) -> Projection {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    #[test]
+    fn evaluates_fields_and_functions() {
+        let key = Pubkey::new_unique();
+        let account = account(&key);
+        let projection = parse(
+            "{ pk: .pubkey, owner: .owner, lamports: .lamports, slot: .slot, id: .id, \
+             executable: .executable, amount: u64le(.data, 64), nested: u8(slice(.data, 60, 10), 4), \
+             key: pubkey(.data, 100), head: hex(.data, 0, 4), tail: base64(slice(.data, 1, 3)), \
+             len: len(slice(.data, 0, 9)), data: .data }",
+        );
+        let headers: Vec<&str> = projection.headers().collect();
+        assert_eq!(
+            headers,
+            [
+                "pk",
+                "owner",
+                "lamports",
+                "slot",
+                "id",
+                "executable",
+                "amount",
+                "nested",
+                "key",
+                "head",
+                "tail",
+                "len",
+                "data"
+            ]
+        );
+        let row = projection.eval(5, 6, &account.stored(), None);
+        assert_eq!(
+            row,
+            [
+                account.meta.pubkey.to_string(),
+                account.account_meta.owner.to_string(),
+                "1000000000".to_string(),
+                "5".to_string(),
+                "6".to_string(),
+                "false".to_string(),
+                "500".to_string(),
+                // byte 64, the low byte of the amount
+                (500 % 256).to_string(),
+                key.to_string(),
+                "01020304".to_string(),
+                STANDARD.encode([2, 3, 4]),
+                "9".to_string(),
+                STANDARD.encode(&account.data),
+            ]
+        );
+        assert!(projection.exposes_data());
+        assert!(projection.header_fields().is_none());
+    }
+
+    #[test]
+    fn reads_out_of_range_as_empty() {
+        let account = TestAccount::new(Pubkey::new_unique(), vec![7; 10]);
+        let projection = parse(
+            "{a: u64le(.data, 64), b: u64le(slice(.data, 0, 4), 0), c: u8(.data, 9), \
+             d: slice(.data, 8, 4), e: u8(.data, 18446744073709551615), f: u8(hex(.data), 0)}",
+        );
+        let row = projection.eval(0, 0, &account.stored(), None);
+        assert_eq!(row, ["", "", "7", "", "", ""]);
+    }
+
+    #[test]
+    fn tells_which_projections_expose_data() {
+        for (s, exposes) in [
+            ("{d: .data}", true),
+            ("{h: hex(slice(.data, 0, 4))}", true),
+            ("{b: base64(.data, 0, 4)}", true),
+            ("{s: slice(slice(.data, 4, 8), 0, 2)}", true),
+            ("{a: u64le(.data, 0)}", false),
+            ("{k: pubkey(.data, 0)}", false),
+            ("{l: len(.data)}", false),
+            ("{p: .pubkey, o: .owner}", false),
+        ] {
+            assert_eq!(parse(s).exposes_data(), exposes, "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_projections() {
+        let error = |s: &str| match s.parse::<Projection>() {
+            Ok(_) => panic!("{} parsed", s),
+            Err(e) => e.to_string(),
+        };
+        let syntax = |position: usize| ProjectionParseError::InvalidSyntax(position).to_string();
+        assert_eq!(error(""), syntax(0));
+        assert_eq!(error("{}"), syntax(1));
+        assert_eq!(error("{a .pubkey}"), syntax(3));
+        assert_eq!(error("{a: .pubkey"), syntax(11));
+        assert_eq!(error("{a: .pubkey,}"), syntax(12));
+        assert_eq!(error("{a: .pubkey} x"), syntax(13));
+        assert_eq!(error("{a: u8(.data, x)}"), syntax(13));
+        assert_eq!(error("{a: u8(.data, -1)}"), syntax(14));
+        assert_eq!(error("{a: u8(.data, 99999999999999999999999)}"), syntax(13));
+        assert_eq!(error("{a: .bogus}"), "Unknown projection field: .bogus");
+        assert_eq!(
+            error("{a: nope(.data)}"),
+            "Unknown projection function: nope"
+        );
+        assert_eq!(
+            error("{a: u64le(.data)}"),
+            "Wrong number of arguments for u64le"
+        );
+        assert_eq!(
+            error("{a: slice(.data, 1)}"),
+            "Wrong number of arguments for slice"
+        );
+        assert_eq!(
+            error("{a: hex(.data, 1)}"),
+            "Wrong number of arguments for hex"
+        );
+        assert_eq!(
+            error("{a: len(.data, 1)}"),
+            "Wrong number of arguments for len"
+        );
+    }
+
+    #[test]
+    fn parses_field_lists() {
+        let account = account(&Pubkey::new_unique());
+        let projection = Projection::from_fields("pubkey, owner,lamports,data_len").unwrap();
+        let headers: Vec<&str> = projection.headers().collect();
+        assert_eq!(headers, ["pubkey", "owner", "lamports", "data_len"]);
+        assert_eq!(
+            projection.header_fields(),
+            Some(&[Field::Pubkey, Field::Owner, Field::Lamports, Field::DataLen][..])
+        );
+        assert!(!projection.exposes_data());
+        assert_eq!(
+            projection.eval(0, 0, &account.stored(), None),
+            [
+                account.meta.pubkey.to_string(),
+                account.account_meta.owner.to_string(),
+                "1000000000".to_string(),
+                "132".to_string(),
+            ]
+        );
+
+        let with_data = Projection::from_fields("pubkey,data").unwrap();
+        assert!(with_data.header_fields().is_none());
+        assert!(with_data.exposes_data());
+    }
+
+    #[test]
+    fn rejects_malformed_field_lists() {
+        let error = |s: &str| Projection::from_fields(s).unwrap_err().to_string();
+        let syntax = |position: usize| ProjectionParseError::InvalidSyntax(position).to_string();
+        assert_eq!(error(""), syntax(0));
+        assert_eq!(error("pubkey,,owner"), syntax(7));
+        assert_eq!(error("pubkey,"), syntax(7));
+        assert_eq!(error("pubkey, ,owner"), syntax(7));
+        assert_eq!(error("pubkey,bogus"), "Unknown projection field: .bogus");
+    }
+}
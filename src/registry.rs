@@ -0,0 +1,48 @@
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Program ids common enough to be worth naming without a label database.
+#[rustfmt::skip]
+pub const WELL_KNOWN_PROGRAMS: &[(&str, &str)] = &[
+    ("11111111111111111111111111111111", "System Program"),
+    ("Config1111111111111111111111111111111111111", "Config Program"),
+    ("Stake11111111111111111111111111111111111111", "Stake Program"),
+    ("Vote111111111111111111111111111111111111111", "Vote Program"),
+    ("Sysvar1111111111111111111111111111111111111", "Sysvar"),
+    ("NativeLoader1111111111111111111111111111111", "Native Loader"),
+    ("BPFLoader1111111111111111111111111111111111", "BPF Loader (deprecated)"),
+    ("BPFLoader2111111111111111111111111111111111", "BPF Loader 2"),
+    ("BPFLoaderUpgradeab1e11111111111111111111111", "BPF Upgradeable Loader"),
+    ("ComputeBudget111111111111111111111111111111", "Compute Budget Program"),
+    ("AddressLookupTab1e1111111111111111111111111", "Address Lookup Table Program"),
+    ("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA", "Token Program"),
+    ("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb", "Token-2022 Program"),
+    ("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL", "Associated Token Account Program"),
+    ("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr", "Memo Program"),
+    ("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo", "Memo Program (v1)"),
+    ("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s", "Token Metadata Program"),
+];
+
+/// Lookup table over `WELL_KNOWN_PROGRAMS`.
+#[derive(Debug)]
+pub struct ProgramRegistry {
+    names: HashMap<Pubkey, &'static str>,
+}
+
+impl Default for ProgramRegistry {
+    fn default() -> Self {
+        let names = WELL_KNOWN_PROGRAMS
+            .iter()
+            .map(|(pubkey, name)| (Pubkey::from_str(pubkey).unwrap(), *name))
+            .collect();
+        ProgramRegistry { names }
+    }
+}
+
+impl ProgramRegistry {
+    /// Friendly name of a well-known program, or an empty string.
+    pub fn name(&self, program_id: &Pubkey) -> &'static str {
+        self.names.get(program_id).copied().unwrap_or("")
+    }
+}
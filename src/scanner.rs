@@ -0,0 +1,167 @@
+use crate::append_vec::{append_vec_iter, AppendVec};
+use crate::filter::AccountFilter;
+use crate::modified_solana_snapshot_etl::{AppendVecIterator, SnapshotExtractor};
+
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use solana_snapshot_etl::Result;
+use std::rc::Rc;
+
+/// Iterates the append vecs of a snapshot, yielding the accounts of each that match a filter.
+///
+/// Accounts borrow the memory of their append vec, so they are yielded per append vec: each
+/// item holds one append vec and the offsets of its matches, and lends them out through
+/// [`Matches::accounts`]. [`SnapshotScanner::for_each_match`] hides that for callers that
+/// don't keep accounts around.
+pub struct SnapshotScanner<'a> {
+    append_vecs: AppendVecIterator<'a>,
+    filter: AccountFilter,
+}
+
+/// The accounts of one append vec that passed the filter.
+pub struct Matches {
+    pub slot: u64,
+    pub id: u64,
+    append_vec: AppendVec,
+    offsets: Vec<usize>,
+}
+
+impl<'a> SnapshotScanner<'a> {
+    pub fn new<E: SnapshotExtractor>(extractor: &'a mut E, filter: AccountFilter) -> Self {
+        Self {
+            append_vecs: extractor.iter(),
+            filter,
+        }
+    }
+
+    /// Calls `f` with the slot and contents of every matching account, in archive order.
+    pub fn for_each_match(self, mut f: impl FnMut(u64, &StoredAccountMeta)) -> Result<()> {
+        for matches in self {
+            let matches = matches?;
+            for account in matches.accounts() {
+                f(matches.slot, &account);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for SnapshotScanner<'_> {
+    type Item = Result<Matches>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (slot, id, append_vec) = match self.append_vecs.next()? {
+            Ok(append_vec) => append_vec,
+            Err(e) => return Some(Err(e)),
+        };
+        let append_vec = Rc::new(append_vec);
+        let offsets = append_vec_iter(append_vec.clone())
+            .filter_map(|account| {
+                let account = account.access().unwrap();
+                self.filter.is_match(&account).then_some(account.offset)
+            })
+            .collect();
+        let append_vec = match Rc::try_unwrap(append_vec) {
+            Ok(append_vec) => append_vec,
+            Err(_) => unreachable!("account iterator dropped"),
+        };
+        Some(Ok(Matches {
+            slot,
+            id,
+            append_vec,
+            offsets,
+        }))
+    }
+}
+
+impl Matches {
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = StoredAccountMeta<'_>> {
+        self.offsets
+            .iter()
+            .map(|offset| self.append_vec.get_account(*offset).unwrap().0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MatchMode;
+    use crate::testing::{TestAccount, TestSnapshot};
+    use solana_program::pubkey::Pubkey;
+
+    /// Append vecs at slots 10, 11 and 12, with accounts of `owner` in the first and last.
+    fn snapshot(owner: Pubkey) -> TestSnapshot {
+        let other = Pubkey::new_unique();
+        TestSnapshot(vec![
+            (
+                10,
+                vec![
+                    TestAccount::new(owner, vec![1; 3]),
+                    TestAccount::new(other, vec![]),
+                    TestAccount::new(owner, vec![]),
+                ],
+            ),
+            (11, vec![TestAccount::new(other, vec![2; 5])]),
+            (12, vec![TestAccount::new(owner, vec![3; 8])]),
+        ])
+    }
+
+    fn owner_filter(owner: &Pubkey) -> AccountFilter {
+        AccountFilter::new(&[], None, &[owner.to_string()], &[], None, MatchMode::Any).unwrap()
+    }
+
+    #[test]
+    fn yields_the_matches_of_every_append_vec() {
+        let owner = Pubkey::new_unique();
+        let mut snapshot = snapshot(owner);
+        let pubkey = |vec: usize, account: usize| snapshot.0[vec].1[account].meta.pubkey;
+        let expected = [
+            (10, 0, vec![pubkey(0, 0), pubkey(0, 2)]),
+            (11, 1, vec![]),
+            (12, 2, vec![pubkey(2, 0)]),
+        ];
+
+        let scanned: Vec<_> = SnapshotScanner::new(&mut snapshot, owner_filter(&owner))
+            .map(|matches| {
+                let matches = matches.unwrap();
+                let pubkeys: Vec<_> = matches
+                    .accounts()
+                    .map(|account| account.meta.pubkey)
+                    .collect();
+                assert_eq!(pubkeys.len(), matches.len());
+                assert_eq!(pubkeys.is_empty(), matches.is_empty());
+                (matches.slot, matches.id, pubkeys)
+            })
+            .collect();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn calls_back_for_each_match() {
+        let owner = Pubkey::new_unique();
+        let mut snapshot = snapshot(owner);
+        let data_lens: Vec<_> = snapshot
+            .0
+            .iter()
+            .flat_map(|(slot, accounts)| accounts.iter().map(move |account| (*slot, account)))
+            .filter(|(_, account)| account.account_meta.owner == owner)
+            .map(|(slot, account)| (slot, account.meta.pubkey, account.data.len()))
+            .collect();
+
+        let mut matched = vec![];
+        SnapshotScanner::new(&mut snapshot, owner_filter(&owner))
+            .for_each_match(|slot, account| {
+                matched.push((slot, account.meta.pubkey, account.data.len()));
+            })
+            .unwrap();
+        assert_eq!(matched, data_lens);
+        assert_eq!(matched.len(), 3);
+    }
+}
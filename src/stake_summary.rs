@@ -0,0 +1,132 @@
+use crate::decode::delegation;
+use crate::encoding::LamportsFormat;
+use crate::output::{Output, OutputCell};
+
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Default)]
+struct VoteAccountStake {
+    stake_accounts: u64,
+    delegated_stake: u64,
+    deactivated_stake: u64,
+}
+
+/// `--summarize-stake`: delegated stake totals per vote account, written as CSV once the scan
+/// ends instead of one record per matched account.
+pub struct StakeSummary {
+    writer: csv::Writer<OutputCell>,
+    lamports_as: LamportsFormat,
+    by_voter: BTreeMap<Pubkey, VoteAccountStake>,
+}
+
+impl StakeSummary {
+    pub fn new(output: Output, lamports_as: LamportsFormat) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new().from_writer(OutputCell::new(output)),
+            lamports_as,
+            by_voter: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the delegation of a stake account; other matches are ignored.
+    pub fn dump_account(&mut self, account: &StoredAccountMeta) {
+        if let Some(delegation) = delegation(account) {
+            let totals = self.by_voter.entry(delegation.voter).or_default();
+            totals.stake_accounts += 1;
+            totals.delegated_stake += delegation.stake;
+            if delegation.deactivation_epoch.is_some() {
+                totals.deactivated_stake += delegation.stake;
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_record([
+            "vote_account",
+            "stake_accounts",
+            "delegated_stake",
+            "deactivated_stake",
+        ])?;
+        for (voter, totals) in std::mem::take(&mut self.by_voter) {
+            self.writer.write_record([
+                voter.to_string(),
+                totals.stake_accounts.to_string(),
+                self.lamports_as.format(totals.delegated_stake as u128),
+                self.lamports_as.format(totals.deactivated_stake as u128),
+            ])?;
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().borrow_mut().finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::{DumpOptions, Dumper, Sink};
+    use crate::filter::{AccountFilter, MatchMode};
+    use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+    use crate::testing::{put, TempPath, TestAccount, TestSnapshot};
+    use solana_program::{stake, system_program};
+    use std::collections::HashMap;
+
+    fn delegated(pubkey: Pubkey, voter: Pubkey, stake: u64) -> TestAccount {
+        let mut data = vec![0; 200];
+        put(&mut data, 0, &2u32.to_le_bytes());
+        put(&mut data, 124, voter.as_ref());
+        put(&mut data, 156, &stake.to_le_bytes());
+        put(&mut data, 172, &u64::MAX.to_le_bytes());
+        let mut account = TestAccount::new(stake::program::id(), data);
+        account.meta.pubkey = pubkey;
+        account
+    }
+
+    #[test]
+    fn counts_the_newest_version_of_live_stake_accounts() {
+        let voter = Pubkey::new_unique();
+        let (topped_up, withdrawn) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut closed = TestAccount::new(system_program::id(), vec![]);
+        closed.meta.pubkey = withdrawn;
+        closed.account_meta.lamports = 0;
+        let mut snapshot = TestSnapshot(vec![
+            (
+                10,
+                vec![
+                    delegated(topped_up, voter, 100),
+                    delegated(withdrawn, voter, 50),
+                ],
+            ),
+            (20, vec![delegated(topped_up, voter, 300)]),
+            (30, vec![closed]),
+        ]);
+
+        let path = TempPath::new("stake-summary.csv");
+        let output = Output::open(path.to_str(), None).unwrap();
+        let owners = [stake::program::id().to_string()];
+        let filter = AccountFilter::new(&[], None, &owners, &[], None, MatchMode::Any).unwrap();
+        // what --dedup finds, which --summarize-stake implies
+        let options = DumpOptions {
+            newest_versions: Some(HashMap::from([(topped_up, (20, 1))])),
+            ..Default::default()
+        };
+        let sink = Sink::StakeSummary(StakeSummary::new(output, LamportsFormat::U64));
+        let mut dumper = Dumper::new(sink, filter, options, None);
+        for append_vec in snapshot.iter() {
+            let (slot, id, append_vec) = append_vec.unwrap();
+            dumper.dump_append_vec(slot, id, append_vec, None).unwrap();
+        }
+        dumper.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                "vote_account,stake_accounts,delegated_stake,deactivated_stake\n{},1,300,0\n",
+                voter
+            )
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
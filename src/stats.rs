@@ -0,0 +1,169 @@
+use crate::encoding::LamportsFormat;
+use crate::output::Output;
+
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Power-of-two data size buckets: `0` holds empty accounts, `k` sizes in `2^(k-1)..2^k`.
+const SIZE_BUCKETS: usize = 65;
+
+struct Totals {
+    accounts: u64,
+    lamports: u128,
+    data_bytes: u64,
+    sizes: [u64; SIZE_BUCKETS],
+}
+
+impl Default for Totals {
+    fn default() -> Self {
+        Self {
+            accounts: 0,
+            lamports: 0,
+            data_bytes: 0,
+            sizes: [0; SIZE_BUCKETS],
+        }
+    }
+}
+
+impl Totals {
+    fn add(&mut self, account: &StoredAccountMeta) {
+        let len = account.data.len() as u64;
+        self.accounts += 1;
+        self.lamports += account.account_meta.lamports as u128;
+        self.data_bytes += len;
+        self.sizes[(u64::BITS - len.leading_zeros()) as usize] += 1;
+    }
+
+    /// Lamports are strings, since the sum over a whole snapshot can exceed what JSON
+    /// readers parse losslessly.
+    fn to_json(&self, lamports_as: LamportsFormat) -> Value {
+        let histogram = self
+            .sizes
+            .iter()
+            .enumerate()
+            .filter(|(_, accounts)| **accounts > 0)
+            .map(|(bucket, accounts)| {
+                let (min, max) = match bucket {
+                    0 => (0, 0),
+                    _ => (1u128 << (bucket - 1), (1u128 << bucket) - 1),
+                };
+                json!({ "min_size": min as u64, "max_size": max as u64, "accounts": accounts })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "accounts": self.accounts,
+            "lamports": lamports_as.format(self.lamports),
+            "data_bytes": self.data_bytes,
+            "size_histogram": histogram,
+        })
+    }
+}
+
+/// `--stats`: totals and data size histograms of the matches, overall and per owner, written
+/// as one JSON document once the scan ends instead of one record per matched account.
+pub struct Stats {
+    output: Output,
+    lamports_as: LamportsFormat,
+    total: Totals,
+    by_owner: BTreeMap<Pubkey, Totals>,
+}
+
+impl Stats {
+    pub fn new(output: Output, lamports_as: LamportsFormat) -> Self {
+        Self {
+            output,
+            lamports_as,
+            total: Totals::default(),
+            by_owner: BTreeMap::new(),
+        }
+    }
+
+    pub fn dump_account(&mut self, account: &StoredAccountMeta) {
+        self.total.add(account);
+        self.by_owner
+            .entry(account.account_meta.owner)
+            .or_default()
+            .add(account);
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut document = self.total.to_json(self.lamports_as);
+        document["owners"] = self
+            .by_owner
+            .iter()
+            .map(|(owner, totals)| {
+                let mut entry = totals.to_json(self.lamports_as);
+                entry["owner"] = Value::from(owner.to_string());
+                entry
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut self.output, &document)?;
+        writeln!(self.output)?;
+        self.output.flush()?;
+        self.output.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempPath, TestAccount};
+
+    #[test]
+    fn writes_totals_and_size_histograms() {
+        let path = TempPath::new("stats.json");
+        let mut stats = Stats::new(
+            Output::open(path.to_str(), None).unwrap(),
+            LamportsFormat::U64,
+        );
+        for (owner, len) in [(2, 0), (2, 1), (2, 2), (4, 1023), (4, 1024)] {
+            let account = TestAccount::new(Pubkey::new_from_array([owner; 32]), vec![0; len]);
+            stats.dump_account(&account.stored());
+        }
+        stats.finish().unwrap();
+
+        let document: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "accounts": 5,
+                "lamports": "5000000000",
+                "data_bytes": 2050,
+                "size_histogram": [
+                    { "min_size": 0, "max_size": 0, "accounts": 1 },
+                    { "min_size": 1, "max_size": 1, "accounts": 1 },
+                    { "min_size": 2, "max_size": 3, "accounts": 1 },
+                    { "min_size": 512, "max_size": 1023, "accounts": 1 },
+                    { "min_size": 1024, "max_size": 2047, "accounts": 1 },
+                ],
+                "owners": [
+                    {
+                        "owner": "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR",
+                        "accounts": 3,
+                        "lamports": "3000000000",
+                        "data_bytes": 3,
+                        "size_histogram": [
+                            { "min_size": 0, "max_size": 0, "accounts": 1 },
+                            { "min_size": 1, "max_size": 1, "accounts": 1 },
+                            { "min_size": 2, "max_size": 3, "accounts": 1 },
+                        ],
+                    },
+                    {
+                        "owner": "GgBaCs3NCBuZN12kCJgAW63ydqohFkHEdfdEXBPzLHq",
+                        "accounts": 2,
+                        "lamports": "2000000000",
+                        "data_bytes": 2047,
+                        "size_histogram": [
+                            { "min_size": 512, "max_size": 1023, "accounts": 1 },
+                            { "min_size": 1024, "max_size": 2047, "accounts": 1 },
+                        ],
+                    },
+                ],
+            })
+        );
+    }
+}
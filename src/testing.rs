@@ -0,0 +1,180 @@
+//! Test fixtures, public only so the binary's tests reach them through the library.
+
+use crate::append_vec::AppendVec;
+use crate::modified_solana_snapshot_etl::{AppendVecIterator, SnapshotExtractor};
+
+use solana_program::pubkey::Pubkey;
+use solana_sdk::hash::Hash;
+use solana_snapshot_etl::append_vec::{AccountMeta, StoredAccountMeta, StoredMeta};
+use std::mem::{offset_of, size_of};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// An account record owned by a test, for code that reads a `StoredAccountMeta` out of an
+/// append vec.
+pub struct TestAccount {
+    pub meta: StoredMeta,
+    pub account_meta: AccountMeta,
+    pub data: Vec<u8>,
+    hash: Hash,
+}
+
+impl TestAccount {
+    /// A rent-paying account of `owner` with a fresh pubkey.
+    pub fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+        Self {
+            meta: StoredMeta {
+                write_version: 1,
+                pubkey: Pubkey::new_unique(),
+                data_len: data.len() as u64,
+            },
+            account_meta: AccountMeta {
+                lamports: 1_000_000_000,
+                rent_epoch: 0,
+                owner,
+                executable: false,
+            },
+            data,
+            hash: Hash::default(),
+        }
+    }
+
+    pub fn stored(&self) -> StoredAccountMeta<'_> {
+        StoredAccountMeta {
+            meta: &self.meta,
+            account_meta: &self.account_meta,
+            data: &self.data,
+            offset: 0,
+            stored_size: 0,
+            hash: &self.hash,
+        }
+    }
+}
+
+/// Puts `bytes` into `data` at `offset`.
+pub fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// A path in the temp dir for one test, removed with whatever was created there on drop,
+/// so a failing assertion doesn't leave files behind.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// A path not yet created; `name` only makes leftovers easier to trace.
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        Self(std::env::temp_dir().join(format!(
+            "solana-snapshot-gpa-test-{}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            name
+        )))
+    }
+
+    /// A file holding `contents`.
+    pub fn file(name: &str, contents: impl AsRef<[u8]>) -> Self {
+        let path = Self::new(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// An empty directory.
+    pub fn dir(name: &str) -> Self {
+        let path = Self::new(name);
+        std::fs::create_dir(&path).unwrap();
+        path
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if self.0.is_dir() {
+            let _ = std::fs::remove_dir_all(&self.0);
+        } else {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// Snapshot of `(slot, accounts)` append vecs, in archive order.
+pub struct TestSnapshot(pub Vec<(u64, Vec<TestAccount>)>);
+
+impl SnapshotExtractor for TestSnapshot {
+    fn iter(&mut self) -> AppendVecIterator<'_> {
+        Box::new(self.0.iter().enumerate().map(|(id, (slot, accounts))| {
+            let bytes = append_vec_bytes(accounts);
+            let append_vec = AppendVec::new_from_reader(&mut &bytes[..], bytes.len())?;
+            Ok((*slot, id as u64, append_vec))
+        }))
+    }
+}
+
+/// Storage file layout of `accounts`: each record's headers, hash and data, 8-byte aligned.
+pub fn append_vec_bytes(accounts: &[TestAccount]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for account in accounts {
+        let offset = bytes.len();
+        let account_meta = offset + size_of::<StoredMeta>();
+        let hash = account_meta + size_of::<AccountMeta>();
+        let data = hash + size_of::<Hash>();
+        bytes.resize((data + account.data.len()).next_multiple_of(8), 0);
+
+        let write_version = account.meta.write_version.to_ne_bytes();
+        put(
+            &mut bytes,
+            offset + offset_of!(StoredMeta, write_version),
+            &write_version,
+        );
+        let data_len = (account.data.len() as u64).to_ne_bytes();
+        put(
+            &mut bytes,
+            offset + offset_of!(StoredMeta, data_len),
+            &data_len,
+        );
+        let pubkey = account.meta.pubkey.to_bytes();
+        put(&mut bytes, offset + offset_of!(StoredMeta, pubkey), &pubkey);
+        let lamports = account.account_meta.lamports.to_ne_bytes();
+        put(
+            &mut bytes,
+            account_meta + offset_of!(AccountMeta, lamports),
+            &lamports,
+        );
+        let rent_epoch = account.account_meta.rent_epoch.to_ne_bytes();
+        put(
+            &mut bytes,
+            account_meta + offset_of!(AccountMeta, rent_epoch),
+            &rent_epoch,
+        );
+        let owner = account.account_meta.owner.to_bytes();
+        put(
+            &mut bytes,
+            account_meta + offset_of!(AccountMeta, owner),
+            &owner,
+        );
+        let executable = [account.account_meta.executable as u8];
+        put(
+            &mut bytes,
+            account_meta + offset_of!(AccountMeta, executable),
+            &executable,
+        );
+        put(&mut bytes, hash, account.hash.as_ref());
+        put(&mut bytes, data, &account.data);
+    }
+    bytes
+}
@@ -0,0 +1,177 @@
+use crate::append_vec::append_vec_iter;
+use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+
+use solana_program::bpf_loader_upgradeable;
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// bincode layout of UpgradeableLoaderState
+const PROGRAM_TAG: u32 = 2;
+const PROGRAM_DATA_TAG: u32 = 3;
+const PROGRAM_DATA_METADATA_SIZE: usize = 45;
+
+enum LoaderAccount {
+    Program {
+        programdata_address: Pubkey,
+    },
+    ProgramData {
+        slot: u64,
+        upgrade_authority: Option<Pubkey>,
+        size: u64,
+    },
+}
+
+/// Deployment state of a program account owned by the upgradeable loader.
+pub(crate) struct ProgramInfo {
+    pub(crate) program_id: Pubkey,
+    pub(crate) programdata_address: Pubkey,
+    /// `None` when the programdata account is missing from the snapshot (closed program).
+    pub(crate) programdata: Option<ProgramDataInfo>,
+}
+
+pub(crate) struct ProgramDataInfo {
+    pub(crate) last_deploy_slot: u64,
+    /// `None` once the program has been made immutable.
+    pub(crate) upgrade_authority: Option<Pubkey>,
+    pub(crate) size: u64,
+}
+
+fn parse_loader_account(data: &[u8]) -> Option<LoaderAccount> {
+    let tag = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+    match tag {
+        PROGRAM_TAG => Some(LoaderAccount::Program {
+            programdata_address: Pubkey::new(data.get(4..36)?),
+        }),
+        PROGRAM_DATA_TAG => {
+            let slot = u64::from_le_bytes(data.get(4..12)?.try_into().ok()?);
+            let upgrade_authority = match data.get(12)? {
+                0 => None,
+                _ => Some(Pubkey::new(data.get(13..45)?)),
+            };
+            let size = data.len().saturating_sub(PROGRAM_DATA_METADATA_SIZE) as u64;
+            Some(LoaderAccount::ProgramData {
+                slot,
+                upgrade_authority,
+                size,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Every live upgradeable program with its programdata state, sorted by program id.
+pub(crate) fn upgradeable_programs<L: SnapshotExtractor>(
+    loader: &mut L,
+) -> solana_snapshot_etl::Result<Vec<ProgramInfo>> {
+    let loader_id = bpf_loader_upgradeable::id();
+    // newest version per pubkey, as (slot, write_version, lamports, state); the state is
+    // `None` when that version is no program or programdata account, e.g. once closed
+    let mut latest: HashMap<Pubkey, (u64, u64, u64, Option<LoaderAccount>)> = HashMap::new();
+    for append_vec in loader.iter() {
+        let (slot, _id, append_vec) = append_vec?;
+        for account in append_vec_iter(Rc::new(append_vec)) {
+            let account = account.access().unwrap();
+            if account.account_meta.owner != loader_id {
+                continue;
+            }
+            let write_version = account.meta.write_version;
+            let newer = latest
+                .get(&account.meta.pubkey)
+                .is_none_or(|(s, w, _, _)| (slot, write_version) > (*s, *w));
+            if newer {
+                let state = parse_loader_account(account.data);
+                let lamports = account.account_meta.lamports;
+                latest.insert(account.meta.pubkey, (slot, write_version, lamports, state));
+            }
+        }
+    }
+    let latest: HashMap<Pubkey, LoaderAccount> = latest
+        .into_iter()
+        .filter(|(_, (_, _, lamports, _))| *lamports > 0)
+        .filter_map(|(pubkey, (_, _, _, state))| Some((pubkey, state?)))
+        .collect();
+
+    let programdata = |address: &Pubkey| match latest.get(address) {
+        Some(LoaderAccount::ProgramData {
+            slot,
+            upgrade_authority,
+            size,
+        }) => Some(ProgramDataInfo {
+            last_deploy_slot: *slot,
+            upgrade_authority: *upgrade_authority,
+            size: *size,
+        }),
+        _ => None,
+    };
+    let mut programs: Vec<ProgramInfo> = latest
+        .iter()
+        .filter_map(|(pubkey, state)| match state {
+            LoaderAccount::Program {
+                programdata_address,
+            } => Some(ProgramInfo {
+                program_id: *pubkey,
+                programdata_address: *programdata_address,
+                programdata: programdata(programdata_address),
+            }),
+            LoaderAccount::ProgramData { .. } => None,
+        })
+        .collect();
+    programs.sort_by_key(|program| program.program_id.to_bytes());
+    Ok(programs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TestAccount, TestSnapshot};
+
+    fn program(programdata_address: Pubkey) -> TestAccount {
+        let mut data = PROGRAM_TAG.to_le_bytes().to_vec();
+        data.extend_from_slice(programdata_address.as_ref());
+        TestAccount::new(bpf_loader_upgradeable::id(), data)
+    }
+
+    fn programdata(slot: u64, upgrade_authority: Pubkey) -> TestAccount {
+        let mut data = PROGRAM_DATA_TAG.to_le_bytes().to_vec();
+        data.extend_from_slice(&slot.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(upgrade_authority.as_ref());
+        data.extend_from_slice(&[0x7f; 100]);
+        TestAccount::new(bpf_loader_upgradeable::id(), data)
+    }
+
+    #[test]
+    fn reads_the_newest_programdata() {
+        let authority = Pubkey::new_unique();
+        let deployed = programdata(10, authority);
+        let mut upgraded = programdata(20, authority);
+        upgraded.meta.pubkey = deployed.meta.pubkey;
+        let program = program(deployed.meta.pubkey);
+        let program_id = program.meta.pubkey;
+        let mut snapshot = TestSnapshot(vec![(30, vec![upgraded]), (10, vec![program, deployed])]);
+
+        let programs = upgradeable_programs(&mut snapshot).unwrap();
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].program_id, program_id);
+        let programdata = programs[0].programdata.as_ref().unwrap();
+        assert_eq!(programdata.last_deploy_slot, 20);
+        assert_eq!(programdata.upgrade_authority, Some(authority));
+        assert_eq!(programdata.size, 100);
+    }
+
+    #[test]
+    fn closed_programdata_hides_older_versions() {
+        let deployed = programdata(10, Pubkey::new_unique());
+        // `solana program close` leaves the programdata uninitialized and drained
+        let mut closed = TestAccount::new(bpf_loader_upgradeable::id(), vec![0; 4]);
+        closed.meta.pubkey = deployed.meta.pubkey;
+        closed.account_meta.lamports = 0;
+        let program = program(deployed.meta.pubkey);
+        let mut snapshot = TestSnapshot(vec![(10, vec![program, deployed]), (20, vec![closed])]);
+
+        let programs = upgradeable_programs(&mut snapshot).unwrap();
+        assert_eq!(programs.len(), 1);
+        assert!(programs[0].programdata.is_none());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::append_vec::append_vec_iter;
+use crate::filter::AccountFilter;
+use crate::modified_solana_snapshot_etl::SnapshotExtractor;
+use crate::prepass::newest_versions;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// `getProgramAccounts` of a large program can take minutes to answer.
+const RPC_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("Invalid --rpc-filters: {0}")]
+    InvalidFilters(String),
+    #[error("RPC request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Unexpected RPC response: {0}")]
+    InvalidResponse(String),
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct KeyedAccount {
+    pubkey: String,
+}
+
+#[derive(Deserialize)]
+struct Context {
+    slot: u64,
+}
+
+/// `getProgramAccounts` result with `withContext`.
+#[derive(Deserialize)]
+struct WithContext {
+    context: Context,
+    value: Vec<KeyedAccount>,
+}
+
+/// One page of a `getProgramAccountsV2` result.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Page {
+    accounts: Vec<KeyedAccount>,
+    pagination_key: Option<String>,
+}
+
+/// Pubkeys returned by the RPC node, with the slot it answered at when it says.
+pub(crate) struct RpcAccounts {
+    pub(crate) slot: Option<u64>,
+    pub(crate) pubkeys: HashSet<Pubkey>,
+}
+
+/// Reads the `filters` array of a `getProgramAccounts` call, inline or from `@PATH`.
+pub(crate) fn read_rpc_filters(s: &str) -> Result<Value, VerifyError> {
+    let json = match s.strip_prefix('@') {
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|e| VerifyError::InvalidFilters(e.to_string()))?
+        }
+        None => s.to_string(),
+    };
+    match serde_json::from_str(&json) {
+        Ok(filters @ Value::Array(_)) => Ok(filters),
+        Ok(_) => Err(VerifyError::InvalidFilters("expected an array".to_string())),
+        Err(e) => Err(VerifyError::InvalidFilters(e.to_string())),
+    }
+}
+
+/// The accounts of `program` matching `filters` on the RPC node at `url`. Only pubkeys are
+/// fetched (an empty `dataSlice`). With `page_size` the results come from
+/// `getProgramAccountsV2` in pages of that many accounts, for providers that offer it,
+/// instead of one `getProgramAccounts` response.
+pub(crate) fn rpc_pubkeys(
+    url: &str,
+    program: &Pubkey,
+    filters: &Value,
+    page_size: Option<usize>,
+) -> Result<RpcAccounts, VerifyError> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(RPC_TIMEOUT)
+        .build()?;
+    let mut config = json!({
+        "encoding": "base64",
+        "dataSlice": { "offset": 0, "length": 0 },
+        "filters": filters,
+    });
+    let mut pubkeys = HashSet::new();
+    let Some(page_size) = page_size else {
+        config["withContext"] = Value::from(true);
+        let result: WithContext = call(&client, url, "getProgramAccounts", program, &config)?;
+        insert_pubkeys(&mut pubkeys, result.value)?;
+        return Ok(RpcAccounts {
+            slot: Some(result.context.slot),
+            pubkeys,
+        });
+    };
+    config["limit"] = Value::from(page_size);
+    loop {
+        let page: Page = call(&client, url, "getProgramAccountsV2", program, &config)?;
+        let last = page.accounts.is_empty();
+        insert_pubkeys(&mut pubkeys, page.accounts)?;
+        match page.pagination_key {
+            Some(key) if !last => config["paginationKey"] = Value::from(key),
+            _ => break,
+        }
+    }
+    Ok(RpcAccounts {
+        slot: None,
+        pubkeys,
+    })
+}
+
+fn call<T: serde::de::DeserializeOwned>(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    method: &str,
+    program: &Pubkey,
+    config: &Value,
+) -> Result<T, VerifyError> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": [program.to_string(), config],
+    });
+    let body = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(request.to_string())
+        .send()?
+        .error_for_status()?
+        .bytes()?;
+    let response: RpcResponse<T> =
+        serde_json::from_slice(&body).map_err(|e| VerifyError::InvalidResponse(e.to_string()))?;
+    match (response.result, response.error) {
+        (_, Some(RpcError { code, message })) => Err(VerifyError::Rpc { code, message }),
+        (Some(result), None) => Ok(result),
+        (None, None) => Err(VerifyError::InvalidResponse(
+            "neither result nor error".to_string(),
+        )),
+    }
+}
+
+fn insert_pubkeys(
+    pubkeys: &mut HashSet<Pubkey>,
+    accounts: Vec<KeyedAccount>,
+) -> Result<(), VerifyError> {
+    for account in accounts {
+        let pubkey = Pubkey::from_str(&account.pubkey)
+            .map_err(|_e| VerifyError::InvalidResponse(format!("pubkey {}", account.pubkey)))?;
+        pubkeys.insert(pubkey);
+    }
+    Ok(())
+}
+
+/// Pubkeys of the accounts whose newest version holds lamports and passes `filter`, which is
+/// what `getProgramAccounts` would have returned at the snapshot slot.
+///
+/// Takes three scans: two to find the newest versions, then one to check them.
+pub(crate) fn snapshot_pubkeys<A: SnapshotExtractor, B: SnapshotExtractor, C: SnapshotExtractor>(
+    first: &mut A,
+    second: &mut B,
+    third: &mut C,
+    filter: &AccountFilter,
+) -> solana_snapshot_etl::Result<HashSet<Pubkey>> {
+    let newest = newest_versions(first, second, |account| filter.is_match(account))?;
+    let mut pubkeys = HashSet::new();
+    for append_vec in third.iter() {
+        let (slot, _id, append_vec) = append_vec?;
+        for account in append_vec_iter(Rc::new(append_vec)) {
+            let account = account.access().unwrap();
+            let version = (slot, account.meta.write_version);
+            if newest.get(&account.meta.pubkey) == Some(&version) && filter.is_match(&account) {
+                pubkeys.insert(account.meta.pubkey);
+            }
+        }
+    }
+    Ok(pubkeys)
+}